@@ -0,0 +1,1241 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{BufRead, Error as IoError, ErrorKind, Read, Result as IoResult, Seek, SeekFrom};
+use std::path::{Component, PathBuf};
+
+use crate::inflate::Inflater;
+
+/// Size of a tar block.
+const BLOCK_SIZE: usize = 512;
+
+/// Magic bytes of zip archives: either the first local file header, or the
+/// end of central directory record of an empty archive.
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const EMPTY_ZIP_MAGIC: &[u8] = b"PK\x05\x06";
+
+/// Magic bytes of the archive formats that are recognized but not supported.
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+
+/// Signatures of the zip archive structures.
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4b50;
+const ZIP_CENTRAL_HEADER: u32 = 0x0201_4b50;
+const ZIP_END: u32 = 0x0605_4b50;
+const ZIP64_END: u32 = 0x0606_4b50;
+const ZIP64_END_LOCATOR: u32 = 0x0706_4b50;
+
+/// Sizes of the fixed parts of the zip archive structures.
+const ZIP_LOCAL_HEADER_SIZE: usize = 30;
+const ZIP_CENTRAL_HEADER_SIZE: usize = 46;
+const ZIP_END_SIZE: usize = 22;
+const ZIP64_END_SIZE: usize = 56;
+const ZIP64_END_LOCATOR_SIZE: usize = 20;
+
+/// Maximum length of the archive comment that follows the end of central
+/// directory record.
+const ZIP_MAX_COMMENT: usize = 0xffff;
+
+/// Zip extra field IDs of the 64-bit sizes and of the Unix timestamps.
+const ZIP64_EXTRA: u16 = 0x0001;
+const EXTENDED_TIMESTAMP_EXTRA: u16 = 0x5455;
+
+/// Zip compression methods that are supported.
+const ZIP_STORED: u16 = 0;
+const ZIP_DEFLATED: u16 = 8;
+
+/// Zip host system with Unix file modes in the external attributes.
+const ZIP_UNIX_HOST: u16 = 3;
+
+/// Unix file type bits.
+const S_IFMT: u32 = 0o170_000;
+const S_IFREG: u32 = 0o100_000;
+const S_IFDIR: u32 = 0o040_000;
+const S_IFLNK: u32 = 0o120_000;
+
+/// Magic value of a POSIX ustar header, which has the path prefix field.
+const USTAR_MAGIC: &[u8] = b"ustar\0";
+
+/// An error returned when an archive cannot be read.
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// The underlying reader failed.
+    Io(IoError),
+
+    /// The archive format is recognized, but not supported.
+    UnsupportedFormat(&'static str),
+
+    /// A tar header is malformed.
+    InvalidHeader,
+
+    /// An entry path escapes the archive root.
+    InvalidPath(PathBuf),
+
+    /// The zip archive structure is malformed.
+    InvalidZip,
+
+    /// A zip entry is compressed with an unsupported method.
+    UnsupportedCompression(u16),
+
+    /// A zip entry is encrypted.
+    Encrypted(PathBuf),
+
+    /// The contents of a zip entry do not match its checksum.
+    ChecksumMismatch,
+}
+
+impl Display for ArchiveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match &self {
+            ArchiveError::Io(err) => write!(f, "Archive I/O error: {}", err),
+            ArchiveError::UnsupportedFormat(format) => {
+                write!(f, "Unsupported archive format: {}", format)
+            }
+            ArchiveError::InvalidHeader => write!(f, "Invalid tar header"),
+            ArchiveError::InvalidPath(p) => write!(f, "Invalid archive entry path: {:?}", p),
+            ArchiveError::InvalidZip => write!(f, "Invalid zip archive"),
+            ArchiveError::UnsupportedCompression(m) => {
+                write!(f, "Unsupported zip compression method: {}", m)
+            }
+            ArchiveError::Encrypted(p) => {
+                write!(f, "Encrypted zip entries are not supported: {:?}", p)
+            }
+            ArchiveError::ChecksumMismatch => write!(f, "Zip entry checksum mismatch"),
+        }
+    }
+}
+
+impl Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self {
+            ArchiveError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<IoError> for ArchiveError {
+    fn from(err: IoError) -> Self {
+        ArchiveError::Io(err)
+    }
+}
+
+/// Kind of an archive entry.
+#[derive(Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    /// Regular file.
+    File,
+
+    /// Directory.
+    Directory,
+
+    /// Symbolic link with the given target.
+    Symlink(PathBuf),
+
+    /// Hard link to an earlier entry with the given path relative to the
+    /// archive root, whose contents it shares instead of storing them.
+    HardLink(PathBuf),
+
+    /// Any other entry (device, FIFO etc.), which has no contents.
+    Other,
+}
+
+/// An archive entry header.
+#[derive(Debug)]
+pub struct Entry {
+    /// Path of the entry relative to the archive root.
+    pub path: PathBuf,
+
+    /// Size of the entry contents.
+    pub size: u64,
+
+    /// Kind of the entry.
+    pub kind: EntryKind,
+
+    /// Modification time of the entry in seconds since the Unix epoch, if
+    /// known.
+    pub mtime: Option<u64>,
+}
+
+/// Checks if the archive starts with the magic bytes of a zip archive.
+pub fn is_zip(head: &[u8]) -> bool {
+    head.starts_with(ZIP_MAGIC) || head.starts_with(EMPTY_ZIP_MAGIC)
+}
+
+/// A streaming reader of tar archives.
+///
+/// The archive is read sequentially: `next_entry()` returns the header of the
+/// next entry, after which the reader itself yields the entry contents. Any
+/// unread contents are skipped by the following call to `next_entry()`.
+///
+/// POSIX ustar, GNU long names and PAX extended headers are supported.
+pub struct TarReader<R: BufRead> {
+    /// The underlying reader.
+    reader: R,
+
+    /// Unread bytes of the current entry contents.
+    remaining: u64,
+
+    /// Padding after the current entry contents.
+    padding: u64,
+}
+
+impl<R: BufRead> TarReader<R> {
+    /// Creates a new tar reader, rejecting archive formats that are recognized
+    /// but not supported.
+    pub fn new(mut reader: R) -> Result<Self, ArchiveError> {
+        let head = reader.fill_buf()?;
+        if is_zip(head) {
+            return Err(ArchiveError::UnsupportedFormat("zip"));
+        } else if head.starts_with(GZIP_MAGIC) {
+            return Err(ArchiveError::UnsupportedFormat("gzip-compressed tar"));
+        }
+
+        Ok(TarReader {
+            reader,
+            remaining: 0,
+            padding: 0,
+        })
+    }
+
+    /// Reads the header of the next entry, or returns `None` at the end of
+    /// the archive.
+    pub fn next_entry(&mut self) -> Result<Option<Entry>, ArchiveError> {
+        // Extended headers override the fields of the following entry.
+        let mut long_path: Option<Vec<u8>> = None;
+        let mut long_link: Option<Vec<u8>> = None;
+        let mut pax_size: Option<u64> = None;
+
+        loop {
+            self.skip_contents()?;
+
+            let mut header = [0u8; BLOCK_SIZE];
+            if !self.read_block(&mut header)? || header.iter().all(|&b| b == 0) {
+                return Ok(None);
+            }
+            verify_checksum(&header)?;
+
+            let size = pax_size
+                .take()
+                .map_or_else(|| parse_number(&header[124..136]), Ok)?;
+            self.remaining = size;
+            self.padding = (BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64;
+
+            match header[156] {
+                // GNU long name or long link target of the next entry.
+                b'L' => long_path = Some(trim_nul(&self.read_contents()?).to_vec()),
+                b'K' => long_link = Some(trim_nul(&self.read_contents()?).to_vec()),
+
+                // PAX extended header of the next entry.
+                b'x' => {
+                    for (key, value) in parse_pax(&self.read_contents()?)? {
+                        match key {
+                            b"path" => long_path = Some(value.to_vec()),
+                            b"linkpath" => long_link = Some(value.to_vec()),
+                            b"size" => pax_size = Some(parse_decimal(value)?),
+                            _ => {}
+                        }
+                    }
+                }
+
+                // PAX global header, which does not describe an entry.
+                b'g' => continue,
+
+                flag => {
+                    let name = long_path.take().unwrap_or_else(|| header_path(&header));
+                    let path = normalize(bytes_to_path(&name))?;
+
+                    let link = long_link
+                        .take()
+                        .unwrap_or_else(|| trim_nul(&header[157..257]).to_vec());
+                    let kind = match flag {
+                        b'0' | b'\0' | b'7' => EntryKind::File,
+                        b'5' => EntryKind::Directory,
+                        b'2' => EntryKind::Symlink(bytes_to_path(&link)),
+                        b'1' => EntryKind::HardLink(normalize(bytes_to_path(&link))?),
+                        _ => EntryKind::Other,
+                    };
+
+                    // Only regular files have contents.
+                    let size = if kind == EntryKind::File { size } else { 0 };
+
+                    // Negative and malformed times are unknown rather than
+                    // invalid, since the time is only used for filtering.
+                    let mtime = parse_number(&header[136..148]).ok();
+
+                    return Ok(Some(Entry {
+                        path,
+                        size,
+                        kind,
+                        mtime,
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Reads a full block, returning `false` at the end of the archive.
+    fn read_block(&mut self, block: &mut [u8; BLOCK_SIZE]) -> Result<bool, ArchiveError> {
+        let mut read = 0;
+        while read < BLOCK_SIZE {
+            match self.reader.read(&mut block[read..])? {
+                0 if read == 0 => return Ok(false),
+                0 => return Err(ArchiveError::Io(IoError::from(ErrorKind::UnexpectedEof))),
+                n => read += n,
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Reads the contents of the current entry in full.
+    fn read_contents(&mut self) -> Result<Vec<u8>, ArchiveError> {
+        // Do not trust the size to preallocate, it may come from a corrupted
+        // archive.
+        let mut buf = Vec::new();
+        self.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Skips the unread contents of the current entry and its padding.
+    fn skip_contents(&mut self) -> Result<(), ArchiveError> {
+        let len = self.remaining + self.padding;
+        let skipped = std::io::copy(&mut (&mut self.reader).take(len), &mut std::io::sink())?;
+        if skipped < len {
+            return Err(ArchiveError::Io(IoError::from(ErrorKind::UnexpectedEof)));
+        }
+
+        self.remaining = 0;
+        self.padding = 0;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for TarReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let len = buf
+            .len()
+            .min(self.remaining.min(usize::MAX as u64) as usize);
+        let n = self.reader.read(&mut buf[..len])?;
+        if n == 0 && len > 0 {
+            return Err(IoError::from(ErrorKind::UnexpectedEof));
+        }
+
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// A central directory record of a zip entry.
+struct ZipRecord {
+    /// The entry header; symbolic link targets are read with the contents.
+    entry: Entry,
+
+    /// General purpose flags.
+    flags: u16,
+
+    /// Compression method.
+    method: u16,
+
+    /// CRC-32 of the uncompressed contents.
+    crc: u32,
+
+    /// Size of the compressed contents.
+    compressed_size: u64,
+
+    /// Offset of the local header of the entry.
+    offset: u64,
+}
+
+/// Contents of the current zip entry being read.
+struct ZipContents {
+    /// Decoder of the deflated contents, or `None` for the stored ones.
+    inflater: Option<Inflater>,
+
+    /// Unread bytes of the compressed contents.
+    input_left: u64,
+
+    /// Unread bytes of the uncompressed contents.
+    output_left: u64,
+
+    /// CRC-32 of the contents read so far, and the expected one.
+    crc: u32,
+    expected_crc: u32,
+}
+
+/// A reader of zip archives.
+///
+/// The entries are listed by the central directory at the end of the
+/// archive, which is read first; they are then visited in its order the same
+/// way as with `TarReader`: `next_entry()` returns the header of the next
+/// entry, after which the reader itself yields the entry contents, with the
+/// checksum verified at the end.
+///
+/// Stored and deflated entries are supported, including the zip64
+/// extensions for large archives. Symbolic links are recognized by the Unix
+/// file modes, if the archiver stored them.
+pub struct ZipReader<R: Read + Seek> {
+    /// The underlying reader.
+    reader: R,
+
+    /// Remaining entries of the central directory.
+    records: std::vec::IntoIter<ZipRecord>,
+
+    /// Contents of the current entry, if it has any.
+    contents: Option<ZipContents>,
+}
+
+impl<R: Read + Seek> ZipReader<R> {
+    /// Creates a new zip reader, reading the central directory of the archive.
+    pub fn new(mut reader: R) -> Result<Self, ArchiveError> {
+        let (count, size, offset) = read_zip_end(&mut reader)?;
+
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut directory = Vec::new();
+        (&mut reader).take(size).read_to_end(&mut directory)?;
+        if (directory.len() as u64) < size {
+            return Err(ArchiveError::InvalidZip);
+        }
+
+        // Do not trust the count to preallocate, it may come from a
+        // corrupted archive.
+        let mut records = Vec::new();
+        let mut data = &directory[..];
+        for _ in 0..count {
+            let (record, rest) = parse_central_header(data)?;
+            records.push(record);
+            data = rest;
+        }
+
+        Ok(ZipReader {
+            reader,
+            records: records.into_iter(),
+            contents: None,
+        })
+    }
+
+    /// Returns the header of the next entry, or `None` at the end of the
+    /// archive.
+    pub fn next_entry(&mut self) -> Result<Option<Entry>, ArchiveError> {
+        self.contents = None;
+        let record = match self.records.next() {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+
+        let mut entry = match record.entry.kind {
+            EntryKind::File | EntryKind::Symlink(_) => {
+                self.open(&record)?;
+                record.entry
+            }
+            _ => return Ok(Some(record.entry)),
+        };
+
+        // The contents of a link are its target.
+        if let EntryKind::Symlink(_) = entry.kind {
+            let mut target = Vec::new();
+            self.read_to_end(&mut target)?;
+            entry.kind = EntryKind::Symlink(bytes_to_path(&target));
+            entry.size = 0;
+        }
+
+        Ok(Some(entry))
+    }
+
+    /// Positions the reader at the contents of the entry.
+    fn open(&mut self, record: &ZipRecord) -> Result<(), ArchiveError> {
+        if record.flags & 1 != 0 {
+            return Err(ArchiveError::Encrypted(record.entry.path.clone()));
+        }
+
+        let size = record.entry.size;
+        let inflater = match record.method {
+            ZIP_STORED if record.compressed_size == size => None,
+            ZIP_STORED => return Err(ArchiveError::InvalidZip),
+            ZIP_DEFLATED => Some(Inflater::new()),
+            method => return Err(ArchiveError::UnsupportedCompression(method)),
+        };
+
+        // The local header repeats the central directory fields, but its
+        // name and extra field may differ in length.
+        self.reader.seek(SeekFrom::Start(record.offset))?;
+        let mut header = [0u8; ZIP_LOCAL_HEADER_SIZE];
+        self.reader.read_exact(&mut header)?;
+        if le32(&header[0..]) != ZIP_LOCAL_HEADER {
+            return Err(ArchiveError::InvalidZip);
+        }
+        let skip = i64::from(le16(&header[26..])) + i64::from(le16(&header[28..]));
+        self.reader.seek(SeekFrom::Current(skip))?;
+
+        self.contents = Some(ZipContents {
+            inflater,
+            input_left: record.compressed_size,
+            output_left: size,
+            crc: 0,
+            expected_crc: record.crc,
+        });
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for ZipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let contents = match &mut self.contents {
+            Some(contents) if contents.output_left > 0 => contents,
+            _ => return Ok(0),
+        };
+
+        let len = buf
+            .len()
+            .min(contents.output_left.min(usize::MAX as u64) as usize);
+        let mut input = (&mut self.reader).take(contents.input_left);
+        let n = match &mut contents.inflater {
+            Some(inflater) => inflater.read(&mut input, &mut buf[..len])?,
+            None => input.read(&mut buf[..len])?,
+        };
+        contents.input_left = input.limit();
+        if n == 0 && len > 0 {
+            return Err(IoError::from(ErrorKind::UnexpectedEof));
+        }
+
+        contents.output_left -= n as u64;
+        contents.crc = crc32(contents.crc, &buf[..n]);
+        if contents.output_left == 0 && contents.crc != contents.expected_crc {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                ArchiveError::ChecksumMismatch,
+            ));
+        }
+
+        Ok(n)
+    }
+}
+
+/// Reads a little-endian 16-bit integer.
+fn le16(data: &[u8]) -> u16 {
+    u16::from_le_bytes([data[0], data[1]])
+}
+
+/// Reads a little-endian 32-bit integer.
+fn le32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+/// Reads a little-endian 64-bit integer.
+fn le64(data: &[u8]) -> u64 {
+    u64::from(le32(data)) | (u64::from(le32(&data[4..])) << 32)
+}
+
+/// Finds the end of central directory record of a zip archive, returning
+/// the number of entries and the size and offset of the central directory.
+fn read_zip_end<R: Read + Seek>(reader: &mut R) -> Result<(u64, u64, u64), ArchiveError> {
+    // The record is followed by the archive comment, so it is searched for
+    // from the end.
+    let len = reader.seek(SeekFrom::End(0))?;
+    let tail_len = len.min((ZIP_END_SIZE + ZIP_MAX_COMMENT) as u64);
+    reader.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail)?;
+
+    let pos = (0..=tail.len().saturating_sub(ZIP_END_SIZE))
+        .rev()
+        .find(|&i| tail.len() >= i + ZIP_END_SIZE && le32(&tail[i..]) == ZIP_END)
+        .ok_or(ArchiveError::InvalidZip)?;
+    let end = &tail[pos..];
+
+    let count = u64::from(le16(&end[10..]));
+    let size = u64::from(le32(&end[12..]));
+    let offset = u64::from(le32(&end[16..]));
+    if count != 0xffff && size != 0xffff_ffff && offset != 0xffff_ffff {
+        return Ok((count, size, offset));
+    }
+
+    // The zip64 end of central directory record holds the values that do
+    // not fit, and its locator precedes the end record.
+    let end_pos = len - tail_len + pos as u64;
+    let locator_pos = end_pos
+        .checked_sub(ZIP64_END_LOCATOR_SIZE as u64)
+        .ok_or(ArchiveError::InvalidZip)?;
+    reader.seek(SeekFrom::Start(locator_pos))?;
+    let mut locator = [0u8; ZIP64_END_LOCATOR_SIZE];
+    reader.read_exact(&mut locator)?;
+    if le32(&locator) != ZIP64_END_LOCATOR {
+        return Err(ArchiveError::InvalidZip);
+    }
+
+    reader.seek(SeekFrom::Start(le64(&locator[8..])))?;
+    let mut end = [0u8; ZIP64_END_SIZE];
+    reader.read_exact(&mut end)?;
+    if le32(&end) != ZIP64_END {
+        return Err(ArchiveError::InvalidZip);
+    }
+
+    Ok((le64(&end[32..]), le64(&end[40..]), le64(&end[48..])))
+}
+
+/// Parses a central directory header, returning the record and the rest of
+/// the directory.
+fn parse_central_header(data: &[u8]) -> Result<(ZipRecord, &[u8]), ArchiveError> {
+    if data.len() < ZIP_CENTRAL_HEADER_SIZE || le32(data) != ZIP_CENTRAL_HEADER {
+        return Err(ArchiveError::InvalidZip);
+    }
+
+    let name_len = le16(&data[28..]) as usize;
+    let extra_len = le16(&data[30..]) as usize;
+    let comment_len = le16(&data[32..]) as usize;
+    let end = ZIP_CENTRAL_HEADER_SIZE + name_len + extra_len + comment_len;
+    if data.len() < end {
+        return Err(ArchiveError::InvalidZip);
+    }
+
+    let name = &data[ZIP_CENTRAL_HEADER_SIZE..ZIP_CENTRAL_HEADER_SIZE + name_len];
+    let extra =
+        &data[ZIP_CENTRAL_HEADER_SIZE + name_len..ZIP_CENTRAL_HEADER_SIZE + name_len + extra_len];
+
+    let mut size = u64::from(le32(&data[24..]));
+    let mut compressed_size = u64::from(le32(&data[20..]));
+    let mut offset = u64::from(le32(&data[42..]));
+    let mut mtime = None;
+
+    let mut fields = extra;
+    while fields.len() >= 4 {
+        let id = le16(fields);
+        let len = (le16(&fields[2..]) as usize).min(fields.len() - 4);
+        let mut field = &fields[4..4 + len];
+        fields = &fields[4 + len..];
+
+        match id {
+            // Only the values that do not fit in the header are present, in
+            // this order.
+            ZIP64_EXTRA => {
+                for value in [&mut size, &mut compressed_size, &mut offset] {
+                    if *value == 0xffff_ffff {
+                        if field.len() < 8 {
+                            return Err(ArchiveError::InvalidZip);
+                        }
+                        *value = le64(field);
+                        field = &field[8..];
+                    }
+                }
+            }
+            // Negative times are unknown, as with tar archives.
+            EXTENDED_TIMESTAMP_EXTRA if field.len() >= 5 && field[0] & 1 != 0 => {
+                mtime = u64::try_from(le32(&field[1..]) as i32).ok();
+            }
+            _ => {}
+        }
+    }
+
+    // The modes are only known if stored by a Unix archiver. The DOS times of
+    // the header are in an unknown time zone, so they are not used.
+    let mode = if le16(&data[4..]) >> 8 == ZIP_UNIX_HOST {
+        le32(&data[38..]) >> 16
+    } else {
+        0
+    };
+    let kind = if name.ends_with(b"/") || mode & S_IFMT == S_IFDIR {
+        EntryKind::Directory
+    } else if mode & S_IFMT == S_IFLNK {
+        EntryKind::Symlink(PathBuf::new())
+    } else if mode & S_IFMT == 0 || mode & S_IFMT == S_IFREG {
+        EntryKind::File
+    } else {
+        EntryKind::Other
+    };
+    let size = match kind {
+        EntryKind::File | EntryKind::Symlink(_) => size,
+        _ => 0,
+    };
+
+    let record = ZipRecord {
+        entry: Entry {
+            path: normalize(bytes_to_path(name))?,
+            size,
+            kind,
+            mtime,
+        },
+        flags: le16(&data[8..]),
+        method: le16(&data[10..]),
+        crc: le32(&data[16..]),
+        compressed_size,
+        offset,
+    };
+
+    Ok((record, &data[end..]))
+}
+
+/// Table of the CRC-32 remainders of the byte values.
+const CRC_TABLE: [u32; 256] = crc_table();
+
+/// Computes the CRC-32 table, for the reflected polynomial used by zip.
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+
+    table
+}
+
+/// Updates the CRC-32 of the data read so far with more data.
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |c, &b| {
+        CRC_TABLE[((c ^ u32::from(b)) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+/// Verifies the header checksum, which is computed with the checksum field
+/// itself filled with spaces.
+fn verify_checksum(header: &[u8; BLOCK_SIZE]) -> Result<(), ArchiveError> {
+    let expected = parse_number(&header[148..156])?;
+    let actual: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (148..156).contains(&i) {
+                32
+            } else {
+                u64::from(b)
+            }
+        })
+        .sum();
+
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(ArchiveError::InvalidHeader)
+    }
+}
+
+/// Returns the entry path from a header, joining the ustar prefix if present.
+fn header_path(header: &[u8; BLOCK_SIZE]) -> Vec<u8> {
+    let name = trim_nul(&header[0..100]);
+    let prefix = trim_nul(&header[345..500]);
+
+    if &header[257..263] == USTAR_MAGIC && !prefix.is_empty() {
+        [prefix, b"/", name].concat()
+    } else {
+        name.to_vec()
+    }
+}
+
+/// Parses a numeric header field, either in octal or in the GNU base-256
+/// encoding.
+fn parse_number(field: &[u8]) -> Result<u64, ArchiveError> {
+    if field[0] & 0x80 != 0 {
+        return field[1..]
+            .iter()
+            .try_fold(u64::from(field[0] & 0x7f), |n, &b| {
+                n.checked_mul(256)
+                    .map(|n| n + u64::from(b))
+                    .ok_or(ArchiveError::InvalidHeader)
+            });
+    }
+
+    let digits = trim_nul(field);
+    let digits = std::str::from_utf8(digits).or(Err(ArchiveError::InvalidHeader))?;
+    let digits = digits.trim_matches(|c| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+
+    u64::from_str_radix(digits, 8).or(Err(ArchiveError::InvalidHeader))
+}
+
+/// Parses a decimal PAX header value.
+fn parse_decimal(value: &[u8]) -> Result<u64, ArchiveError> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(ArchiveError::InvalidHeader)
+}
+
+/// A key/value record of a PAX extended header.
+type PaxRecord<'a> = (&'a [u8], &'a [u8]);
+
+/// Parses the records of a PAX extended header, each of which has the form
+/// `<length> <key>=<value>\n`, where the length covers the whole record.
+fn parse_pax(mut data: &[u8]) -> Result<Vec<PaxRecord<'_>>, ArchiveError> {
+    let mut records = Vec::new();
+
+    while !data.is_empty() && data[0] != 0 {
+        let space = data
+            .iter()
+            .position(|&b| b == b' ')
+            .ok_or(ArchiveError::InvalidHeader)?;
+        let len = parse_decimal(&data[..space])? as usize;
+        if len <= space + 1 || len > data.len() || data[len - 1] != b'\n' {
+            return Err(ArchiveError::InvalidHeader);
+        }
+
+        let record = &data[space + 1..len - 1];
+        let eq = record
+            .iter()
+            .position(|&b| b == b'=')
+            .ok_or(ArchiveError::InvalidHeader)?;
+        records.push((&record[..eq], &record[eq + 1..]));
+
+        data = &data[len..];
+    }
+
+    Ok(records)
+}
+
+/// Returns the field contents up to the first NUL character.
+fn trim_nul(field: &[u8]) -> &[u8] {
+    field.split(|&b| b == 0).next().unwrap_or(field)
+}
+
+/// Converts the raw bytes of an entry path to a path.
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+/// Converts the raw bytes of an entry path to a path.
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Makes an entry path relative to the archive root, dropping the `.` and
+/// leading `/` components that archivers commonly produce.
+fn normalize(path: PathBuf) -> Result<PathBuf, ArchiveError> {
+    let mut ret = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => ret.push(c),
+            Component::CurDir | Component::RootDir => {}
+            _ => return Err(ArchiveError::InvalidPath(path.clone())),
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Builds a ustar header for tests, moving the directories of a name longer
+/// than the name field to the prefix field.
+#[cfg(test)]
+pub fn tar_header(name: &str, typeflag: u8, size: u64, link: &str) -> [u8; BLOCK_SIZE] {
+    let (prefix, name) = match name.rsplit_once('/') {
+        Some((prefix, base)) if name.len() > 100 => (prefix, base),
+        _ => ("", name),
+    };
+
+    let mut header = [0u8; BLOCK_SIZE];
+    let mut put = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    put(0, name.as_bytes());
+    put(100, b"0000644\0");
+    put(108, b"0000000\0");
+    put(116, b"0000000\0");
+    put(124, format!("{:011o}\0", size).as_bytes());
+    put(136, format!("{:011o}\0", 1_600_000_000).as_bytes());
+    put(156, &[typeflag]);
+    put(157, link.as_bytes());
+    put(257, USTAR_MAGIC);
+    put(263, b"00");
+    put(345, prefix.as_bytes());
+
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u64 = header.iter().map(|&b| u64::from(b)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+/// Builds a tar archive for tests from headers, each followed by its
+/// contents padded to the block size.
+#[cfg(test)]
+pub fn tar_archive(entries: &[([u8; BLOCK_SIZE], &[u8])]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    for (header, contents) in entries {
+        archive.extend_from_slice(header);
+        archive.extend_from_slice(contents);
+        archive.resize(archive.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+    }
+    archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+    archive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::path::Path;
+
+    /// An entry of a test zip archive.
+    struct TestEntry {
+        name: &'static str,
+        contents: &'static [u8],
+        method: u16,
+        data: Vec<u8>,
+        flags: u16,
+        mode: u32,
+    }
+
+    impl TestEntry {
+        fn stored(name: &'static str, contents: &'static [u8], mode: u32) -> Self {
+            TestEntry {
+                name,
+                contents,
+                method: ZIP_STORED,
+                data: contents.to_vec(),
+                flags: 0,
+                mode,
+            }
+        }
+    }
+
+    /// Builds a zip archive, storing the sizes in the zip64 extra fields if
+    /// enabled.
+    fn zip(entries: &[TestEntry], zip64: bool) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut directory = Vec::new();
+
+        for entry in entries {
+            let offset = archive.len() as u32;
+            let crc = crc32(0, entry.contents);
+            let (size, compressed_size, extra) = if zip64 {
+                let mut extra = Vec::new();
+                extra.extend_from_slice(&ZIP64_EXTRA.to_le_bytes());
+                extra.extend_from_slice(&16u16.to_le_bytes());
+                extra.extend_from_slice(&(entry.contents.len() as u64).to_le_bytes());
+                extra.extend_from_slice(&(entry.data.len() as u64).to_le_bytes());
+                (0xffff_ffff, 0xffff_ffff, extra)
+            } else {
+                (
+                    entry.contents.len() as u32,
+                    entry.data.len() as u32,
+                    Vec::new(),
+                )
+            };
+
+            let mut common = Vec::new();
+            common.extend_from_slice(&20u16.to_le_bytes());
+            common.extend_from_slice(&entry.flags.to_le_bytes());
+            common.extend_from_slice(&entry.method.to_le_bytes());
+            common.extend_from_slice(&[0u8; 4]);
+            common.extend_from_slice(&crc.to_le_bytes());
+            common.extend_from_slice(&compressed_size.to_le_bytes());
+            common.extend_from_slice(&size.to_le_bytes());
+            common.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            common.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+
+            archive.extend_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+            archive.extend_from_slice(&common);
+            archive.extend_from_slice(entry.name.as_bytes());
+            archive.extend_from_slice(&extra);
+            archive.extend_from_slice(&entry.data);
+
+            directory.extend_from_slice(&ZIP_CENTRAL_HEADER.to_le_bytes());
+            directory.extend_from_slice(&(ZIP_UNIX_HOST << 8 | 20).to_le_bytes());
+            directory.extend_from_slice(&common);
+            // No comment, first disk, no internal attributes.
+            directory.extend_from_slice(&[0u8; 6]);
+            directory.extend_from_slice(&(entry.mode << 16).to_le_bytes());
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+            directory.extend_from_slice(&extra);
+        }
+
+        let offset = archive.len() as u32;
+        archive.extend_from_slice(&directory);
+        archive.extend_from_slice(&ZIP_END.to_le_bytes());
+        archive.extend_from_slice(&[0u8; 4]);
+        archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&offset.to_le_bytes());
+        archive.extend_from_slice(b"\x07\x00comment");
+        archive
+    }
+
+    /// Reads all entries of a zip archive with their contents.
+    fn read_zip(archive: Vec<u8>) -> Result<Vec<(Entry, Vec<u8>)>, ArchiveError> {
+        let mut reader = ZipReader::new(Cursor::new(archive))?;
+        let mut entries = Vec::new();
+        while let Some(entry) = reader.next_entry()? {
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents)?;
+            entries.push((entry, contents));
+        }
+
+        Ok(entries)
+    }
+
+    fn entries() -> Vec<TestEntry> {
+        vec![
+            TestEntry::stored("dir/", b"", S_IFDIR | 0o755),
+            TestEntry::stored("./dir/a.txt", b"hello\n", S_IFREG | 0o644),
+            TestEntry::stored("link", b"dir/a.txt", S_IFLNK | 0o777),
+            TestEntry {
+                name: "b.txt",
+                contents: b"hello hello hello world\n",
+                method: ZIP_DEFLATED,
+                data: vec![
+                    0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x22, 0xcb, 0xf3, 0x8b, 0x72,
+                    0x52, 0xb8, 0x00,
+                ],
+                flags: 0,
+                mode: 0,
+            },
+        ]
+    }
+
+    #[test]
+    fn reads_zip_entries() {
+        assert!(is_zip(&zip(&[], false)));
+
+        for zip64 in [false, true] {
+            let archive = zip(&entries(), zip64);
+            assert!(is_zip(&archive));
+
+            let entries = read_zip(archive).unwrap();
+            let summary: Vec<_> = entries
+                .iter()
+                .map(|(e, c)| (e.path.to_str().unwrap(), e.size, &e.kind, &c[..]))
+                .collect();
+            assert_eq!(
+                summary,
+                vec![
+                    ("dir", 0, &EntryKind::Directory, &b""[..]),
+                    ("dir/a.txt", 6, &EntryKind::File, &b"hello\n"[..]),
+                    (
+                        "link",
+                        0,
+                        &EntryKind::Symlink(PathBuf::from("dir/a.txt")),
+                        &b""[..]
+                    ),
+                    (
+                        "b.txt",
+                        24,
+                        &EntryKind::File,
+                        &b"hello hello hello world\n"[..]
+                    ),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn reads_empty_zip() {
+        assert!(read_zip(zip(&[], false)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn detects_corrupted_zip_contents() {
+        let mut archive = zip(&entries(), false);
+        let pos = archive.windows(6).position(|w| w == b"hello\n").unwrap();
+        archive[pos] = b'j';
+
+        let err = read_zip(archive).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_unsupported_zip_entries() {
+        let mut entry = TestEntry::stored("a.txt", b"hello\n", 0);
+        entry.flags = 1;
+        assert!(matches!(
+            read_zip(zip(&[entry], false)),
+            Err(ArchiveError::Encrypted(_))
+        ));
+
+        let mut entry = TestEntry::stored("a.txt", b"hello\n", 0);
+        entry.method = 12;
+        assert!(matches!(
+            read_zip(zip(&[entry], false)),
+            Err(ArchiveError::UnsupportedCompression(12))
+        ));
+
+        let entry = TestEntry::stored("../a.txt", b"hello\n", 0);
+        assert!(matches!(
+            read_zip(zip(&[entry], false)),
+            Err(ArchiveError::InvalidPath(_))
+        ));
+
+        assert!(matches!(
+            read_zip(b"PK\x03\x04 not really".to_vec()),
+            Err(ArchiveError::InvalidZip)
+        ));
+    }
+
+    #[test]
+    fn computes_crc32() {
+        assert_eq!(crc32(0, b""), 0);
+        assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn tar_reader_rejects_zip() {
+        let archive = zip(&entries(), false);
+        assert!(matches!(
+            TarReader::new(&archive[..]),
+            Err(ArchiveError::UnsupportedFormat("zip"))
+        ));
+    }
+
+    /// Reads all entries of a tar archive, along with their contents.
+    fn read_tar(archive: &[u8]) -> Vec<(Entry, Vec<u8>)> {
+        let mut reader = TarReader::new(archive).unwrap();
+        let mut entries = Vec::new();
+        while let Some(entry) = reader.next_entry().unwrap() {
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents).unwrap();
+            entries.push((entry, contents));
+        }
+        entries
+    }
+
+    /// Returns a PAX extended header record.
+    fn pax_record(key: &str, value: &str) -> Vec<u8> {
+        // The length includes the length field itself.
+        let rest = format!(" {}={}\n", key, value);
+        let mut len = rest.len() + 1;
+        while format!("{}", len).len() + rest.len() > len {
+            len += 1;
+        }
+        format!("{}{}", len, rest).into_bytes()
+    }
+
+    #[test]
+    fn reads_ustar_entries() {
+        let long = format!("{}/{}/file.txt", "d".repeat(60), "e".repeat(60));
+        let archive = tar_archive(&[
+            (tar_header("./dir/", b'5', 0, ""), b""),
+            (tar_header("./dir/a.txt", b'0', 6, ""), b"hello\n"),
+            (tar_header(&long, b'0', 3, ""), b"abc"),
+            (tar_header("dir/link", b'2', 0, "a.txt"), b""),
+            (tar_header("dir/hard", b'1', 0, "./dir/a.txt"), b""),
+            (tar_header("fifo", b'6', 0, ""), b""),
+        ]);
+
+        let entries = read_tar(&archive);
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|(e, c)| (e.path.to_str().unwrap(), e.size, &e.kind, c.as_slice()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("dir", 0, &EntryKind::Directory, &b""[..]),
+                ("dir/a.txt", 6, &EntryKind::File, b"hello\n"),
+                (long.as_str(), 3, &EntryKind::File, b"abc"),
+                ("dir/link", 0, &EntryKind::Symlink("a.txt".into()), b""),
+                ("dir/hard", 0, &EntryKind::HardLink("dir/a.txt".into()), b""),
+                ("fifo", 0, &EntryKind::Other, b""),
+            ]
+        );
+        assert_eq!(entries[1].0.mtime, Some(1_600_000_000));
+    }
+
+    #[test]
+    fn skips_unread_contents() {
+        let archive = tar_archive(&[
+            (tar_header("a.txt", b'0', 600, ""), &[b'a'; 600]),
+            (tar_header("b.txt", b'0', 2, ""), b"b\n"),
+        ]);
+
+        let mut reader = TarReader::new(&archive[..]).unwrap();
+        reader.next_entry().unwrap().unwrap();
+        let entry = reader.next_entry().unwrap().unwrap();
+        assert_eq!(entry.path, Path::new("b.txt"));
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"b\n");
+        assert!(reader.next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn reads_gnu_long_names_and_links() {
+        let name = format!("{}/long-name.txt", "n".repeat(120));
+        let target = format!("{}/target.txt", "t".repeat(120));
+        let archive = tar_archive(&[
+            (
+                tar_header("././@LongLink", b'L', name.len() as u64 + 1, ""),
+                format!("{}\0", name).as_bytes(),
+            ),
+            (
+                tar_header("././@LongLink", b'K', target.len() as u64, ""),
+                target.as_bytes(),
+            ),
+            (tar_header("truncated", b'2', 0, "truncated"), b""),
+            (tar_header("next.txt", b'0', 1, ""), b"x"),
+        ]);
+
+        let entries = read_tar(&archive);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0.path, Path::new(&name));
+        assert_eq!(entries[0].0.kind, EntryKind::Symlink(target.into()));
+        // The long name only applies to the entry that follows it.
+        assert_eq!(entries[1].0.path, Path::new("next.txt"));
+        assert_eq!(entries[1].1, b"x");
+    }
+
+    #[test]
+    fn reads_pax_paths_and_sizes() {
+        let pax = [
+            pax_record("path", "pax/caf\u{e9}.txt"),
+            pax_record("size", "5"),
+            pax_record("mtime", "1600000000.5"),
+        ]
+        .concat();
+        let global = pax_record("comment", "ignored");
+        let archive = tar_archive(&[
+            (
+                tar_header("pax_global_header", b'g', global.len() as u64, ""),
+                &global,
+            ),
+            (tar_header("PaxHeaders/x", b'x', pax.len() as u64, ""), &pax),
+            (tar_header("short", b'0', 0, ""), b"hello"),
+            (tar_header("next.txt", b'0', 1, ""), b"x"),
+        ]);
+
+        let entries = read_tar(&archive);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0.path, Path::new("pax/caf\u{e9}.txt"));
+        assert_eq!(entries[0].0.size, 5);
+        assert_eq!(entries[0].1, b"hello");
+        assert_eq!(entries[1].0.path, Path::new("next.txt"));
+        assert_eq!(entries[1].1, b"x");
+    }
+
+    #[test]
+    fn tar_reader_rejects_invalid_archives() {
+        let mut header = tar_header("a.txt", b'0', 0, "");
+        header[0] = b'b';
+        let corrupted = tar_archive(&[(header, b"")]);
+        assert!(matches!(
+            TarReader::new(&corrupted[..]).unwrap().next_entry(),
+            Err(ArchiveError::InvalidHeader)
+        ));
+
+        let escaping = tar_archive(&[(tar_header("../a.txt", b'0', 0, ""), b"")]);
+        assert!(matches!(
+            TarReader::new(&escaping[..]).unwrap().next_entry(),
+            Err(ArchiveError::InvalidPath(_))
+        ));
+
+        let truncated = tar_archive(&[(tar_header("a.txt", b'0', 600, ""), b"a")]);
+        let mut reader = TarReader::new(&truncated[..BLOCK_SIZE * 2]).unwrap();
+        reader.next_entry().unwrap().unwrap();
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    }
+}
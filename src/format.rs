@@ -0,0 +1,1134 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{BufRead, Error as IoError, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::de::{self, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Impossible, Serialize};
+use serde::{forward_to_deserialize_any, Deserialize};
+
+use crate::inventory::{Inventory, NestedInventory};
+
+/// Magic bytes identifying the binary inventory format.
+const BINARY_MAGIC: &[u8] = b"INVB";
+
+/// Version of the binary inventory format.
+///
+/// Version 1 stored the hash values as hexadecimal strings and every string
+/// in full. It is still read, but no longer written.
+const BINARY_VERSION: u8 = 2;
+const OLDEST_BINARY_VERSION: u8 = 1;
+
+/// Strings up to this length are written in full once, and referred to by
+/// their index afterwards. This covers the field names, the hash algorithm
+/// names and the directory names of the nested layout, which repeat in every
+/// record, while the unique paths are not kept around.
+const MAX_SHARED_STRING_LEN: usize = 64;
+
+/// File name extension selecting the binary inventory format.
+const BINARY_EXTENSION: &str = "bin";
+
+/// Magic bytes of age-encrypted files, in the binary and armored forms, which
+/// are recognized but not supported.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1";
+const AGE_ARMOR_MAGIC: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Value tags of the binary format.
+const TAG_NULL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_UNSIGNED: u8 = 3;
+const TAG_SIGNED: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_STRING: u8 = 6;
+const TAG_ARRAY: u8 = 7;
+const TAG_OBJECT: u8 = 8;
+const TAG_BYTES: u8 = 9;
+
+/// An error returned when a binary inventory cannot be decoded.
+#[derive(Debug)]
+pub enum FormatError {
+    /// The underlying reader or writer failed.
+    Io(IoError),
+
+    /// The binary format version is not supported.
+    UnsupportedVersion(u8),
+
+    /// An unknown value tag was encountered.
+    InvalidTag(u8),
+
+    /// A string is not valid UTF-8.
+    InvalidString,
+
+    /// A variable-length integer is malformed.
+    InvalidInteger,
+
+    /// The inventory is encrypted, which is not supported.
+    Encrypted,
+
+    /// The inventory was expected to be binary, but has no magic bytes.
+    NotBinary,
+
+    /// A string refers to one that has not been read.
+    InvalidStringReference(u64),
+
+    /// The inventory structure cannot be encoded or decoded.
+    Custom(String),
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match &self {
+            FormatError::Io(err) => write!(f, "Binary inventory I/O error: {}", err),
+            FormatError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported binary inventory version: {}", v)
+            }
+            FormatError::InvalidTag(t) => write!(f, "Invalid binary inventory value tag: {}", t),
+            FormatError::InvalidString => write!(f, "Invalid string in binary inventory"),
+            FormatError::InvalidInteger => write!(f, "Invalid integer in binary inventory"),
+            FormatError::Encrypted => write!(
+                f,
+                "Inventory is encrypted, decrypt it before use (encrypted inventories are not \
+                 supported)"
+            ),
+            FormatError::NotBinary => write!(f, "Inventory is not in the binary format"),
+            FormatError::InvalidStringReference(i) => {
+                write!(f, "Invalid string reference in binary inventory: {}", i)
+            }
+            FormatError::Custom(msg) => write!(f, "Invalid binary inventory: {}", msg),
+        }
+    }
+}
+
+impl Error for FormatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self {
+            FormatError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl ser::Error for FormatError {
+    fn custom<T: Display>(msg: T) -> Self {
+        FormatError::Custom(msg.to_string())
+    }
+}
+
+impl de::Error for FormatError {
+    fn custom<T: Display>(msg: T) -> Self {
+        FormatError::Custom(msg.to_string())
+    }
+}
+
+impl From<IoError> for FormatError {
+    fn from(err: IoError) -> Self {
+        FormatError::Io(err)
+    }
+}
+
+/// Inventory file format.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// Pretty-printed JSON.
+    Json,
+
+    /// Compact binary encoding of the same structure.
+    Binary,
+}
+
+/// An error returned when the inventory format name cannot be parsed.
+#[derive(Debug)]
+pub struct ParseFormatError();
+
+impl FromStr for Format {
+    type Err = ParseFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "bin" => Ok(Format::Binary),
+            _ => Err(ParseFormatError()),
+        }
+    }
+}
+
+impl Format {
+    /// Returns the file name extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Binary => BINARY_EXTENSION,
+        }
+    }
+
+    /// Selects the format to write based on the inventory file name.
+    ///
+    /// Files with the `.bin` extension are written in the binary format, all
+    /// others in JSON.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some(BINARY_EXTENSION) => Format::Binary,
+            _ => Format::Json,
+        }
+    }
+}
+
+/// Reads an inventory in the specified format, or detecting its format by
+/// the magic bytes if none is.
+///
+/// Encrypted inventories are recognized and rejected with a clear error,
+/// rather than failing to parse as JSON.
+pub fn read_inventory<R: BufRead>(
+    mut reader: R,
+    format: Option<Format>,
+) -> Result<Inventory, Box<dyn Error>> {
+    let head = reader.fill_buf()?;
+    if head.starts_with(AGE_MAGIC) || head.starts_with(AGE_ARMOR_MAGIC) {
+        return Err(Box::new(FormatError::Encrypted));
+    }
+
+    let binary = head.starts_with(BINARY_MAGIC);
+    let format = match format {
+        Some(Format::Binary) if !binary => return Err(Box::new(FormatError::NotBinary)),
+        Some(format) => format,
+        None if binary => Format::Binary,
+        None => Format::Json,
+    };
+
+    if format == Format::Binary {
+        reader.consume(BINARY_MAGIC.len());
+
+        let version = read_u8(&mut reader)?;
+        if !(OLDEST_BINARY_VERSION..=BINARY_VERSION).contains(&version) {
+            return Err(Box::new(FormatError::UnsupportedVersion(version)));
+        }
+
+        let mut deserializer = BinaryDeserializer::new(reader, version);
+        Ok(Inventory::deserialize(&mut deserializer)?)
+    } else {
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+/// Writes an inventory in the specified format.
+///
+/// If enabled, the records are written in the nested layout, which stores
+/// every directory name once instead of repeating it in the paths of all of
+/// its files, and JSON is written without indentation. Both layouts are read
+/// the same way.
+///
+/// The binary format is a tagged encoding of the same structure as JSON,
+/// except that the hash values are stored as raw bytes, and the short strings
+/// are stored once and referred to afterwards.
+pub fn write_inventory<W: Write>(
+    mut writer: W,
+    inventory: &Inventory,
+    format: Format,
+    nested_records: bool,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        Format::Json if !nested_records => serde_json::to_writer_pretty(&mut writer, inventory)?,
+        // Indenting the nested records would outweigh the savings, so they
+        // are written compactly.
+        Format::Json => serde_json::to_writer(&mut writer, &NestedInventory(inventory))?,
+        Format::Binary => {
+            writer.write_all(BINARY_MAGIC)?;
+            writer.write_all(&[BINARY_VERSION])?;
+
+            let mut serializer = BinarySerializer::new(&mut writer);
+            if nested_records {
+                NestedInventory(inventory).serialize(&mut serializer)?;
+            } else {
+                inventory.serialize(&mut serializer)?;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes an unsigned integer in the LEB128 variable-length encoding.
+fn write_varint<W: Write>(writer: &mut W, mut n: u64) -> Result<(), FormatError> {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            writer.write_all(&[byte])?;
+            return Ok(());
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+/// Reads a single byte.
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, FormatError> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+/// Reads an unsigned integer in the LEB128 variable-length encoding.
+fn read_varint<R: Read>(reader: &mut R) -> Result<u64, FormatError> {
+    let mut n = 0u64;
+    let mut shift = 0;
+
+    loop {
+        let byte = read_u8(reader)?;
+        if shift >= 64 {
+            return Err(FormatError::InvalidInteger);
+        }
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+        shift += 7;
+    }
+}
+
+/// Reads the given number of bytes.
+fn read_bytes<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>, FormatError> {
+    // Do not trust the length to preallocate, it may come from a corrupted
+    // file.
+    let mut buf = Vec::new();
+    reader.take(len).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < len {
+        return Err(FormatError::Io(IoError::from(
+            std::io::ErrorKind::UnexpectedEof,
+        )));
+    }
+
+    Ok(buf)
+}
+
+/// A serializer writing the binary format.
+///
+/// Map keys and field names are written as strings without tags. Strings are
+/// written as a header followed by the UTF-8 bytes, the header being the
+/// length shifted left by one bit; short strings that have already been
+/// written are replaced by a header with the lowest bit set, holding their
+/// index instead.
+struct BinarySerializer<W> {
+    /// The underlying writer.
+    writer: W,
+
+    /// Indices of the short strings written so far.
+    strings: HashMap<String, u64>,
+}
+
+impl<W: Write> BinarySerializer<W> {
+    /// Creates a new serializer.
+    fn new(writer: W) -> Self {
+        BinarySerializer {
+            writer,
+            strings: HashMap::new(),
+        }
+    }
+
+    /// Writes a value tag.
+    fn write_tag(&mut self, tag: u8) -> Result<(), FormatError> {
+        Ok(self.writer.write_all(&[tag])?)
+    }
+
+    /// Writes a string, or a reference to it if it has been written before.
+    fn write_string(&mut self, s: &str) -> Result<(), FormatError> {
+        if let Some(&index) = self.strings.get(s) {
+            return write_varint(&mut self.writer, (index << 1) | 1);
+        }
+
+        write_varint(&mut self.writer, (s.len() as u64) << 1)?;
+        self.writer.write_all(s.as_bytes())?;
+        if s.len() <= MAX_SHARED_STRING_LEN {
+            let index = self.strings.len() as u64;
+            self.strings.insert(s.to_string(), index);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the tag and the length of an array or an object.
+    fn write_header(&mut self, tag: u8, len: Option<usize>) -> Result<(), FormatError> {
+        let len = len.ok_or_else(|| FormatError::Custom("unknown length".to_string()))?;
+        self.write_tag(tag)?;
+        write_varint(&mut self.writer, len as u64)
+    }
+}
+
+/// Returns the error for the values the binary format cannot encode.
+fn unsupported(what: &str) -> FormatError {
+    FormatError::Custom(format!("{} cannot be encoded", what))
+}
+
+impl<W: Write> ser::Serializer for &mut BinarySerializer<W> {
+    type Ok = ();
+    type Error = FormatError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Impossible<(), FormatError>;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), FormatError>;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<(), FormatError> {
+        self.write_tag(if v { TAG_TRUE } else { TAG_FALSE })
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), FormatError> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), FormatError> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), FormatError> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), FormatError> {
+        if v >= 0 {
+            return self.serialize_u64(v as u64);
+        }
+
+        // Zigzag-encode negative integers to keep them short.
+        self.write_tag(TAG_SIGNED)?;
+        write_varint(&mut self.writer, ((v << 1) ^ (v >> 63)) as u64)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), FormatError> {
+        self.serialize_u64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), FormatError> {
+        self.serialize_u64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), FormatError> {
+        self.serialize_u64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), FormatError> {
+        self.write_tag(TAG_UNSIGNED)?;
+        write_varint(&mut self.writer, v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), FormatError> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), FormatError> {
+        self.write_tag(TAG_FLOAT)?;
+        Ok(self.writer.write_all(&v.to_le_bytes())?)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), FormatError> {
+        self.serialize_str(v.encode_utf8(&mut [0u8; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), FormatError> {
+        self.write_tag(TAG_STRING)?;
+        self.write_string(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), FormatError> {
+        self.write_tag(TAG_BYTES)?;
+        write_varint(&mut self.writer, v.len() as u64)?;
+        Ok(self.writer.write_all(v)?)
+    }
+
+    fn serialize_none(self) -> Result<(), FormatError> {
+        self.write_tag(TAG_NULL)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), FormatError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), FormatError> {
+        self.write_tag(TAG_NULL)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), FormatError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), FormatError> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), FormatError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), FormatError> {
+        Err(unsupported("Enum variants with data"))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self, FormatError> {
+        self.write_header(TAG_ARRAY, len)?;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self, FormatError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Self, FormatError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, FormatError> {
+        Err(unsupported("Enum variants with data"))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self, FormatError> {
+        self.write_header(TAG_OBJECT, len)?;
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self, FormatError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, FormatError> {
+        Err(unsupported("Enum variants with data"))
+    }
+}
+
+impl<W: Write> ser::SerializeSeq for &mut BinarySerializer<W> {
+    type Ok = ();
+    type Error = FormatError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FormatError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), FormatError> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeTuple for &mut BinarySerializer<W> {
+    type Ok = ();
+    type Error = FormatError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FormatError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), FormatError> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeTupleStruct for &mut BinarySerializer<W> {
+    type Ok = ();
+    type Error = FormatError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FormatError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), FormatError> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeMap for &mut BinarySerializer<W> {
+    type Ok = ();
+    type Error = FormatError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), FormatError> {
+        key.serialize(KeySerializer(&mut **self))
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FormatError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), FormatError> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeStruct for &mut BinarySerializer<W> {
+    type Ok = ();
+    type Error = FormatError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), FormatError> {
+        self.write_string(key)?;
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), FormatError> {
+        Ok(())
+    }
+}
+
+/// A serializer of the map keys, which must be strings, as in JSON.
+struct KeySerializer<'a, W>(&'a mut BinarySerializer<W>);
+
+/// Implements the serializer methods for the values that cannot be map keys.
+macro_rules! reject_keys {
+    ($($method:ident($($arg:ty),*);)*) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<(), FormatError> {
+                Err(unsupported("Map keys other than strings"))
+            }
+        )*
+    };
+}
+
+impl<W: Write> ser::Serializer for KeySerializer<'_, W> {
+    type Ok = ();
+    type Error = FormatError;
+    type SerializeSeq = Impossible<(), FormatError>;
+    type SerializeTuple = Impossible<(), FormatError>;
+    type SerializeTupleStruct = Impossible<(), FormatError>;
+    type SerializeTupleVariant = Impossible<(), FormatError>;
+    type SerializeMap = Impossible<(), FormatError>;
+    type SerializeStruct = Impossible<(), FormatError>;
+    type SerializeStructVariant = Impossible<(), FormatError>;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), FormatError> {
+        self.0.write_string(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), FormatError> {
+        self.serialize_str(v.encode_utf8(&mut [0u8; 4]))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), FormatError> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), FormatError> {
+        value.serialize(self)
+    }
+
+    reject_keys! {
+        serialize_bool(bool);
+        serialize_i8(i8);
+        serialize_i16(i16);
+        serialize_i32(i32);
+        serialize_i64(i64);
+        serialize_u8(u8);
+        serialize_u16(u16);
+        serialize_u32(u32);
+        serialize_u64(u64);
+        serialize_f32(f32);
+        serialize_f64(f64);
+        serialize_bytes(&[u8]);
+        serialize_none();
+        serialize_unit();
+        serialize_unit_struct(&'static str);
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<(), FormatError> {
+        Err(unsupported("Map keys other than strings"))
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), FormatError> {
+        Err(unsupported("Map keys other than strings"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, FormatError> {
+        Err(unsupported("Map keys other than strings"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, FormatError> {
+        Err(unsupported("Map keys other than strings"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, FormatError> {
+        Err(unsupported("Map keys other than strings"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, FormatError> {
+        Err(unsupported("Map keys other than strings"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, FormatError> {
+        Err(unsupported("Map keys other than strings"))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, FormatError> {
+        Err(unsupported("Map keys other than strings"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, FormatError> {
+        Err(unsupported("Map keys other than strings"))
+    }
+}
+
+/// A deserializer reading the binary format, of the current or an older
+/// version.
+struct BinaryDeserializer<R> {
+    /// The underlying reader.
+    reader: R,
+
+    /// Version of the format being read.
+    version: u8,
+
+    /// The short strings read so far, which later strings may refer to.
+    strings: Vec<String>,
+
+    /// Value tag read ahead to tell `None` from `Some`.
+    peeked: Option<u8>,
+}
+
+impl<R: Read> BinaryDeserializer<R> {
+    /// Creates a new deserializer for the given format version.
+    fn new(reader: R, version: u8) -> Self {
+        BinaryDeserializer {
+            reader,
+            version,
+            strings: Vec::new(),
+            peeked: None,
+        }
+    }
+
+    /// Reads a value tag.
+    fn read_tag(&mut self) -> Result<u8, FormatError> {
+        match self.peeked.take() {
+            Some(tag) => Ok(tag),
+            None => read_u8(&mut self.reader),
+        }
+    }
+
+    /// Reads a string, resolving the references to the earlier ones.
+    fn read_string(&mut self) -> Result<String, FormatError> {
+        let header = read_varint(&mut self.reader)?;
+        if self.version == 1 {
+            return self.read_utf8(header);
+        }
+
+        if header & 1 == 1 {
+            let index = header >> 1;
+            return match self.strings.get(index as usize) {
+                Some(s) => Ok(s.clone()),
+                None => Err(FormatError::InvalidStringReference(index)),
+            };
+        }
+
+        let s = self.read_utf8(header >> 1)?;
+        if s.len() <= MAX_SHARED_STRING_LEN {
+            self.strings.push(s.clone());
+        }
+        Ok(s)
+    }
+
+    /// Reads a UTF-8 string of the given length.
+    fn read_utf8(&mut self, len: u64) -> Result<String, FormatError> {
+        String::from_utf8(read_bytes(&mut self.reader, len)?).or(Err(FormatError::InvalidString))
+    }
+}
+
+impl<'de, R: Read> de::Deserializer<'de> for &mut BinaryDeserializer<R> {
+    type Error = FormatError;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormatError> {
+        match self.read_tag()? {
+            TAG_NULL => visitor.visit_unit(),
+            TAG_FALSE => visitor.visit_bool(false),
+            TAG_TRUE => visitor.visit_bool(true),
+            TAG_UNSIGNED => visitor.visit_u64(read_varint(&mut self.reader)?),
+            TAG_SIGNED => {
+                let z = read_varint(&mut self.reader)?;
+                visitor.visit_i64(((z >> 1) as i64) ^ -((z & 1) as i64))
+            }
+            TAG_FLOAT => {
+                let mut buf = [0u8; 8];
+                self.reader.read_exact(&mut buf)?;
+                visitor.visit_f64(f64::from_le_bytes(buf))
+            }
+            TAG_STRING => visitor.visit_string(self.read_string()?),
+            TAG_BYTES if self.version > 1 => {
+                let len = read_varint(&mut self.reader)?;
+                visitor.visit_byte_buf(read_bytes(&mut self.reader, len)?)
+            }
+            TAG_ARRAY => {
+                let remaining = read_varint(&mut self.reader)?;
+                visitor.visit_seq(Entries {
+                    de: self,
+                    remaining,
+                })
+            }
+            TAG_OBJECT => {
+                let remaining = read_varint(&mut self.reader)?;
+                visitor.visit_map(Entries {
+                    de: self,
+                    remaining,
+                })
+            }
+            tag => Err(FormatError::InvalidTag(tag)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormatError> {
+        match self.read_tag()? {
+            TAG_NULL => visitor.visit_none(),
+            tag => {
+                self.peeked = Some(tag);
+                visitor.visit_some(self)
+            }
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, FormatError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, FormatError> {
+        // Only the variants without data are encoded, as their names.
+        match self.read_tag()? {
+            TAG_STRING => visitor.visit_enum(self.read_string()?.into_deserializer()),
+            tag => Err(FormatError::InvalidTag(tag)),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+/// Access to the elements of an array or the entries of an object.
+struct Entries<'a, R> {
+    /// The deserializer reading the elements.
+    de: &'a mut BinaryDeserializer<R>,
+
+    /// Number of the elements left to read.
+    remaining: u64,
+}
+
+impl<'de, R: Read> SeqAccess<'de> for Entries<'_, R> {
+    type Error = FormatError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, FormatError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+}
+
+impl<'de, R: Read> MapAccess<'de> for Entries<'_, R> {
+    type Error = FormatError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, FormatError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+
+        self.remaining -= 1;
+        let key: de::value::StringDeserializer<FormatError> =
+            self.de.read_string()?.into_deserializer();
+        seed.deserialize(key).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, FormatError> {
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    const INVENTORY: &str = r#"{
+        "configuration": {
+            "version": "0.1.1",
+            "skip_hidden": false,
+            "hash_algorithms": ["md5", "sha1"],
+            "follow_symlink_dirs": true,
+            "follow_symlink_files": true
+        },
+        "records": {
+            "a.txt": {
+                "hashes": {
+                    "md5": "b1946ac92492d2347c6235b4d2611184",
+                    "sha1": "f572d396fae9206628714fb2ce00f72e94f2258f"
+                },
+                "size": 6,
+                "mtime": 1600000000000000000
+            },
+            "sub/b.txt": {
+                "hashes": {
+                    "md5": "591785b794601e212b260e25925636fd",
+                    "sha1": "9591818c07e900db7e1e0bc4b884c945e6a61b24"
+                },
+                "size": 6
+            },
+            "sub/deeper/link": {"hashes": {}, "size": 0, "symlink": "../b.txt"}
+        },
+        "description": "Test inventory",
+        "metadata": {"owner": "tests"}
+    }"#;
+
+    fn inventory() -> Inventory {
+        read_inventory(INVENTORY.as_bytes(), Some(Format::Json)).unwrap()
+    }
+
+    fn write(inventory: &Inventory, format: Format, nested: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        write_inventory(&mut data, inventory, format, nested).unwrap();
+        data
+    }
+
+    /// Compares the inventories by their JSON representation.
+    fn assert_same(a: &Inventory, b: &Inventory) {
+        assert_eq!(
+            serde_json::to_value(a).unwrap(),
+            serde_json::to_value(b).unwrap()
+        );
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> usize {
+        data.windows(needle.len()).filter(|w| *w == needle).count()
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let inventory = inventory();
+        for nested in [false, true] {
+            let data = write(&inventory, Format::Binary, nested);
+            assert!(data.starts_with(BINARY_MAGIC));
+            assert_eq!(data[BINARY_MAGIC.len()], BINARY_VERSION);
+            assert_same(&read_inventory(&data[..], None).unwrap(), &inventory);
+        }
+    }
+
+    #[test]
+    fn nested_json_round_trip() {
+        let inventory = inventory();
+        let data = write(&inventory, Format::Json, true);
+
+        let value: Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(
+            value["records"]["sub/"]["deeper/"]["link"]["symlink"],
+            "../b.txt"
+        );
+        assert_eq!(value["records"]["a.txt"]["size"], 6);
+        assert_same(&read_inventory(&data[..], None).unwrap(), &inventory);
+    }
+
+    #[test]
+    fn flat_json_matches_the_input() {
+        let data = write(&inventory(), Format::Json, false);
+        assert_eq!(
+            serde_json::from_slice::<Value>(&data).unwrap(),
+            serde_json::from_str::<Value>(INVENTORY).unwrap()
+        );
+    }
+
+    #[test]
+    fn binary_stores_raw_digests() {
+        let data = write(&inventory(), Format::Binary, false);
+        let digest = crate::util::hex_string_to_bytes("b1946ac92492d2347c6235b4d2611184").unwrap();
+
+        assert_eq!(contains(&data, &digest), 1);
+        assert_eq!(contains(&data, b"b1946ac92492d2347c6235b4d2611184"), 0);
+    }
+
+    #[test]
+    fn binary_stores_repeated_strings_once() {
+        let data = write(&inventory(), Format::Binary, true);
+        assert_eq!(contains(&data, b"hashes"), 1);
+        assert_eq!(contains(&data, b"sha1"), 1);
+    }
+
+    #[test]
+    fn binary_is_smaller_than_json() {
+        let mut value: Value = serde_json::from_str(INVENTORY).unwrap();
+        let records = value["records"].as_object_mut().unwrap();
+        let record = records["sub/b.txt"].clone();
+        for i in 0..100 {
+            records.insert(format!("dir{}/file{}.txt", i % 10, i), record.clone());
+        }
+        let inventory = read_inventory(value.to_string().as_bytes(), None).unwrap();
+
+        for nested in [false, true] {
+            let binary = write(&inventory, Format::Binary, nested).len();
+            let json = write(&inventory, Format::Json, nested).len();
+            assert!(binary * 2 < json, "{} vs {}", binary, json);
+        }
+    }
+
+    /// Encodes a value the way the version 1 of the format did.
+    fn write_v1_value(data: &mut Vec<u8>, value: &Value) {
+        fn string(data: &mut Vec<u8>, s: &str) {
+            write_varint(data, s.len() as u64).unwrap();
+            data.extend_from_slice(s.as_bytes());
+        }
+
+        match value {
+            Value::Null => data.push(TAG_NULL),
+            Value::Bool(b) => data.push(if *b { TAG_TRUE } else { TAG_FALSE }),
+            Value::Number(n) => {
+                data.push(TAG_UNSIGNED);
+                write_varint(data, n.as_u64().unwrap()).unwrap();
+            }
+            Value::String(s) => {
+                data.push(TAG_STRING);
+                string(data, s);
+            }
+            Value::Array(items) => {
+                data.push(TAG_ARRAY);
+                write_varint(data, items.len() as u64).unwrap();
+                items.iter().for_each(|v| write_v1_value(data, v));
+            }
+            Value::Object(map) => {
+                data.push(TAG_OBJECT);
+                write_varint(data, map.len() as u64).unwrap();
+                for (k, v) in map {
+                    string(data, k);
+                    write_v1_value(data, v);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let mut data = BINARY_MAGIC.to_vec();
+        data.push(1);
+        write_v1_value(&mut data, &serde_json::from_str(INVENTORY).unwrap());
+
+        assert_same(&read_inventory(&data[..], None).unwrap(), &inventory());
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        let mut data = write(&inventory(), Format::Binary, false);
+        data[BINARY_MAGIC.len()] = BINARY_VERSION + 1;
+
+        let err = read_inventory(&data[..], None).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(FormatError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_binary() {
+        let data = write(&inventory(), Format::Binary, false);
+        assert!(read_inventory(&data[..data.len() - 1], None).is_err());
+    }
+
+    #[test]
+    fn detects_formats() {
+        let err = read_inventory(INVENTORY.as_bytes(), Some(Format::Binary)).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FormatError::NotBinary)));
+
+        let err = read_inventory(&b"age-encryption.org/v1\n"[..], None).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FormatError::Encrypted)));
+
+        assert_eq!(Format::from_path("inventory.bin"), Format::Binary);
+        assert_eq!(Format::from_path("inventory.json"), Format::Json);
+    }
+}
@@ -1,22 +1,96 @@
 use std::convert::TryFrom;
 use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
 use std::io::{Error as IoError, Read};
 use std::iter::Iterator;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use digest::{Digest, DynDigest};
 use md5::Md5;
 use sha1::Sha1;
 
-use serde::{Deserialize, Serialize};
+use serde::de::{Error as DeError, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::util;
+use crate::sha3::{Sha3_256, Sha3_512};
+use crate::util::{self, FileError};
 
-/// MD5 hash algorithm name.
-const NAME_MD5: &str = "md5";
+/// A registered hash algorithm implementation.
+struct Registration {
+    /// Identifier of the algorithm.
+    algorithm: HashAlgorithm,
 
-/// SHA1 hash algorithm name.
-const NAME_SHA1: &str = "sha1";
+    /// Name of the algorithm, which is stored in the inventories and must
+    /// not change.
+    name: &'static str,
+
+    /// Alternative names accepted when parsing the algorithm name.
+    aliases: &'static [&'static str],
+
+    /// Length of the produced digest, in bytes.
+    digest_len: usize,
+
+    /// Size of the input blocks of the algorithm, in bytes, which determines
+    /// the HMAC key padding.
+    block_size: usize,
+
+    /// Creates a new digest of the algorithm.
+    new_digest: fn() -> Box<dyn DynDigest>,
+}
+
+/// Registry of the supported hash algorithms.
+///
+/// Everything the hasher and the inventory need to know about an algorithm
+/// is described here, so adding an algorithm only takes an identifier and a
+/// registration. The algorithms are registered in the order of increasing
+/// strength, which defines their ranking.
+const REGISTRY: &[Registration] = &[
+    Registration {
+        algorithm: HashAlgorithm::Md5,
+        name: "md5",
+        aliases: &["md-5"],
+        digest_len: 16,
+        block_size: 64,
+        new_digest: new_digest::<Md5>,
+    },
+    Registration {
+        algorithm: HashAlgorithm::Sha1,
+        name: "sha1",
+        aliases: &["sha-1"],
+        digest_len: 20,
+        block_size: 64,
+        new_digest: new_digest::<Sha1>,
+    },
+    Registration {
+        algorithm: HashAlgorithm::Sha3_256,
+        name: "sha3-256",
+        aliases: &["sha3_256"],
+        digest_len: 32,
+        block_size: 136,
+        new_digest: new_digest::<Sha3_256>,
+    },
+    Registration {
+        algorithm: HashAlgorithm::Sha3_512,
+        name: "sha3-512",
+        aliases: &["sha3_512"],
+        digest_len: 64,
+        block_size: 72,
+        new_digest: new_digest::<Sha3_512>,
+    },
+];
+
+/// HMAC inner and outer key pad bytes.
+const HMAC_INNER_PAD: u8 = 0x36;
+const HMAC_OUTER_PAD: u8 = 0x5c;
+
+/// Creates a new boxed digest of the given implementation.
+fn new_digest<D: Digest + DynDigest + 'static>() -> Box<dyn DynDigest> {
+    Box::new(D::new())
+}
 
 /// An error returned when the hash algorithm name cannot be parsed.
 #[derive(Debug)]
@@ -37,17 +111,71 @@ pub enum HashAlgorithm {
 
     /// SHA1 hash algorithm.
     Sha1,
+
+    /// SHA3-256 hash algorithm.
+    Sha3_256,
+
+    /// SHA3-512 hash algorithm.
+    Sha3_512,
+}
+
+impl HashAlgorithm {
+    /// Returns the length of the digest produced by the algorithm, in bytes.
+    pub fn digest_len(&self) -> usize {
+        self.registration().digest_len
+    }
+
+    /// Returns the rank of the algorithm by strength: stronger algorithms
+    /// have higher ranks.
+    pub fn strength(self) -> usize {
+        // Every algorithm identifier has a registration.
+        REGISTRY.iter().position(|r| r.algorithm == self).unwrap()
+    }
+
+    /// Returns the key of the algorithm padded to its block size with the
+    /// given byte, as used by HMAC. Keys longer than the block size are
+    /// hashed first.
+    fn padded_key(self, key: &[u8], pad: u8) -> Vec<u8> {
+        let registration = self.registration();
+
+        let mut padded = if key.len() > registration.block_size {
+            let mut digest = (registration.new_digest)();
+            digest.update(key);
+            digest.finalize_reset().into_vec()
+        } else {
+            key.to_vec()
+        };
+        padded.resize(registration.block_size, 0);
+        padded.iter_mut().for_each(|b| *b ^= pad);
+        padded
+    }
+
+    /// Returns the registration of the algorithm.
+    fn registration(self) -> &'static Registration {
+        // Every algorithm identifier has a registration.
+        REGISTRY.iter().find(|r| r.algorithm == self).unwrap()
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", <&str>::from(*self))
+    }
 }
 
 impl TryFrom<&str> for HashAlgorithm {
     type Error = ParseHashAlgorithmError;
 
+    /// Parses the algorithm name or one of its aliases, ignoring the case.
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            NAME_MD5 => Ok(HashAlgorithm::Md5),
-            NAME_SHA1 => Ok(HashAlgorithm::Sha1),
-            _ => Err(ParseHashAlgorithmError()),
-        }
+        REGISTRY
+            .iter()
+            .find(|r| {
+                r.name.eq_ignore_ascii_case(value)
+                    || r.aliases.iter().any(|a| a.eq_ignore_ascii_case(value))
+            })
+            .map(|r| r.algorithm)
+            .ok_or(ParseHashAlgorithmError())
     }
 }
 
@@ -69,10 +197,7 @@ impl FromStr for HashAlgorithm {
 
 impl From<HashAlgorithm> for &str {
     fn from(a: HashAlgorithm) -> Self {
-        match a {
-            HashAlgorithm::Md5 => NAME_MD5,
-            HashAlgorithm::Sha1 => NAME_SHA1,
-        }
+        a.registration().name
     }
 }
 
@@ -86,11 +211,67 @@ impl Display for ParseHashValueError {
     }
 }
 
+/// Text encoding of hash values in the output.
+///
+/// Inventories always store the values in hex, regardless of the encoding.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DigestEncoding {
+    /// Lowercase hexadecimal string.
+    Hex,
+
+    /// Padded base64 string with the standard alphabet.
+    Base64,
+}
+
+/// An error returned when the digest encoding name cannot be parsed.
+#[derive(Debug)]
+pub struct ParseDigestEncodingError();
+
+impl FromStr for DigestEncoding {
+    type Err = ParseDigestEncodingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(DigestEncoding::Hex),
+            "base64" => Ok(DigestEncoding::Base64),
+            _ => Err(ParseDigestEncodingError()),
+        }
+    }
+}
+
 /// A hash value produced by a hash algorithm.
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-#[serde(try_from = "String", into = "String")]
+///
+/// Hash values are serialized as hexadecimal strings in human-readable
+/// formats, such as JSON, and as raw bytes in the binary ones.
+#[derive(Clone, Debug, PartialEq)]
 pub struct HashValue(Box<[u8]>);
 
+impl HashValue {
+    /// Returns the raw bytes of the hash value.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns the hexadecimal representation of the hash value, which is
+    /// the one stored in inventories.
+    pub fn to_hex(&self) -> String {
+        util::bytes_to_hex_string(&self.0)
+    }
+
+    /// Returns the base64 representation of the hash value.
+    pub fn to_base64(&self) -> String {
+        util::bytes_to_base64_string(&self.0)
+    }
+
+    /// Returns the representation of the hash value in the given encoding.
+    pub fn encode(&self, encoding: DigestEncoding) -> String {
+        match encoding {
+            DigestEncoding::Hex => self.to_hex(),
+            DigestEncoding::Base64 => self.to_base64(),
+        }
+    }
+}
+
 impl From<Box<[u8]>> for HashValue {
     fn from(b: Box<[u8]>) -> Self {
         HashValue(b)
@@ -116,67 +297,564 @@ impl TryFrom<String> for HashValue {
 
 impl From<HashValue> for String {
     fn from(value: HashValue) -> Self {
-        util::bytes_to_hex_string(&value.0)
+        value.to_hex()
+    }
+}
+
+impl Serialize for HashValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_hex())
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+/// A visitor accepting hash values as either hexadecimal strings or raw
+/// bytes.
+struct HashValueVisitor;
+
+impl<'de> Visitor<'de> for HashValueVisitor {
+    type Value = HashValue;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "a hexadecimal hash value")
+    }
+
+    fn visit_str<E: DeError>(self, v: &str) -> Result<HashValue, E> {
+        HashValue::try_from(v).map_err(E::custom)
+    }
+
+    fn visit_bytes<E: DeError>(self, v: &[u8]) -> Result<HashValue, E> {
+        Ok(HashValue(v.into()))
+    }
+
+    fn visit_byte_buf<E: DeError>(self, v: Vec<u8>) -> Result<HashValue, E> {
+        Ok(HashValue(v.into_boxed_slice()))
+    }
+}
+
+impl<'de> Deserialize<'de> for HashValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Binary inventories written before the hash values were stored as
+        // bytes have them as strings, which the visitor accepts as well.
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(HashValueVisitor)
+        } else {
+            deserializer.deserialize_bytes(HashValueVisitor)
+        }
     }
 }
 
+/// A limiter of the data throughput.
+///
+/// The limit applies to all data passed through the limiter, so that it holds
+/// across files rather than per file. The limiter works as a single token
+/// bucket that may be shared by several hashers, even on different threads,
+/// so that the limit holds for all of them together.
+pub struct RateLimiter {
+    /// Maximum throughput in bytes per second.
+    bytes_per_second: u64,
+
+    /// Start of the current measurement period, and the number of bytes
+    /// processed since then.
+    period: Mutex<(Instant, u64)>,
+}
+
+impl RateLimiter {
+    /// Creates a new rate limiter.
+    pub fn new(bytes_per_second: u64) -> Self {
+        RateLimiter {
+            bytes_per_second,
+            period: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Returns the time it takes to process the number of bytes at the limit.
+    fn time_of(&self, bytes: u64) -> Duration {
+        Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64)
+    }
+
+    /// Accounts for a processed chunk of data, sleeping as long as needed to
+    /// stay under the limit.
+    fn throttle(&self, len: usize) {
+        // The lock is only held to take the bytes from the budget, not while
+        // sleeping, so that the other users can take their share meanwhile.
+        let delay = {
+            // The period stays consistent even if a holder of the lock
+            // panics.
+            let mut period = self.period.lock().unwrap_or_else(PoisonError::into_inner);
+            let (start, total) = &mut *period;
+
+            // Do not let idle time (e.g. between files) accumulate into a
+            // burst.
+            if start.elapsed() > self.time_of(*total) + Duration::from_secs(1) {
+                *start = Instant::now();
+                *total = 0;
+            }
+
+            *total += len as u64;
+            self.time_of(*total).checked_sub(start.elapsed())
+        };
+
+        if let Some(delay) = delay {
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// Finalizes a digest, hashing the result again along with the outer HMAC
+/// key pad if a key is given.
+fn finalize_digest(
+    algorithm: HashAlgorithm,
+    digest: &mut dyn DynDigest,
+    key: Option<&[u8]>,
+) -> HashValue {
+    let inner = digest.finalize_reset();
+    match key {
+        Some(key) => {
+            digest.update(&algorithm.padded_key(key, HMAC_OUTER_PAD));
+            digest.update(&inner);
+            digest.finalize_reset().into()
+        }
+        None => inner.into(),
+    }
+}
+
+/// Hash values produced by a hasher and their algorithms.
+type HashList = Vec<(HashAlgorithm, HashValue)>;
+
 /// A hasher that contains one or more hash algorithms.
 pub struct Hasher {
     /// A list of digest algorithm implementations and their identifiers.
     digests: Vec<(HashAlgorithm, Box<dyn DynDigest>)>,
+
+    /// Limiter of the hashing throughput, if enabled.
+    rate_limiter: Option<Arc<RateLimiter>>,
+
+    /// Key to compute the HMAC values with instead of the bare digests.
+    key: Option<Box<[u8]>>,
+
+    /// Buffer the data is read into, reused across all computations.
+    buffer: Vec<u8>,
 }
 
+/// Default size of the hasher read buffer.
+pub const DEFAULT_BUFFER_SIZE: usize = 128 * 1024;
+
 impl Hasher {
     /// Creates a new hasher with a given set of hash algorithm implementations.
     pub fn new<A: Iterator<Item = HashAlgorithm>>(algorithms: A) -> Self {
         let digests: Vec<_> = algorithms
-            .map(|a| {
-                let d: Box<dyn DynDigest> = match a {
-                    HashAlgorithm::Md5 => Box::new(Md5::new()),
-                    HashAlgorithm::Sha1 => Box::new(Sha1::new()),
-                };
-                (a, d)
-            })
+            .map(|a| (a, (a.registration().new_digest)()))
             .collect();
 
-        debug_assert!(!digests.is_empty());
+        Hasher {
+            digests,
+            rate_limiter: None,
+            key: None,
+            buffer: vec![0; DEFAULT_BUFFER_SIZE],
+        }
+    }
 
-        Hasher { digests }
+    /// Sets the size of the buffer the data is read into.
+    ///
+    /// The buffer is allocated once and reused by all computations of this
+    /// hasher, so workers hashing in parallel never allocate per file. A
+    /// zero size is rounded up to one byte.
+    pub fn set_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.buffer = vec![0; size.max(1)];
+        self
+    }
+
+    /// Sets the maximum hashing throughput in bytes per second.
+    ///
+    /// Reading is throttled to stay under the limit across all computations
+    /// of this hasher.
+    pub fn set_rate_limit(&mut self, bytes_per_second: Option<u64>) -> &mut Self {
+        self.rate_limiter = bytes_per_second.map(|limit| Arc::new(RateLimiter::new(limit)));
+        self
     }
 
-    /// Updates all contained digests with a chunk of data.
-    fn update(&mut self, data: &[u8]) {
-        self.digests.iter_mut().for_each(|(_, d)| d.update(data));
+    /// Sets the rate limiter to throttle reading with, which may be shared
+    /// with other hashers so that the limit holds for all of them together.
+    pub fn set_rate_limiter(&mut self, limiter: Option<Arc<RateLimiter>>) -> &mut Self {
+        self.rate_limiter = limiter;
+        self
+    }
+
+    /// Sets the key to compute the HMAC values (RFC 2104) of the data with,
+    /// instead of the bare digests.
+    pub fn set_key(&mut self, key: Option<&[u8]>) -> &mut Self {
+        self.key = key.map(Box::from);
+        self
+    }
+
+    /// Resets the selected digests before a computation, feeding them the
+    /// inner HMAC key pad if a key is set.
+    fn begin(&mut self, selected: &[HashAlgorithm]) {
+        let key = self.key.as_deref();
+        for (a, d) in self
+            .digests
+            .iter_mut()
+            .filter(|(a, _)| selected.contains(a))
+        {
+            d.reset();
+            if let Some(key) = key {
+                d.update(&a.padded_key(key, HMAC_INNER_PAD));
+            }
+        }
+    }
+
+    /// Updates the selected digests with a chunk of data.
+    fn update(&mut self, data: &[u8], selected: &[HashAlgorithm]) {
+        self.digests
+            .iter_mut()
+            .filter(|(a, _)| selected.contains(a))
+            .for_each(|(_, d)| d.update(data));
     }
 
-    /// Finalizes the computation and resets the digests.
+    /// Finalizes the computation and resets the selected digests.
     ///
     /// Returns the produced hash values.
-    fn finalize_reset(&mut self) -> Vec<(HashAlgorithm, HashValue)> {
+    ///
+    /// With a key, the inner digests are hashed again along with the outer
+    /// HMAC key pad.
+    fn finalize_reset(&mut self, selected: &[HashAlgorithm]) -> Vec<(HashAlgorithm, HashValue)> {
+        let key = self.key.as_deref();
         self.digests
             .iter_mut()
-            .map(|(a, d)| (*a, d.finalize_reset().into()))
+            .filter(|(a, _)| selected.contains(a))
+            .map(|(a, d)| (*a, finalize_digest(*a, d.as_mut(), key)))
             .collect()
     }
 
+    /// Finalizes copies of the selected digests, leaving the computation in
+    /// progress intact.
+    ///
+    /// Returns the hash values of the data fed so far.
+    fn finalize_copy(&self, selected: &[HashAlgorithm]) -> Vec<(HashAlgorithm, HashValue)> {
+        let key = self.key.as_deref();
+        self.digests
+            .iter()
+            .filter(|(a, _)| selected.contains(a))
+            .map(|(a, d)| (*a, finalize_digest(*a, d.box_clone().as_mut(), key)))
+            .collect()
+    }
+
+    /// Feeds the data returned by the specified reader to the selected
+    /// digests.
+    ///
+    /// Returns the number of bytes fed.
+    fn feed<R: Read>(&mut self, mut source: R, selected: &[HashAlgorithm]) -> Result<u64, IoError> {
+        // The buffer is taken out for the duration of the loop, so that the
+        // digests can be updated while it is borrowed.
+        let mut buf = std::mem::take(&mut self.buffer);
+        let mut total = 0;
+
+        // Read data in chunks and update the digests.
+        let result = loop {
+            let nread = match source.read(&mut buf[..]) {
+                Ok(nread) => nread,
+                Err(err) => break Err(err),
+            };
+            if nread > 0 {
+                self.update(&buf[..nread], selected);
+                if let Some(limiter) = &self.rate_limiter {
+                    limiter.throttle(nread);
+                }
+                total += nread as u64;
+            } else {
+                break Ok(total);
+            }
+        };
+
+        self.buffer = buf;
+        result
+    }
+
     /// Computes the hashes of data returned by the specified reader.
+    ///
+    /// A reader that returns no data produces the digests of empty input,
+    /// just as any other data is hashed.
     pub fn compute<R: Read>(
         &mut self,
-        mut source: R,
+        source: R,
     ) -> Result<Vec<(HashAlgorithm, HashValue)>, IoError> {
-        const CHUNK_SIZE: usize = 128 * 1024;
-        let mut buf = [0u8; CHUNK_SIZE];
+        let all: Vec<_> = self.digests.iter().map(|(a, _)| *a).collect();
+        self.compute_selected(source, &all)
+    }
 
-        // Read data in chunks and update the digests.
-        loop {
-            let nread = source.read(&mut buf[..])?;
-            if nread > 0 {
-                self.update(&buf[..nread]);
-            } else {
-                break;
+    /// Computes the hashes of data returned by the specified reader, using
+    /// only the selected algorithms of the hasher.
+    ///
+    /// Selected algorithms that the hasher does not contain are ignored.
+    pub fn compute_selected<R: Read>(
+        &mut self,
+        source: R,
+        selected: &[HashAlgorithm],
+    ) -> Result<Vec<(HashAlgorithm, HashValue)>, IoError> {
+        self.begin(selected);
+        self.feed(source, selected)?;
+        Ok(self.finalize_reset(selected))
+    }
+
+    /// Computes the hashes of the file with the specified path, using only
+    /// the selected algorithms of the hasher.
+    ///
+    /// Returns the size of the file, counted as the number of bytes hashed,
+    /// along with the hashes. Errors carry the path of the file. Selected
+    /// algorithms that the hasher does not contain are ignored.
+    pub fn compute_file_selected<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        selected: &[HashAlgorithm],
+    ) -> Result<(u64, Vec<(HashAlgorithm, HashValue)>), FileError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| FileError::new(path, e))?;
+
+        self.begin(selected);
+        let size = self
+            .feed(file, selected)
+            .map_err(|e| FileError::new(path, e))?;
+        Ok((size, self.finalize_reset(selected)))
+    }
+
+    /// Computes the hashes of data returned by the specified reader, along
+    /// with the hashes of its first `prefix_len` bytes, in a single pass.
+    ///
+    /// The prefix hashes are `None` if the data is shorter than the prefix.
+    /// Selected algorithms that the hasher does not contain are ignored.
+    pub fn compute_with_prefix<R: Read>(
+        &mut self,
+        mut source: R,
+        prefix_len: u64,
+        selected: &[HashAlgorithm],
+    ) -> Result<(Option<HashList>, HashList), IoError> {
+        self.begin(selected);
+        let prefix = if self.feed(source.by_ref().take(prefix_len), selected)? == prefix_len {
+            Some(self.finalize_copy(selected))
+        } else {
+            None
+        };
+        self.feed(source, selected)?;
+        Ok((prefix, self.finalize_reset(selected)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_names_ignoring_case_and_aliases() {
+        let cases = [
+            ("md5", HashAlgorithm::Md5),
+            ("MD5", HashAlgorithm::Md5),
+            ("Md-5", HashAlgorithm::Md5),
+            ("sha1", HashAlgorithm::Sha1),
+            ("SHA-1", HashAlgorithm::Sha1),
+            ("sha3-256", HashAlgorithm::Sha3_256),
+            ("SHA3_256", HashAlgorithm::Sha3_256),
+            ("Sha3-512", HashAlgorithm::Sha3_512),
+            ("sha3_512", HashAlgorithm::Sha3_512),
+        ];
+        for (name, algorithm) in &cases {
+            assert_eq!(name.parse::<HashAlgorithm>().unwrap(), *algorithm);
+        }
+
+        for name in &["", "md", "sha-256", "sha3", " md5", "sha1 "] {
+            assert!(name.parse::<HashAlgorithm>().is_err());
+        }
+    }
+
+    #[test]
+    fn serializes_canonical_names() {
+        let algorithms: Vec<HashAlgorithm> =
+            serde_json::from_str(r#"["MD-5", "Sha1", "SHA3_256", "sha3-512"]"#).unwrap();
+        assert_eq!(
+            serde_json::to_string(&algorithms).unwrap(),
+            r#"["md5","sha1","sha3-256","sha3-512"]"#
+        );
+    }
+
+    #[test]
+    fn strength_follows_the_registry() {
+        let mut algorithms = vec![
+            HashAlgorithm::Sha3_256,
+            HashAlgorithm::Md5,
+            HashAlgorithm::Sha3_512,
+            HashAlgorithm::Sha1,
+        ];
+        algorithms.sort_by_key(|a| a.strength());
+        assert_eq!(
+            algorithms,
+            [
+                HashAlgorithm::Md5,
+                HashAlgorithm::Sha1,
+                HashAlgorithm::Sha3_256,
+                HashAlgorithm::Sha3_512
+            ]
+        );
+    }
+
+    #[test]
+    fn rate_limit_holds_across_computations() {
+        let mut hasher = Hasher::new(std::iter::once(HashAlgorithm::Md5));
+        hasher.set_buffer_size(1000).set_rate_limit(Some(10_000));
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            hasher.compute(&[0u8; 1000][..]).unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(250));
+    }
+
+    #[test]
+    fn empty_input_produces_the_empty_digests() {
+        let expected = [
+            (HashAlgorithm::Md5, "d41d8cd98f00b204e9800998ecf8427e"),
+            (
+                HashAlgorithm::Sha1,
+                "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            ),
+            (
+                HashAlgorithm::Sha3_256,
+                "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a",
+            ),
+            (
+                HashAlgorithm::Sha3_512,
+                "a69f73cca23a9ac5c8b567dc185a756e97c982164fe25859e0d1dcc1475c80a6\
+                 15b2123af1f5f94c11e3e9402c3ac558f500199d95b6d3e301758586281dcd26",
+            ),
+        ];
+        let mut hasher = Hasher::new(expected.iter().map(|(a, _)| *a));
+
+        // Also after hashing other data, which must not leak into the next
+        // computation.
+        for input in [&b""[..], b"data", b""] {
+            let hashes = hasher.compute(input).unwrap();
+            if input.is_empty() {
+                let hashes: Vec<_> = hashes.iter().map(|(a, v)| (*a, v.to_hex())).collect();
+                let expected: Vec<_> = expected.iter().map(|(a, v)| (*a, v.to_string())).collect();
+                assert_eq!(hashes, expected);
             }
         }
+    }
+
+    /// Computes the HMAC value of the data with the key as a hex string.
+    fn hmac(algorithm: HashAlgorithm, key: &[u8], data: &[u8]) -> String {
+        let mut hasher = Hasher::new(std::iter::once(algorithm));
+        hasher.set_key(Some(key));
+        hasher.compute(data).unwrap()[0].1.to_hex()
+    }
+
+    #[test]
+    fn matches_rfc_2202_hmac_vectors() {
+        let long_key = [0xaa; 80];
+        let long_data = &b"Test Using Larger Than Block-Size Key - Hash Key First"[..];
+        let cases = [
+            (
+                HashAlgorithm::Md5,
+                &[0x0b; 16][..],
+                &b"Hi There"[..],
+                "9294727a3638bb1c13f48ef8158bfc9d",
+            ),
+            (
+                HashAlgorithm::Md5,
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "750c783e6ab0b503eaa86e310a5db738",
+            ),
+            (
+                HashAlgorithm::Md5,
+                &long_key,
+                long_data,
+                "6b1ab7fe4bd7bf8f0b62e6ce61b9d0cd",
+            ),
+            (
+                HashAlgorithm::Sha1,
+                &[0x0b; 20],
+                b"Hi There",
+                "b617318655057264e28bc0b6fb378c8ef146be00",
+            ),
+            (
+                HashAlgorithm::Sha1,
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79",
+            ),
+            (
+                HashAlgorithm::Sha1,
+                &long_key,
+                long_data,
+                "aa4ae5e15272d00e95705637ce8a3b55ed402112",
+            ),
+        ];
+        for (algorithm, key, data, expected) in &cases {
+            assert_eq!(hmac(*algorithm, key, data), *expected);
+        }
+    }
+
+    #[test]
+    fn hashes_sha3_hmac_keys_longer_than_the_block() {
+        // Keys of the block size are used as is, and longer ones are hashed
+        // first. The values were computed with Python's hmac module.
+        let cases = [
+            (
+                HashAlgorithm::Sha3_256,
+                136,
+                "1bd45325b7888277b39e98966e4ab19a965a8e4f50fb59ab783b11ff90bdee6f",
+            ),
+            (
+                HashAlgorithm::Sha3_256,
+                137,
+                "d8553742213122362af0c8589edf4d3405a1ec7a31ad32019885de0f57f7a012",
+            ),
+            (
+                HashAlgorithm::Sha3_512,
+                72,
+                "772727b6cff3667cceff3263bde133a4dc25c674b491f883b9dfc16361fd2b3a\
+                 a2a7efb97ef43d34e10b42293dd7edd93f918865bfcf0c5433689f6ae908857c",
+            ),
+            (
+                HashAlgorithm::Sha3_512,
+                73,
+                "f2bce61720793e25abf4b80c39385ae63e3cb85a65819144427a2c88c2e463b9\
+                 95bc8f9f275e54a034a9368828dad6420e69246d2525d7f57dc324499a056a6e",
+            ),
+        ];
+        for (algorithm, key_len, expected) in &cases {
+            assert_eq!(
+                hmac(*algorithm, &vec![0xaa; *key_len], b"Hi There"),
+                *expected
+            );
+        }
+
+        assert_eq!(
+            hmac(
+                HashAlgorithm::Sha3_256,
+                b"key",
+                b"The quick brown fox jumps over the lazy dog"
+            ),
+            "8c6e0683409427f8931711b10ca92a506eb1fafa48fadd66d76126f47ac2c333"
+        );
+    }
+
+    #[test]
+    fn keyed_computations_do_not_leak_into_each_other() {
+        let mut hasher = Hasher::new(std::iter::once(HashAlgorithm::Md5));
+        hasher.set_key(Some(b"Jefe"));
+        for _ in 0..2 {
+            let hashes = hasher
+                .compute(&b"what do ya want for nothing?"[..])
+                .unwrap();
+            assert_eq!(hashes[0].1.to_hex(), "750c783e6ab0b503eaa86e310a5db738");
+        }
 
-        Ok(self.finalize_reset())
+        hasher.set_key(None);
+        let hashes = hasher.compute(&b""[..]).unwrap();
+        assert_eq!(hashes[0].1.to_hex(), "d41d8cd98f00b204e9800998ecf8427e");
     }
 }
@@ -0,0 +1,627 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{BufRead, BufReader, Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Component, Path};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Timeout of connecting to the server and of every read and write.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Scheme prefix of the supported URLs.
+const SCHEME: &str = "http://";
+
+/// Maximum length of a line of the response head, or of a chunk size line,
+/// so that a misbehaving server cannot make the line grow without bound.
+const MAX_LINE_LENGTH: u64 = 8192;
+
+/// Maximum number of header or trailer lines of a response.
+const MAX_HEADERS: usize = 100;
+
+/// An error returned when a file cannot be downloaded.
+#[derive(Debug)]
+pub enum HttpError {
+    /// The connection failed.
+    Io(IoError),
+
+    /// The path cannot be put into a URL, since it is not valid UTF-8.
+    InvalidPath,
+
+    /// The server response is malformed.
+    InvalidResponse,
+
+    /// The server responded with an unsuccessful status (status code).
+    Status(u16),
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match &self {
+            HttpError::Io(err) => write!(f, "HTTP I/O error: {}", err),
+            HttpError::InvalidPath => write!(f, "Path is not valid UTF-8"),
+            HttpError::InvalidResponse => write!(f, "Invalid HTTP response"),
+            HttpError::Status(code) => write!(f, "HTTP status {}", code),
+        }
+    }
+}
+
+impl Error for HttpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self {
+            HttpError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<IoError> for HttpError {
+    fn from(err: IoError) -> Self {
+        HttpError::Io(err)
+    }
+}
+
+/// An error returned when the base URL cannot be parsed.
+#[derive(Debug)]
+pub struct ParseBaseUrlError(&'static str);
+
+impl Display for ParseBaseUrlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ParseBaseUrlError {}
+
+/// A plain HTTP URL under which the repository files are served.
+#[derive(Debug, Clone)]
+pub struct BaseUrl {
+    /// Host name or address, with IPv6 addresses in brackets.
+    host: String,
+
+    /// TCP port.
+    port: u16,
+
+    /// Path prefix of the files, ending with a slash.
+    path: String,
+}
+
+impl FromStr for BaseUrl {
+    type Err = ParseBaseUrlError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("https://") {
+            return Err(ParseBaseUrlError("HTTPS is not supported"));
+        }
+        let rest = s
+            .strip_prefix(SCHEME)
+            .ok_or(ParseBaseUrlError("URL must start with http://"))?;
+
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        if path.contains(['?', '#']) {
+            return Err(ParseBaseUrlError("URL must not have a query or fragment"));
+        }
+
+        // The port follows the last colon, unless it is inside an IPv6
+        // address.
+        let (host, port) = match authority.rfind(':') {
+            Some(colon) if !authority[colon..].contains(']') => (
+                &authority[..colon],
+                u16::from_str(&authority[colon + 1..])
+                    .map_err(|_| ParseBaseUrlError("invalid port"))?,
+            ),
+            _ => (authority, 80),
+        };
+        if host.is_empty() || host.contains('@') {
+            return Err(ParseBaseUrlError("invalid host"));
+        }
+
+        let mut path = path.to_string();
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+
+        Ok(BaseUrl {
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+}
+
+impl Display for BaseUrl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}{}:{}{}", SCHEME, self.host, self.port, self.path)
+    }
+}
+
+impl BaseUrl {
+    /// Requests the file with the given path relative to the base URL.
+    ///
+    /// The response is returned whatever its status, with the body ready to
+    /// be read.
+    pub fn get(&self, path: &Path) -> Result<Response<BufReader<TcpStream>>, HttpError> {
+        let target = format!("{}{}", self.path, encode_path(path)?);
+        let stream = self.connect()?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        // A connection per file keeps the response framing simple.
+        let mut writer = &stream;
+        write!(
+            writer,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}/{}\r\nAccept-Encoding: identity\r\n\
+             Connection: close\r\n\r\n",
+            target,
+            self.host_header(),
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+        )?;
+        writer.flush()?;
+
+        Response::read(BufReader::new(stream))
+    }
+
+    /// Connects to the first reachable address of the host.
+    fn connect(&self) -> IoResult<TcpStream> {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        let mut last_err = IoError::new(ErrorKind::NotFound, "host has no addresses");
+        for addr in (host, self.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Returns the value of the `Host` header.
+    fn host_header(&self) -> String {
+        if self.port == 80 {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Percent-encodes a relative path for a URL, keeping the separators.
+fn encode_path(path: &Path) -> Result<String, HttpError> {
+    let mut ret = String::new();
+
+    for component in path.components() {
+        let name = match component {
+            Component::Normal(name) => name.to_str().ok_or(HttpError::InvalidPath)?,
+            _ => continue,
+        };
+        if !ret.is_empty() {
+            ret.push('/');
+        }
+        for b in name.bytes() {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                ret.push(b as char);
+            } else {
+                ret.push_str(&format!("%{:02X}", b));
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Framing of a response body.
+enum Framing {
+    /// The body has the given number of bytes left.
+    Length(u64),
+
+    /// The body is chunked, with the given number of bytes left in the
+    /// current chunk, or is complete.
+    Chunked { left: u64, done: bool },
+
+    /// The body lasts until the connection is closed.
+    UntilClose,
+}
+
+/// A response of the server, whose body is read as it is received.
+pub struct Response<R: BufRead> {
+    /// Status code.
+    status: u16,
+
+    /// Declared body length, if known in advance.
+    content_length: Option<u64>,
+
+    /// The connection, positioned at the body.
+    reader: R,
+
+    /// Framing of the body.
+    framing: Framing,
+
+    /// Number of body bytes read so far.
+    received: u64,
+}
+
+impl<R: BufRead> Response<R> {
+    /// Reads the status line and the headers of a response.
+    fn read(mut reader: R) -> Result<Self, HttpError> {
+        let line = read_line(&mut reader)?;
+        let mut fields = line.splitn(3, ' ');
+        if !fields.next().is_some_and(|v| v.starts_with("HTTP/1.")) {
+            return Err(HttpError::InvalidResponse);
+        }
+        let status = fields
+            .next()
+            .and_then(|s| u16::from_str(s).ok())
+            .ok_or(HttpError::InvalidResponse)?;
+
+        let mut content_length = None;
+        let mut chunked = false;
+        for count in 0.. {
+            let line = read_line(&mut reader)?;
+            if line.is_empty() {
+                break;
+            } else if count == MAX_HEADERS {
+                return Err(HttpError::InvalidResponse);
+            }
+
+            let colon = line.find(':').ok_or(HttpError::InvalidResponse)?;
+            let (name, value) = (&line[..colon], line[colon + 1..].trim());
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length =
+                    Some(u64::from_str(value).map_err(|_| HttpError::InvalidResponse)?);
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.to_ascii_lowercase().ends_with("chunked");
+            }
+        }
+
+        // Chunked encoding takes precedence over the declared length.
+        let (content_length, framing) = if chunked {
+            (
+                None,
+                Framing::Chunked {
+                    left: 0,
+                    done: false,
+                },
+            )
+        } else if let Some(len) = content_length {
+            (Some(len), Framing::Length(len))
+        } else {
+            (None, Framing::UntilClose)
+        };
+
+        Ok(Response {
+            status,
+            content_length,
+            reader,
+            framing,
+            received: 0,
+        })
+    }
+
+    /// Returns the status code.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Fails unless the status is successful.
+    pub fn ensure_success(&self) -> Result<(), HttpError> {
+        if (200..300).contains(&self.status) {
+            Ok(())
+        } else {
+            Err(HttpError::Status(self.status))
+        }
+    }
+
+    /// Returns the body length declared in the headers, if any.
+    pub fn content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
+    /// Returns the number of body bytes read so far.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Reads the size line of the next chunk, returning the size.
+    fn next_chunk(&mut self) -> IoResult<u64> {
+        let line = read_line(&mut self.reader).map_err(into_io_error)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        u64::from_str_radix(size, 16).map_err(|_| invalid_data("invalid chunk size"))
+    }
+
+    /// Skips the trailers after the last chunk.
+    fn skip_trailers(&mut self) -> IoResult<()> {
+        for _ in 0..=MAX_HEADERS {
+            if read_line(&mut self.reader)
+                .map_err(into_io_error)?
+                .is_empty()
+            {
+                return Ok(());
+            }
+        }
+        Err(invalid_data("too many trailers"))
+    }
+}
+
+impl<R: BufRead> Read for Response<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let n = match self.framing {
+            Framing::Length(0) => 0,
+            Framing::Length(left) => {
+                let max = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+                let n = self.reader.read(&mut buf[..max])?;
+                if n == 0 {
+                    return Err(IoError::new(ErrorKind::UnexpectedEof, "body is truncated"));
+                }
+                self.framing = Framing::Length(left - n as u64);
+                n
+            }
+            Framing::Chunked { done: true, .. } => 0,
+            Framing::Chunked { left: 0, .. } => {
+                let size = self.next_chunk()?;
+                if size == 0 {
+                    self.skip_trailers()?;
+                    self.framing = Framing::Chunked {
+                        left: 0,
+                        done: true,
+                    };
+                } else {
+                    self.framing = Framing::Chunked {
+                        left: size,
+                        done: false,
+                    };
+                }
+                return self.read(buf);
+            }
+            Framing::Chunked { left, .. } => {
+                let max = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+                let n = self.reader.read(&mut buf[..max])?;
+                if n == 0 {
+                    return Err(IoError::new(ErrorKind::UnexpectedEof, "chunk is truncated"));
+                }
+                let left = left - n as u64;
+                if left == 0
+                    && !read_line(&mut self.reader)
+                        .map_err(into_io_error)?
+                        .is_empty()
+                {
+                    return Err(invalid_data("chunk is not terminated"));
+                }
+                self.framing = Framing::Chunked { left, done: false };
+                n
+            }
+            Framing::UntilClose => self.reader.read(buf)?,
+        };
+
+        self.received += n as u64;
+        Ok(n)
+    }
+}
+
+/// Reads a CRLF-terminated line of the response head, of at most
+/// `MAX_LINE_LENGTH` bytes.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, HttpError> {
+    let mut line = Vec::new();
+    if reader
+        .take(MAX_LINE_LENGTH + 1)
+        .read_until(b'\n', &mut line)?
+        == 0
+    {
+        return Err(HttpError::Io(IoError::new(
+            ErrorKind::UnexpectedEof,
+            "connection closed",
+        )));
+    }
+    if line.ends_with(b"\n") {
+        line.pop();
+    } else if line.len() as u64 > MAX_LINE_LENGTH {
+        return Err(HttpError::InvalidResponse);
+    }
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| HttpError::InvalidResponse)
+}
+
+/// Converts an error reading the response head into an I/O error.
+fn into_io_error(err: HttpError) -> IoError {
+    match err {
+        HttpError::Io(err) => err,
+        err => invalid_data(&err.to_string()),
+    }
+}
+
+/// Returns an error about malformed data.
+fn invalid_data(msg: &str) -> IoError {
+    IoError::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads the head of an in-memory response.
+    fn response(data: &str) -> Result<Response<&[u8]>, HttpError> {
+        Response::read(data.as_bytes())
+    }
+
+    /// Reads the body of an in-memory response.
+    fn body(data: &str) -> IoResult<Vec<u8>> {
+        let mut body = Vec::new();
+        response(data).unwrap().read_to_end(&mut body)?;
+        Ok(body)
+    }
+
+    #[test]
+    fn reads_the_status_and_the_declared_length() {
+        let mut response =
+            response("HTTP/1.1 200 OK\r\nContent-Length: 5\r\nServer: test\r\n\r\nhello!").unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(response.ensure_success().is_ok());
+        assert_eq!(response.content_length(), Some(5));
+
+        let mut body = Vec::new();
+        response.read_to_end(&mut body).unwrap();
+        assert_eq!(body, b"hello");
+        assert_eq!(response.received(), 5);
+
+        let response = self::response("HTTP/1.0 404 Not Found\nCONTENT-LENGTH:  0\n\n").unwrap();
+        assert_eq!(response.status(), 404);
+        assert!(matches!(
+            response.ensure_success(),
+            Err(HttpError::Status(404))
+        ));
+        assert_eq!(response.content_length(), Some(0));
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        for data in &[
+            "HTTP/2 200 OK\r\n\r\n",
+            "ICY 200 OK\r\n\r\n",
+            "HTTP/1.1 OK\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nno colon\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: five\r\n\r\n",
+        ] {
+            assert!(matches!(response(data), Err(HttpError::InvalidResponse)));
+        }
+
+        assert!(matches!(
+            response("HTTP/1.1 200 OK\r\n"),
+            Err(HttpError::Io(err)) if err.kind() == ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn limits_the_response_head() {
+        let long_line = format!("HTTP/1.1 200 OK\r\nX: {}\r\n\r\n", "x".repeat(10_000));
+        assert!(matches!(
+            response(&long_line),
+            Err(HttpError::InvalidResponse)
+        ));
+
+        let many_headers = format!("HTTP/1.1 200 OK\r\n{}\r\n", "X: y\r\n".repeat(200));
+        assert!(matches!(
+            response(&many_headers),
+            Err(HttpError::InvalidResponse)
+        ));
+
+        let many_trailers = format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n{}\r\n",
+            "X: y\r\n".repeat(200)
+        );
+        assert_eq!(
+            body(&many_trailers).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn reads_chunked_bodies() {
+        // Chunked encoding takes precedence over the declared length.
+        let data =
+            "HTTP/1.1 200 OK\r\nContent-Length: 99\r\nTransfer-Encoding: gzip, chunked\r\n\r\n\
+                    5;name=value\r\nhello\r\n6\r\n world\r\n0\r\nExpires: never\r\n\r\nignored";
+        assert_eq!(response(data).unwrap().content_length(), None);
+        assert_eq!(body(data).unwrap(), b"hello world");
+
+        let head = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+        for (chunks, kind) in &[
+            ("5\r\nhel", ErrorKind::UnexpectedEof),
+            ("5\r\nhello", ErrorKind::UnexpectedEof),
+            ("5\r\nhelloXX\r\n0\r\n\r\n", ErrorKind::InvalidData),
+            ("z\r\nhello\r\n", ErrorKind::InvalidData),
+            ("5\r\nhello\r\n", ErrorKind::UnexpectedEof),
+        ] {
+            let data = format!("{}{}", head, chunks);
+            assert_eq!(body(&data).unwrap_err().kind(), *kind, "{:?}", chunks);
+        }
+    }
+
+    #[test]
+    fn reads_bodies_until_the_end() {
+        let data = "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello";
+        assert_eq!(body(data).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+        let data = "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nhello\r\nworld";
+        assert_eq!(response(data).unwrap().content_length(), None);
+        assert_eq!(body(data).unwrap(), b"hello\r\nworld");
+    }
+
+    #[test]
+    fn parses_base_urls() {
+        let parse = |s: &str| {
+            BaseUrl::from_str(s).map(|url| (url.host.clone(), url.port, url.path.clone()))
+        };
+        let url = |host: &str, port: u16, path: &str| (host.to_string(), port, path.to_string());
+
+        assert_eq!(
+            parse("http://example.com").unwrap(),
+            url("example.com", 80, "/")
+        );
+        assert_eq!(
+            parse("http://example.com:8080/files").unwrap(),
+            url("example.com", 8080, "/files/")
+        );
+        assert_eq!(
+            parse("http://[::1]:8000/a/").unwrap(),
+            url("[::1]", 8000, "/a/")
+        );
+        assert_eq!(parse("http://[::1]/").unwrap(), url("[::1]", 80, "/"));
+
+        for (s, error) in &[
+            ("https://example.com/", "HTTPS is not supported"),
+            ("ftp://example.com/", "URL must start with http://"),
+            (
+                "http://example.com/?a=1",
+                "URL must not have a query or fragment",
+            ),
+            (
+                "http://example.com/#top",
+                "URL must not have a query or fragment",
+            ),
+            ("http://example.com:99999/", "invalid port"),
+            ("http://example.com:/", "invalid port"),
+            ("http:///files", "invalid host"),
+            ("http://user@example.com/", "invalid host"),
+        ] {
+            assert_eq!(parse(s).unwrap_err().to_string(), *error, "{}", s);
+        }
+    }
+
+    #[test]
+    fn formats_base_urls_and_host_headers() {
+        let url = BaseUrl::from_str("http://example.com/files").unwrap();
+        assert_eq!(url.to_string(), "http://example.com:80/files/");
+        assert_eq!(url.host_header(), "example.com");
+
+        let url = BaseUrl::from_str("http://[::1]:8000").unwrap();
+        assert_eq!(url.host_header(), "[::1]:8000");
+    }
+
+    #[test]
+    fn encodes_paths() {
+        assert_eq!(
+            encode_path(Path::new("dir/caf\u{e9} 1%.txt")).unwrap(),
+            "dir/caf%C3%A9%201%25.txt"
+        );
+        assert_eq!(
+            encode_path(Path::new("./a/~b-c_d.e")).unwrap(),
+            "a/~b-c_d.e"
+        );
+        assert_eq!(encode_path(Path::new("a/?#")).unwrap(), "a/%3F%23");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn rejects_non_utf8_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"caf\xe9.txt"));
+        assert!(matches!(encode_path(path), Err(HttpError::InvalidPath)));
+    }
+}
@@ -0,0 +1,307 @@
+use std::fs;
+use std::io::{ErrorKind, Result as IoResult};
+use std::path::{Path, PathBuf};
+
+/// Name of the files listing the ignore patterns of their directories.
+pub const IGNORE_FILE_NAME: &str = ".inventorizeignore";
+
+/// A pattern of an ignore file.
+#[derive(Debug)]
+struct Pattern {
+    /// Glob to match, without the negation and the slashes that only mark
+    /// the pattern kind.
+    glob: String,
+
+    /// Re-include the matching entries instead of ignoring them.
+    negated: bool,
+
+    /// Only match directories.
+    dir_only: bool,
+
+    /// Match the path relative to the ignore file directory instead of the
+    /// entry name.
+    anchored: bool,
+}
+
+impl Pattern {
+    /// Parses a line of an ignore file, returning `None` for empty lines and
+    /// comments.
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (line, negated) = match line.strip_prefix('!') {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        let (line, dir_only) = match line.strip_suffix('/') {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        // A slash anywhere but at the end anchors the pattern to the ignore
+        // file directory.
+        let anchored = line.contains('/');
+        let glob = line.trim_start_matches('/');
+        if glob.is_empty() {
+            return None;
+        }
+
+        Some(Pattern {
+            glob: glob.to_string(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    /// Checks if the pattern matches the entry with the relative path.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        match_subject(&self.glob, self.anchored, path)
+    }
+}
+
+/// Patterns of an ignore file, applying to the subtree of its directory.
+#[derive(Debug)]
+pub struct IgnoreFile {
+    /// Directory of the ignore file.
+    dir: PathBuf,
+
+    /// Patterns in the order of the file lines.
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreFile {
+    /// Reads the ignore file of the directory, if it has one.
+    pub fn read<P: AsRef<Path>>(dir: P) -> IoResult<Option<Self>> {
+        let dir = dir.as_ref();
+        let contents = match fs::read_to_string(dir.join(IGNORE_FILE_NAME)) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let patterns: Vec<_> = contents.lines().filter_map(Pattern::parse).collect();
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(IgnoreFile {
+            dir: dir.to_path_buf(),
+            patterns,
+        }))
+    }
+
+    /// Checks the entry with the given path against the patterns.
+    ///
+    /// Returns whether the entry is ignored according to the last matching
+    /// pattern, or `None` if no pattern matches or the entry is outside of
+    /// the ignore file directory.
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = slash_path(path.strip_prefix(&self.dir).ok()?);
+
+        self.patterns
+            .iter()
+            .rev()
+            .find(|p| p.matches(&relative, is_dir))
+            .map(|p| !p.negated)
+    }
+}
+
+/// Matches a relative path against a glob like an ignore pattern: a glob
+/// with a slash matches the whole path, and a glob without one matches the
+/// file name in any directory. A leading slash only anchors the glob.
+pub fn glob_matches_path(glob: &str, path: &Path) -> bool {
+    match_subject(
+        glob.trim_start_matches('/'),
+        glob.contains('/'),
+        &slash_path(path),
+    )
+}
+
+/// Matches the `/` separated relative path, or only its file name if not
+/// anchored, against a glob.
+fn match_subject(glob: &str, anchored: bool, path: &str) -> bool {
+    let subject = if anchored {
+        path
+    } else {
+        path.rsplit('/').next().unwrap_or(path)
+    };
+    glob_match(glob.as_bytes(), subject.as_bytes())
+}
+
+/// Joins the components of a relative path with `/`, whatever the platform
+/// separator is.
+fn slash_path(path: &Path) -> String {
+    let components: Vec<_> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+/// Matches a path against a glob.
+///
+/// `*` matches any sequence of characters except `/`, `?` matches any
+/// character except `/`, and `**` matches everything, with `**/` matching
+/// zero or more whole directories. A backslash makes the following character
+/// literal.
+fn glob_match(glob: &[u8], path: &[u8]) -> bool {
+    match glob {
+        [] => path.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            glob_match(rest, path)
+                || path
+                    .iter()
+                    .enumerate()
+                    .any(|(i, c)| *c == b'/' && glob_match(rest, &path[i + 1..]))
+        }
+        [b'*', b'*'] => true,
+        [b'*', rest @ ..] => {
+            for i in 0..=path.len() {
+                if glob_match(rest, &path[i..]) {
+                    return true;
+                }
+                if path.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        [b'?', rest @ ..] => match path {
+            [c, tail @ ..] if *c != b'/' => glob_match(rest, tail),
+            _ => false,
+        },
+        [b'\\', c, rest @ ..] | [c, rest @ ..] => match path {
+            [d, tail @ ..] if d == c => glob_match(rest, tail),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an ignore file of the directory with the given contents.
+    fn ignore_file(dir: &str, contents: &str) -> IgnoreFile {
+        IgnoreFile {
+            dir: PathBuf::from(dir),
+            patterns: contents.lines().filter_map(Pattern::parse).collect(),
+        }
+    }
+
+    #[test]
+    fn matches_globs() {
+        let matches = |glob: &str, path: &str| glob_match(glob.as_bytes(), path.as_bytes());
+
+        assert!(matches("*.log", "a.log"));
+        assert!(matches("*.log", ".log"));
+        assert!(!matches("*.log", "a/b.log"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "a/c"));
+        assert!(!matches("a?c", "ac"));
+
+        // `**/` matches zero or more whole directories.
+        assert!(matches("**/b.txt", "b.txt"));
+        assert!(matches("**/b.txt", "a/b.txt"));
+        assert!(matches("**/b.txt", "a/x/y/b.txt"));
+        assert!(!matches("**/b.txt", "ab.txt"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        assert!(!matches("a/**/b", "ax/b"));
+        assert!(matches("a/**", "a/x/y"));
+
+        // A backslash escapes the following character.
+        assert!(matches(r"\*.txt", "*.txt"));
+        assert!(!matches(r"\*.txt", "a.txt"));
+        assert!(matches(r"\!important", "!important"));
+        assert!(matches(r"a\?", "a?"));
+        assert!(!matches(r"a\?", "ab"));
+    }
+
+    #[test]
+    fn parses_patterns() {
+        assert!(Pattern::parse("").is_none());
+        assert!(Pattern::parse("   ").is_none());
+        assert!(Pattern::parse("# comment").is_none());
+        assert!(Pattern::parse("/").is_none());
+
+        let pattern = Pattern::parse("!/build/").unwrap();
+        assert_eq!(pattern.glob, "build");
+        assert!(pattern.negated && pattern.dir_only && pattern.anchored);
+
+        let pattern = Pattern::parse("*.tmp  ").unwrap();
+        assert_eq!(pattern.glob, "*.tmp");
+        assert!(!pattern.negated && !pattern.dir_only && !pattern.anchored);
+
+        assert!(Pattern::parse("doc/*.md").unwrap().anchored);
+    }
+
+    #[test]
+    fn matches_anchored_and_unanchored_patterns() {
+        let ignore = ignore_file("/repo", "*.tmp\n/top.txt\ndoc/*.md\n");
+        let matched = |path: &str| ignore.matched(Path::new(path), false);
+
+        // Unanchored patterns match the entry name at any depth.
+        assert_eq!(matched("/repo/a.tmp"), Some(true));
+        assert_eq!(matched("/repo/x/y/a.tmp"), Some(true));
+
+        // Anchored patterns match the path relative to the directory.
+        assert_eq!(matched("/repo/top.txt"), Some(true));
+        assert_eq!(matched("/repo/sub/top.txt"), None);
+        assert_eq!(matched("/repo/doc/a.md"), Some(true));
+        assert_eq!(matched("/repo/sub/doc/a.md"), None);
+
+        // Entries outside of the directory are never matched.
+        assert_eq!(matched("/other/a.tmp"), None);
+    }
+
+    #[test]
+    fn last_matching_pattern_decides() {
+        let ignore = ignore_file("/repo", "*.log\n!keep.log\nkeep.log.d/\n");
+        assert_eq!(ignore.matched(Path::new("/repo/a.log"), false), Some(true));
+        assert_eq!(
+            ignore.matched(Path::new("/repo/keep.log"), false),
+            Some(false)
+        );
+
+        let ignore = ignore_file("/repo", "!keep.log\n*.log\n");
+        assert_eq!(
+            ignore.matched(Path::new("/repo/keep.log"), false),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn dir_only_patterns_skip_files() {
+        let ignore = ignore_file("/repo", "build/\n");
+        assert_eq!(ignore.matched(Path::new("/repo/build"), true), Some(true));
+        assert_eq!(ignore.matched(Path::new("/repo/x/build"), true), Some(true));
+        assert_eq!(ignore.matched(Path::new("/repo/build"), false), None);
+    }
+
+    #[test]
+    fn matches_paths_against_globs() {
+        let matches = |glob: &str, path: &str| glob_matches_path(glob, Path::new(path));
+
+        // Globs without a slash match the file name anywhere.
+        assert!(matches("*.iso", "a.iso"));
+        assert!(matches("*.iso", "images/old/a.iso"));
+        assert!(!matches("*.iso", "a.iso.part"));
+
+        // Others match the whole path.
+        assert!(matches("images/*.iso", "images/a.iso"));
+        assert!(!matches("images/*.iso", "images/old/a.iso"));
+        assert!(!matches("images/*.iso", "backup/images/a.iso"));
+        assert!(matches("/a.iso", "a.iso"));
+        assert!(!matches("/a.iso", "images/a.iso"));
+        assert!(matches("images/**", "images/old/a.iso"));
+        assert!(matches("**/old/*", "images/old/a.iso"));
+    }
+}
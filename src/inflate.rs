@@ -0,0 +1,491 @@
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult};
+
+/// Distance that back-references can reach.
+const WINDOW_SIZE: usize = 32 * 1024;
+
+/// Maximum number of bytes decoded at a time, so that the output buffer stays
+/// bounded.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Maximum length of a Huffman code.
+const MAX_BITS: usize = 15;
+
+/// Base lengths and extra bits of the length symbols 257 to 285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances and extra bits of the distance symbols.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order in which the code length code lengths of a dynamic block are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Returns the error for malformed compressed data.
+fn invalid(msg: &str) -> IoError {
+    IoError::new(
+        ErrorKind::InvalidData,
+        format!("Invalid deflate data: {}", msg),
+    )
+}
+
+/// A canonical Huffman code.
+struct Huffman {
+    /// Number of the codes of every length.
+    counts: [u16; MAX_BITS + 1],
+
+    /// Symbols ordered by their codes.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the code from the code lengths of the symbols, zero meaning that
+    /// the symbol is not used.
+    ///
+    /// Incomplete codes are accepted, since a single distance code is stored
+    /// as one; the unused codes are rejected when decoded.
+    fn new(lengths: &[u8]) -> IoResult<Self> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(invalid("over-subscribed code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Ok(Huffman { counts, symbols })
+    }
+
+    /// Returns the fixed literal/length and distance codes.
+    fn fixed() -> (Self, Self) {
+        let mut lengths = [0u8; 288];
+        lengths[..144].iter_mut().for_each(|l| *l = 8);
+        lengths[144..256].iter_mut().for_each(|l| *l = 9);
+        lengths[256..280].iter_mut().for_each(|l| *l = 7);
+        lengths[280..].iter_mut().for_each(|l| *l = 8);
+
+        // The fixed codes are complete, so building them never fails.
+        (
+            Huffman::new(&lengths).unwrap(),
+            Huffman::new(&[5u8; 30]).unwrap(),
+        )
+    }
+}
+
+/// State of the decoder between the blocks and within them.
+enum State {
+    /// A block header is expected next.
+    Header,
+
+    /// Within a stored block, with the given number of bytes left.
+    Stored(usize),
+
+    /// Within a compressed block with the given literal/length and distance
+    /// codes.
+    Codes(Huffman, Huffman),
+
+    /// The final block has been decoded.
+    Done,
+}
+
+/// A streaming decoder of raw DEFLATE data (RFC 1951), as stored in zip
+/// archives.
+///
+/// The compressed data is read from the reader passed to every call, one
+/// byte at a time as needed, so that no input beyond the end of the data is
+/// consumed.
+pub struct Inflater {
+    /// Bits read from the input but not consumed yet, lowest first.
+    bit_buffer: u32,
+
+    /// Number of the bits in the buffer.
+    bit_count: u32,
+
+    /// Decoded output, keeping at least the last `WINDOW_SIZE` bytes that
+    /// have been returned for the back-references.
+    window: Vec<u8>,
+
+    /// Number of the bytes of the window that have been returned.
+    pos: usize,
+
+    /// Decoder state.
+    state: State,
+
+    /// The block being decoded is the final one.
+    final_block: bool,
+}
+
+impl Inflater {
+    /// Creates a new decoder.
+    pub fn new() -> Self {
+        Inflater {
+            bit_buffer: 0,
+            bit_count: 0,
+            window: Vec::new(),
+            pos: 0,
+            state: State::Header,
+            final_block: false,
+        }
+    }
+
+    /// Decodes the data into the buffer, returning the number of bytes
+    /// decoded, zero at the end of the data.
+    pub fn read<R: Read>(&mut self, input: &mut R, buf: &mut [u8]) -> IoResult<usize> {
+        while self.pos == self.window.len() && !matches!(self.state, State::Done) {
+            if self.pos >= 2 * WINDOW_SIZE {
+                self.window.drain(..self.pos - WINDOW_SIZE);
+                self.pos = WINDOW_SIZE;
+            }
+            self.step(input)?;
+        }
+
+        let n = buf.len().min(self.window.len() - self.pos);
+        buf[..n].copy_from_slice(&self.window[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+
+    /// Reads the given number of bits.
+    fn bits<R: Read>(&mut self, input: &mut R, n: u32) -> IoResult<u32> {
+        while self.bit_count < n {
+            let mut byte = [0u8; 1];
+            input.read_exact(&mut byte)?;
+            self.bit_buffer |= u32::from(byte[0]) << self.bit_count;
+            self.bit_count += 8;
+        }
+
+        let value = self.bit_buffer & ((1 << n) - 1);
+        self.bit_buffer >>= n;
+        self.bit_count -= n;
+        Ok(value)
+    }
+
+    /// Decodes a symbol with the code, reading its bits one at a time.
+    fn decode<R: Read>(&mut self, input: &mut R, code: &Huffman) -> IoResult<u16> {
+        // Canonical codes of every length are consecutive, starting right
+        // after the codes of the previous length shifted left by one bit.
+        let mut bits = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in &code.counts[1..] {
+            bits |= self.bits(input, 1)? as i32;
+            let count = i32::from(count);
+            if bits - first < count {
+                return Ok(code.symbols[(index + bits - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            bits <<= 1;
+        }
+
+        Err(invalid("unused code"))
+    }
+
+    /// Reads the codes of a dynamic block.
+    fn dynamic_codes<R: Read>(&mut self, input: &mut R) -> IoResult<(Huffman, Huffman)> {
+        let literals = self.bits(input, 5)? as usize + 257;
+        let distances = self.bits(input, 5)? as usize + 1;
+        let code_lengths = self.bits(input, 4)? as usize + 4;
+        if literals > 286 || distances > 30 {
+            return Err(invalid("too many codes"));
+        }
+
+        let mut lengths = [0u8; 19];
+        for &i in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[i] = self.bits(input, 3)? as u8;
+        }
+        let length_code = Huffman::new(&lengths)?;
+
+        let mut lengths = vec![0u8; literals + distances];
+        let mut i = 0;
+        while i < lengths.len() {
+            let symbol = self.decode(input, &length_code)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 if i > 0 => (lengths[i - 1], 3 + self.bits(input, 2)? as usize),
+                16 => return Err(invalid("repeat without a previous length")),
+                17 => (0, 3 + self.bits(input, 3)? as usize),
+                _ => (0, 11 + self.bits(input, 7)? as usize),
+            };
+            if i + repeat > lengths.len() {
+                return Err(invalid("too many lengths"));
+            }
+            lengths[i..i + repeat].iter_mut().for_each(|l| *l = value);
+            i += repeat;
+        }
+
+        if lengths[256] == 0 {
+            return Err(invalid("no end of block code"));
+        }
+
+        Ok((
+            Huffman::new(&lengths[..literals])?,
+            Huffman::new(&lengths[literals..])?,
+        ))
+    }
+
+    /// Decodes a block header or a chunk of a block.
+    fn step<R: Read>(&mut self, input: &mut R) -> IoResult<()> {
+        match std::mem::replace(&mut self.state, State::Done) {
+            State::Header if self.final_block => {}
+            State::Header => {
+                self.final_block = self.bits(input, 1)? == 1;
+                self.state = match self.bits(input, 2)? {
+                    0 => {
+                        // Stored blocks start at a byte boundary.
+                        self.bit_buffer = 0;
+                        self.bit_count = 0;
+
+                        let mut header = [0u8; 4];
+                        input.read_exact(&mut header)?;
+                        let len = u16::from_le_bytes([header[0], header[1]]);
+                        let nlen = u16::from_le_bytes([header[2], header[3]]);
+                        if len != !nlen {
+                            return Err(invalid("stored block length mismatch"));
+                        }
+                        State::Stored(len.into())
+                    }
+                    1 => {
+                        let (literals, distances) = Huffman::fixed();
+                        State::Codes(literals, distances)
+                    }
+                    2 => {
+                        let (literals, distances) = self.dynamic_codes(input)?;
+                        State::Codes(literals, distances)
+                    }
+                    _ => return Err(invalid("invalid block type")),
+                };
+            }
+            State::Stored(len) => {
+                let n = len.min(CHUNK_SIZE);
+                let start = self.window.len();
+                self.window.resize(start + n, 0);
+                input.read_exact(&mut self.window[start..])?;
+                self.state = if len > n {
+                    State::Stored(len - n)
+                } else {
+                    State::Header
+                };
+            }
+            State::Codes(literals, distances) => {
+                let end = self.window.len() + CHUNK_SIZE;
+                while self.window.len() < end {
+                    let symbol = self.decode(input, &literals)?;
+                    if symbol < 256 {
+                        self.window.push(symbol as u8);
+                        continue;
+                    } else if symbol == 256 {
+                        self.state = State::Header;
+                        return Ok(());
+                    }
+
+                    let symbol = symbol as usize - 257;
+                    if symbol >= LENGTH_BASE.len() {
+                        return Err(invalid("invalid length symbol"));
+                    }
+                    let len = LENGTH_BASE[symbol] as usize
+                        + self.bits(input, LENGTH_EXTRA[symbol].into())? as usize;
+
+                    let symbol = self.decode(input, &distances)? as usize;
+                    if symbol >= DISTANCE_BASE.len() {
+                        return Err(invalid("invalid distance symbol"));
+                    }
+                    let distance = DISTANCE_BASE[symbol] as usize
+                        + self.bits(input, DISTANCE_EXTRA[symbol].into())? as usize;
+                    if distance > self.window.len() {
+                        return Err(invalid("distance too far back"));
+                    }
+
+                    // The source and the copy may overlap, repeating the
+                    // last bytes.
+                    let start = self.window.len() - distance;
+                    for i in start..start + len {
+                        let byte = self.window[i];
+                        self.window.push(byte);
+                    }
+                }
+                self.state = State::Codes(literals, distances);
+            }
+            State::Done => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes the data, reading the output in chunks of the given size.
+    fn inflate(data: &[u8], chunk: usize) -> IoResult<Vec<u8>> {
+        let mut input = data;
+        let mut inflater = Inflater::new();
+        let mut output = Vec::new();
+        let mut buf = vec![0u8; chunk];
+        loop {
+            match inflater.read(&mut input, &mut buf)? {
+                0 => return Ok(output),
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        crate::util::hex_string_to_bytes(s).unwrap().into_vec()
+    }
+
+    fn lines(n: usize) -> Vec<u8> {
+        (0..n)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[test]
+    fn decodes_fixed_block() {
+        let data = hex("cb48cdc9c957c84022cbf38b7252b800");
+        assert_eq!(inflate(&data, 4096).unwrap(), b"hello hello hello world\n");
+    }
+
+    #[test]
+    fn decodes_dynamic_blocks() {
+        let data = hex(
+            "35ccb10d803000c4c03e5330020642c840144851f62f117a535d658f67decb5ac607610b7b38420d67\
+             68e10addfcdff8c1119e70852f9ce10d77f4f202",
+        );
+        assert_eq!(inflate(&data, 4096).unwrap(), lines(20));
+
+        let data = hex(
+            "35cfbb0d80401003d1fcaaa0046cf3bb8208904ef41f22b473d144fbe41dcf7b2f6b1b7f54712595ad\
+             b2578eca59b92a9df3c9e0084848821296c0842638e119cf73179ef18c673ce319cf78c60b5ef0321f\
+             c50b5ef08217bce0a5b70f",
+        );
+        assert_eq!(inflate(&data, 3).unwrap(), lines(40));
+    }
+
+    #[test]
+    fn decodes_stored_block() {
+        let data = hex("010b00f4ff73746f7265642064617461");
+        assert_eq!(inflate(&data, 4096).unwrap(), b"stored data");
+    }
+
+    /// A writer of the bits of a fixed Huffman block.
+    struct BitWriter {
+        data: Vec<u8>,
+        bits: u32,
+        count: u32,
+    }
+
+    impl BitWriter {
+        /// Writes the value, lowest bit first.
+        fn put(&mut self, value: u32, n: u32) {
+            self.bits |= value << self.count;
+            self.count += n;
+            while self.count >= 8 {
+                self.data.push(self.bits as u8);
+                self.bits >>= 8;
+                self.count -= 8;
+            }
+        }
+
+        /// Writes a Huffman code, highest bit first.
+        fn code(&mut self, code: u32, n: u32) {
+            self.put(code.reverse_bits() >> (32 - n), n);
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            self.put(0, 7);
+            self.data
+        }
+    }
+
+    /// Encodes a fixed block with a literal followed by maximum-length
+    /// copies of it.
+    fn repeated_literal(copies: usize) -> Vec<u8> {
+        let mut w = BitWriter {
+            data: Vec::new(),
+            bits: 0,
+            count: 0,
+        };
+        w.put(1, 1);
+        w.put(1, 2);
+        w.code(0x30 + u32::from(b'a'), 8);
+        for _ in 0..copies {
+            // Length 258 is symbol 285, distance 1 is symbol 0.
+            w.code(0xc0 + 285 - 280, 8);
+            w.code(0, 5);
+        }
+        w.code(0, 7);
+        w.finish()
+    }
+
+    #[test]
+    fn decodes_long_back_references() {
+        // The output outgrows the window several times over.
+        let copies = 1000;
+        let output = inflate(&repeated_literal(copies), 1000).unwrap();
+        assert_eq!(output.len(), 1 + 258 * copies);
+        assert!(output.iter().all(|&b| b == b'a'));
+    }
+
+    #[test]
+    fn rejects_invalid_data() {
+        // Reserved block type.
+        assert!(inflate(&[0x07], 4096).is_err());
+
+        // Distance beyond the start of the output.
+        let mut w = BitWriter {
+            data: Vec::new(),
+            bits: 0,
+            count: 0,
+        };
+        w.put(1, 1);
+        w.put(1, 2);
+        w.code(0xc0 + 285 - 280, 8);
+        w.code(0, 5);
+        assert!(inflate(&w.finish(), 4096).is_err());
+
+        // Stored block length mismatch.
+        assert!(inflate(&hex("010b00f4fe73746f7265642064617461"), 4096).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = repeated_literal(10);
+        let err = inflate(&data[..data.len() - 2], 4096).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -1,337 +1,5932 @@
-use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::convert::TryFrom;
 use std::error::Error;
-use std::fmt::Debug;
-use std::fs::{self, OpenOptions};
-use std::io::{BufReader, Result as IoResult};
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::fs::{self, Metadata, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Result as IoResult, Seek, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-use log::debug;
+use log::{debug, log_enabled, trace, warn, Level};
 
-use serde::{Deserialize, Serialize};
+use serde::de::{DeserializeSeed, Error as DeError, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::archive::{self, Entry, EntryKind, TarReader, ZipReader};
 use crate::file_err;
-use crate::hash::{HashAlgorithm, HashValue, Hasher};
-use crate::iterdir::RelativePathIterator;
+use crate::format::{self, Format};
+use crate::hash::{HashAlgorithm, HashValue, Hasher, RateLimiter};
+#[cfg(feature = "http")]
+use crate::http::{BaseUrl, HttpError};
+use crate::ignore;
+use crate::iterdir::{EntryType, RelativePathIterator};
+use crate::listing::ListingEntry;
+use crate::progress::ProgressObserver;
+use crate::sumfile::SumfileEntry;
 use crate::util::{self, FileError};
 
-/// Inventory configuration.
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Configuration {
-    /// Version of the app used to build the inventory.
-    version: String,
+/// Errors returned by the inventory operations.
+#[derive(Debug)]
+pub enum InventoryError {
+    /// A record contains a hash value whose length does not match the digest
+    /// length of its algorithm.
+    InvalidHashLength(PathBuf, HashAlgorithm),
 
-    /// Skip hidden files.
-    skip_hidden: bool,
+    /// The inventory cannot be sealed, since it has no hash algorithms.
+    NoSealAlgorithm,
 
-    /// Hash algorithms to use.
-    hash_algorithms: BTreeSet<HashAlgorithm>,
-}
+    /// The recorded inventory seal does not match the inventory contents.
+    SealMismatch,
 
-impl Configuration {
-    /// Returns an empty configuration.
-    pub fn new() -> Self {
-        Configuration::default()
-    }
+    /// The hash algorithm is not used by the inventory.
+    AlgorithmNotPresent(HashAlgorithm),
 
-    /// Sets the `skip_hidden` mode.
-    pub fn set_skip_hidden(&mut self, skip_hidden: bool) -> &mut Self {
-        self.skip_hidden = skip_hidden;
-        self
-    }
+    /// The hash algorithms differ from the ones used by the inventory.
+    AlgorithmMismatch,
 
-    /// Sets the hash algorithms to use.
-    pub fn set_hash_algorithms(&mut self, algorithms: &[HashAlgorithm]) {
-        self.hash_algorithms.clear();
-        self.hash_algorithms.extend(algorithms.iter());
-    }
+    /// A record with the same path already exists in the inventory.
+    RecordExists(PathBuf),
+
+    /// The repository has more files than allowed (count, limit).
+    TooManyFiles(usize, usize),
+
+    /// A record path is empty, absolute, or contains `..` components.
+    InvalidRecordPath(PathBuf),
+
+    /// A record path is not valid UTF-8, which the path encoding requires.
+    NonUtf8Path(PathBuf),
+
+    /// The recorded totals do not match the records.
+    TotalsMismatch,
+
+    /// The inventory is keyed, but no HMAC key is provided.
+    KeyRequired,
+
+    /// The HMAC key is not the one the inventory is keyed with.
+    KeyMismatch,
+
+    /// An HMAC key is provided, but the inventory is not keyed.
+    NotKeyed,
+
+    /// Two paths differ only in case, so they cannot both exist on a
+    /// case-insensitive filesystem.
+    CaseCollision(PathBuf, PathBuf),
+
+    /// The filesystem of the inventory has too little free space for it
+    /// (estimated size, available space).
+    InsufficientSpace(u64, u64),
+
+    /// The inventory file cannot be parsed or is invalid (path, cause).
+    Unreadable(PathBuf, Box<dyn Error>),
 }
 
-impl Default for Configuration {
-    fn default() -> Self {
-        Configuration {
-            version: env!("CARGO_PKG_VERSION").to_string(),
-            skip_hidden: false,
-            hash_algorithms: BTreeSet::new(),
+impl Display for InventoryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match &self {
+            InventoryError::InvalidHashLength(path, algorithm) => {
+                write!(f, "Invalid {} hash value length: {:?}", algorithm, path)
+            }
+            InventoryError::NoSealAlgorithm => {
+                write!(f, "Cannot seal an inventory without hash algorithms")
+            }
+            InventoryError::SealMismatch => {
+                write!(f, "Inventory seal does not match its contents")
+            }
+            InventoryError::AlgorithmNotPresent(algorithm) => {
+                write!(f, "Hash algorithm not used by the inventory: {}", algorithm)
+            }
+            InventoryError::AlgorithmMismatch => {
+                write!(
+                    f,
+                    "Hash algorithms differ from the ones used by the inventory"
+                )
+            }
+            InventoryError::RecordExists(path) => {
+                write!(f, "Record already exists in the inventory: {:?}", path)
+            }
+            InventoryError::TooManyFiles(count, limit) => write!(
+                f,
+                "Too many files in the repository: found {} files, the limit is {}",
+                count, limit
+            ),
+            InventoryError::InvalidRecordPath(path) => {
+                write!(f, "Invalid record path: {:?}", path)
+            }
+            InventoryError::NonUtf8Path(path) => write!(
+                f,
+                "Path is not valid UTF-8: {:?}, build with --path-encoding=bytes to record it",
+                path
+            ),
+            InventoryError::TotalsMismatch => {
+                write!(f, "Inventory totals do not match its records")
+            }
+            InventoryError::KeyRequired => {
+                write!(f, "Inventory is keyed, the HMAC key is required")
+            }
+            InventoryError::KeyMismatch => {
+                write!(
+                    f,
+                    "HMAC key does not match the one the inventory is keyed with"
+                )
+            }
+            InventoryError::NotKeyed => {
+                write!(f, "Inventory is not keyed, but an HMAC key is provided")
+            }
+            InventoryError::CaseCollision(first, second) => {
+                write!(f, "Paths differ only in case: {:?} and {:?}", first, second)
+            }
+            InventoryError::InsufficientSpace(size, available) => write!(
+                f,
+                "The inventory needs about {} bytes, but only {} bytes are free on its filesystem",
+                size, available
+            ),
+            InventoryError::Unreadable(path, _) => {
+                write!(f, "Cannot read the inventory {:?}", path)
+            }
         }
     }
 }
 
-/// An inventory record.
-#[derive(Debug, Deserialize, Serialize)]
-struct Record {
-    /// Hashes of the file.
-    hashes: BTreeMap<HashAlgorithm, HashValue>,
-
-    /// Size of the file.
-    size: u64,
-}
-
-impl Record {
-    /// Creates a new inventory record.
-    fn new(size: u64, hashes: Vec<(HashAlgorithm, HashValue)>) -> Self {
-        Record {
-            hashes: hashes.into_iter().collect(),
-            size,
+impl Error for InventoryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self {
+            InventoryError::InvalidHashLength(_, _) => None,
+            InventoryError::NoSealAlgorithm => None,
+            InventoryError::SealMismatch => None,
+            InventoryError::AlgorithmNotPresent(_) => None,
+            InventoryError::AlgorithmMismatch => None,
+            InventoryError::RecordExists(_) => None,
+            InventoryError::TooManyFiles(_, _) => None,
+            InventoryError::InvalidRecordPath(_) => None,
+            InventoryError::NonUtf8Path(_) => None,
+            InventoryError::TotalsMismatch => None,
+            InventoryError::KeyRequired => None,
+            InventoryError::KeyMismatch => None,
+            InventoryError::NotKeyed => None,
+            InventoryError::CaseCollision(_, _) => None,
+            InventoryError::InsufficientSpace(_, _) => None,
+            InventoryError::Unreadable(_, err) => Some(err.as_ref()),
         }
     }
 }
 
-/// Inventory verification failure kind.
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
-pub enum FailureKind {
-    /// A file is present in the inventory but missing from the repository.
-    MissingFromRepository,
+/// Version of the inventory file structure, stored instead of the app version
+/// in reproducible inventories.
+pub const SCHEMA_VERSION: &str = "1";
 
-    /// A file is found in the repository but missing from the inventory.
-    MissingFromInventory,
+/// Prefix of the record keys holding the base64-encoded bytes of a path that
+/// is not valid UTF-8. Paths cannot contain NUL characters, so no plain key
+/// starts with it.
+pub const ENCODED_PATH_PREFIX: &str = "\0base64:";
 
-    /// Actual file size does not match the size recorded in the inventory.
-    SizeMismatch,
+/// Storage of the record paths that are not valid UTF-8.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathEncoding {
+    /// Fail to add the files with such paths.
+    Utf8,
 
-    /// Actual file hash value does not match the value recorded in the inventory.
-    HashMismatch,
-}
+    /// Replace the invalid sequences with U+FFFD, with a warning. The
+    /// original files cannot be found by the replaced paths.
+    Lossy,
 
-/// Inventory verification report.
-#[derive(Default)]
-pub struct Report {
-    /// Issues found during the verification and the corresponding file paths.
-    contents: HashMap<FailureKind, HashSet<PathBuf>>,
+    /// Store the path bytes base64-encoded under `ENCODED_PATH_PREFIX`, so
+    /// that the paths round-trip exactly. Only used on Unix; elsewhere, the
+    /// paths are stored lossily.
+    #[default]
+    Bytes,
 }
 
-impl Report {
-    /// Returns a new empty report.
-    fn new() -> Self {
-        Report::default()
-    }
+/// An error returned when the path encoding name cannot be parsed.
+#[derive(Debug)]
+pub struct ParsePathEncodingError();
 
-    /// Returns `true` if the report is empty, `false` otherwise.
-    pub fn is_empty(&self) -> bool {
-        self.contents.is_empty()
-    }
+impl FromStr for PathEncoding {
+    type Err = ParsePathEncodingError;
 
-    /// Returns a list of the discovered failure kinds.
-    pub fn failures(&self) -> Vec<FailureKind> {
-        self.contents.keys().copied().collect()
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf8" => Ok(PathEncoding::Utf8),
+            "lossy" => Ok(PathEncoding::Lossy),
+            "bytes" => Ok(PathEncoding::Bytes),
+            _ => Err(ParsePathEncodingError()),
+        }
     }
+}
 
-    /// Returns a list of files that caused the specific failure.
-    pub fn by_failure(&self, kind: FailureKind) -> Option<impl Iterator<Item = &Path>> {
-        self.contents
-            .get(&kind)
-            .map(|h| h.iter().map(|p| p.as_path()))
+impl Display for PathEncoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let name = match self {
+            PathEncoding::Utf8 => "utf8",
+            PathEncoding::Lossy => "lossy",
+            PathEncoding::Bytes => "bytes",
+        };
+        write!(f, "{}", name)
     }
+}
 
-    /// Records a failure in the report.
-    fn add_failure<P: AsRef<Path>>(&mut self, file: P, kind: FailureKind) {
-        self.contents
-            .entry(kind)
-            .or_default()
-            .insert(file.as_ref().to_path_buf());
+impl PathEncoding {
+    /// Checks if this is the default encoding, which is not serialized.
+    fn is_default(&self) -> bool {
+        *self == PathEncoding::default()
     }
 }
 
-/// Inventory structure.
+/// Inventory configuration.
 #[derive(Debug, Deserialize, Serialize)]
-pub struct Inventory {
-    /// Inventory configuration.
-    configuration: Configuration,
+pub struct Configuration {
+    /// Version of the app used to build the inventory, or the schema version
+    /// if the inventory is reproducible.
+    version: String,
 
-    /// File records.
-    records: BTreeMap<PathBuf, Record>,
-}
+    /// Skip hidden files.
+    skip_hidden: bool,
 
-impl Inventory {
-    /// Creates a new inventory with the provided configuration.
-    pub fn new(configuration: Configuration) -> Self {
-        Inventory {
-            records: BTreeMap::new(),
-            configuration,
-        }
-    }
+    /// Skip version control system metadata directories.
+    #[serde(default, skip_serializing_if = "is_false")]
+    skip_vcs: bool,
 
-    /// Builds an inventory for the provided repository directory.
-    pub fn build(configuration: Configuration, repository: &Path) -> Result<Self, Box<dyn Error>> {
-        let mut hasher = Hasher::new(configuration.hash_algorithms.iter().copied());
-        let mut inventory = Inventory::new(configuration);
+    /// Skip the entries matched by the `.inventorizeignore` files.
+    #[serde(default, skip_serializing_if = "is_false")]
+    ignore_files: bool,
 
-        let files = inventory.repo_files(repository)?;
+    /// Storage of the record paths that are not valid UTF-8.
+    #[serde(default, skip_serializing_if = "PathEncoding::is_default")]
+    path_encoding: PathEncoding,
 
-        // Add the discovered files to the inventory.
-        files
-            .into_iter()
-            .try_for_each(|p| inventory.add_file(repository, &p, &mut hasher))?;
+    /// Record empty directories.
+    #[serde(default, skip_serializing_if = "is_false")]
+    include_empty_dirs: bool,
 
-        Ok(inventory)
+    /// Hash algorithms to use, in the order of their specification.
+    #[serde(deserialize_with = "deserialize_algorithms")]
+    hash_algorithms: Vec<HashAlgorithm>,
+
+    /// Hash algorithms to use instead of the default ones for files with
+    /// specific extensions, keyed by the lowercase extension.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    extension_algorithms: BTreeMap<String, Vec<HashAlgorithm>>,
+
+    /// Hash algorithms to use instead of the default and the extension ones
+    /// for files whose paths match globs, in the order of their
+    /// specification.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    glob_algorithms: Vec<(String, Vec<HashAlgorithm>)>,
+
+    /// Descend into symbolically linked directories.
+    #[serde(default = "default_true")]
+    follow_symlink_dirs: bool,
+
+    /// Hash the targets of symbolically linked files. If disabled, the links
+    /// are recorded as such.
+    #[serde(default = "default_true")]
+    follow_symlink_files: bool,
+
+    /// Skip all symbolic links, overriding the follow settings.
+    #[serde(default, skip_serializing_if = "is_false")]
+    exclude_symlinks: bool,
+
+    /// Types of the directory entries to skip, other than symbolic links.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    excluded_types: BTreeSet<EntryType>,
+
+    /// Modification time in nanoseconds since the Unix epoch before which
+    /// files are skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    since_mtime: Option<u64>,
+
+    /// Name of the extended attribute to take the file hashes from instead of
+    /// computing them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trust_xattr: Option<String>,
+
+    /// Record the modification times of the files.
+    #[serde(default, skip_serializing_if = "is_false")]
+    record_mtime: bool,
+
+    /// Record the creation times of the files, where available.
+    #[serde(default, skip_serializing_if = "is_false")]
+    record_btime: bool,
+
+    /// Treat grown files whose recorded contents are unchanged as appended
+    /// to rather than modified.
+    #[serde(default, skip_serializing_if = "is_false")]
+    append_aware: bool,
+
+    /// Check value of the HMAC key the hashes are computed with, if the
+    /// inventory is keyed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hmac_key_check: Option<HashValue>,
+}
+
+/// Hash algorithm and message of the HMAC key check value.
+const KEY_CHECK_ALGORITHM: HashAlgorithm = HashAlgorithm::Sha3_256;
+const KEY_CHECK_MESSAGE: &[u8] = b"inventorize HMAC key check";
+
+/// Computes the check value of an HMAC key, which identifies the key without
+/// revealing it.
+fn key_check_value(key: &[u8]) -> HashValue {
+    let mut hasher = Hasher::new(std::iter::once(KEY_CHECK_ALGORITHM));
+    hasher.set_key(Some(key));
+    // Reading from a slice never fails.
+    let (_, value) = hasher.compute(KEY_CHECK_MESSAGE).unwrap().pop().unwrap();
+    value
+}
+
+/// Deserializes a list of hash algorithms, dropping the duplicates.
+///
+/// Older inventories stored the algorithms as a set, which is serialized as a
+/// sorted list, so they are read the same way.
+fn deserialize_algorithms<'de, D>(deserializer: D) -> Result<Vec<HashAlgorithm>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut algorithms = Vec::new();
+    for algorithm in Vec::<HashAlgorithm>::deserialize(deserializer)? {
+        if !algorithms.contains(&algorithm) {
+            algorithms.push(algorithm);
+        }
     }
 
-    /// Checks the repository and produces the verification report.
-    pub fn check(&self, repository: &Path, check_hashes: bool) -> Result<Report, Box<dyn Error>> {
-        let mut hasher = Hasher::new(self.configuration.hash_algorithms.iter().copied());
+    Ok(algorithms)
+}
 
-        // Build a set of repository file paths and a set of file paths recorded in the inventory.
-        let repository_files = self.repo_files(repository)?;
-        let inventory_files: BTreeSet<_> = self.records.keys().cloned().collect();
+/// Suffix of the keys of directories in the nested layout of the records.
+pub const NESTED_DIR_SUFFIX: &str = "/";
 
-        let mut report = Report::new();
+/// Deserializes the records in either the flat layout, keyed by the file
+/// paths, or the nested layout, where the records of the files in a directory
+/// are nested under the directory name followed by `NESTED_DIR_SUFFIX`.
+fn deserialize_records<'de, D>(deserializer: D) -> Result<BTreeMap<PathBuf, Record>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut records = BTreeMap::new();
+    RecordsVisitor {
+        prefix: PathBuf::new(),
+        records: &mut records,
+    }
+    .deserialize(deserializer)?;
 
-        // Find files present in the repository but missing from the inventory.
-        repository_files
-            .difference(&inventory_files)
-            .for_each(|p| report.add_failure(p, FailureKind::MissingFromInventory));
+    Ok(records)
+}
 
-        // Find files present in the inventory but missing from the repository.
-        inventory_files
-            .difference(&repository_files)
-            .for_each(|p| report.add_failure(p, FailureKind::MissingFromRepository));
+/// A visitor collecting the records of a directory in either layout into
+/// the flat map.
+struct RecordsVisitor<'a> {
+    /// Path of the directory relative to the repository.
+    prefix: PathBuf,
 
-        // Verify files one by one.
-        for file in inventory_files.intersection(&repository_files) {
-            debug!("Verifying file {:?}", file);
+    /// The collected records.
+    records: &'a mut BTreeMap<PathBuf, Record>,
+}
 
-            let rec = self.records.get(file).unwrap();
+impl<'de, 'a> Visitor<'de> for RecordsVisitor<'a> {
+    type Value = ();
 
-            // Produce the absolute path to the file.
-            let mut file_abs = repository.to_path_buf();
-            file_abs.push(file);
+    fn expecting(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "a map of records")
+    }
 
-            // Check size first. It does not make sense to check hashes if sizes
-            // don't match.
-            let attr = fs::metadata(&file_abs).or_else(|e| file_err!(&file_abs, e))?;
-            if attr.len() != rec.size {
-                report.add_failure(file, FailureKind::SizeMismatch);
-            } else if check_hashes {
-                let reader = BufReader::new(
-                    OpenOptions::new()
-                        .read(true)
-                        .open(&file_abs)
-                        .or_else(|e| file_err!(&file_abs, e))?,
-                );
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let RecordsVisitor { prefix, records } = self;
 
-                let hashes: BTreeMap<_, _> = hasher.compute(reader)?.into_iter().collect();
+        while let Some(key) = map.next_key::<String>()? {
+            // Encoded keys are never nested, and may end with a slash.
+            if let Some(encoded) = key.strip_prefix(ENCODED_PATH_PREFIX) {
+                let path = decoded_key(encoded).ok_or_else(|| {
+                    A::Error::custom(format!("invalid encoded path: {}", encoded))
+                })?;
+                let record = map.next_value()?;
+                insert_record(records, prefix.join(path), record)?;
+                continue;
+            }
 
-                if hashes != rec.hashes {
-                    report.add_failure(file, FailureKind::HashMismatch);
+            match key.strip_suffix(NESTED_DIR_SUFFIX) {
+                Some(dir) => map.next_value_seed(RecordsVisitor {
+                    prefix: prefix.join(dir),
+                    records: &mut *records,
+                })?,
+                None => {
+                    let record = map.next_value()?;
+                    insert_record(records, prefix.join(key), record)?;
                 }
             }
         }
 
-        Ok(report)
+        Ok(())
     }
+}
 
-    /// Updates the inventory by adding new files and removing missing files.
-    pub fn update(
-        &mut self,
-        repository: &Path,
-        remove_missing: bool,
-    ) -> Result<(), Box<dyn Error>> {
-        let mut hasher = Hasher::new(self.configuration.hash_algorithms.iter().copied());
+/// Adds a record to the collected ones.
+///
+/// Paths that differ only in repeated slashes or inner `.` components are
+/// equal, so a record replacing another one means that the inventory has
+/// colliding record paths.
+fn insert_record<E: DeError>(
+    records: &mut BTreeMap<PathBuf, Record>,
+    path: PathBuf,
+    record: Record,
+) -> Result<(), E> {
+    if records.contains_key(&path) {
+        return Err(E::custom(InventoryError::RecordExists(path)));
+    }
+    records.insert(path, record);
+    Ok(())
+}
 
-        // Build a set of repository file paths and a set of file paths recorded in the inventory.
-        let repository_files = self.repo_files(repository)?;
-        let inventory_files: BTreeSet<_> = self.records.keys().cloned().collect();
+impl<'de, 'a> DeserializeSeed<'de> for RecordsVisitor<'a> {
+    type Value = ();
 
-        // Discover files missing from the inventory and add them.
-        repository_files
-            .difference(&inventory_files)
-            .try_for_each(|p| self.add_file(repository, p, &mut hasher))?;
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
 
-        // If enabled, remove missing files from the inventory.
-        if remove_missing {
-            inventory_files.difference(&repository_files).for_each(|p| {
-                self.records.remove(p);
-            });
-        }
+/// Returns `true`; used as the default value of flags that were introduced
+/// after the inventory format was first released.
+fn default_true() -> bool {
+    true
+}
 
-        Ok(())
+/// Returns `true` if the flag is not set; used to omit flags that are unset
+/// by default from the inventory.
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl Configuration {
+    /// Returns an empty configuration.
+    pub fn new() -> Self {
+        Configuration::default()
     }
 
-    /// Returns a set of repository files.
-    fn repo_files<P>(&self, repository: P) -> IoResult<BTreeSet<PathBuf>>
-    where
-        P: AsRef<Path>,
-    {
-        self.repo_iter(repository)?.collect()
+    /// Returns a builder of a configuration.
+    pub fn builder() -> ConfigurationBuilder {
+        ConfigurationBuilder {
+            configuration: Configuration::new(),
+        }
     }
 
-    /// Returns an iterator over the repository files.
-    fn repo_iter<P>(&self, repository: P) -> IoResult<impl Iterator<Item = IoResult<PathBuf>>>
-    where
-        P: AsRef<Path>,
-    {
-        Ok(RepositoryIterator::new(
-            RelativePathIterator::new(repository)?,
-            &self.configuration,
-        ))
+    /// Returns the version of the app used to build the inventory.
+    pub fn version(&self) -> &str {
+        &self.version
     }
 
-    /// Produces a file record for the specified file and adds it to the inventory.
-    fn add_file<P: AsRef<Path>>(
-        &mut self,
-        repository: P,
-        rel_path: P,
-        hasher: &mut Hasher,
-    ) -> Result<(), Box<dyn Error>> {
-        debug!("Adding file {:?}", rel_path.as_ref());
+    /// Returns the `skip_hidden` mode.
+    pub fn skip_hidden(&self) -> bool {
+        self.skip_hidden
+    }
 
-        // Produce the absolute path to the file.
-        let mut abs_path = repository.as_ref().to_path_buf();
-        abs_path.push(&rel_path);
+    /// Returns the `skip_vcs` mode.
+    pub fn skip_vcs(&self) -> bool {
+        self.skip_vcs
+    }
 
-        let attr = abs_path.metadata().or_else(|e| file_err!(&abs_path, e))?;
+    /// Returns whether the `.inventorizeignore` files are applied.
+    pub fn ignore_files(&self) -> bool {
+        self.ignore_files
+    }
 
-        // Create a reader to compute the hash(es) of the file contents.
-        let reader = BufReader::new(
-            OpenOptions::new()
-                .read(true)
-                .open(&abs_path)
-                .or_else(|e| file_err!(&abs_path, e))?,
-        );
+    /// Returns the storage of the record paths that are not valid UTF-8.
+    pub fn path_encoding(&self) -> PathEncoding {
+        self.path_encoding
+    }
 
-        let hashes = hasher.compute(reader)?;
-        let rec = Record::new(attr.len(), hashes);
-        self.records.insert(rel_path.as_ref().to_path_buf(), rec);
+    /// Returns whether empty directories are recorded.
+    pub fn include_empty_dirs(&self) -> bool {
+        self.include_empty_dirs
+    }
 
-        Ok(())
+    /// Returns the hash algorithms, in the order of their specification.
+    pub fn hash_algorithms(&self) -> &[HashAlgorithm] {
+        &self.hash_algorithms
     }
-}
 
-/// An iterator over the repository file paths.
-///
-/// This iterator honors the inventory settings (e.g. filters out hidden files
-/// if needed).
-struct RepositoryIterator<I> {
-    iter: I,
-    skip_hidden: bool,
-}
+    /// Returns the hash algorithms used for files with specific extensions.
+    pub fn extension_algorithms(&self) -> &BTreeMap<String, Vec<HashAlgorithm>> {
+        &self.extension_algorithms
+    }
 
-impl<I: Iterator<Item = IoResult<PathBuf>>> RepositoryIterator<I> {
-    // Creates a new repository file iterator.
-    fn new(iter: I, config: &Configuration) -> Self {
-        RepositoryIterator {
-            skip_hidden: config.skip_hidden,
-            iter,
-        }
+    /// Returns the hash algorithms used for files with paths matching globs,
+    /// in the order of their specification.
+    pub fn glob_algorithms(&self) -> &[(String, Vec<HashAlgorithm>)] {
+        &self.glob_algorithms
     }
-}
 
-impl<I: Iterator<Item = IoResult<PathBuf>>> Iterator for RepositoryIterator<I> {
-    type Item = IoResult<PathBuf>;
+    /// Returns the hash algorithms used for the file with the given path,
+    /// relative to the repository: the ones of the last glob it matches, if
+    /// any, the ones of its extension, if any, or the default ones otherwise.
+    pub fn algorithms_for(&self, path: &Path) -> &[HashAlgorithm] {
+        if let Some((_, algorithms)) = self
+            .glob_algorithms
+            .iter()
+            .rev()
+            .find(|(glob, _)| ignore::glob_matches_path(glob, path))
+        {
+            return algorithms;
+        }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        for item in self.iter.by_ref() {
-            match item {
-                Ok(path) => {
-                    if self.skip_hidden && util::is_hidden(&path) {
-                        continue;
-                    } else {
-                        return Some(Ok(path));
-                    }
-                }
-                Err(e) => return Some(Err(e)),
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(|e| self.extension_algorithms.get(&e.to_lowercase()))
+            .unwrap_or(&self.hash_algorithms)
+    }
+
+    /// Returns all hash algorithms used by the inventory, the default ones
+    /// first.
+    pub fn all_algorithms(&self) -> Vec<HashAlgorithm> {
+        let mut algorithms = self.hash_algorithms.clone();
+        let specific = self
+            .extension_algorithms
+            .values()
+            .chain(self.glob_algorithms.iter().map(|(_, a)| a));
+        for algorithm in specific.flatten() {
+            if !algorithms.contains(algorithm) {
+                algorithms.push(*algorithm);
             }
         }
+        algorithms
+    }
+
+    /// Returns whether symbolically linked directories are descended into.
+    pub fn follow_symlink_dirs(&self) -> bool {
+        self.follow_symlink_dirs
+    }
 
-        None
+    /// Returns whether symbolically linked files are hashed through.
+    pub fn follow_symlink_files(&self) -> bool {
+        self.follow_symlink_files
+    }
+
+    /// Returns whether all symbolic links are skipped.
+    pub fn exclude_symlinks(&self) -> bool {
+        self.exclude_symlinks
+    }
+
+    /// Returns the types of the directory entries to skip, including
+    /// symbolic links if all of them are skipped.
+    pub fn excluded_types(&self) -> Vec<EntryType> {
+        let mut types: Vec<_> = self.excluded_types.iter().copied().collect();
+        if self.exclude_symlinks {
+            types.push(EntryType::Symlink);
+        }
+        types
+    }
+
+    /// Returns the modification time in nanoseconds since the Unix epoch
+    /// before which files are skipped, if any.
+    pub fn since_mtime(&self) -> Option<u64> {
+        self.since_mtime
+    }
+
+    /// Returns the name of the extended attribute to take the file hashes
+    /// from, if any.
+    pub fn trust_xattr(&self) -> Option<&str> {
+        self.trust_xattr.as_deref()
+    }
+
+    /// Returns whether the modification times of the files are recorded.
+    pub fn record_mtime(&self) -> bool {
+        self.record_mtime
+    }
+
+    /// Returns whether the creation times of the files are recorded.
+    pub fn record_btime(&self) -> bool {
+        self.record_btime
+    }
+
+    /// Returns whether grown files may be appended to.
+    pub fn append_aware(&self) -> bool {
+        self.append_aware
+    }
+
+    /// Returns whether the hashes are HMAC values computed with a key.
+    pub fn is_keyed(&self) -> bool {
+        self.hmac_key_check.is_some()
+    }
+
+    /// Sets the `skip_hidden` mode.
+    pub fn set_skip_hidden(&mut self, skip_hidden: bool) -> &mut Self {
+        self.skip_hidden = skip_hidden;
+        self
+    }
+
+    /// Sets the `skip_vcs` mode.
+    ///
+    /// In this mode, the version control system metadata directories listed
+    /// in `util::VCS_DIRS` are not descended into.
+    pub fn set_skip_vcs(&mut self, skip_vcs: bool) -> &mut Self {
+        self.skip_vcs = skip_vcs;
+        self
+    }
+
+    /// Sets whether the `.inventorizeignore` files are applied.
+    ///
+    /// If enabled, the patterns of the ignore file of every directory apply
+    /// to its subtree, as described in `iterdir::DirectoryIterator`.
+    pub fn set_ignore_files(&mut self, ignore_files: bool) -> &mut Self {
+        self.ignore_files = ignore_files;
+        self
+    }
+
+    /// Sets the storage of the record paths that are not valid UTF-8.
+    pub fn set_path_encoding(&mut self, encoding: PathEncoding) -> &mut Self {
+        self.path_encoding = encoding;
+        self
+    }
+
+    /// Sets whether empty directories are recorded.
+    ///
+    /// Empty directories are recorded without hashes, so that a missing
+    /// expected directory can be detected by the verification.
+    pub fn set_include_empty_dirs(&mut self, include: bool) -> &mut Self {
+        self.include_empty_dirs = include;
+        self
+    }
+
+    /// Sets the hash algorithms to use.
+    ///
+    /// The order of the algorithms is preserved; duplicates are ignored.
+    pub fn set_hash_algorithms(&mut self, algorithms: &[HashAlgorithm]) -> &mut Self {
+        self.hash_algorithms.clear();
+        for algorithm in algorithms {
+            if !self.hash_algorithms.contains(algorithm) {
+                self.hash_algorithms.push(*algorithm);
+            }
+        }
+        self
+    }
+
+    /// Sets the hash algorithms to use instead of the default ones for files
+    /// with the given extension.
+    ///
+    /// The extension is specified without the leading dot and matched
+    /// case-insensitively. The order of the algorithms is preserved;
+    /// duplicates are ignored. An empty list of algorithms removes the
+    /// extension-specific setting.
+    pub fn set_extension_algorithms(
+        &mut self,
+        extension: &str,
+        algorithms: &[HashAlgorithm],
+    ) -> &mut Self {
+        let extension = extension.to_lowercase();
+        if algorithms.is_empty() {
+            self.extension_algorithms.remove(&extension);
+            return self;
+        }
+
+        let entry = self.extension_algorithms.entry(extension).or_default();
+        entry.clear();
+        for algorithm in algorithms {
+            if !entry.contains(algorithm) {
+                entry.push(*algorithm);
+            }
+        }
+        self
+    }
+
+    /// Sets the hash algorithms to use instead of the default and the
+    /// extension ones for files whose paths match the glob.
+    ///
+    /// Globs with a slash are matched against the whole path relative to the
+    /// repository, and others against the file name, as in the ignore files.
+    /// Of several matching globs, the one set last applies; setting a glob
+    /// again moves it to the end. The order of the algorithms is preserved;
+    /// duplicates are ignored. An empty list of algorithms removes the glob.
+    pub fn set_glob_algorithms(&mut self, glob: &str, algorithms: &[HashAlgorithm]) -> &mut Self {
+        self.glob_algorithms.retain(|(g, _)| g != glob);
+        if algorithms.is_empty() {
+            return self;
+        }
+
+        let mut unique = Vec::new();
+        for algorithm in algorithms {
+            if !unique.contains(algorithm) {
+                unique.push(*algorithm);
+            }
+        }
+        self.glob_algorithms.push((glob.to_string(), unique));
+        self
+    }
+
+    /// Sets whether symbolically linked directories are descended into.
+    pub fn set_follow_symlink_dirs(&mut self, follow: bool) -> &mut Self {
+        self.follow_symlink_dirs = follow;
+        self
+    }
+
+    /// Sets whether symbolically linked files are hashed through.
+    pub fn set_follow_symlink_files(&mut self, follow: bool) -> &mut Self {
+        self.follow_symlink_files = follow;
+        self
+    }
+
+    /// Sets whether all symbolic links are skipped.
+    ///
+    /// Links to both files and directories are neither recorded nor followed,
+    /// whatever the follow settings are, as if they were not in the
+    /// repository.
+    pub fn set_exclude_symlinks(&mut self, exclude: bool) -> &mut Self {
+        self.exclude_symlinks = exclude;
+        self
+    }
+
+    /// Sets the types of the directory entries to skip.
+    ///
+    /// Entries of these types are neither recorded nor descended into, as if
+    /// they were not in the repository. Excluding symbolic links is the same
+    /// as `set_exclude_symlinks(true)`. Types that do not exist on the
+    /// platform are never matched.
+    pub fn set_excluded_types(&mut self, types: &[EntryType]) -> &mut Self {
+        self.excluded_types = types.iter().copied().collect();
+        if self.excluded_types.remove(&EntryType::Symlink) {
+            self.exclude_symlinks = true;
+        }
+        self
+    }
+
+    /// Sets the modification time in nanoseconds since the Unix epoch before
+    /// which files are skipped.
+    ///
+    /// Files modified earlier are neither recorded nor reported, as if they
+    /// were not in the repository. The cutoff is absolute, so that the same
+    /// files are skipped when the inventory is verified or updated later.
+    pub fn set_since_mtime(&mut self, since: Option<u64>) -> &mut Self {
+        self.since_mtime = since;
+        self
+    }
+
+    /// Checks if a file with the given modification time in nanoseconds
+    /// since the Unix epoch is skipped by the modification time cutoff.
+    ///
+    /// Files whose modification time is unknown are never skipped.
+    fn is_before_cutoff(&self, mtime: Option<u64>) -> bool {
+        matches!((self.since_mtime, mtime), (Some(since), Some(mtime)) if mtime < since)
+    }
+
+    /// Sets the name of the extended attribute to take the file hashes from.
+    ///
+    /// If the attribute of a file holds a valid hash value, either in the
+    /// hexadecimal or in the raw form, it is recorded instead of computing the
+    /// hash. Otherwise, the hash is computed as usual. Since an attribute can
+    /// only hold a single hash value, it is only used if the inventory has a
+    /// single hash algorithm.
+    pub fn set_trust_xattr(&mut self, name: Option<String>) -> &mut Self {
+        self.trust_xattr = name;
+        self
+    }
+
+    /// Sets whether the modification times of the files are recorded.
+    ///
+    /// The modification times are not verified, but allow verifying only the
+    /// files that have been modified since they were recorded.
+    pub fn set_record_mtime(&mut self, record_mtime: bool) -> &mut Self {
+        self.record_mtime = record_mtime;
+        self
+    }
+
+    /// Sets whether the creation (birth) times of the files are recorded.
+    ///
+    /// A file whose creation time differs from the recorded one has been
+    /// deleted and recreated, which fails the verification even if its
+    /// contents match. Files whose creation time is not available on the
+    /// platform or filesystem are recorded without it and never compared.
+    pub fn set_record_btime(&mut self, record_btime: bool) -> &mut Self {
+        self.record_btime = record_btime;
+        self
+    }
+
+    /// Sets the `append_aware` mode.
+    ///
+    /// In this mode, a file that has grown is checked to still start with
+    /// the recorded contents. If it does, verification reports it as
+    /// appended to rather than failing, and `update()` records its new
+    /// contents.
+    pub fn set_append_aware(&mut self, append_aware: bool) -> &mut Self {
+        self.append_aware = append_aware;
+        self
+    }
+
+    /// Sets the HMAC key to compute the hashes with, or `None` to compute the
+    /// bare digests.
+    ///
+    /// The key itself is not stored, only a check value that allows detecting
+    /// a wrong key. Hashing with a key and trusting the hashes from extended
+    /// attributes (which are bare digests) are mutually exclusive, so the
+    /// attributes of a keyed inventory are ignored.
+    pub fn set_hmac_key(&mut self, key: Option<&[u8]>) -> &mut Self {
+        self.hmac_key_check = key.map(key_check_value);
+        self
+    }
+
+    /// Checks that the HMAC key provided for an operation matches the
+    /// inventory.
+    fn check_key(&self, key: Option<&[u8]>) -> Result<(), InventoryError> {
+        match (&self.hmac_key_check, key) {
+            (None, None) => Ok(()),
+            (None, Some(_)) => Err(InventoryError::NotKeyed),
+            (Some(_), None) => Err(InventoryError::KeyRequired),
+            (Some(check), Some(key)) if *check == key_check_value(key) => Ok(()),
+            (Some(_), Some(_)) => Err(InventoryError::KeyMismatch),
+        }
+    }
+
+    /// Sets whether the inventory is reproducible.
+    ///
+    /// All other inventory contents only depend on the repository, so pinning
+    /// the stored version to the schema version makes builds of the same
+    /// repository byte-identical across app releases.
+    pub fn set_reproducible(&mut self, reproducible: bool) -> &mut Self {
+        self.version = if reproducible {
+            SCHEMA_VERSION.to_string()
+        } else {
+            env!("CARGO_PKG_VERSION").to_string()
+        };
+        self
+    }
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            skip_hidden: false,
+            skip_vcs: false,
+            ignore_files: false,
+            path_encoding: PathEncoding::default(),
+            include_empty_dirs: false,
+            hash_algorithms: Vec::new(),
+            extension_algorithms: BTreeMap::new(),
+            glob_algorithms: Vec::new(),
+            follow_symlink_dirs: true,
+            follow_symlink_files: true,
+            exclude_symlinks: false,
+            excluded_types: BTreeSet::new(),
+            since_mtime: None,
+            trust_xattr: None,
+            record_mtime: false,
+            record_btime: false,
+            append_aware: false,
+            hmac_key_check: None,
+        }
+    }
+}
+
+/// A builder of inventory configurations.
+///
+/// The builder methods go through the same validation as the `Configuration`
+/// setters.
+#[derive(Debug)]
+pub struct ConfigurationBuilder {
+    /// The configuration being built.
+    configuration: Configuration,
+}
+
+impl ConfigurationBuilder {
+    /// Sets the `skip_hidden` mode.
+    pub fn skip_hidden(mut self, skip_hidden: bool) -> Self {
+        self.configuration.set_skip_hidden(skip_hidden);
+        self
+    }
+
+    /// Sets the `skip_vcs` mode.
+    pub fn skip_vcs(mut self, skip_vcs: bool) -> Self {
+        self.configuration.set_skip_vcs(skip_vcs);
+        self
+    }
+
+    /// Sets whether the `.inventorizeignore` files are applied.
+    pub fn ignore_files(mut self, ignore_files: bool) -> Self {
+        self.configuration.set_ignore_files(ignore_files);
+        self
+    }
+
+    /// Sets the storage of the record paths that are not valid UTF-8.
+    pub fn path_encoding(mut self, encoding: PathEncoding) -> Self {
+        self.configuration.set_path_encoding(encoding);
+        self
+    }
+
+    /// Sets whether empty directories are recorded.
+    pub fn include_empty_dirs(mut self, include: bool) -> Self {
+        self.configuration.set_include_empty_dirs(include);
+        self
+    }
+
+    /// Sets the hash algorithms to use.
+    ///
+    /// The order of the algorithms is preserved; duplicates are ignored.
+    pub fn hash_algorithms<I>(mut self, algorithms: I) -> Self
+    where
+        I: IntoIterator<Item = HashAlgorithm>,
+    {
+        let algorithms: Vec<_> = algorithms.into_iter().collect();
+        self.configuration.set_hash_algorithms(&algorithms);
+        self
+    }
+
+    /// Sets the hash algorithms to use instead of the default ones for files
+    /// with specific extensions.
+    pub fn extension_algorithms<I>(mut self, rules: I) -> Self
+    where
+        I: IntoIterator<Item = (String, Vec<HashAlgorithm>)>,
+    {
+        for (extension, algorithms) in rules {
+            self.configuration
+                .set_extension_algorithms(&extension, &algorithms);
+        }
+        self
+    }
+
+    /// Sets the hash algorithms to use instead of the default and the
+    /// extension ones for files whose paths match globs.
+    pub fn glob_algorithms<I>(mut self, rules: I) -> Self
+    where
+        I: IntoIterator<Item = (String, Vec<HashAlgorithm>)>,
+    {
+        for (glob, algorithms) in rules {
+            self.configuration.set_glob_algorithms(&glob, &algorithms);
+        }
+        self
+    }
+
+    /// Sets whether symbolically linked directories are descended into.
+    pub fn follow_symlink_dirs(mut self, follow: bool) -> Self {
+        self.configuration.set_follow_symlink_dirs(follow);
+        self
+    }
+
+    /// Sets whether symbolically linked files are hashed through.
+    pub fn follow_symlink_files(mut self, follow: bool) -> Self {
+        self.configuration.set_follow_symlink_files(follow);
+        self
+    }
+
+    /// Sets whether all symbolic links are skipped.
+    pub fn exclude_symlinks(mut self, exclude: bool) -> Self {
+        self.configuration.set_exclude_symlinks(exclude);
+        self
+    }
+
+    /// Sets the types of the directory entries to skip.
+    pub fn excluded_types(mut self, types: &[EntryType]) -> Self {
+        self.configuration.set_excluded_types(types);
+        self
+    }
+
+    /// Sets the modification time before which files are skipped.
+    pub fn since_mtime(mut self, since: Option<u64>) -> Self {
+        self.configuration.set_since_mtime(since);
+        self
+    }
+
+    /// Sets the name of the extended attribute to take the file hashes from.
+    pub fn trust_xattr(mut self, name: Option<String>) -> Self {
+        self.configuration.set_trust_xattr(name);
+        self
+    }
+
+    /// Sets whether the modification times of the files are recorded.
+    pub fn record_mtime(mut self, record_mtime: bool) -> Self {
+        self.configuration.set_record_mtime(record_mtime);
+        self
+    }
+
+    /// Sets whether the creation times of the files are recorded.
+    pub fn record_btime(mut self, record_btime: bool) -> Self {
+        self.configuration.set_record_btime(record_btime);
+        self
+    }
+
+    /// Sets the `append_aware` mode.
+    pub fn append_aware(mut self, append_aware: bool) -> Self {
+        self.configuration.set_append_aware(append_aware);
+        self
+    }
+
+    /// Sets the HMAC key to compute the hashes with.
+    pub fn hmac_key(mut self, key: Option<&[u8]>) -> Self {
+        self.configuration.set_hmac_key(key);
+        self
+    }
+
+    /// Sets whether the inventory is reproducible.
+    pub fn reproducible(mut self, reproducible: bool) -> Self {
+        self.configuration.set_reproducible(reproducible);
+        self
+    }
+
+    /// Returns the built configuration.
+    pub fn build(self) -> Configuration {
+        self.configuration
+    }
+}
+
+/// Inventory build options.
+#[derive(Debug, Default)]
+pub struct BuildOptions {
+    /// Abort the build if the repository has more files than this.
+    max_files: Option<usize>,
+
+    /// Handling of per-file I/O errors.
+    error_policy: ErrorPolicy,
+
+    /// Maximum hashing throughput in bytes per second.
+    rate_limit: Option<u64>,
+
+    /// Size of the hasher read buffer, if not the default.
+    buffer_size: Option<usize>,
+
+    /// Number of leading components to strip from the repository paths.
+    strip_components: usize,
+
+    /// Number of added files after which the progress is checkpointed.
+    checkpoint_every: Option<usize>,
+
+    /// HMAC key to compute the hashes with.
+    hmac_key: Option<Box<[u8]>>,
+
+    /// Repository paths to leave out of the inventory.
+    excluded_paths: Vec<PathBuf>,
+
+    /// Fail on paths that differ only in case instead of warning.
+    strict_case: bool,
+
+    /// Path the inventory is written to, whose filesystem is checked for
+    /// enough free space before hashing.
+    output_path: Option<PathBuf>,
+
+    /// Fail on too little free space for the inventory instead of warning.
+    strict_space: bool,
+}
+
+impl BuildOptions {
+    /// Returns the default build options.
+    pub fn new() -> Self {
+        BuildOptions::default()
+    }
+
+    /// Sets the HMAC key to compute the hashes with.
+    ///
+    /// The key must match the one the inventory is keyed with, if any.
+    pub fn set_hmac_key(&mut self, key: Option<&[u8]>) -> &mut Self {
+        self.hmac_key = key.map(Box::from);
+        self
+    }
+
+    /// Sets the maximum number of files in the repository.
+    ///
+    /// The build is aborted as soon as the directory walk discovers more
+    /// files, before any of them are hashed.
+    pub fn set_max_files(&mut self, max_files: Option<usize>) -> &mut Self {
+        self.max_files = max_files;
+        self
+    }
+
+    /// Sets the handling of per-file I/O errors.
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) -> &mut Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Sets the maximum hashing throughput in bytes per second.
+    pub fn set_rate_limit(&mut self, bytes_per_second: Option<u64>) -> &mut Self {
+        self.rate_limit = bytes_per_second;
+        self
+    }
+
+    /// Sets the size of the buffer the files are read into when hashing.
+    ///
+    /// Every hasher, including the one of each verification thread,
+    /// allocates its buffer once and reuses it for all files.
+    pub fn set_buffer_size(&mut self, size: Option<usize>) -> &mut Self {
+        self.buffer_size = size;
+        self
+    }
+
+    /// Sets the number of leading components to strip from the repository
+    /// paths when matching them against the records.
+    ///
+    /// Only used by `update()`, see `CheckOptions::set_strip_components()`.
+    pub fn set_strip_components(&mut self, n: usize) -> &mut Self {
+        self.strip_components = n;
+        self
+    }
+
+    /// Sets the number of added files after which the checkpoint callback is
+    /// called with the inventory.
+    ///
+    /// Only used by `update()`.
+    pub fn set_checkpoint_every(&mut self, n: Option<usize>) -> &mut Self {
+        self.checkpoint_every = n;
+        self
+    }
+
+    /// Sets the paths of the files, relative to the repository, that are
+    /// left out of the repository walk, e.g. the inventory itself if it is
+    /// located inside the repository.
+    pub fn set_excluded_paths(&mut self, paths: &[PathBuf]) -> &mut Self {
+        self.excluded_paths = paths.to_vec();
+        self
+    }
+
+    /// Sets whether paths that differ only in case fail the build.
+    ///
+    /// Such paths are always detected before any file is hashed, since their
+    /// records cannot both be verified on a case-insensitive filesystem. By
+    /// default, they are only warned about.
+    pub fn set_strict_case(&mut self, strict: bool) -> &mut Self {
+        self.strict_case = strict;
+        self
+    }
+
+    /// Sets the path the inventory is written to.
+    ///
+    /// If set, the free space of its filesystem is compared with an estimate
+    /// of the inventory size once the files are discovered, before any of
+    /// them are hashed, so that a nearly full disk is detected early.
+    pub fn set_output_path(&mut self, path: Option<&Path>) -> &mut Self {
+        self.output_path = path.map(Path::to_path_buf);
+        self
+    }
+
+    /// Sets whether too little free space for the inventory fails the build
+    /// or update. By default, it is only warned about.
+    pub fn set_strict_space(&mut self, strict: bool) -> &mut Self {
+        self.strict_space = strict;
+        self
+    }
+}
+
+/// A callback persisting a complete inventory while it is being updated.
+pub type Checkpoint<'a> = dyn FnMut(&Inventory) -> Result<(), Box<dyn Error>> + 'a;
+
+/// Inventory verification options.
+#[derive(Debug)]
+pub struct CheckOptions {
+    /// Compare file hashes in addition to sizes.
+    check_hashes: bool,
+
+    /// Compare file sizes before the hashes.
+    check_sizes: bool,
+
+    /// Do not compare hashes of files larger than this size.
+    skip_hash_larger_than: Option<u64>,
+
+    /// Report hash mismatches of files smaller than this size as notices.
+    ignore_hash_mismatch_under: Option<u64>,
+
+    /// Verify files in the order of their inode numbers.
+    optimize_io: bool,
+
+    /// Hash algorithms to compare; all recorded algorithms if empty.
+    hash_algorithms: BTreeSet<HashAlgorithm>,
+
+    /// Handling of per-file I/O errors.
+    error_policy: ErrorPolicy,
+
+    /// Maximum hashing throughput in bytes per second.
+    rate_limit: Option<u64>,
+
+    /// Size of the hasher read buffer, if not the default.
+    buffer_size: Option<usize>,
+
+    /// Number of leading components to strip from the repository paths.
+    strip_components: usize,
+
+    /// Merge the sorted repository walk with the records in a single pass.
+    low_memory: bool,
+
+    /// Record the files that passed the verification in the report.
+    track_passed: bool,
+
+    /// Only compare the hashes of files whose modification time changed.
+    verify_changed: bool,
+
+    /// HMAC key to compute the hashes with.
+    hmac_key: Option<Box<[u8]>>,
+
+    /// Number of threads to verify the files on.
+    threads: usize,
+
+    /// Repository paths to leave out of the verification.
+    excluded_paths: Vec<PathBuf>,
+
+    /// Record paths to verify instead of the whole repository.
+    only_paths: Option<BTreeSet<PathBuf>>,
+
+    /// Report files missing from the repository as not restored instead of
+    /// failing.
+    partial: bool,
+}
+
+impl CheckOptions {
+    /// Returns the default verification options.
+    pub fn new() -> Self {
+        CheckOptions::default()
+    }
+
+    /// Sets whether file hashes should be compared.
+    pub fn set_check_hashes(&mut self, check_hashes: bool) -> &mut Self {
+        self.check_hashes = check_hashes;
+        self
+    }
+
+    /// Sets whether file sizes should be compared.
+    ///
+    /// By default, files whose size differs from the recorded one fail
+    /// without being hashed. Otherwise, all files are hashed, and only hash
+    /// mismatches are reported, which suits stores whose hashes are
+    /// authoritative while the recorded sizes may be stale. Files grown since
+    /// recorded are then not told apart as appended to either.
+    pub fn set_check_sizes(&mut self, check_sizes: bool) -> &mut Self {
+        self.check_sizes = check_sizes;
+        self
+    }
+
+    /// Sets the size above which file hashes are not compared.
+    pub fn set_skip_hash_larger_than(&mut self, size: Option<u64>) -> &mut Self {
+        self.skip_hash_larger_than = size;
+        self
+    }
+
+    /// Sets the size below which hash mismatches are reported as notices
+    /// rather than failures.
+    pub fn set_ignore_hash_mismatch_under(&mut self, size: Option<u64>) -> &mut Self {
+        self.ignore_hash_mismatch_under = size;
+        self
+    }
+
+    /// Sets whether files are verified in the order of their inode numbers.
+    ///
+    /// On rotational disks, this approximates the on-disk order of the files
+    /// and reduces seeking. Has no effect on platforms that do not expose
+    /// inode numbers.
+    pub fn set_optimize_io(&mut self, optimize_io: bool) -> &mut Self {
+        self.optimize_io = optimize_io;
+        self
+    }
+
+    /// Sets the hash algorithms to compare.
+    ///
+    /// The algorithms must be recorded in the inventory. If the list is
+    /// empty, all recorded algorithms are compared.
+    pub fn set_hash_algorithms(&mut self, algorithms: &[HashAlgorithm]) -> &mut Self {
+        self.hash_algorithms.clear();
+        self.hash_algorithms.extend(algorithms.iter());
+        self
+    }
+
+    /// Sets the handling of per-file I/O errors.
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) -> &mut Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Sets the maximum hashing throughput in bytes per second.
+    pub fn set_rate_limit(&mut self, bytes_per_second: Option<u64>) -> &mut Self {
+        self.rate_limit = bytes_per_second;
+        self
+    }
+
+    /// Sets the size of the buffer the files are read into when hashing.
+    ///
+    /// Every hasher, including the one of each verification thread,
+    /// allocates its buffer once and reuses it for all files.
+    pub fn set_buffer_size(&mut self, size: Option<usize>) -> &mut Self {
+        self.buffer_size = size;
+        self
+    }
+
+    /// Sets the number of leading components to strip from the repository
+    /// paths when matching them against the records.
+    ///
+    /// This allows verifying a tree that has been relocated under wrapper
+    /// directories, e.g. by restoring an archive. Files with too few
+    /// components are reported as missing from the inventory.
+    pub fn set_strip_components(&mut self, n: usize) -> &mut Self {
+        self.strip_components = n;
+        self
+    }
+
+    /// Sets whether the repository is checked in a single pass over its
+    /// sorted walk and the records, without collecting the repository paths.
+    ///
+    /// This bounds the memory used beyond the inventory itself, at the cost of
+    /// sorting the entries of every directory. Since stripping components, the
+    /// inode order and the path selection all require the full set of paths,
+    /// any of them takes precedence over this setting. The single pass is
+    /// always made on one thread.
+    pub fn set_low_memory(&mut self, low_memory: bool) -> &mut Self {
+        self.low_memory = low_memory;
+        self
+    }
+
+    /// Sets whether the files that passed the verification are recorded in
+    /// the report, which takes memory proportional to their number.
+    ///
+    /// A file passes if it caused neither failures nor notices.
+    pub fn set_track_passed(&mut self, track_passed: bool) -> &mut Self {
+        self.track_passed = track_passed;
+        self
+    }
+
+    /// Sets whether only the hashes of files whose modification time differs
+    /// from the recorded one are compared.
+    ///
+    /// The hashes of the other files are trusted, while their sizes are still
+    /// compared. Files recorded without a modification time are always
+    /// hashed.
+    pub fn set_verify_changed(&mut self, verify_changed: bool) -> &mut Self {
+        self.verify_changed = verify_changed;
+        self
+    }
+
+    /// Sets the HMAC key to compute the hashes with.
+    ///
+    /// The key must match the one the inventory is keyed with, if any.
+    pub fn set_hmac_key(&mut self, key: Option<&[u8]>) -> &mut Self {
+        self.hmac_key = key.map(Box::from);
+        self
+    }
+
+    /// Sets the number of threads to verify the files of the repository on,
+    /// each with its own hasher.
+    ///
+    /// The rate limit, if any, is shared between the threads. Archives,
+    /// listings and the single-pass check are always verified on one thread.
+    pub fn set_threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Sets the paths of the files, relative to the repository, that are
+    /// left out of the repository walk, see
+    /// `BuildOptions::set_excluded_paths()`.
+    pub fn set_excluded_paths(&mut self, paths: &[PathBuf]) -> &mut Self {
+        self.excluded_paths = paths.to_vec();
+        self
+    }
+
+    /// Sets the record paths to verify, if only some of the records should
+    /// be.
+    ///
+    /// Only the selected records are looked up in the repository, so other
+    /// files are reported neither as missing nor as added, and selected paths
+    /// without records are ignored. The repository is not walked, unless
+    /// components are stripped, in which case the walk finds the repository
+    /// paths of the selected records. The low memory mode does not apply.
+    /// Only used by `check()`.
+    pub fn set_only_paths(&mut self, paths: Option<BTreeSet<PathBuf>>) -> &mut Self {
+        self.only_paths = paths;
+        self
+    }
+
+    /// Sets whether the repository may be a partial restore.
+    ///
+    /// Files missing from a partial repository are reported as notices
+    /// rather than failures, while the files that are present are verified
+    /// as usual. Missing directories still fail.
+    pub fn set_partial(&mut self, partial: bool) -> &mut Self {
+        self.partial = partial;
+        self
+    }
+
+    /// Returns `true` if the hash algorithm is selected for comparison.
+    fn selects(&self, algorithm: HashAlgorithm) -> bool {
+        self.hash_algorithms.is_empty() || self.hash_algorithms.contains(&algorithm)
+    }
+
+    /// Records a verified file as passed in the report, if enabled and the
+    /// file caused no failures or notices.
+    fn report_passed(&self, report: &mut Report, file: &Path) {
+        if self.track_passed && !report.has_issues(file) {
+            report.passed.insert(file.to_path_buf());
+        }
+    }
+
+    /// Records a hash mismatch of a file with the given size in the report,
+    /// as a notice if the file is smaller than the threshold.
+    fn report_hash_mismatch<P: AsRef<Path>>(&self, report: &mut Report, file: P, size: u64) {
+        if matches!(self.ignore_hash_mismatch_under, Some(limit) if size < limit) {
+            report.add_notice(file, NoticeKind::HashMismatchIgnored);
+        } else {
+            report.add_failure(file, FailureKind::HashMismatch);
+        }
+    }
+
+    /// Records a file missing from the repository in the report, as a notice
+    /// if the repository may be partial.
+    ///
+    /// Returns `true` if the file is reported as a failure.
+    fn report_missing<P: AsRef<Path>>(&self, report: &mut Report, file: P) -> bool {
+        if self.partial {
+            report.add_notice(file, NoticeKind::NotRestored);
+            false
+        } else {
+            report.add_failure(file, FailureKind::MissingFromRepository);
+            true
+        }
+    }
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        CheckOptions {
+            check_hashes: true,
+            check_sizes: true,
+            skip_hash_larger_than: None,
+            ignore_hash_mismatch_under: None,
+            optimize_io: false,
+            hash_algorithms: BTreeSet::new(),
+            error_policy: ErrorPolicy::default(),
+            rate_limit: None,
+            buffer_size: None,
+            strip_components: 0,
+            low_memory: false,
+            track_passed: false,
+            verify_changed: false,
+            hmac_key: None,
+            threads: 1,
+            excluded_paths: Vec::new(),
+            only_paths: None,
+            partial: false,
+        }
+    }
+}
+
+/// An inventory record.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Record {
+    /// Hashes of the file.
+    hashes: BTreeMap<HashAlgorithm, HashValue>,
+
+    /// Size of the file.
+    size: u64,
+
+    /// Target of the symbolic link, if the file is a link that is not
+    /// followed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    symlink: Option<PathBuf>,
+
+    /// The hashes have been taken from an extended attribute rather than
+    /// computed.
+    #[serde(default, skip_serializing_if = "is_false")]
+    from_xattr: bool,
+
+    /// The record is an empty directory.
+    #[serde(default, skip_serializing_if = "is_false")]
+    directory: bool,
+
+    /// Modification time of the file in nanoseconds since the Unix epoch, if
+    /// recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mtime: Option<u64>,
+
+    /// Creation time of the file in nanoseconds since the Unix epoch, if
+    /// recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    btime: Option<u64>,
+}
+
+impl Record {
+    /// Returns the recorded hashes of the file.
+    pub fn hashes(&self) -> &BTreeMap<HashAlgorithm, HashValue> {
+        &self.hashes
+    }
+
+    /// Returns the hash of the strongest recorded algorithm, which
+    /// represents the file contents wherever a single hash is needed.
+    pub fn primary_hash(&self) -> Option<(HashAlgorithm, &HashValue)> {
+        self.hashes
+            .iter()
+            .max_by_key(|(a, _)| a.strength())
+            .map(|(a, v)| (*a, v))
+    }
+
+    /// Returns the size of the file.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the target of the symbolic link, if the record is a link that
+    /// is not followed.
+    pub fn symlink(&self) -> Option<&Path> {
+        self.symlink.as_deref()
+    }
+
+    /// Returns whether the record is an empty directory.
+    pub fn is_directory(&self) -> bool {
+        self.directory
+    }
+
+    /// Creates a new inventory record.
+    fn new(size: u64, hashes: Vec<(HashAlgorithm, HashValue)>) -> Self {
+        Record {
+            hashes: hashes.into_iter().collect(),
+            size,
+            symlink: None,
+            from_xattr: false,
+            directory: false,
+            mtime: None,
+            btime: None,
+        }
+    }
+
+    /// Creates a new inventory record with the hashes taken from an extended
+    /// attribute.
+    fn new_from_xattr(size: u64, hashes: Vec<(HashAlgorithm, HashValue)>) -> Self {
+        Record {
+            from_xattr: true,
+            ..Record::new(size, hashes)
+        }
+    }
+
+    /// Creates a new inventory record for a symbolic link that is not
+    /// followed.
+    fn new_symlink(target: PathBuf) -> Self {
+        Record {
+            hashes: BTreeMap::new(),
+            size: 0,
+            symlink: Some(target),
+            from_xattr: false,
+            directory: false,
+            mtime: None,
+            btime: None,
+        }
+    }
+
+    /// Creates a new inventory record for an empty directory.
+    fn new_directory() -> Self {
+        Record {
+            directory: true,
+            ..Record::new(0, Vec::new())
+        }
+    }
+}
+
+/// Handling of per-file I/O errors (unreadable or vanished files).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// Abort the operation.
+    #[default]
+    Abort,
+
+    /// Log the error and continue with the next file.
+    Skip,
+
+    /// Log the error, continue with the next file, and record the file as
+    /// failed in the report.
+    Report,
+}
+
+/// An error returned when the error policy name cannot be parsed.
+#[derive(Debug)]
+pub struct ParseErrorPolicyError();
+
+impl FromStr for ErrorPolicy {
+    type Err = ParseErrorPolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(ErrorPolicy::Abort),
+            "skip" => Ok(ErrorPolicy::Skip),
+            "report" => Ok(ErrorPolicy::Report),
+            _ => Err(ParseErrorPolicyError()),
+        }
+    }
+}
+
+impl ErrorPolicy {
+    /// Handles an error that occurred while processing a file.
+    fn handle(
+        self,
+        path: &Path,
+        err: Box<dyn Error>,
+        report: &mut Report,
+    ) -> Result<(), Box<dyn Error>> {
+        if self == ErrorPolicy::Abort {
+            return Err(err);
+        }
+
+        warn!("Skipping file: {}", err);
+        if self == ErrorPolicy::Report {
+            report.add_failure(path, FailureKind::ReadError);
+        }
+
+        Ok(())
+    }
+}
+
+/// Inventory verification failure kind.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FailureKind {
+    /// A file is present in the inventory but missing from the repository.
+    MissingFromRepository,
+
+    /// A file is found in the repository but missing from the inventory.
+    MissingFromInventory,
+
+    /// Actual file size does not match the size recorded in the inventory.
+    SizeMismatch,
+
+    /// Actual file hash value does not match the value recorded in the inventory.
+    HashMismatch,
+
+    /// A symbolic link recorded in the inventory is not a link anymore or
+    /// points to a different target, or a file has been replaced with a link.
+    SymlinkMismatch,
+
+    /// A file could not be read.
+    ReadError,
+
+    /// An empty directory recorded in the inventory is missing from the
+    /// repository or has been replaced with a file.
+    MissingDirectory,
+
+    /// The creation time of a file does not match the recorded one, so the
+    /// file has been deleted and recreated.
+    CreatedTimeMismatch,
+
+    /// A file could not be downloaded from the server the repository is
+    /// served by.
+    NetworkError,
+}
+
+impl FailureKind {
+    /// Returns the name of the failure kind, as accepted by `from_str()`.
+    pub fn name(self) -> &'static str {
+        match self {
+            FailureKind::MissingFromRepository => "missing-from-repository",
+            FailureKind::MissingFromInventory => "missing-from-inventory",
+            FailureKind::SizeMismatch => "size-mismatch",
+            FailureKind::HashMismatch => "hash-mismatch",
+            FailureKind::SymlinkMismatch => "symlink-mismatch",
+            FailureKind::ReadError => "read-error",
+            FailureKind::MissingDirectory => "missing-directory",
+            FailureKind::CreatedTimeMismatch => "created-time-mismatch",
+            FailureKind::NetworkError => "network-error",
+        }
+    }
+}
+
+/// An error returned when the failure kind name cannot be parsed.
+#[derive(Debug)]
+pub struct ParseFailureKindError();
+
+impl FromStr for FailureKind {
+    type Err = ParseFailureKindError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "missing-from-repository" => Ok(FailureKind::MissingFromRepository),
+            "missing-from-inventory" => Ok(FailureKind::MissingFromInventory),
+            "size-mismatch" => Ok(FailureKind::SizeMismatch),
+            "hash-mismatch" => Ok(FailureKind::HashMismatch),
+            "symlink-mismatch" => Ok(FailureKind::SymlinkMismatch),
+            "read-error" => Ok(FailureKind::ReadError),
+            "missing-directory" => Ok(FailureKind::MissingDirectory),
+            "created-time-mismatch" => Ok(FailureKind::CreatedTimeMismatch),
+            "network-error" => Ok(FailureKind::NetworkError),
+            _ => Err(ParseFailureKindError()),
+        }
+    }
+}
+
+/// Inventory verification notice kind.
+///
+/// Notices describe files that did not fail the verification, but were not
+/// fully verified either.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NoticeKind {
+    /// The file size matches, but the hash comparison was skipped because the
+    /// file is larger than the configured threshold, or none of the compared
+    /// algorithms is used for the file.
+    HashSkipped,
+
+    /// The file size matches, but the hashes could not be compared because
+    /// the records have no hash algorithm in common.
+    SizeComparedOnly,
+
+    /// The file hash does not match, but the file is smaller than the
+    /// threshold below which hash mismatches are ignored.
+    HashMismatchIgnored,
+
+    /// The file has grown, but still starts with the recorded contents, so
+    /// data has only been appended to it.
+    Appended,
+
+    /// The file is missing from a repository verified as a partial restore.
+    NotRestored,
+
+    /// The file is a hard link in an archive to an entry that was not
+    /// checked, so its contents could not be verified.
+    UnresolvedHardLink,
+}
+
+impl NoticeKind {
+    /// Returns the name of the notice kind.
+    pub fn name(self) -> &'static str {
+        match self {
+            NoticeKind::HashSkipped => "hash-skipped",
+            NoticeKind::SizeComparedOnly => "size-compared-only",
+            NoticeKind::HashMismatchIgnored => "hash-mismatch-ignored",
+            NoticeKind::Appended => "appended",
+            NoticeKind::NotRestored => "not-restored",
+            NoticeKind::UnresolvedHardLink => "unresolved-hard-link",
+        }
+    }
+}
+
+/// Inventory verification report.
+#[derive(Default)]
+pub struct Report {
+    /// Issues found during the verification and the corresponding file paths.
+    contents: HashMap<FailureKind, HashSet<PathBuf>>,
+
+    /// Notices produced during the verification and the corresponding file
+    /// paths.
+    notices: HashMap<NoticeKind, HashSet<PathBuf>>,
+
+    /// Files that passed the verification, if tracked.
+    passed: HashSet<PathBuf>,
+}
+
+impl Report {
+    /// Returns a new empty report, e.g. to accumulate the reports of several
+    /// verifications with `merge()`.
+    pub fn new() -> Self {
+        Report::default()
+    }
+
+    /// Returns `true` if the report contains no failures, `false` otherwise.
+    ///
+    /// Notices do not affect the result.
+    pub fn is_empty(&self) -> bool {
+        self.contents.is_empty()
+    }
+
+    /// Returns a list of the discovered failure kinds.
+    pub fn failures(&self) -> Vec<FailureKind> {
+        self.contents.keys().copied().collect()
+    }
+
+    /// Returns a list of files that caused the specific failure.
+    pub fn by_failure(&self, kind: FailureKind) -> Option<impl Iterator<Item = &Path>> {
+        self.contents
+            .get(&kind)
+            .map(|h| h.iter().map(|p| p.as_path()))
+    }
+
+    /// Returns a list of the produced notice kinds.
+    pub fn notices(&self) -> Vec<NoticeKind> {
+        self.notices.keys().copied().collect()
+    }
+
+    /// Returns a list of files that caused the specific notice.
+    pub fn by_notice(&self, kind: NoticeKind) -> Option<impl Iterator<Item = &Path>> {
+        self.notices
+            .get(&kind)
+            .map(|h| h.iter().map(|p| p.as_path()))
+    }
+
+    /// Returns a list of files that passed the verification, if tracked.
+    pub fn passed(&self) -> impl Iterator<Item = &Path> {
+        self.passed.iter().map(|p| p.as_path())
+    }
+
+    /// Returns `true` if the file caused any failures or notices.
+    fn has_issues(&self, file: &Path) -> bool {
+        self.contents.values().any(|h| h.contains(file))
+            || self.notices.values().any(|h| h.contains(file))
+    }
+
+    /// Returns the paths of the files that caused any failures.
+    fn failed_paths(&self) -> Vec<&Path> {
+        self.contents
+            .values()
+            .flatten()
+            .map(|p| p.as_path())
+            .collect()
+    }
+
+    /// Returns the failure caused by the file, if any.
+    fn failure_of(&self, file: &Path) -> Option<FailureKind> {
+        self.contents
+            .iter()
+            .find(|(_, h)| h.contains(file))
+            .map(|(k, _)| *k)
+    }
+
+    /// Records a failure in the report.
+    fn add_failure<P: AsRef<Path>>(&mut self, file: P, kind: FailureKind) {
+        self.contents
+            .entry(kind)
+            .or_default()
+            .insert(file.as_ref().to_path_buf());
+    }
+
+    /// Records a notice in the report.
+    fn add_notice<P: AsRef<Path>>(&mut self, file: P, kind: NoticeKind) {
+        self.notices
+            .entry(kind)
+            .or_default()
+            .insert(file.as_ref().to_path_buf());
+    }
+
+    /// Adds the failures, notices and passed files of another report.
+    ///
+    /// The files of every failure and notice kind are the union of both
+    /// reports, so a file reported by both is listed once. The paths are
+    /// merged as they are: reports of different repositories should have
+    /// their paths made distinct (e.g. prefixed) by the caller if they may
+    /// overlap.
+    pub fn merge(&mut self, other: Report) {
+        for (kind, files) in other.contents {
+            self.contents.entry(kind).or_default().extend(files);
+        }
+        for (kind, files) in other.notices {
+            self.notices.entry(kind).or_default().extend(files);
+        }
+        self.passed.extend(other.passed);
+    }
+}
+
+/// Inventory seal: a digest of the inventory records that detects
+/// modifications of the inventory itself.
+#[derive(Debug, Deserialize, Serialize)]
+struct Seal {
+    /// Hash algorithm used to compute the seal.
+    algorithm: HashAlgorithm,
+
+    /// Whether the file paths are sealed along with the record contents.
+    includes_paths: bool,
+
+    /// The seal value.
+    value: HashValue,
+}
+
+/// Inventory structure.
+///
+/// The inventory is serialized with the records in the flat layout; see
+/// `NestedInventory` for the nested one.
+#[derive(Debug, Deserialize)]
+pub struct Inventory {
+    /// Inventory configuration.
+    configuration: Configuration,
+
+    /// Number of records, stored ahead of the records so that it can be read
+    /// without parsing them. Missing from older inventories until they are
+    /// rewritten.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    total_files: Option<usize>,
+
+    /// Total size of the records, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    total_bytes: Option<u64>,
+
+    /// File records.
+    #[serde(deserialize_with = "deserialize_records")]
+    records: BTreeMap<PathBuf, Record>,
+
+    /// Inventory seal, if the inventory is sealed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seal: Option<Seal>,
+
+    /// Free-form description of the inventory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+
+    /// Path to the repository, relative to the directory of the inventory
+    /// file, if stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    repository: Option<PathBuf>,
+
+    /// Arbitrary key/value tags.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
+}
+
+impl Inventory {
+    /// Creates a new inventory with the provided configuration.
+    pub fn new(configuration: Configuration) -> Self {
+        Inventory {
+            records: BTreeMap::new(),
+            configuration,
+            total_files: None,
+            total_bytes: None,
+            seal: None,
+            description: None,
+            repository: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Reads and validates an inventory in the specified format, or
+    /// detecting its format if none is.
+    ///
+    /// See `format::read_inventory()` for the supported formats.
+    pub fn from_reader<R: BufRead>(
+        reader: R,
+        format: Option<Format>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut inventory = format::read_inventory(reader, format)?;
+        inventory.normalize_records()?;
+        inventory.validate()?;
+        Ok(inventory)
+    }
+
+    /// Reads and validates the inventory file in the specified format, or
+    /// detecting its format if none is.
+    ///
+    /// Errors other than failing to open the file are returned as
+    /// `InventoryError::Unreadable`, since parse errors do not tell which
+    /// inventory they are about.
+    pub fn from_path(path: &Path, format: Option<Format>) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(path)
+            .map_err(|e| FileError::new(path, e))?;
+
+        Self::from_reader(BufReader::new(file), format)
+            .map_err(|e| Box::new(InventoryError::Unreadable(path.to_path_buf(), e)).into())
+    }
+
+    /// Writes the inventory in the specified format, with the records in the
+    /// nested layout if enabled.
+    ///
+    /// See `format::write_inventory()` for the layouts.
+    pub fn write_to<W: Write>(
+        &self,
+        writer: W,
+        format: Format,
+        nested: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        format::write_inventory(writer, self, format, nested)
+    }
+
+    /// Writes the inventory to the file atomically, in the specified format,
+    /// or the one selected by the file name if none is.
+    ///
+    /// The inventory is serialized to a temporary file next to the target
+    /// first, which is then synced and renamed over the target. An
+    /// interrupted write thus never leaves a truncated inventory behind.
+    /// Targets that are not regular files, such as named pipes, cannot be
+    /// replaced this way, so they are written directly.
+    pub fn write_to_path(
+        &self,
+        path: &Path,
+        format: Option<Format>,
+        nested: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let format = format.unwrap_or_else(|| Format::from_path(path));
+        if fs::metadata(path).is_ok_and(|m| !m.is_file()) {
+            let file = OpenOptions::new()
+                .write(true)
+                .open(path)
+                .map_err(|e| FileError::new(path, e))?;
+            return self.write_to(BufWriter::new(file), format, nested);
+        }
+
+        let temp_path = util::temp_path(path);
+
+        let result = (|| -> Result<(), Box<dyn Error>> {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .open(&temp_path)
+                .map_err(|e| FileError::new(&temp_path, e))?;
+            self.write_to(BufWriter::new(&file), format, nested)?;
+            file.sync_all().map_err(|e| FileError::new(&temp_path, e))?;
+            fs::rename(&temp_path, path).map_err(|e| FileError::new(path, e))?;
+            Ok(())
+        })();
+
+        // Do not leave the partially written temporary file behind.
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+
+        result
+    }
+
+    /// Builds an inventory for the provided repository directory.
+    ///
+    /// Returns the inventory and the report of the files that could not be
+    /// read, if the error policy records them. The progress is reported to
+    /// the observer, if any.
+    pub fn build(
+        configuration: Configuration,
+        repository: &Path,
+        options: &BuildOptions,
+        progress: Option<&mut dyn ProgressObserver>,
+    ) -> Result<(Self, Report), Box<dyn Error>> {
+        let mut ignored = ();
+        let progress = progress.unwrap_or(&mut ignored);
+
+        if configuration.trust_xattr.is_some() && configuration.hash_algorithms.len() > 1 {
+            warn!("Hash attributes are ignored, since multiple hash algorithms are used");
+        }
+        if configuration.trust_xattr.is_some() && configuration.is_keyed() {
+            warn!("Hash attributes are ignored, since the inventory is keyed");
+        }
+
+        let mut inventory = Inventory::new(configuration);
+        let mut hasher = inventory.hasher(
+            inventory.configuration.all_algorithms(),
+            options.hmac_key.as_deref(),
+            options.rate_limit,
+            options.buffer_size,
+        )?;
+        let mut timings = HashTimings::new();
+        let mut report = Report::new();
+
+        let files =
+            inventory.repo_files_limited(repository, options.max_files, &options.excluded_paths)?;
+        inventory.check_case_collisions(files.iter().cloned(), options)?;
+        inventory.check_free_space(files.iter(), options)?;
+        progress.on_start(files.len(), files_size(repository, &files));
+
+        // Add the discovered files to the inventory.
+        for file in files {
+            if let Err(err) = inventory.add_file(repository, &file, &mut hasher, &mut timings) {
+                options.error_policy.handle(&file, err, &mut report)?;
+            }
+            inventory.observe_file(&file, &report, progress);
+        }
+
+        timings.report();
+        progress.on_finish(&report);
+
+        inventory.update_totals();
+
+        Ok((inventory, report))
+    }
+
+    /// Returns the inventory configuration.
+    pub fn configuration(&self) -> &Configuration {
+        &self.configuration
+    }
+
+    /// Returns the number of records in the inventory.
+    pub fn record_count(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns the record of the file with the given path, if any.
+    pub fn record(&self, path: &Path) -> Option<&Record> {
+        self.records.get(path)
+    }
+
+    /// Returns the records ordered by path.
+    pub fn records(&self) -> impl Iterator<Item = (&Path, &Record)> {
+        self.records.iter().map(|(p, r)| (p.as_path(), r))
+    }
+
+    /// Groups the file records by their hash of the algorithm, which defines
+    /// the content identity.
+    ///
+    /// Returns every distinct hash value once, in the order of the values,
+    /// with the number of the files that have it and the path of the first of
+    /// them. Directories, unfollowed symbolic links, and files without a
+    /// hash of the algorithm are left out.
+    pub fn unique_contents(
+        &self,
+        algorithm: HashAlgorithm,
+    ) -> Result<Vec<(&HashValue, usize, &Path)>, InventoryError> {
+        if !self.configuration.all_algorithms().contains(&algorithm) {
+            return Err(InventoryError::AlgorithmNotPresent(algorithm));
+        }
+
+        let mut contents: BTreeMap<&[u8], (&HashValue, usize, &Path)> = BTreeMap::new();
+        for (path, rec) in &self.records {
+            if rec.directory || rec.symlink.is_some() {
+                continue;
+            }
+            if let Some(value) = rec.hashes.get(&algorithm) {
+                contents
+                    .entry(value.as_bytes())
+                    .or_insert((value, 0, path))
+                    .1 += 1;
+            }
+        }
+
+        Ok(contents.into_values().collect())
+    }
+
+    /// Returns the total size of the records, in bytes.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes.unwrap_or_else(|| self.records_size())
+    }
+
+    /// Returns `Some(includes_paths)` if the inventory is sealed, or `None`
+    /// otherwise.
+    pub fn sealed(&self) -> Option<bool> {
+        self.seal.as_ref().map(|s| s.includes_paths)
+    }
+
+    /// Returns the description of the inventory.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Sets the description of the inventory.
+    pub fn set_description(&mut self, description: Option<String>) -> &mut Self {
+        self.description = description;
+        self
+    }
+
+    /// Returns the stored path to the repository, relative to the directory
+    /// of the inventory file.
+    pub fn repository(&self) -> Option<&Path> {
+        self.repository.as_deref()
+    }
+
+    /// Sets the stored path to the repository.
+    pub fn set_repository(&mut self, repository: Option<PathBuf>) -> &mut Self {
+        self.repository = repository;
+        self
+    }
+
+    /// Returns the key/value tags of the inventory.
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    /// Sets a key/value tag, replacing the previous value of the key.
+    pub fn set_tag(&mut self, key: String, value: String) -> &mut Self {
+        self.metadata.insert(key, value);
+        self
+    }
+
+    /// Adds the files of another repository to the inventory under the given
+    /// path prefix.
+    ///
+    /// Unlike `update()`, this adds a new subtree rather than re-scanning the
+    /// repository the inventory was built for. The repository is walked with
+    /// the inventory configuration, and the specified hash algorithms must
+    /// match the ones used by the inventory. No records are added if any of
+    /// the prefixed paths is already recorded.
+    ///
+    /// Returns the report of the files that could not be read, if the error
+    /// policy records them.
+    pub fn append(
+        &mut self,
+        repository: &Path,
+        prefix: &Path,
+        algorithms: &[HashAlgorithm],
+        options: &BuildOptions,
+    ) -> Result<Report, Box<dyn Error>> {
+        let expected: BTreeSet<_> = self.configuration.hash_algorithms.iter().collect();
+        if algorithms.iter().collect::<BTreeSet<_>>() != expected {
+            return Err(Box::new(InventoryError::AlgorithmMismatch));
+        }
+
+        let mut hasher = self.hasher(
+            self.configuration.all_algorithms(),
+            options.hmac_key.as_deref(),
+            options.rate_limit,
+            options.buffer_size,
+        )?;
+        let mut timings = HashTimings::new();
+
+        // Check all keys for collisions before spending time on hashing.
+        let files =
+            self.repo_files_limited(repository, options.max_files, &options.excluded_paths)?;
+        if let Some(key) = files
+            .iter()
+            .map(|p| prefix.join(p))
+            .find(|k| self.records.contains_key(k))
+        {
+            return Err(Box::new(InventoryError::RecordExists(key)));
+        }
+        self.check_case_collisions(files.iter().map(|p| prefix.join(p)), options)?;
+        self.check_free_space(files.iter().map(|p| prefix.join(p)), options)?;
+
+        let mut report = Report::new();
+        for file in files {
+            let key = prefix.join(&file);
+            if let Err(err) =
+                self.add_file_as(&repository.join(&file), &key, &mut hasher, &mut timings)
+            {
+                options.error_policy.handle(&key, err, &mut report)?;
+            }
+        }
+
+        timings.report();
+
+        self.update_totals();
+        self.reseal()?;
+
+        Ok(report)
+    }
+
+    /// Compares the estimated size of the inventory with the new records
+    /// with the free space of the filesystem it is written to, if the output
+    /// path is set, warning about a shortage or, in the strict mode, failing.
+    ///
+    /// The estimate is conservative: it assumes the pretty-printed JSON
+    /// format, the largest one. The whole inventory is counted even when
+    /// updating, since it is written to a temporary file next to the old
+    /// one before replacing it.
+    fn check_free_space<I, P>(
+        &self,
+        new_keys: I,
+        options: &BuildOptions,
+    ) -> Result<(), InventoryError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let output = match &options.output_path {
+            Some(output) => output,
+            None => return Ok(()),
+        };
+        // Targets other than regular files, such as named pipes, are written
+        // directly and take no space.
+        if output.exists() && !output.is_file() {
+            return Ok(());
+        }
+
+        let dir = match output.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let available = match util::available_space(dir) {
+            Some(available) => available,
+            None => return Ok(()),
+        };
+
+        let size: u64 = self
+            .records
+            .keys()
+            .map(|k| self.estimated_record_size(k))
+            .chain(
+                new_keys
+                    .into_iter()
+                    .map(|k| self.estimated_record_size(k.as_ref())),
+            )
+            .sum();
+        if size > available {
+            if options.strict_space {
+                return Err(InventoryError::InsufficientSpace(size, available));
+            }
+            warn!(
+                "The inventory needs about {} bytes, but only {} bytes are free on its filesystem",
+                size, available
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns a rough upper bound of the size of the record with the given
+    /// path in the pretty-printed JSON format.
+    fn estimated_record_size(&self, key: &Path) -> u64 {
+        // Indentation, punctuation, and the size field take about 64 bytes,
+        // and every hash about 16 bytes besides the name and the hex digest.
+        let hashes: usize = self
+            .configuration
+            .algorithms_for(key)
+            .iter()
+            .map(|a| <&str>::from(*a).len() + 2 * a.digest_len() + 16)
+            .sum();
+        (key.as_os_str().len() + 64 + hashes) as u64
+    }
+
+    /// Finds the new record paths that differ only in case from each other or
+    /// from the recorded ones, warning about them or, in the strict mode,
+    /// failing on the first one.
+    fn check_case_collisions<I>(
+        &self,
+        paths: I,
+        options: &BuildOptions,
+    ) -> Result<(), InventoryError>
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        // Lowercasing stands in for case folding, which is what most
+        // case-insensitive filesystems do for the common scripts.
+        let fold = |p: &Path| p.to_string_lossy().to_lowercase();
+        let mut folded: HashMap<String, PathBuf> =
+            self.records.keys().map(|p| (fold(p), p.clone())).collect();
+
+        for path in paths {
+            match folded.get(&fold(&path)) {
+                Some(other) if *other != path => {
+                    if options.strict_case {
+                        return Err(InventoryError::CaseCollision(other.clone(), path));
+                    }
+                    warn!("Paths differ only in case: {:?} and {:?}", other, path);
+                }
+                Some(_) => {}
+                None => {
+                    folded.insert(fold(&path), path);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Normalizes the record paths of a loaded inventory, so that paths
+    /// written by hand or by other tools, e.g. with `./` components or
+    /// redundant separators, match the ones produced by the directory walk.
+    ///
+    /// Fails if a path escapes the repository, or if several records have the
+    /// same normalized path.
+    fn normalize_records(&mut self) -> Result<(), InventoryError> {
+        if self
+            .records
+            .keys()
+            .all(|p| matches!(normalize_key(p), Ok(key) if key.as_os_str() == p.as_os_str()))
+        {
+            return Ok(());
+        }
+
+        let mut records = BTreeMap::new();
+        for (path, record) in std::mem::take(&mut self.records) {
+            let key = normalize_key(&path)?;
+            if records.contains_key(&key) {
+                return Err(InventoryError::RecordExists(key));
+            }
+            records.insert(key, record);
+        }
+
+        self.records = records;
+        Ok(())
+    }
+
+    /// Validates the inventory records.
+    ///
+    /// Checks that the length of every recorded hash value matches the digest
+    /// length of its algorithm, which catches manual edits and partial writes
+    /// before they cause confusing verification failures. The recorded
+    /// totals, if any, must match the records as well. Record paths must be
+    /// normalized, which [`Inventory::from_reader`] takes care of.
+    pub fn validate(&self) -> Result<(), InventoryError> {
+        for (path, rec) in &self.records {
+            if normalize_key(path)? != *path {
+                return Err(InventoryError::InvalidRecordPath(path.clone()));
+            }
+
+            for (algorithm, value) in &rec.hashes {
+                if value.as_bytes().len() != algorithm.digest_len() {
+                    return Err(InventoryError::InvalidHashLength(path.clone(), *algorithm));
+                }
+            }
+        }
+
+        // Totals are only checked if recorded, since older inventories lack
+        // them.
+        if matches!(self.total_files, Some(n) if n != self.records.len())
+            || matches!(self.total_bytes, Some(n) if n != self.records_size())
+        {
+            return Err(InventoryError::TotalsMismatch);
+        }
+
+        if let Some(seal) = &self.seal {
+            if self.seal_value(seal.algorithm, seal.includes_paths) != seal.value {
+                return Err(InventoryError::SealMismatch);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Recomputes the totals after the records have changed.
+    fn update_totals(&mut self) {
+        self.total_files = Some(self.records.len());
+        self.total_bytes = Some(self.records_size());
+    }
+
+    /// Computes the total size of the records.
+    fn records_size(&self) -> u64 {
+        self.records.values().map(|r| r.size).sum()
+    }
+
+    /// Seals the inventory.
+    ///
+    /// The seal is a digest of the inventory records, computed with the
+    /// strongest of the configured hash algorithms. If `includes_paths` is
+    /// `true`, the file paths are sealed along with the record contents, so
+    /// renaming a file invalidates the seal. Otherwise, only the record
+    /// contents are sealed regardless of the paths they are recorded under:
+    /// the seal then detects changes of the recorded contents, but not
+    /// renames.
+    pub fn seal(&mut self, includes_paths: bool) -> Result<(), InventoryError> {
+        let algorithm = *self
+            .configuration
+            .hash_algorithms
+            .iter()
+            .max_by_key(|a| a.strength())
+            .ok_or(InventoryError::NoSealAlgorithm)?;
+
+        self.seal = Some(Seal {
+            algorithm,
+            includes_paths,
+            value: self.seal_value(algorithm, includes_paths),
+        });
+
+        Ok(())
+    }
+
+    /// Recomputes the seal of a sealed inventory after the records have
+    /// changed.
+    fn reseal(&mut self) -> Result<(), InventoryError> {
+        match &self.seal {
+            Some(seal) => self.seal(seal.includes_paths),
+            None => Ok(()),
+        }
+    }
+
+    /// Computes the seal value of the records.
+    fn seal_value(&self, algorithm: HashAlgorithm, includes_paths: bool) -> HashValue {
+        // The records are serialized deterministically: the map is ordered by
+        // path, and the record fields are always serialized in the same order.
+        // Without the paths, the serialized records are sorted by their
+        // contents, so that the seal does not depend on the file names.
+        let data = if includes_paths {
+            let mut data = Vec::new();
+            serialize_records(&self.records, &mut serde_json::Serializer::new(&mut data)).unwrap();
+            data
+        } else {
+            let mut records: Vec<_> = self
+                .records
+                .values()
+                .map(|r| serde_json::to_vec(r).unwrap())
+                .collect();
+            records.sort();
+            serde_json::to_vec(&records).unwrap()
+        };
+
+        let mut hasher = Hasher::new(std::iter::once(algorithm));
+        // Reading from a slice never fails.
+        let (_, value) = hasher.compute(data.as_slice()).unwrap().pop().unwrap();
+        value
+    }
+
+    /// Checks the repository and produces the verification report.
+    ///
+    /// The progress is reported to the observer, if any.
+    pub fn check(
+        &self,
+        repository: &Path,
+        options: &CheckOptions,
+        progress: Option<&mut dyn ProgressObserver>,
+    ) -> Result<Report, Box<dyn Error>> {
+        let mut ignored = ();
+        let progress = progress.unwrap_or(&mut ignored);
+        if options.low_memory
+            && options.strip_components == 0
+            && !options.optimize_io
+            && options.only_paths.is_none()
+        {
+            return self.check_merged(repository, options, progress);
+        }
+
+        let mut hasher = self.check_hasher(options)?;
+        let mut timings = HashTimings::new();
+
+        // Build a set of repository file paths and a set of file paths recorded in the inventory.
+        let (repository_paths, unmatched, inventory_files) = match &options.only_paths {
+            Some(only) => {
+                let inventory_files: BTreeSet<_> = self
+                    .records
+                    .keys()
+                    .filter(|p| only.contains(*p))
+                    .cloned()
+                    .collect();
+                // The stripped components cannot be told from the record
+                // paths, so the repository is walked to find them.
+                let repository_paths = if options.strip_components == 0 {
+                    Self::present_keys(repository, &inventory_files)
+                } else {
+                    let (mut keys, _) = self.repo_keys(
+                        repository,
+                        options.strip_components,
+                        &options.excluded_paths,
+                    )?;
+                    keys.retain(|key, _| inventory_files.contains(key));
+                    keys
+                };
+                (repository_paths, Vec::new(), inventory_files)
+            }
+            None => {
+                let (repository_paths, unmatched) = self.repo_keys(
+                    repository,
+                    options.strip_components,
+                    &options.excluded_paths,
+                )?;
+                (
+                    repository_paths,
+                    unmatched,
+                    self.records.keys().cloned().collect(),
+                )
+            }
+        };
+        let repository_files: BTreeSet<_> = repository_paths.keys().cloned().collect();
+
+        let mut report = Report::new();
+
+        // Find files present in the repository but missing from the inventory.
+        repository_files
+            .difference(&inventory_files)
+            .chain(&unmatched)
+            .for_each(|p| report.add_failure(p, FailureKind::MissingFromInventory));
+
+        // Find files present in the inventory but missing from the repository.
+        // Empty directories that have files in them now are not missing.
+        for file in inventory_files.difference(&repository_files) {
+            let has_files = if options.strip_components == 0 {
+                repository.join(file).is_dir()
+            } else {
+                repository_files
+                    .range::<PathBuf, _>(file..)
+                    .next()
+                    .is_some_and(|p| p.starts_with(file))
+            };
+
+            if !self.records[file].directory {
+                options.report_missing(&mut report, file);
+            } else if !has_files {
+                report.add_failure(file, FailureKind::MissingDirectory);
+            }
+        }
+
+        // Verify files one by one, optionally in the inode order.
+        let mut files: Vec<_> = inventory_files.intersection(&repository_files).collect();
+        if options.optimize_io {
+            files.sort_by_cached_key(|p| util::inode(repository.join(&repository_paths[*p])));
+        }
+
+        progress.on_start(
+            files.len(),
+            files.iter().map(|p| self.records[*p].size).sum(),
+        );
+        for path in report.failed_paths() {
+            progress.on_failure(path, report.failure_of(path).unwrap());
+        }
+
+        if options.threads > 1 {
+            let files: Vec<_> = files
+                .into_iter()
+                .map(|p| (p.as_path(), repository.join(&repository_paths[p])))
+                .collect();
+            self.check_parallel(&files, options, &mut timings, &mut report, progress)?;
+        } else {
+            for file in files {
+                match self.check_file(
+                    &repository.join(&repository_paths[file]),
+                    file,
+                    options,
+                    &mut hasher,
+                    &mut timings,
+                    &mut report,
+                ) {
+                    Ok(()) => options.report_passed(&mut report, file),
+                    Err(err) => options.error_policy.handle(file, err, &mut report)?,
+                }
+                self.observe_file(file, &report, progress);
+            }
+        }
+
+        timings.report();
+        progress.on_finish(&report);
+
+        Ok(report)
+    }
+
+    /// Walks the repository and returns the number of its files and their
+    /// total size, in bytes, as they would be recorded in the inventory.
+    ///
+    /// Only the file sizes are read, so this is a fast, coarse check of the
+    /// repository against `record_count()` and `total_bytes()`. Unfollowed
+    /// symbolic links and empty directories count as empty files, just like
+    /// their records.
+    pub fn repository_totals(
+        &self,
+        repository: &Path,
+        excluded: &[PathBuf],
+    ) -> Result<(usize, u64), Box<dyn Error>> {
+        let mut files = 0;
+        let mut bytes = 0;
+
+        for file in self.repo_iter(repository, false, excluded)? {
+            let abs = repository.join(file?);
+            files += 1;
+            if self.unfollowed_symlink(&abs)?.is_some() {
+                continue;
+            }
+            let metadata = fs::metadata(&abs).map_err(|e| FileError::new(&abs, e))?;
+            if metadata.is_file() {
+                bytes += metadata.len();
+            }
+        }
+
+        Ok((files, bytes))
+    }
+
+    /// Verifies the files, given by their record and absolute paths, on
+    /// multiple threads.
+    ///
+    /// The threads take the files in order, each hashing them with its own
+    /// hasher. The results are collected on the calling thread, which updates
+    /// the report and the progress, so the per-file error handling is the
+    /// same as on a single thread.
+    fn check_parallel(
+        &self,
+        files: &[(&Path, PathBuf)],
+        options: &CheckOptions,
+        timings: &mut HashTimings,
+        report: &mut Report,
+        progress: &mut dyn ProgressObserver,
+    ) -> Result<(), Box<dyn Error>> {
+        let next = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        // The threads draw from a single budget, so the limit holds for all
+        // of them together however the work is spread.
+        let rate_limiter = options
+            .rate_limit
+            .map(|limit| Arc::new(RateLimiter::new(limit)));
+
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            let workers: Vec<_> = (0..options.threads)
+                .map(|_| {
+                    let sender = sender.clone();
+                    let (next, stop) = (&next, &stop);
+                    let rate_limiter = rate_limiter.clone();
+                    scope.spawn(move || {
+                        // The hasher has already been created with the same
+                        // options, so it is safe to unwrap.
+                        let mut hasher = self.check_hasher(options).unwrap();
+                        hasher.set_rate_limiter(rate_limiter);
+                        let mut timings = HashTimings::new();
+
+                        while !stop.load(AtomicOrdering::Relaxed) {
+                            let (file, file_abs) =
+                                match files.get(next.fetch_add(1, AtomicOrdering::Relaxed)) {
+                                    Some(entry) => entry,
+                                    None => break,
+                                };
+
+                            // Errors are not sendable, so only their messages
+                            // are passed on.
+                            let mut file_report = Report::new();
+                            let result = self
+                                .check_file(
+                                    file_abs,
+                                    file,
+                                    options,
+                                    &mut hasher,
+                                    &mut timings,
+                                    &mut file_report,
+                                )
+                                .map_err(|e| e.to_string());
+                            if sender.send((*file, file_report, result)).is_err() {
+                                break;
+                            }
+                        }
+
+                        timings
+                    })
+                })
+                .collect();
+            drop(sender);
+
+            let mut result = Ok(());
+            for (file, file_report, file_result) in receiver {
+                report.merge(file_report);
+                let handled = match file_result {
+                    Ok(()) => {
+                        options.report_passed(report, file);
+                        Ok(())
+                    }
+                    Err(err) => options.error_policy.handle(file, err.into(), report),
+                };
+                if let Err(err) = handled {
+                    stop.store(true, AtomicOrdering::Relaxed);
+                    result = Err(err);
+                    break;
+                }
+                self.observe_file(file, report, progress);
+            }
+
+            for worker in workers {
+                // Worker panics are propagated by the scope.
+                if let Ok(worker_timings) = worker.join() {
+                    timings.merge(worker_timings);
+                }
+            }
+
+            result
+        })
+    }
+
+    /// Checks the repository in a single pass, merging its walk in the sorted
+    /// order with the records, which are sorted as well.
+    ///
+    /// Unlike `check()`, neither the repository paths nor the record paths
+    /// are collected, so the memory used beyond the inventory itself is
+    /// bounded by the size of the directories being walked.
+    fn check_merged(
+        &self,
+        repository: &Path,
+        options: &CheckOptions,
+        progress: &mut dyn ProgressObserver,
+    ) -> Result<Report, Box<dyn Error>> {
+        let mut hasher = self.check_hasher(options)?;
+        let mut timings = HashTimings::new();
+        let mut report = Report::new();
+
+        progress.on_start(self.records.len(), self.records_size());
+
+        let mut files = self.repo_iter(repository, true, &options.excluded_paths)?;
+        let mut records = self.records.iter();
+        let mut file = files.next().transpose()?;
+        let mut record = records.next();
+
+        loop {
+            let order = match (&file, &record) {
+                (Some(f), Some((r, _))) => f.cmp(r),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+
+            match order {
+                // A file present in the repository but missing from the
+                // inventory.
+                Ordering::Less => {
+                    let f = file.take().unwrap();
+                    report.add_failure(&f, FailureKind::MissingFromInventory);
+                    progress.on_failure(&f, FailureKind::MissingFromInventory);
+                    file = files.next().transpose()?;
+                }
+
+                // A file present in the inventory but missing from the
+                // repository. Empty directories that have files in them now
+                // are not missing.
+                Ordering::Greater => {
+                    let (r, rec) = record.take().unwrap();
+                    if !rec.directory {
+                        if options.report_missing(&mut report, r) {
+                            progress.on_failure(r, FailureKind::MissingFromRepository);
+                        }
+                    } else if !repository.join(r).is_dir() {
+                        report.add_failure(r, FailureKind::MissingDirectory);
+                        progress.on_failure(r, FailureKind::MissingDirectory);
+                    }
+                    record = records.next();
+                }
+
+                Ordering::Equal => {
+                    let (r, _) = record.take().unwrap();
+                    match self.check_file(
+                        &repository.join(r),
+                        r,
+                        options,
+                        &mut hasher,
+                        &mut timings,
+                        &mut report,
+                    ) {
+                        Ok(()) => options.report_passed(&mut report, r),
+                        Err(err) => options.error_policy.handle(r, err, &mut report)?,
+                    }
+                    self.observe_file(r, &report, progress);
+                    file = files.next().transpose()?;
+                    record = records.next();
+                }
+            }
+        }
+
+        timings.report();
+        progress.on_finish(&report);
+
+        Ok(report)
+    }
+
+    /// Checks the contents of a tar or zip archive of the repository against
+    /// the inventory, without extracting it.
+    ///
+    /// Entry paths are taken relative to the archive root. Directory entries
+    /// are skipped, and the files excluded from the inventory by its
+    /// configuration (e.g. hidden files) are ignored. Hard links are checked
+    /// with the size and hashes of the earlier entry they link to.
+    ///
+    /// The progress is reported to the observer, if any. Since the archive is
+    /// read sequentially, the recorded files are taken as the files to
+    /// process.
+    pub fn check_archive(
+        &self,
+        archive: &Path,
+        options: &CheckOptions,
+        progress: Option<&mut dyn ProgressObserver>,
+    ) -> Result<Report, Box<dyn Error>> {
+        let mut reader = BufReader::new(
+            OpenOptions::new()
+                .read(true)
+                .open(archive)
+                .or_else(|e| file_err!(archive, e))?,
+        );
+
+        if archive::is_zip(reader.fill_buf().map_err(|e| FileError::new(archive, e))?) {
+            self.check_source(&mut ZipReader::new(reader)?, options, progress)
+        } else {
+            self.check_source(&mut TarReader::new(reader)?, options, progress)
+        }
+    }
+
+    /// Checks an external listing of the repository files against the
+    /// inventory, without accessing the repository.
+    ///
+    /// The listed sizes are compared as usual, and the hashes are compared
+    /// for the algorithms that are both listed and selected for comparison.
+    /// Files without such hashes are reported as compared by size only.
+    /// Listed directories are only checked for presence, as with archives.
+    ///
+    /// The progress is reported to the observer, if any.
+    pub fn check_listing<I>(
+        &self,
+        listing: I,
+        options: &CheckOptions,
+        progress: Option<&mut dyn ProgressObserver>,
+    ) -> Result<Report, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = ListingEntry>,
+    {
+        let mut source = ListingSource {
+            entries: listing.into_iter(),
+            hashes: Vec::new(),
+        };
+
+        self.check_source(&mut source, options, progress)
+    }
+
+    /// Checks the repository served over HTTP against the inventory,
+    /// downloading the recorded files from under the base URL.
+    ///
+    /// The files are hashed as they are received, without being stored.
+    /// Files the server does not find are missing from the repository, and
+    /// other failed downloads are reported as network errors. Since the
+    /// server cannot be listed, files missing from the inventory are not
+    /// found, and recorded directories and symbolic links are not checked.
+    ///
+    /// The progress is reported to the observer, if any.
+    #[cfg(feature = "http")]
+    pub fn check_url(
+        &self,
+        base: &BaseUrl,
+        options: &CheckOptions,
+        progress: Option<&mut dyn ProgressObserver>,
+    ) -> Result<Report, Box<dyn Error>> {
+        let mut ignored = ();
+        let progress = progress.unwrap_or(&mut ignored);
+        let mut hasher = self.check_hasher(options)?;
+        let mut timings = HashTimings::new();
+        let mut report = Report::new();
+
+        progress.on_start(self.records.len(), self.records_size());
+
+        for (file, rec) in &self.records {
+            if rec.directory || rec.symlink.is_some() {
+                continue;
+            }
+
+            let result =
+                self.check_download(base, file, options, &mut hasher, &mut timings, &mut report);
+            if let Err(err) = result {
+                warn!("Cannot download {:?}: {}", file, err);
+                report.add_failure(file, FailureKind::NetworkError);
+            }
+            options.report_passed(&mut report, file);
+            self.observe_file(file, &report, progress);
+        }
+
+        timings.report();
+        progress.on_finish(&report);
+
+        Ok(report)
+    }
+
+    /// Verifies a single recorded file by downloading it from under the base
+    /// URL, adding the failures other than the network errors to the report.
+    #[cfg(feature = "http")]
+    fn check_download(
+        &self,
+        base: &BaseUrl,
+        file: &Path,
+        options: &CheckOptions,
+        hasher: &mut Hasher,
+        timings: &mut HashTimings,
+        report: &mut Report,
+    ) -> Result<(), HttpError> {
+        let rec = &self.records[file];
+
+        debug!("Downloading {:?}", file);
+        let mut response = base.get(file)?;
+        if response.status() == 404 {
+            options.report_missing(report, file);
+            return Ok(());
+        }
+        response.ensure_success()?;
+
+        // The declared length saves downloading files of a different size.
+        if options.check_sizes && matches!(response.content_length(), Some(len) if len != rec.size)
+        {
+            report.add_failure(file, FailureKind::SizeMismatch);
+            return Ok(());
+        }
+
+        let algorithms = if !options.check_hashes {
+            Vec::new()
+        } else if matches!(options.skip_hash_larger_than, Some(limit) if rec.size > limit) {
+            report.add_notice(file, NoticeKind::HashSkipped);
+            Vec::new()
+        } else {
+            let algorithms = self.check_algorithms(file, options);
+            if algorithms.is_empty() {
+                report.add_notice(file, NoticeKind::HashSkipped);
+            }
+            algorithms
+        };
+
+        // Without hashes to compare, the body is only read to find the size,
+        // unless it is declared.
+        let hashes = if !algorithms.is_empty() {
+            let start = timings.start();
+            let hashes = hasher.compute_selected(&mut response, &algorithms)?;
+            timings.record(file, rec.size, start);
+            hashes
+        } else if response.content_length().is_none() {
+            std::io::copy(&mut response, &mut std::io::sink())?;
+            Vec::new()
+        } else {
+            return Ok(());
+        };
+
+        if options.check_sizes && response.received() != rec.size {
+            report.add_failure(file, FailureKind::SizeMismatch);
+        } else if !hashes.iter().all(|(a, v)| rec.hashes.get(a) == Some(v)) {
+            options.report_hash_mismatch(report, file, rec.size);
+        }
+
+        Ok(())
+    }
+
+    /// Checks the entries of a sequentially read source of the repository
+    /// state against the inventory.
+    fn check_source<S: EntrySource>(
+        &self,
+        source: &mut S,
+        options: &CheckOptions,
+        progress: Option<&mut dyn ProgressObserver>,
+    ) -> Result<Report, Box<dyn Error>> {
+        let mut ignored = ();
+        let progress = progress.unwrap_or(&mut ignored);
+        let mut hasher = self.check_hasher(options)?;
+        let mut timings = HashTimings::new();
+
+        let mut report = Report::new();
+        let mut source_files = BTreeSet::new();
+        let mut source_dirs = BTreeSet::new();
+
+        // Sizes and hashes of the checked files, which the later hard links
+        // to them share.
+        let mut contents = BTreeMap::new();
+
+        progress.on_start(self.records.len(), self.records_size());
+
+        while let Some(mut entry) = source.next_entry()? {
+            let file: PathBuf = entry
+                .path
+                .components()
+                .skip(options.strip_components)
+                .collect();
+            if file.as_os_str().is_empty() {
+                // Stripped directories are not matched, but files are.
+                if entry.kind != EntryKind::Directory {
+                    report.add_failure(&entry.path, FailureKind::MissingFromInventory);
+                    progress.on_failure(&entry.path, FailureKind::MissingFromInventory);
+                }
+                continue;
+            } else if entry.kind == EntryKind::Directory {
+                // Archives list all directories, so recorded directories are
+                // only checked for presence. Listings are treated the same.
+                if self.configuration.include_empty_dirs {
+                    source_dirs.insert(file);
+                }
+                continue;
+            } else if util::is_temp_file(&file)
+                || (self.configuration.skip_hidden && util::is_hidden(&file))
+                || (self.configuration.skip_vcs && util::is_in_vcs_dir(&file))
+                || (self.configuration.exclude_symlinks
+                    && matches!(entry.kind, EntryKind::Symlink(_)))
+                || self.is_old_entry(&entry)
+            {
+                continue;
+            }
+
+            source_files.insert(file.clone());
+
+            entry.path = file;
+            if let EntryKind::HardLink(target) = &entry.kind {
+                let target: PathBuf = target.components().skip(options.strip_components).collect();
+                self.check_hard_link(&entry.path, contents.get(&target), options, &mut report);
+            } else {
+                let hashes = self.check_entry(
+                    &entry,
+                    source,
+                    options,
+                    &mut hasher,
+                    &mut timings,
+                    &mut report,
+                )?;
+                if entry.kind == EntryKind::File {
+                    contents.insert(entry.path.clone(), (entry.size, hashes));
+                }
+            }
+            options.report_passed(&mut report, &entry.path);
+            self.observe_file(&entry.path, &report, progress);
+        }
+
+        timings.report();
+
+        // Find files present in the inventory but missing from the source.
+        for (p, rec) in &self.records {
+            if rec.directory {
+                if !source_dirs.contains(p) {
+                    report.add_failure(p, FailureKind::MissingDirectory);
+                    progress.on_failure(p, FailureKind::MissingDirectory);
+                }
+            } else if !source_files.contains(p) && options.report_missing(&mut report, p) {
+                progress.on_failure(p, FailureKind::MissingFromRepository);
+            }
+        }
+
+        progress.on_finish(&report);
+
+        Ok(report)
+    }
+
+    /// Checks if a source entry is skipped by the modification time cutoff.
+    ///
+    /// Archives only store the modification times to the second, so entries
+    /// modified within the second of the cutoff are not skipped.
+    fn is_old_entry(&self, entry: &Entry) -> bool {
+        let mtime = entry
+            .mtime
+            .map(|t| t.saturating_add(1).saturating_mul(1_000_000_000));
+        self.configuration.is_before_cutoff(mtime)
+    }
+
+    /// Verifies a single entry of a source, whose path has the leading
+    /// components stripped, adding the failures to the report.
+    ///
+    /// Returns the hashes of the entry contents, if they were computed.
+    fn check_entry<S: EntrySource>(
+        &self,
+        entry: &Entry,
+        source: &mut S,
+        options: &CheckOptions,
+        hasher: &mut Hasher,
+        timings: &mut HashTimings,
+        report: &mut Report,
+    ) -> Result<Vec<(HashAlgorithm, HashValue)>, Box<dyn Error>> {
+        let file = &entry.path;
+
+        // Find files present in the archive but missing from the inventory.
+        let rec = match self.records.get(file) {
+            Some(rec) => rec,
+            None => {
+                report.add_failure(file, FailureKind::MissingFromInventory);
+                return Ok(Vec::new());
+            }
+        };
+
+        debug!("Verifying entry {:?}", file);
+
+        // Symbolic links are only compared by target.
+        let target = match &entry.kind {
+            EntryKind::Symlink(target) => Some(target),
+            _ => None,
+        };
+        if target != rec.symlink.as_ref() {
+            report.add_failure(file, FailureKind::SymlinkMismatch);
+            return Ok(Vec::new());
+        } else if target.is_some() {
+            return Ok(Vec::new());
+        }
+
+        if options.check_sizes && entry.size != rec.size {
+            report.add_failure(file, FailureKind::SizeMismatch);
+            return Ok(Vec::new());
+        }
+
+        if !options.check_hashes {
+            return Ok(Vec::new());
+        }
+
+        if matches!(options.skip_hash_larger_than, Some(limit) if rec.size > limit) {
+            report.add_notice(file, NoticeKind::HashSkipped);
+            return Ok(Vec::new());
+        }
+
+        let algorithms = self.check_algorithms(file, options);
+        if algorithms.is_empty() {
+            report.add_notice(file, NoticeKind::HashSkipped);
+            return Ok(Vec::new());
+        }
+
+        let hashes = source.entry_hashes(entry, &algorithms, hasher, timings)?;
+        if hashes.is_empty() {
+            report.add_notice(file, NoticeKind::SizeComparedOnly);
+        } else if !hashes.iter().all(|(a, v)| rec.hashes.get(a) == Some(v)) {
+            options.report_hash_mismatch(report, file, rec.size);
+        }
+
+        Ok(hashes)
+    }
+
+    /// Verifies a hard link of a source, whose path has the leading
+    /// components stripped, against the size and hashes of the entry it links
+    /// to, adding the failures to the report.
+    ///
+    /// Links to entries that were not checked, e.g. ones that are ignored or
+    /// stripped, cannot be verified and are only reported as a notice.
+    fn check_hard_link(
+        &self,
+        file: &Path,
+        target: Option<&(u64, Vec<(HashAlgorithm, HashValue)>)>,
+        options: &CheckOptions,
+        report: &mut Report,
+    ) {
+        let rec = match self.records.get(file) {
+            Some(rec) => rec,
+            None => {
+                report.add_failure(file, FailureKind::MissingFromInventory);
+                return;
+            }
+        };
+
+        debug!("Verifying hard link {:?}", file);
+
+        let (size, hashes) = match target {
+            Some(target) => target,
+            None => {
+                report.add_notice(file, NoticeKind::UnresolvedHardLink);
+                return;
+            }
+        };
+        if rec.symlink.is_some() {
+            report.add_failure(file, FailureKind::SymlinkMismatch);
+            return;
+        }
+
+        if options.check_sizes && *size != rec.size {
+            report.add_failure(file, FailureKind::SizeMismatch);
+            return;
+        }
+
+        if !options.check_hashes {
+            return;
+        }
+
+        // The hashes of the target were computed for its own algorithms,
+        // which may differ from the ones of the link with per-extension
+        // algorithms.
+        let algorithms = self.check_algorithms(file, options);
+        let hashes: Vec<_> = hashes
+            .iter()
+            .filter(|(a, _)| algorithms.contains(a))
+            .collect();
+        if hashes.is_empty() {
+            report.add_notice(file, NoticeKind::SizeComparedOnly);
+        } else if !hashes.iter().all(|(a, v)| rec.hashes.get(a) == Some(v)) {
+            options.report_hash_mismatch(report, file, rec.size);
+        }
+    }
+
+    /// Reports a processed file to the progress observer, along with its
+    /// failure, if any.
+    fn observe_file(&self, file: &Path, report: &Report, progress: &mut dyn ProgressObserver) {
+        progress.on_file(file, self.records.get(file).map_or(0, |r| r.size));
+        if let Some(kind) = report.failure_of(file) {
+            progress.on_failure(file, kind);
+        }
+    }
+
+    /// Verifies a single file present both in the repository and in the
+    /// inventory, adding the failures to the report.
+    fn check_file(
+        &self,
+        file_abs: &Path,
+        file: &Path,
+        options: &CheckOptions,
+        hasher: &mut Hasher,
+        timings: &mut HashTimings,
+        report: &mut Report,
+    ) -> Result<(), Box<dyn Error>> {
+        debug!("Verifying file {:?}", file);
+
+        let rec = self.records.get(file).unwrap();
+
+        // Symbolic links that are not followed are only compared by target.
+        let target = self
+            .unfollowed_symlink(file_abs)
+            .or_else(|e| file_err!(file_abs, e))?;
+        if target != rec.symlink {
+            report.add_failure(file, FailureKind::SymlinkMismatch);
+            return Ok(());
+        } else if target.is_some() {
+            return Ok(());
+        }
+
+        let attr = fs::metadata(file_abs).or_else(|e| file_err!(file_abs, e))?;
+
+        // Empty directories are only compared by kind.
+        match (rec.directory, attr.is_dir()) {
+            (false, false) => {}
+            (true, true) => return Ok(()),
+            (true, false) => {
+                report.add_failure(file, FailureKind::MissingDirectory);
+                return Ok(());
+            }
+            (false, true) => {
+                report.add_failure(file, FailureKind::MissingFromRepository);
+                return Ok(());
+            }
+        }
+
+        // Creation times are only compared if both are known.
+        if let (Some(recorded), Some(actual)) = (rec.btime, btime_nanos(&attr)) {
+            if recorded != actual {
+                report.add_failure(file, FailureKind::CreatedTimeMismatch);
+                return Ok(());
+            }
+        }
+
+        // Check size first. It does not make sense to check hashes if sizes
+        // don't match, unless the sizes are not trusted.
+        if options.check_sizes && attr.len() != rec.size {
+            if attr.len() > rec.size
+                && options.check_hashes
+                && self.configuration.append_aware
+                && self.is_appended(file_abs, file, options, hasher, timings)?
+            {
+                report.add_notice(file, NoticeKind::Appended);
+            } else {
+                report.add_failure(file, FailureKind::SizeMismatch);
+            }
+            return Ok(());
+        }
+
+        if !options.check_hashes {
+            return Ok(());
+        }
+
+        // Files that have not been modified since they were recorded are
+        // trusted.
+        if options.verify_changed && rec.mtime.is_some() && rec.mtime == mtime_nanos(&attr) {
+            trace!("Skipping the hash check of the unmodified file {:?}", file);
+            return Ok(());
+        }
+
+        // Oversized files still had their size checked, but must not be
+        // mistaken for files that passed the hash check.
+        if matches!(options.skip_hash_larger_than, Some(limit) if rec.size > limit) {
+            report.add_notice(file, NoticeKind::HashSkipped);
+            return Ok(());
+        }
+
+        // Only the selected algorithms are computed and compared.
+        let algorithms = self.check_algorithms(file, options);
+        if algorithms.is_empty() {
+            report.add_notice(file, NoticeKind::HashSkipped);
+            return Ok(());
+        }
+
+        let start = timings.start();
+        let (_, hashes) = hasher.compute_file_selected(file_abs, &algorithms)?;
+        timings.record(file, rec.size, start);
+        if !hashes.iter().all(|(a, v)| rec.hashes.get(a) == Some(v)) {
+            options.report_hash_mismatch(report, file, rec.size);
+        }
+
+        Ok(())
+    }
+
+    /// Checks if a grown file starts with the recorded contents, comparing
+    /// the hashes of its first recorded size bytes.
+    fn is_appended(
+        &self,
+        file_abs: &Path,
+        file: &Path,
+        options: &CheckOptions,
+        hasher: &mut Hasher,
+        timings: &mut HashTimings,
+    ) -> Result<bool, Box<dyn Error>> {
+        let rec = self.records.get(file).unwrap();
+        let algorithms = self.check_algorithms(file, options);
+        if algorithms.is_empty() {
+            return Ok(false);
+        }
+
+        let reader = BufReader::new(
+            OpenOptions::new()
+                .read(true)
+                .open(file_abs)
+                .map_err(|e| FileError::new(file_abs, e))?,
+        );
+
+        // Only the recorded prefix is read.
+        let start = timings.start();
+        let (prefix, _) = hasher
+            .compute_with_prefix(reader.take(rec.size), rec.size, &algorithms)
+            .map_err(|e| FileError::new(file_abs, e))?;
+        timings.record(file, rec.size, start);
+
+        Ok(prefix.is_some_and(|hashes| hashes.iter().all(|(a, v)| rec.hashes.get(a) == Some(v))))
+    }
+
+    /// Compares the inventory with another one and produces the report.
+    ///
+    /// The other inventory takes the place of the repository in the report:
+    /// records missing from this inventory were added, and records missing
+    /// from the other one were removed. The hashes are compared using the
+    /// algorithms shared by both records; records without a common algorithm
+    /// are only compared by size.
+    pub fn diff(&self, other: &Inventory) -> Report {
+        let mut report = Report::new();
+
+        for (path, rec) in &self.records {
+            let other_rec = match other.records.get(path) {
+                Some(other_rec) => other_rec,
+                None => {
+                    report.add_failure(path, FailureKind::MissingFromRepository);
+                    continue;
+                }
+            };
+
+            if rec.directory || other_rec.directory {
+                if rec.directory != other_rec.directory {
+                    report.add_failure(path, FailureKind::MissingDirectory);
+                }
+                continue;
+            }
+
+            if rec.symlink != other_rec.symlink {
+                report.add_failure(path, FailureKind::SymlinkMismatch);
+                continue;
+            } else if rec.symlink.is_some() {
+                continue;
+            }
+
+            if let (Some(btime), Some(other_btime)) = (rec.btime, other_rec.btime) {
+                if btime != other_btime {
+                    report.add_failure(path, FailureKind::CreatedTimeMismatch);
+                    continue;
+                }
+            }
+
+            if rec.size != other_rec.size {
+                report.add_failure(path, FailureKind::SizeMismatch);
+                continue;
+            }
+
+            match hashes_match(&rec.hashes, &other_rec.hashes) {
+                None => report.add_notice(path, NoticeKind::SizeComparedOnly),
+                Some(false) => report.add_failure(path, FailureKind::HashMismatch),
+                Some(true) => {}
+            }
+        }
+
+        other
+            .records
+            .keys()
+            .filter(|p| !self.records.contains_key(*p))
+            .for_each(|p| report.add_failure(p, FailureKind::MissingFromInventory));
+
+        report
+    }
+
+    /// Compares the inventory with a checksum manifest and produces the
+    /// report.
+    ///
+    /// The manifest takes the place of the repository in the report, like the
+    /// other inventory in `diff()`. Manifests do not list sizes, so only the
+    /// hashes of the algorithms both the record and the manifest have are
+    /// compared; records without a common algorithm are reported as not
+    /// hash-checked. Empty directories and symbolic links that are not
+    /// followed are not expected to be listed.
+    pub fn diff_manifest(&self, manifest: Vec<SumfileEntry>) -> Report {
+        let mut report = Report::new();
+
+        let mut listed: BTreeMap<PathBuf, BTreeMap<HashAlgorithm, HashValue>> = BTreeMap::new();
+        for entry in manifest {
+            listed
+                .entry(entry.path)
+                .or_default()
+                .insert(entry.algorithm, entry.value);
+        }
+
+        for (path, rec) in &self.records {
+            let hashes = listed.get(path);
+            match (hashes, rec.directory, rec.symlink.is_some()) {
+                (None, true, _) | (None, _, true) => {}
+                (None, false, false) => {
+                    report.add_failure(path, FailureKind::MissingFromRepository)
+                }
+                (Some(_), true, _) => report.add_failure(path, FailureKind::MissingDirectory),
+                (Some(_), _, true) => report.add_failure(path, FailureKind::SymlinkMismatch),
+                (Some(hashes), false, false) => match hashes_match(&rec.hashes, hashes) {
+                    None => report.add_notice(path, NoticeKind::HashSkipped),
+                    Some(false) => report.add_failure(path, FailureKind::HashMismatch),
+                    Some(true) => {}
+                },
+            }
+        }
+
+        listed
+            .keys()
+            .filter(|p| !self.records.contains_key(*p))
+            .for_each(|p| report.add_failure(p, FailureKind::MissingFromInventory));
+
+        report
+    }
+
+    /// Verifies the repository against a checksum manifest, which takes the
+    /// place of the inventory records.
+    ///
+    /// The repository is walked with the default configuration, leaving out
+    /// the excluded paths of the options, and every listed file is hashed
+    /// with the algorithms of its manifest lines. Files missing from the
+    /// repository or from the manifest and hash mismatches are reported as
+    /// by `check()`. Sizes are not compared, since manifests do not list
+    /// them. The progress is reported to the observer, if any.
+    pub fn check_sumfile(
+        repository: &Path,
+        manifest: Vec<SumfileEntry>,
+        options: &CheckOptions,
+        progress: Option<&mut dyn ProgressObserver>,
+    ) -> Result<Report, Box<dyn Error>> {
+        let mut ignored = ();
+        let progress = progress.unwrap_or(&mut ignored);
+
+        let mut listed: BTreeMap<PathBuf, BTreeMap<HashAlgorithm, HashValue>> = BTreeMap::new();
+        for entry in manifest {
+            listed
+                .entry(entry.path)
+                .or_default()
+                .insert(entry.algorithm, entry.value);
+        }
+
+        let algorithms: BTreeSet<_> = listed.values().flat_map(|h| h.keys().copied()).collect();
+        let mut hasher = Hasher::new(algorithms.into_iter());
+        hasher.set_rate_limit(options.rate_limit);
+        if let Some(size) = options.buffer_size {
+            hasher.set_buffer_size(size);
+        }
+
+        let walker = Inventory::new(Configuration::default());
+        let mut files = BTreeSet::new();
+        for file in walker.repo_iter(repository, true, &options.excluded_paths)? {
+            files.insert(file?);
+        }
+
+        let mut report = Report::new();
+        progress.on_start(listed.len(), 0);
+        for (file, hashes) in &listed {
+            if !files.contains(file) {
+                if options.report_missing(&mut report, file) {
+                    progress.on_failure(file, FailureKind::MissingFromRepository);
+                }
+                continue;
+            }
+
+            debug!("Verifying file {:?}", file);
+            let selected: Vec<_> = hashes.keys().copied().collect();
+            match hasher.compute_file_selected(repository.join(file), &selected) {
+                Ok((size, computed)) => {
+                    if computed.iter().any(|(a, v)| hashes[a] != *v) {
+                        report.add_failure(file, FailureKind::HashMismatch);
+                        progress.on_failure(file, FailureKind::HashMismatch);
+                    } else {
+                        options.report_passed(&mut report, file);
+                    }
+                    progress.on_file(file, size);
+                }
+                Err(err) => options
+                    .error_policy
+                    .handle(file, Box::new(err), &mut report)?,
+            }
+        }
+
+        for file in files.iter().filter(|p| !listed.contains_key(*p)) {
+            report.add_failure(file, FailureKind::MissingFromInventory);
+            progress.on_failure(file, FailureKind::MissingFromInventory);
+        }
+
+        progress.on_finish(&report);
+
+        Ok(report)
+    }
+
+    /// Creates a hasher of the algorithms selected by the check options,
+    /// making sure they are recorded in the inventory.
+    fn check_hasher(&self, options: &CheckOptions) -> Result<Hasher, InventoryError> {
+        let all = self.configuration.all_algorithms();
+        if let Some(algorithm) = options.hash_algorithms.iter().find(|a| !all.contains(a)) {
+            return Err(InventoryError::AlgorithmNotPresent(*algorithm));
+        }
+
+        let algorithms = all.into_iter().filter(|a| options.selects(*a)).collect();
+        self.hasher(
+            algorithms,
+            options.hmac_key.as_deref(),
+            options.rate_limit,
+            options.buffer_size,
+        )
+    }
+
+    /// Creates a hasher of the algorithms, making sure that the HMAC key, if
+    /// any, matches the inventory.
+    fn hasher(
+        &self,
+        algorithms: Vec<HashAlgorithm>,
+        key: Option<&[u8]>,
+        rate_limit: Option<u64>,
+        buffer_size: Option<usize>,
+    ) -> Result<Hasher, InventoryError> {
+        self.configuration.check_key(key)?;
+
+        let mut hasher = Hasher::new(algorithms.into_iter());
+        hasher.set_key(key).set_rate_limit(rate_limit);
+        if let Some(size) = buffer_size {
+            hasher.set_buffer_size(size);
+        }
+        Ok(hasher)
+    }
+
+    /// Returns the algorithms to verify the file with the given path: the
+    /// ones used for the file, restricted to the ones selected by the check
+    /// options.
+    fn check_algorithms(&self, path: &Path, options: &CheckOptions) -> Vec<HashAlgorithm> {
+        self.configuration
+            .algorithms_for(path)
+            .iter()
+            .copied()
+            .filter(|a| options.selects(*a))
+            .collect()
+    }
+
+    /// Updates the inventory by adding new files and removing missing files.
+    ///
+    /// The configuration, including the version of the app that built the
+    /// inventory, is left as is, so updating an inventory of an unchanged
+    /// repository does not change its serialized form.
+    ///
+    /// Records of append-aware inventories are also updated for the files
+    /// that have been appended to.
+    ///
+    /// Returns the outcome of the update: the report of the files that could
+    /// not be read, if the error policy records them, and the paths of the
+    /// records that have been added, updated or removed, so that an unchanged
+    /// inventory need not be written. The progress of adding the new files is
+    /// reported to the observer, if any.
+    ///
+    /// If checkpoints are enabled by the options, the checkpoint callback is
+    /// called after every so many added files with the inventory, whose
+    /// totals and seal are up to date, so that an interrupted update leaves
+    /// a valid inventory behind.
+    pub fn update(
+        &mut self,
+        repository: &Path,
+        remove_missing: bool,
+        options: &BuildOptions,
+        progress: Option<&mut dyn ProgressObserver>,
+        mut checkpoint: Option<&mut Checkpoint<'_>>,
+    ) -> Result<UpdateOutcome, Box<dyn Error>> {
+        let mut ignored = ();
+        let progress = progress.unwrap_or(&mut ignored);
+        let mut hasher = self.hasher(
+            self.configuration.all_algorithms(),
+            options.hmac_key.as_deref(),
+            options.rate_limit,
+            options.buffer_size,
+        )?;
+        let mut timings = HashTimings::new();
+
+        let delta = self.update_delta(repository, options)?;
+        self.check_free_space(delta.added.keys(), options)?;
+
+        // Add the files missing from the inventory.
+        let mut report = Report::new();
+        progress.on_start(delta.added.len(), delta.added_size(repository));
+        let mut added = Vec::new();
+        for (file, path) in &delta.added {
+            let file_abs = repository.join(path);
+            let checkpoint_due = match self.add_file_as(&file_abs, file, &mut hasher, &mut timings)
+            {
+                Ok(()) => {
+                    added.push(file.clone());
+                    options
+                        .checkpoint_every
+                        .is_some_and(|n| added.len().is_multiple_of(n))
+                }
+                Err(err) => {
+                    options.error_policy.handle(file, err, &mut report)?;
+                    false
+                }
+            };
+            self.observe_file(file, &report, progress);
+
+            if let (true, Some(checkpoint)) = (checkpoint_due, &mut checkpoint) {
+                self.update_totals();
+                self.reseal()?;
+                checkpoint(self)?;
+            }
+        }
+
+        // Record the new contents of the files that have been appended to.
+        let mut appended = Vec::new();
+        for (file, path) in &delta.grown {
+            let file_abs = repository.join(path);
+            match self.update_appended(&file_abs, file, &mut hasher, &mut timings) {
+                Ok(()) => appended.push(file.clone()),
+                Err(err) => options.error_policy.handle(file, err, &mut report)?,
+            }
+        }
+
+        timings.report();
+        progress.on_finish(&report);
+
+        // If enabled, remove missing files from the inventory.
+        let removed = if remove_missing {
+            for file in &delta.missing {
+                self.records.remove(file);
+            }
+            delta.missing
+        } else {
+            Vec::new()
+        };
+
+        self.update_totals();
+        self.reseal()?;
+
+        Ok(UpdateOutcome {
+            report,
+            added,
+            appended,
+            removed,
+        })
+    }
+
+    /// Computes the records that updating the inventory would add and the
+    /// ones it would remove if enabled, without hashing any files.
+    ///
+    /// Repository files that cannot be matched to a record are logged and
+    /// skipped, as in `update()`.
+    pub fn update_delta(&self, repository: &Path, options: &BuildOptions) -> IoResult<UpdateDelta> {
+        let (repository_paths, unmatched) = self.repo_keys(
+            repository,
+            options.strip_components,
+            &options.excluded_paths,
+        )?;
+
+        for path in unmatched {
+            warn!(
+                "Skipping file that cannot be matched to a record: {:?}",
+                path
+            );
+        }
+
+        let missing = self
+            .records
+            .keys()
+            .filter(|p| !repository_paths.contains_key(*p))
+            .cloned()
+            .collect();
+        let grown = if self.configuration.append_aware {
+            repository_paths
+                .iter()
+                .filter(|(p, path)| self.is_grown(p, &repository.join(path)))
+                .map(|(p, path)| (p.clone(), path.clone()))
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
+        let added = repository_paths
+            .into_iter()
+            .filter(|(p, _)| !self.records.contains_key(p))
+            .collect();
+
+        Ok(UpdateDelta {
+            added,
+            missing,
+            grown,
+        })
+    }
+
+    /// Returns the repository file paths keyed by the record paths they are
+    /// matched against, which have the given number of leading components
+    /// stripped.
+    ///
+    /// Paths that cannot be matched, because they have too few components or
+    /// clash with a path under another stripped directory, are returned
+    /// separately.
+    fn repo_keys<P>(
+        &self,
+        repository: P,
+        strip_components: usize,
+        excluded: &[PathBuf],
+    ) -> IoResult<(BTreeMap<PathBuf, PathBuf>, Vec<PathBuf>)>
+    where
+        P: AsRef<Path>,
+    {
+        let mut keys = BTreeMap::new();
+        let mut unmatched = Vec::new();
+
+        for path in self.repo_iter(repository, false, excluded)? {
+            let path = path?;
+            let key: PathBuf = path.components().skip(strip_components).collect();
+            if key.as_os_str().is_empty() || keys.contains_key(&key) {
+                unmatched.push(path);
+            } else {
+                keys.insert(key, path);
+            }
+        }
+
+        Ok((keys, unmatched))
+    }
+
+    /// Returns the repository paths of the given record paths that are
+    /// present in the repository, keyed by the record paths, without walking
+    /// the repository.
+    ///
+    /// Paths that cannot be looked up for other reasons than being absent
+    /// are kept, so that verifying them reports the error.
+    fn present_keys(repository: &Path, files: &BTreeSet<PathBuf>) -> BTreeMap<PathBuf, PathBuf> {
+        files
+            .iter()
+            .filter(|p| {
+                !matches!(fs::symlink_metadata(repository.join(p)),
+                    Err(e) if e.kind() == ErrorKind::NotFound)
+            })
+            .map(|p| (p.clone(), p.clone()))
+            .collect()
+    }
+
+    /// Returns a set of repository file paths, failing as soon as the walk
+    /// discovers more files than the limit, if any.
+    fn repo_files_limited<P>(
+        &self,
+        repository: P,
+        max_files: Option<usize>,
+        excluded: &[PathBuf],
+    ) -> Result<BTreeSet<PathBuf>, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let mut files = BTreeSet::new();
+
+        for file in self.repo_iter(repository, false, excluded)? {
+            files.insert(file?);
+            if let Some(limit) = max_files {
+                if files.len() > limit {
+                    return Err(Box::new(InventoryError::TooManyFiles(files.len(), limit)));
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Returns an iterator over the repository files.
+    fn repo_iter<P>(
+        &self,
+        repository: P,
+        sorted: bool,
+        excluded: &[PathBuf],
+    ) -> IoResult<impl Iterator<Item = IoResult<PathBuf>>>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(RepositoryIterator::new(
+            RelativePathIterator::new(
+                &repository,
+                self.configuration.follow_symlink_dirs,
+                self.configuration.excluded_types(),
+                if self.configuration.skip_vcs {
+                    util::VCS_DIRS
+                } else {
+                    &[]
+                },
+                self.configuration.ignore_files,
+                self.configuration.include_empty_dirs,
+                sorted,
+            )?,
+            repository.as_ref(),
+            &self.configuration,
+            excluded,
+        ))
+    }
+
+    /// Returns the record key of a normalized path according to the path
+    /// encoding of the configuration.
+    fn encode_key(&self, key: &Path) -> Result<PathBuf, InventoryError> {
+        if key.to_str().is_some() {
+            return Ok(key.to_path_buf());
+        }
+
+        match self.configuration.path_encoding {
+            PathEncoding::Utf8 => Err(InventoryError::NonUtf8Path(key.to_path_buf())),
+            PathEncoding::Lossy => {
+                warn!(
+                    "Recording the path {:?} lossily, since it is not valid UTF-8",
+                    key
+                );
+                Ok(PathBuf::from(key.to_string_lossy().into_owned()))
+            }
+            PathEncoding::Bytes => Ok(key.to_path_buf()),
+        }
+    }
+
+    /// Returns the target of the symbolic link if the file is a link that is
+    /// not followed according to the configuration, or `None` otherwise.
+    fn unfollowed_symlink(&self, abs_path: &Path) -> IoResult<Option<PathBuf>> {
+        if !fs::symlink_metadata(abs_path)?.file_type().is_symlink() {
+            return Ok(None);
+        }
+
+        // Links to directories only get here if the directory walk does not
+        // follow them, and cannot be hashed through anyway.
+        if !self.configuration.follow_symlink_files || abs_path.is_dir() {
+            Ok(Some(fs::read_link(abs_path)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Produces a file record for the specified file and adds it to the inventory.
+    fn add_file<P: AsRef<Path>>(
+        &mut self,
+        repository: P,
+        rel_path: P,
+        hasher: &mut Hasher,
+        timings: &mut HashTimings,
+    ) -> Result<(), Box<dyn Error>> {
+        // Produce the absolute path to the file.
+        let mut abs_path = repository.as_ref().to_path_buf();
+        abs_path.push(&rel_path);
+
+        self.add_file_as(&abs_path, rel_path.as_ref(), hasher, timings)
+    }
+
+    /// Returns the hashes of the file taken from the trusted extended
+    /// attribute, or `None` if the attribute is not configured, not set, or
+    /// does not hold a valid hash value.
+    fn xattr_hashes(&self, abs_path: &Path, key: &Path) -> Option<Vec<(HashAlgorithm, HashValue)>> {
+        let name = self.configuration.trust_xattr.as_ref()?;
+        if self.configuration.is_keyed() {
+            return None;
+        }
+
+        // A single attribute can only hold the hash of a single algorithm.
+        let algorithm = match self.configuration.algorithms_for(key) {
+            [algorithm] => *algorithm,
+            _ => return None,
+        };
+
+        let value = util::xattr(abs_path, name)?;
+        let bytes = if value.len() == algorithm.digest_len() {
+            value.into_boxed_slice()
+        } else {
+            util::hex_string_to_bytes(std::str::from_utf8(&value).ok()?.trim())?
+        };
+
+        if bytes.len() != algorithm.digest_len() {
+            debug!("Ignoring invalid hash attribute of {:?}", abs_path);
+            return None;
+        }
+
+        Some(vec![(algorithm, HashValue::from(bytes))])
+    }
+
+    /// Produces a file record for the file with the specified absolute path
+    /// and adds it to the inventory under the given key.
+    fn add_file_as(
+        &mut self,
+        abs_path: &Path,
+        key: &Path,
+        hasher: &mut Hasher,
+        timings: &mut HashTimings,
+    ) -> Result<(), Box<dyn Error>> {
+        let key = self.encode_key(&normalize_key(key)?)?;
+        let key = key.as_path();
+        debug!("Adding file {:?}", key);
+
+        // Record symbolic links that are not followed without hashing.
+        let target = self
+            .unfollowed_symlink(abs_path)
+            .or_else(|e| file_err!(abs_path, e))?;
+        if let Some(target) = target {
+            let rec = Record::new_symlink(target);
+            self.records.insert(key.to_path_buf(), rec);
+            return Ok(());
+        }
+
+        let attr = abs_path.metadata().or_else(|e| file_err!(abs_path, e))?;
+
+        // Directories only get here if they are empty and recorded.
+        if attr.is_dir() {
+            self.records
+                .insert(key.to_path_buf(), Record::new_directory());
+            return Ok(());
+        }
+
+        // Take the hash from the trusted extended attribute, if present.
+        if let Some(hashes) = self.xattr_hashes(abs_path, key) {
+            let mut rec = Record::new_from_xattr(attr.len(), hashes);
+            rec.mtime = self.recorded_mtime(&attr);
+            rec.btime = self.recorded_btime(&attr);
+            self.records.insert(key.to_path_buf(), rec);
+            return Ok(());
+        }
+
+        // Compute the hash(es) of the file contents.
+        let algorithms = self.configuration.algorithms_for(key).to_vec();
+        let start = timings.start();
+        let (size, hashes) = hasher.compute_file_selected(abs_path, &algorithms)?;
+        timings.record(key, size, start);
+
+        let mut rec = Record::new(size, hashes);
+        rec.mtime = self.recorded_mtime(&attr);
+        rec.btime = self.recorded_btime(&attr);
+        self.records.insert(key.to_path_buf(), rec);
+
+        Ok(())
+    }
+
+    /// Checks if a recorded regular file is now larger than recorded.
+    fn is_grown(&self, key: &Path, abs_path: &Path) -> bool {
+        match self.records.get(key) {
+            Some(rec) if !rec.directory && rec.symlink.is_none() => {
+                fs::metadata(abs_path).is_ok_and(|m| m.is_file() && m.len() > rec.size)
+            }
+            _ => false,
+        }
+    }
+
+    /// Records the new contents of a file that has grown, if it still starts
+    /// with the recorded contents.
+    ///
+    /// Otherwise, the file has been modified rather than appended to, and the
+    /// record is left for the verification to report.
+    fn update_appended(
+        &mut self,
+        abs_path: &Path,
+        key: &Path,
+        hasher: &mut Hasher,
+        timings: &mut HashTimings,
+    ) -> Result<(), Box<dyn Error>> {
+        let rec = self.records.get(key).unwrap();
+        let attr = abs_path
+            .metadata()
+            .map_err(|e| FileError::new(abs_path, e))?;
+        let reader = BufReader::new(
+            OpenOptions::new()
+                .read(true)
+                .open(abs_path)
+                .map_err(|e| FileError::new(abs_path, e))?,
+        );
+
+        // The recorded prefix and the whole file are hashed in a single pass.
+        let algorithms: Vec<_> = rec.hashes.keys().copied().collect();
+        let start = timings.start();
+        let (prefix, hashes) = hasher
+            .compute_with_prefix(reader.take(attr.len()), rec.size, &algorithms)
+            .map_err(|e| FileError::new(abs_path, e))?;
+        timings.record(key, attr.len(), start);
+
+        let appended = prefix.is_some_and(|prefix| {
+            !prefix.is_empty() && prefix.iter().all(|(a, v)| rec.hashes.get(a) == Some(v))
+        });
+        if !appended {
+            warn!("Not updating the record of the modified file {:?}", key);
+            return Ok(());
+        }
+
+        debug!("Updating the record of the appended file {:?}", key);
+        let mut rec = Record::new(attr.len(), hashes);
+        rec.mtime = self.recorded_mtime(&attr);
+        rec.btime = self.recorded_btime(&attr);
+        self.records.insert(key.to_path_buf(), rec);
+
+        Ok(())
+    }
+
+    /// Returns the modification time of the file to record, if enabled.
+    fn recorded_mtime(&self, attr: &Metadata) -> Option<u64> {
+        if self.configuration.record_mtime {
+            mtime_nanos(attr)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the creation time of the file to record, if enabled and
+    /// available.
+    fn recorded_btime(&self, attr: &Metadata) -> Option<u64> {
+        if self.configuration.record_btime {
+            btime_nanos(attr)
+        } else {
+            None
+        }
+    }
+}
+
+/// Compares two sets of hashes using the algorithms present in both.
+///
+/// Returns `None` if they have no algorithm in common.
+fn hashes_match(
+    hashes: &BTreeMap<HashAlgorithm, HashValue>,
+    other: &BTreeMap<HashAlgorithm, HashValue>,
+) -> Option<bool> {
+    let mut common = hashes
+        .iter()
+        .filter_map(|(a, v)| other.get(a).map(|other_v| (v, other_v)))
+        .peekable();
+    common.peek()?;
+    Some(common.all(|(v, other_v)| v == other_v))
+}
+
+/// Returns the modification time of the file in nanoseconds since the Unix
+/// epoch, or `None` if it is not available or out of range.
+fn mtime_nanos(attr: &Metadata) -> Option<u64> {
+    let mtime = attr.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    u64::try_from(mtime.as_nanos()).ok()
+}
+
+/// Returns the creation time of the file in nanoseconds since the Unix
+/// epoch, or `None` if the platform or filesystem does not provide it or it
+/// is out of range.
+fn btime_nanos(attr: &Metadata) -> Option<u64> {
+    let btime = attr.created().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    u64::try_from(btime.as_nanos()).ok()
+}
+
+/// Returns the record key of a path that is not valid UTF-8, or `None` on
+/// non-Unix platforms, where the path bytes are not exposed.
+#[cfg(unix)]
+fn encoded_key(path: &Path) -> Option<String> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Some(format!(
+        "{}{}",
+        ENCODED_PATH_PREFIX,
+        util::bytes_to_base64_string(path.as_os_str().as_bytes())
+    ))
+}
+
+#[cfg(not(unix))]
+fn encoded_key(_path: &Path) -> Option<String> {
+    None
+}
+
+/// Returns the path of an encoded record key (without the prefix).
+#[cfg(unix)]
+fn decoded_key(encoded: &str) -> Option<PathBuf> {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    util::base64_string_to_bytes(encoded).map(|b| PathBuf::from(OsString::from_vec(b)))
+}
+
+#[cfg(not(unix))]
+fn decoded_key(_encoded: &str) -> Option<PathBuf> {
+    None
+}
+
+/// Returns the key of the record with the given path, encoding the paths
+/// that are not valid UTF-8.
+fn record_key(path: &Path) -> String {
+    match path.to_str() {
+        Some(key) => key.to_string(),
+        None => encoded_key(path).unwrap_or_else(|| path.to_string_lossy().into_owned()),
+    }
+}
+
+/// Serializes the records keyed by the paths, encoding the ones that are
+/// not valid UTF-8.
+fn serialize_records<S>(
+    records: &BTreeMap<PathBuf, Record>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_map(
+        records
+            .iter()
+            .map(|(path, record)| (record_key(path), record)),
+    )
+}
+
+/// A node of the nested layout of the records: either a record, or the
+/// nodes of a directory keyed by their names.
+enum NestedNode<'a> {
+    Record(&'a Record),
+    Dir(BTreeMap<String, NestedNode<'a>>),
+}
+
+impl Serialize for NestedNode<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            NestedNode::Record(record) => record.serialize(serializer),
+            NestedNode::Dir(nodes) => serializer.collect_map(nodes),
+        }
+    }
+}
+
+/// Serializes the records in the nested layout, where the records of the
+/// files in a directory are nested under the directory name followed by
+/// `NESTED_DIR_SUFFIX`.
+fn serialize_nested_records<S>(
+    records: &BTreeMap<PathBuf, Record>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut root = BTreeMap::new();
+    for (path, record) in records {
+        let key = record_key(path);
+
+        // Encoded paths are opaque, so they stay flat.
+        if key.starts_with(ENCODED_PATH_PREFIX) {
+            root.insert(key, NestedNode::Record(record));
+            continue;
+        }
+
+        let mut components: Vec<_> = Path::new(&key)
+            .iter()
+            .map(|c| c.to_string_lossy().into_owned())
+            .collect();
+        let name = components.pop().unwrap_or(key);
+
+        let mut nodes = &mut root;
+        for dir in components {
+            // Directory keys end with the suffix, which no file name does, so
+            // they never collide with records.
+            nodes = match nodes
+                .entry(dir + NESTED_DIR_SUFFIX)
+                .or_insert_with(|| NestedNode::Dir(BTreeMap::new()))
+            {
+                NestedNode::Dir(nodes) => nodes,
+                NestedNode::Record(_) => unreachable!(),
+            };
+        }
+        nodes.insert(name, NestedNode::Record(record));
+    }
+
+    serializer.collect_map(&root)
+}
+
+impl Inventory {
+    /// Serializes the inventory, with the records in the nested layout if
+    /// enabled.
+    ///
+    /// The optional fields are omitted if unset, the same way the derived
+    /// implementations expect them.
+    fn serialize_layout<S: Serializer>(
+        &self,
+        serializer: S,
+        nested: bool,
+    ) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        /// The records in the selected layout.
+        struct Records<'a>(&'a BTreeMap<PathBuf, Record>, bool);
+
+        impl Serialize for Records<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                if self.1 {
+                    serialize_nested_records(self.0, serializer)
+                } else {
+                    serialize_records(self.0, serializer)
+                }
+            }
+        }
+
+        /// Serializes an optional field, or skips it if unset.
+        fn optional<S: SerializeStruct, T: Serialize>(
+            s: &mut S,
+            name: &'static str,
+            value: Option<&T>,
+        ) -> Result<(), S::Error> {
+            match value {
+                Some(value) => s.serialize_field(name, value),
+                None => s.skip_field(name),
+            }
+        }
+
+        let len = 2
+            + self.total_files.is_some() as usize
+            + self.total_bytes.is_some() as usize
+            + self.seal.is_some() as usize
+            + self.description.is_some() as usize
+            + self.repository.is_some() as usize
+            + !self.metadata.is_empty() as usize;
+        let mut s = serializer.serialize_struct("Inventory", len)?;
+        s.serialize_field("configuration", &self.configuration)?;
+        optional(&mut s, "total_files", self.total_files.as_ref())?;
+        optional(&mut s, "total_bytes", self.total_bytes.as_ref())?;
+        s.serialize_field("records", &Records(&self.records, nested))?;
+        optional(&mut s, "seal", self.seal.as_ref())?;
+        optional(&mut s, "description", self.description.as_ref())?;
+        optional(&mut s, "repository", self.repository.as_ref())?;
+        optional(
+            &mut s,
+            "metadata",
+            Some(&self.metadata).filter(|m| !m.is_empty()),
+        )?;
+        s.end()
+    }
+}
+
+impl Serialize for Inventory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.serialize_layout(serializer, false)
+    }
+}
+
+/// An inventory serialized with the records in the nested layout, which
+/// stores every directory name once instead of repeating it in the paths of
+/// all of its files.
+pub struct NestedInventory<'a>(pub &'a Inventory);
+
+impl Serialize for NestedInventory<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_layout(serializer, true)
+    }
+}
+
+/// Normalizes a record path, so that it matches the paths produced by the
+/// directory walk.
+fn normalize_key(path: &Path) -> Result<PathBuf, InventoryError> {
+    match util::normalize_path(path) {
+        Some(key) if !key.as_os_str().is_empty() => Ok(key),
+        _ => Err(InventoryError::InvalidRecordPath(path.to_path_buf())),
+    }
+}
+
+/// Number of the slowest files listed at the trace verbosity.
+const SLOWEST_FILES_COUNT: usize = 10;
+
+/// Records that updating an inventory would add or remove.
+pub struct UpdateDelta {
+    /// Record paths of the files to add and their repository paths.
+    added: BTreeMap<PathBuf, PathBuf>,
+
+    /// Record paths of the files missing from the repository.
+    missing: Vec<PathBuf>,
+
+    /// Record paths and repository paths of the files that have grown, if
+    /// the inventory is append-aware.
+    grown: BTreeMap<PathBuf, PathBuf>,
+}
+
+impl UpdateDelta {
+    /// Returns the record paths of the files to add, ordered by path.
+    pub fn added(&self) -> impl Iterator<Item = &Path> {
+        self.added.keys().map(|p| p.as_path())
+    }
+
+    /// Returns the record paths of the files missing from the repository,
+    /// ordered by path.
+    pub fn missing(&self) -> impl Iterator<Item = &Path> {
+        self.missing.iter().map(|p| p.as_path())
+    }
+
+    /// Returns the record paths of the files that have grown and whose
+    /// records are updated if they have been appended to, ordered by path.
+    pub fn grown(&self) -> impl Iterator<Item = &Path> {
+        self.grown.keys().map(|p| p.as_path())
+    }
+
+    /// Returns the size of the file to add with the given record path, or
+    /// `None` if it cannot be accessed.
+    pub fn added_file_size(&self, repository: &Path, file: &Path) -> Option<u64> {
+        let path = self.added.get(file)?;
+        fs::metadata(repository.join(path)).ok().map(|m| m.len())
+    }
+
+    /// Returns the total size of the files to add, counting the files that
+    /// cannot be accessed as empty.
+    pub fn added_size(&self, repository: &Path) -> u64 {
+        self.added
+            .values()
+            .map(|p| fs::metadata(repository.join(p)).map_or(0, |m| m.len()))
+            .sum()
+    }
+}
+
+/// Outcome of updating an inventory.
+pub struct UpdateOutcome {
+    /// Report of the files that could not be read.
+    report: Report,
+
+    /// Record paths of the added files, ordered by path.
+    added: Vec<PathBuf>,
+
+    /// Record paths of the files whose records are updated, since they have
+    /// been appended to, ordered by path.
+    appended: Vec<PathBuf>,
+
+    /// Record paths of the removed records, ordered by path.
+    removed: Vec<PathBuf>,
+}
+
+impl UpdateOutcome {
+    /// Returns the report of the files that could not be read, if the error
+    /// policy records them.
+    pub fn report(&self) -> &Report {
+        &self.report
+    }
+
+    /// Returns the record paths of the added files.
+    pub fn added(&self) -> impl Iterator<Item = &Path> {
+        self.added.iter().map(|p| p.as_path())
+    }
+
+    /// Returns the record paths of the files whose records are updated,
+    /// since they have been appended to.
+    pub fn appended(&self) -> impl Iterator<Item = &Path> {
+        self.appended.iter().map(|p| p.as_path())
+    }
+
+    /// Returns the record paths of the removed records.
+    pub fn removed(&self) -> impl Iterator<Item = &Path> {
+        self.removed.iter().map(|p| p.as_path())
+    }
+
+    /// Returns `true` if any records have been added, updated or removed.
+    pub fn is_changed(&self) -> bool {
+        !(self.added.is_empty() && self.appended.is_empty() && self.removed.is_empty())
+    }
+}
+
+/// A sequentially read source of the repository state, such as an archive of
+/// the repository or an external listing of its files.
+trait EntrySource {
+    /// Returns the next entry, or `None` at the end of the source.
+    fn next_entry(&mut self) -> Result<Option<Entry>, Box<dyn Error>>;
+
+    /// Returns the hashes of the current entry for the given algorithms, or
+    /// for the ones of them that are available.
+    fn entry_hashes(
+        &mut self,
+        entry: &Entry,
+        algorithms: &[HashAlgorithm],
+        hasher: &mut Hasher,
+        timings: &mut HashTimings,
+    ) -> Result<Vec<(HashAlgorithm, HashValue)>, Box<dyn Error>>;
+}
+
+impl<R: BufRead> EntrySource for TarReader<R> {
+    fn next_entry(&mut self) -> Result<Option<Entry>, Box<dyn Error>> {
+        Ok(TarReader::next_entry(self)?)
+    }
+
+    fn entry_hashes(
+        &mut self,
+        entry: &Entry,
+        algorithms: &[HashAlgorithm],
+        hasher: &mut Hasher,
+        timings: &mut HashTimings,
+    ) -> Result<Vec<(HashAlgorithm, HashValue)>, Box<dyn Error>> {
+        // The reader yields the contents of the current entry.
+        let start = timings.start();
+        let hashes = hasher.compute_selected(self, algorithms)?;
+        timings.record(&entry.path, entry.size, start);
+        Ok(hashes)
+    }
+}
+
+impl<R: Read + Seek> EntrySource for ZipReader<R> {
+    fn next_entry(&mut self) -> Result<Option<Entry>, Box<dyn Error>> {
+        Ok(ZipReader::next_entry(self)?)
+    }
+
+    fn entry_hashes(
+        &mut self,
+        entry: &Entry,
+        algorithms: &[HashAlgorithm],
+        hasher: &mut Hasher,
+        timings: &mut HashTimings,
+    ) -> Result<Vec<(HashAlgorithm, HashValue)>, Box<dyn Error>> {
+        // The reader yields the decompressed contents of the current entry.
+        let start = timings.start();
+        let hashes = hasher.compute_selected(self, algorithms)?;
+        timings.record(&entry.path, entry.size, start);
+        Ok(hashes)
+    }
+}
+
+/// An entry source over the files of an external listing.
+struct ListingSource<I> {
+    /// The remaining listed files.
+    entries: I,
+
+    /// Listed hashes of the current file.
+    hashes: Vec<(HashAlgorithm, HashValue)>,
+}
+
+impl<I: Iterator<Item = ListingEntry>> EntrySource for ListingSource<I> {
+    fn next_entry(&mut self) -> Result<Option<Entry>, Box<dyn Error>> {
+        Ok(self.entries.next().map(|e| {
+            self.hashes = e.hashes;
+            Entry {
+                path: e.path,
+                size: e.size,
+                kind: e.kind,
+                mtime: None,
+            }
+        }))
+    }
+
+    fn entry_hashes(
+        &mut self,
+        _entry: &Entry,
+        algorithms: &[HashAlgorithm],
+        _hasher: &mut Hasher,
+        _timings: &mut HashTimings,
+    ) -> Result<Vec<(HashAlgorithm, HashValue)>, Box<dyn Error>> {
+        Ok(self
+            .hashes
+            .iter()
+            .filter(|(a, _)| algorithms.contains(a))
+            .cloned()
+            .collect())
+    }
+}
+
+/// Returns the total size of the repository files, counting the files that
+/// cannot be accessed as empty.
+fn files_size(repository: &Path, files: &BTreeSet<PathBuf>) -> u64 {
+    files
+        .iter()
+        .map(|p| fs::metadata(repository.join(p)).map_or(0, |m| m.len()))
+        .sum()
+}
+
+/// Per-file hashing time diagnostics.
+///
+/// The timings are only collected if trace logging is enabled: each hashed
+/// file is logged with its size and hashing duration, and the slowest files
+/// are listed at the end of the operation.
+struct HashTimings {
+    /// Collect the timings.
+    enabled: bool,
+
+    /// The slowest files seen so far, in a min-heap bounded to
+    /// `SLOWEST_FILES_COUNT` entries.
+    slowest: BinaryHeap<Reverse<(Duration, PathBuf)>>,
+}
+
+impl HashTimings {
+    /// Creates a new timing tracker.
+    fn new() -> Self {
+        HashTimings {
+            enabled: log_enabled!(Level::Trace),
+            slowest: BinaryHeap::new(),
+        }
+    }
+
+    /// Returns the start time of the hashing, if the timings are collected.
+    fn start(&self) -> Option<Instant> {
+        if self.enabled {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    /// Records the hashing duration of a file.
+    fn record(&mut self, path: &Path, size: u64, start: Option<Instant>) {
+        if let Some(start) = start {
+            let duration = start.elapsed();
+            trace!("Hashed {:?} ({} bytes) in {:?}", path, size, duration);
+
+            self.slowest.push(Reverse((duration, path.to_path_buf())));
+            if self.slowest.len() > SLOWEST_FILES_COUNT {
+                self.slowest.pop();
+            }
+        }
+    }
+
+    /// Adds the slowest files seen by another timing tracker.
+    fn merge(&mut self, other: HashTimings) {
+        for entry in other.slowest {
+            self.slowest.push(entry);
+            if self.slowest.len() > SLOWEST_FILES_COUNT {
+                self.slowest.pop();
+            }
+        }
+    }
+
+    /// Lists the slowest files.
+    fn report(self) {
+        if self.slowest.is_empty() {
+            return;
+        }
+
+        trace!("Slowest files:");
+        for Reverse((duration, path)) in self.slowest.into_sorted_vec() {
+            trace!("  {:?}: {:?}", path, duration);
+        }
+    }
+}
+
+/// An iterator over the repository file paths.
+///
+/// This iterator honors the inventory settings (e.g. filters out hidden files
+/// if needed) and leaves out the explicitly excluded paths. Temporary files
+/// left behind by interrupted inventory writes are always filtered out.
+struct RepositoryIterator<I> {
+    iter: I,
+    root: PathBuf,
+    skip_hidden: bool,
+    since_mtime: Option<u64>,
+    excluded: Vec<PathBuf>,
+}
+
+impl<I: Iterator<Item = IoResult<PathBuf>>> RepositoryIterator<I> {
+    // Creates a new repository file iterator.
+    fn new(iter: I, root: &Path, config: &Configuration, excluded: &[PathBuf]) -> Self {
+        RepositoryIterator {
+            root: root.to_path_buf(),
+            skip_hidden: config.skip_hidden,
+            since_mtime: config.since_mtime,
+            excluded: excluded.to_vec(),
+            iter,
+        }
+    }
+}
+
+impl<I> RepositoryIterator<I> {
+    /// Checks if the file should be excluded from the inventory.
+    fn is_excluded(&self, path: &Path) -> bool {
+        // Leftover temporary inventory files are never inventoried.
+        util::is_temp_file(path)
+            || (self.skip_hidden && util::is_hidden(path))
+            || self.excluded.iter().any(|p| p == path)
+            || self.is_old(path)
+    }
+
+    /// Checks if the file is modified before the modification time cutoff.
+    ///
+    /// Directories and files that cannot be queried are never skipped: the
+    /// latter fail later with the usual errors.
+    fn is_old(&self, path: &Path) -> bool {
+        let since = match self.since_mtime {
+            Some(since) => since,
+            None => return false,
+        };
+
+        match fs::metadata(self.root.join(path)) {
+            Ok(attr) if !attr.is_dir() => mtime_nanos(&attr).is_some_and(|t| t < since),
+            _ => false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = IoResult<PathBuf>>> Iterator for RepositoryIterator<I> {
+    type Item = IoResult<PathBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Skip the excluded files, but pass the errors through.
+        loop {
+            match self.iter.next()? {
+                Ok(path) if self.is_excluded(&path) => continue,
+                item => return Some(item),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{create_files, TempDir};
+
+    /// A small repository with nested files.
+    const FILES: &[(&str, &str)] = &[
+        ("a.txt", "hello\n"),
+        ("sub/b.txt", "world\n"),
+        ("sub/deeper/c.txt", "!\n"),
+    ];
+
+    /// Builds an inventory of the repository with the default options.
+    fn build(configuration: Configuration, repository: &Path) -> Inventory {
+        let (inventory, report) =
+            Inventory::build(configuration, repository, &BuildOptions::new(), None).unwrap();
+        assert!(report.is_empty());
+        inventory
+    }
+
+    /// Serializes the inventory in the default format.
+    fn to_bytes(inventory: &Inventory) -> Vec<u8> {
+        let mut data = Vec::new();
+        inventory.write_to(&mut data, Format::Json, false).unwrap();
+        data
+    }
+
+    #[test]
+    fn reproducible_builds_are_byte_identical() {
+        let first = TempDir::new();
+        let second = TempDir::new();
+        create_files(first.path(), FILES);
+        create_files(second.path(), FILES);
+
+        let configuration = || {
+            Configuration::builder()
+                .hash_algorithms(vec![HashAlgorithm::Md5, HashAlgorithm::Sha1])
+                .reproducible(true)
+                .build()
+        };
+        let data = to_bytes(&build(configuration(), first.path()));
+        assert_eq!(data, to_bytes(&build(configuration(), first.path())));
+        assert_eq!(data, to_bytes(&build(configuration(), second.path())));
+
+        let inventory = Inventory::from_reader(&data[..], None).unwrap();
+        assert_eq!(inventory.configuration().version(), SCHEMA_VERSION);
+        let text = String::from_utf8(data).unwrap();
+        assert!(!text.contains(first.path().to_str().unwrap()));
+    }
+
+    #[test]
+    fn omits_unset_flags_from_the_configuration() {
+        let dir = TempDir::new();
+        create_files(dir.path(), FILES);
+        let configuration = |set: bool| {
+            Configuration::builder()
+                .hash_algorithms(vec![HashAlgorithm::Md5])
+                .skip_vcs(set)
+                .include_empty_dirs(set)
+                .build()
+        };
+        let text = |set: bool| {
+            String::from_utf8(to_bytes(&build(configuration(set), dir.path()))).unwrap()
+        };
+        let (unset, set) = (text(false), text(true));
+        for flag in &["skip_vcs", "include_empty_dirs"] {
+            assert!(!unset.contains(flag));
+            assert!(set.contains(&format!(r#""{}": true"#, flag)));
+        }
+    }
+
+    /// Returns an inventory in the JSON format with records at the given
+    /// paths.
+    fn json_with_paths(paths: &[&str]) -> String {
+        let records = paths
+            .iter()
+            .map(|path| {
+                format!(
+                    r#""{}": {{"hashes": {{"md5": "764efa883dda1e11db47671c4a3bbd9e"}}, "size": 3}}"#,
+                    path
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            r#"{{"configuration": {{"version": "0.1.1", "skip_hidden": false, "skip_vcs": false,
+                "include_empty_dirs": false, "hash_algorithms": ["md5"],
+                "follow_symlink_dirs": true, "follow_symlink_files": true}},
+              "records": {{{}}}}}"#,
+            records
+        )
+    }
+
+    #[test]
+    fn loading_normalizes_record_paths() {
+        let json = json_with_paths(&["./a.txt", "sub//b.txt", "sub/./c", "d.txt"]);
+        let inventory = Inventory::from_reader(json.as_bytes(), None).unwrap();
+        let paths: Vec<_> = inventory
+            .records()
+            .map(|(path, _)| path.to_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(paths, ["a.txt", "d.txt", "sub/b.txt", "sub/c"]);
+    }
+
+    #[test]
+    fn loading_rejects_paths_outside_the_repository() {
+        for path in &["../a.txt", "sub/../../a.txt", "/a.txt", "."] {
+            let json = json_with_paths(&[path]);
+            let err = Inventory::from_reader(json.as_bytes(), None).unwrap_err();
+            assert!(matches!(
+                *err.downcast::<InventoryError>().unwrap(),
+                InventoryError::InvalidRecordPath(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn loading_rejects_colliding_record_paths() {
+        let json = json_with_paths(&["a.txt", "./a.txt"]);
+        let err = Inventory::from_reader(json.as_bytes(), None).unwrap_err();
+        match *err.downcast::<InventoryError>().unwrap() {
+            InventoryError::RecordExists(path) => assert_eq!(path, Path::new("a.txt")),
+            err => panic!("unexpected error: {}", err),
+        }
+
+        // Paths equal component by component collide while the records are
+        // being read, before they are normalized.
+        let nested = json_with_paths(&["sub/b.txt"]).replace(
+            r#""sub/b.txt": "#,
+            r#""sub/": {"b.txt": {"hashes": {}, "size": 0}}, "sub/b.txt": "#,
+        );
+        for json in &[
+            json_with_paths(&["sub/b.txt", "sub//b.txt"]),
+            json_with_paths(&["sub/b.txt", "sub/./b.txt"]),
+            nested,
+        ] {
+            let err = Inventory::from_reader(json.as_bytes(), None).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("Record already exists in the inventory: \"sub/"),
+                "unexpected error: {}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn nested_records_store_directory_names_once() {
+        let repository = TempDir::new();
+        create_files(repository.path(), FILES);
+        create_files(repository.path(), &[("sub/deeper/d.txt", "?\n")]);
+        let inventory = build(Configuration::default(), repository.path());
+
+        let mut data = Vec::new();
+        inventory.write_to(&mut data, Format::Json, true).unwrap();
+        let text = String::from_utf8(data).unwrap();
+        assert!(!text.contains('\n'));
+        assert_eq!(text.matches(r#""sub/""#).count(), 1);
+        assert_eq!(text.matches(r#""deeper/""#).count(), 1);
+        assert!(!text.contains("sub/deeper"));
+
+        let read = Inventory::from_reader(text.as_bytes(), None).unwrap();
+        assert_eq!(to_bytes(&read), to_bytes(&inventory));
+    }
+
+    #[test]
+    fn reads_mixed_record_layouts() {
+        let json = json_with_paths(&["a.txt", "sub/b.txt"]).replace(
+            r#""sub/b.txt": "#,
+            r#""sub/": {"deeper/c.txt": {"hashes": {}, "size": 0}}, "sub/b.txt": "#,
+        );
+        let inventory = Inventory::from_reader(json.as_bytes(), None).unwrap();
+        let paths: Vec<_> = inventory.records().map(|(path, _)| path).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("a.txt"),
+                Path::new("sub/b.txt"),
+                Path::new("sub/deeper/c.txt")
+            ]
+        );
+    }
+
+    #[test]
+    fn primary_hash_is_the_strongest_one() {
+        let repository = TempDir::new();
+        create_files(repository.path(), FILES);
+
+        let cases = [
+            (vec![HashAlgorithm::Md5], HashAlgorithm::Md5),
+            (
+                vec![HashAlgorithm::Sha1, HashAlgorithm::Md5],
+                HashAlgorithm::Sha1,
+            ),
+            (
+                vec![
+                    HashAlgorithm::Sha3_512,
+                    HashAlgorithm::Md5,
+                    HashAlgorithm::Sha3_256,
+                ],
+                HashAlgorithm::Sha3_512,
+            ),
+        ];
+        for (algorithms, strongest) in cases {
+            let configuration = Configuration::builder().hash_algorithms(algorithms).build();
+            let inventory = build(configuration, repository.path());
+            let record = inventory.record(Path::new("a.txt")).unwrap();
+            let (algorithm, value) = record.primary_hash().unwrap();
+            assert_eq!(algorithm, strongest);
+            assert_eq!(value.as_bytes().len(), strongest.digest_len());
+        }
+
+        let json =
+            json_with_paths(&["a.txt"]).replace(r#""md5": "764efa883dda1e11db47671c4a3bbd9e""#, "");
+        let json = json.replace(r#""hash_algorithms": ["md5"]"#, r#""hash_algorithms": []"#);
+        let inventory = Inventory::from_reader(json.as_bytes(), None).unwrap();
+        assert!(inventory
+            .record(Path::new("a.txt"))
+            .unwrap()
+            .primary_hash()
+            .is_none());
+    }
+
+    /// Checks the repository with the default options.
+    fn check(inventory: &Inventory, repository: &Path) -> Report {
+        inventory
+            .check(repository, &CheckOptions::new(), None)
+            .unwrap()
+    }
+
+    /// Returns the sorted paths of the files in the report entry.
+    fn sorted<'a>(paths: Option<impl Iterator<Item = &'a Path>>) -> Vec<&'a Path> {
+        let mut paths: Vec<_> = paths.into_iter().flatten().collect();
+        paths.sort();
+        paths
+    }
+
+    /// Appends the data to the file.
+    fn append(path: &Path, data: &str) {
+        use std::io::Write;
+
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn append_aware_inventories_accept_appended_files() {
+        let repository = TempDir::new();
+        create_files(repository.path(), FILES);
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .append_aware(true)
+            .build();
+        let mut inventory = build(configuration, repository.path());
+
+        append(&repository.path().join("a.txt"), "more\n");
+        create_files(repository.path(), &[("sub/b.txt", "World\nmore\n")]);
+
+        let report = check(&inventory, repository.path());
+        assert_eq!(
+            sorted(report.by_notice(NoticeKind::Appended)),
+            [Path::new("a.txt")]
+        );
+        assert_eq!(report.failures(), [FailureKind::SizeMismatch]);
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::SizeMismatch)),
+            [Path::new("sub/b.txt")]
+        );
+
+        // Only the record of the appended file is updated.
+        let delta = inventory
+            .update_delta(repository.path(), &BuildOptions::new())
+            .unwrap();
+        assert_eq!(delta.grown().count(), 2);
+        inventory
+            .update(repository.path(), false, &BuildOptions::new(), None, None)
+            .unwrap();
+        assert_eq!(inventory.record(Path::new("a.txt")).unwrap().size(), 11);
+        assert_eq!(inventory.record(Path::new("sub/b.txt")).unwrap().size(), 6);
+
+        let report = check(&inventory, repository.path());
+        assert!(report.notices().is_empty());
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::SizeMismatch)),
+            [Path::new("sub/b.txt")]
+        );
+    }
+
+    #[test]
+    fn appended_files_fail_by_default() {
+        let repository = TempDir::new();
+        create_files(repository.path(), FILES);
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .build();
+        let inventory = build(configuration, repository.path());
+
+        append(&repository.path().join("a.txt"), "more\n");
+        let report = check(&inventory, repository.path());
+        assert!(report.notices().is_empty());
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::SizeMismatch)),
+            [Path::new("a.txt")]
+        );
+    }
+
+    #[test]
+    fn parallel_check_matches_single_thread() {
+        let repository = TempDir::new();
+        create_files(repository.path(), FILES);
+        let files: Vec<_> = (0..20)
+            .map(|i| (format!("many/{}.txt", i), i.to_string()))
+            .collect();
+        let files: Vec<_> = files
+            .iter()
+            .map(|(p, c)| (p.as_str(), c.as_str()))
+            .collect();
+        create_files(repository.path(), &files);
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .build();
+        let inventory = build(configuration, repository.path());
+
+        create_files(
+            repository.path(),
+            &[("sub/b.txt", "World\n"), ("new.txt", "")],
+        );
+        fs::remove_file(repository.path().join("many/7.txt")).unwrap();
+
+        let mut options = CheckOptions::new();
+        let single = inventory.check(repository.path(), &options, None).unwrap();
+        options.set_threads(4);
+        let parallel = inventory.check(repository.path(), &options, None).unwrap();
+
+        let mut failures = parallel.failures();
+        failures.sort_by_key(|kind| kind.name());
+        let mut expected = single.failures();
+        expected.sort_by_key(|kind| kind.name());
+        assert_eq!(failures, expected);
+        for kind in expected {
+            assert_eq!(
+                sorted(parallel.by_failure(kind)),
+                sorted(single.by_failure(kind))
+            );
+        }
+        assert_eq!(
+            sorted(parallel.by_failure(FailureKind::HashMismatch)),
+            [Path::new("sub/b.txt")]
+        );
+    }
+
+    #[test]
+    fn parallel_check_shares_the_rate_limit() {
+        let repository = TempDir::new();
+        create_files(repository.path(), &[("big.bin", &"x".repeat(3000))]);
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .build();
+        let inventory = build(configuration, repository.path());
+
+        // A single file takes as long as on one thread: the idle threads do
+        // not hold back a share of the limit.
+        let mut options = CheckOptions::new();
+        options
+            .set_threads(4)
+            .set_rate_limit(Some(10_000))
+            .set_buffer_size(Some(1000));
+        let start = Instant::now();
+        let report = inventory.check(repository.path(), &options, None).unwrap();
+        let elapsed = start.elapsed();
+        assert!(report.is_empty());
+        assert!(elapsed >= Duration::from_millis(250), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(900), "{:?}", elapsed);
+    }
+
+    /// Returns an inventory in the JSON format with the record of `a.txt`
+    /// containing `hi\n` created at the given time, if any.
+    fn json_with_btime(btime: Option<u64>) -> String {
+        let json = json_with_paths(&["a.txt"]).replace(
+            r#""include_empty_dirs": false,"#,
+            r#""include_empty_dirs": false, "record_btime": true,"#,
+        );
+        match btime {
+            Some(btime) => json.replace(
+                r#""size": 3}"#,
+                &format!(r#""size": 3, "btime": {}}}"#, btime),
+            ),
+            None => json,
+        }
+    }
+
+    #[test]
+    fn created_time_mismatches_fail() {
+        let repository = TempDir::new();
+        create_files(repository.path(), &[("a.txt", "hi\n")]);
+        let attr = fs::metadata(repository.path().join("a.txt")).unwrap();
+        let btime = match btime_nanos(&attr) {
+            Some(btime) => btime,
+            // The filesystem does not record creation times.
+            None => return,
+        };
+
+        let inventory =
+            |btime| Inventory::from_reader(json_with_btime(btime).as_bytes(), None).unwrap();
+        assert!(check(&inventory(Some(btime)), repository.path()).is_empty());
+        assert!(check(&inventory(None), repository.path()).is_empty());
+
+        let report = check(&inventory(Some(btime + 1)), repository.path());
+        assert_eq!(report.failures(), [FailureKind::CreatedTimeMismatch]);
+
+        let report = inventory(Some(btime)).diff(&inventory(Some(btime + 1)));
+        assert_eq!(report.failures(), [FailureKind::CreatedTimeMismatch]);
+        assert!(inventory(Some(btime)).diff(&inventory(None)).is_empty());
+    }
+
+    #[test]
+    fn records_created_times_if_enabled() {
+        let repository = TempDir::new();
+        create_files(repository.path(), FILES);
+        let attr = fs::metadata(repository.path().join("a.txt")).unwrap();
+        let configuration = |record_btime| {
+            Configuration::builder()
+                .hash_algorithms(vec![HashAlgorithm::Md5])
+                .record_btime(record_btime)
+                .build()
+        };
+
+        let inventory = build(configuration(true), repository.path());
+        assert_eq!(
+            inventory.record(Path::new("a.txt")).unwrap().btime,
+            btime_nanos(&attr)
+        );
+        let inventory = build(configuration(false), repository.path());
+        assert_eq!(inventory.record(Path::new("a.txt")).unwrap().btime, None);
+    }
+
+    #[test]
+    fn excluding_symlinks_by_type_is_excluding_symlinks() {
+        let configuration = Configuration::builder()
+            .excluded_types(&[EntryType::Symlink, EntryType::Fifo])
+            .build();
+        assert!(configuration.exclude_symlinks());
+        assert_eq!(
+            configuration.excluded_types(),
+            [EntryType::Fifo, EntryType::Symlink]
+        );
+
+        let value = serde_json::to_value(&configuration).unwrap();
+        assert_eq!(value["excluded_types"], serde_json::json!(["fifo"]));
+        assert_eq!(value["exclude_symlinks"], true);
+    }
+
+    #[test]
+    fn merging_reports_unions_the_files() {
+        let mut report = Report::new();
+        report.add_failure("a.txt", FailureKind::HashMismatch);
+        report.add_failure("b.txt", FailureKind::SizeMismatch);
+        report.add_notice("c.txt", NoticeKind::HashSkipped);
+
+        // Disjoint files of an existing kind and a new kind, and an
+        // overlapping file.
+        let mut other = Report::new();
+        other.add_failure("a.txt", FailureKind::HashMismatch);
+        other.add_failure("d.txt", FailureKind::HashMismatch);
+        other.add_failure("e.txt", FailureKind::MissingFromRepository);
+        other.add_notice("c.txt", NoticeKind::HashSkipped);
+        other.passed.insert(PathBuf::from("f.txt"));
+        report.merge(other);
+
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::HashMismatch)),
+            [Path::new("a.txt"), Path::new("d.txt")]
+        );
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::SizeMismatch)),
+            [Path::new("b.txt")]
+        );
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::MissingFromRepository)),
+            [Path::new("e.txt")]
+        );
+        assert_eq!(
+            sorted(report.by_notice(NoticeKind::HashSkipped)),
+            [Path::new("c.txt")]
+        );
+        assert_eq!(sorted(Some(report.passed())), [Path::new("f.txt")]);
+
+        report.merge(Report::new());
+        assert_eq!(report.failures().len(), 3);
+    }
+
+    /// Creates a repository with a file whose name is not valid UTF-8, and
+    /// returns the path of the file relative to the repository.
+    #[cfg(unix)]
+    fn create_non_utf8_file(repository: &Path) -> PathBuf {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        create_files(repository, &[("a.txt", "hello\n")]);
+        let path = Path::new("sub").join(OsStr::from_bytes(b"caf\xe9.txt"));
+        create_files(repository, &[("sub/b.txt", "world\n")]);
+        fs::write(repository.join(&path), "!\n").unwrap();
+        path
+    }
+
+    /// Returns the configuration with the path encoding.
+    fn path_encoding(encoding: PathEncoding) -> Configuration {
+        Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .path_encoding(encoding)
+            .build()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn stores_non_utf8_paths_as_bytes() {
+        let repository = TempDir::new();
+        let path = create_non_utf8_file(repository.path());
+        let inventory = build(path_encoding(PathEncoding::Bytes), repository.path());
+
+        let json = String::from_utf8(to_bytes(&inventory)).unwrap();
+        assert!(json.contains(r#""\u0000base64:c3ViL2NhZukudHh0""#));
+
+        for (format, nested) in [
+            (Format::Json, false),
+            (Format::Json, true),
+            (Format::Binary, false),
+            (Format::Binary, true),
+        ] {
+            let mut data = Vec::new();
+            inventory.write_to(&mut data, format, nested).unwrap();
+            let read = Inventory::from_reader(&data[..], None).unwrap();
+            assert!(read.record(&path).is_some());
+            assert_eq!(read.record_count(), 3);
+            assert!(check(&read, repository.path()).is_empty());
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn rejects_or_replaces_non_utf8_paths() {
+        let repository = TempDir::new();
+        let path = create_non_utf8_file(repository.path());
+
+        let err = match Inventory::build(
+            path_encoding(PathEncoding::Utf8),
+            repository.path(),
+            &BuildOptions::new(),
+            None,
+        ) {
+            Ok(_) => panic!("a non-UTF-8 path is recorded"),
+            Err(err) => err,
+        };
+        assert!(matches!(
+            err.downcast_ref::<InventoryError>(),
+            Some(InventoryError::NonUtf8Path(p)) if *p == path
+        ));
+
+        let inventory = build(path_encoding(PathEncoding::Lossy), repository.path());
+        let lossy = Path::new("sub/caf\u{fffd}.txt");
+        assert!(inventory.record(lossy).is_some());
+        let inventory = Inventory::from_reader(&to_bytes(&inventory)[..], None).unwrap();
+        let report = check(&inventory, repository.path());
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::MissingFromRepository)),
+            [lossy]
+        );
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::MissingFromInventory)),
+            [path.as_path()]
+        );
+    }
+
+    #[test]
+    fn empty_files_are_verified() {
+        let repository = TempDir::new();
+        create_files(repository.path(), &[("empty", ""), ("sub/empty", "")]);
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5, HashAlgorithm::Sha1])
+            .build();
+        let inventory = build(configuration, repository.path());
+
+        let record = inventory.record(Path::new("empty")).unwrap();
+        assert_eq!(record.size(), 0);
+        assert_eq!(
+            record.hashes[&HashAlgorithm::Md5].to_hex(),
+            "d41d8cd98f00b204e9800998ecf8427e"
+        );
+        assert_eq!(
+            record.hashes[&HashAlgorithm::Sha1].to_hex(),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert!(check(&inventory, repository.path()).is_empty());
+
+        create_files(repository.path(), &[("empty", "!")]);
+        fs::remove_file(repository.path().join("sub/empty")).unwrap();
+        let report = check(&inventory, repository.path());
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::SizeMismatch)),
+            [Path::new("empty")]
+        );
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::MissingFromRepository)),
+            [Path::new("sub/empty")]
+        );
+    }
+
+    #[test]
+    fn checks_only_paths_with_stripped_components() {
+        let repository = TempDir::new();
+        create_files(
+            repository.path(),
+            &[("top/a.txt", "a\n"), ("top/b.txt", "b\n")],
+        );
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .build();
+        let inventory = build(configuration, &repository.path().join("top"));
+        create_files(repository.path(), &[("top/b.txt", "B\n")]);
+        fs::rename(
+            repository.path().join("top"),
+            repository.path().join("moved"),
+        )
+        .unwrap();
+
+        let check_only = |paths: &[&str]| {
+            let mut options = CheckOptions::new();
+            options
+                .set_strip_components(1)
+                .set_only_paths(Some(paths.iter().map(PathBuf::from).collect()));
+            inventory.check(repository.path(), &options, None).unwrap()
+        };
+        assert!(check_only(&["a.txt"]).is_empty());
+        let report = check_only(&["a.txt", "b.txt"]);
+        assert_eq!(report.failures(), [FailureKind::HashMismatch]);
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::HashMismatch)),
+            [Path::new("b.txt")]
+        );
+
+        fs::remove_file(repository.path().join("moved/a.txt")).unwrap();
+        let report = check_only(&["a.txt"]);
+        assert_eq!(report.failures(), [FailureKind::MissingFromRepository]);
+    }
+
+    #[test]
+    fn verifies_hard_links_in_archives() {
+        let dir = TempDir::new();
+        let repository = dir.path().join("repository");
+        create_files(
+            &repository,
+            &[
+                ("a.txt", "hello\n"),
+                ("b.txt", "hello\n"),
+                ("c.txt", "world\n"),
+            ],
+        );
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .build();
+        let inventory = build(configuration, &repository);
+
+        let check_tar = |entries: &[([u8; 512], &[u8])]| {
+            let archive = dir.path().join("archive.tar");
+            fs::write(&archive, archive::tar_archive(entries)).unwrap();
+            inventory
+                .check_archive(&archive, &CheckOptions::new(), None)
+                .unwrap()
+        };
+        let header = archive::tar_header;
+
+        // The links share the contents of the entry they link to.
+        let report = check_tar(&[
+            (header("a.txt", b'0', 6, ""), b"hello\n"),
+            (header("b.txt", b'1', 0, "a.txt"), b""),
+            (header("c.txt", b'0', 6, ""), b"world\n"),
+        ]);
+        assert!(report.is_empty());
+
+        let report = check_tar(&[
+            (header("a.txt", b'0', 6, ""), b"hello\n"),
+            (header("c.txt", b'1', 0, "a.txt"), b""),
+            (header("b.txt", b'1', 0, "missing.txt"), b""),
+        ]);
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::HashMismatch)),
+            [Path::new("c.txt")]
+        );
+        assert_eq!(
+            sorted(report.by_notice(NoticeKind::UnresolvedHardLink)),
+            [Path::new("b.txt")]
+        );
+    }
+
+    #[test]
+    fn checks_the_hmac_key() {
+        let mut configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .build();
+        assert!(configuration.check_key(None).is_ok());
+        assert!(matches!(
+            configuration.check_key(Some(b"key")),
+            Err(InventoryError::NotKeyed)
+        ));
+
+        configuration.set_hmac_key(Some(b"key"));
+        assert!(configuration.check_key(Some(b"key")).is_ok());
+        assert!(matches!(
+            configuration.check_key(None),
+            Err(InventoryError::KeyRequired)
+        ));
+        assert!(matches!(
+            configuration.check_key(Some(b"other")),
+            Err(InventoryError::KeyMismatch)
+        ));
+    }
+
+    /// Builds a sealed inventory of the repository.
+    fn sealed(repository: &Path, includes_paths: bool) -> Inventory {
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5, HashAlgorithm::Sha1])
+            .build();
+        let mut inventory = build(configuration, repository);
+        inventory.seal(includes_paths).unwrap();
+        assert_eq!(inventory.sealed(), Some(includes_paths));
+        inventory
+    }
+
+    /// Serializes and reads the inventory back, validating it.
+    fn reload(inventory: &Inventory) -> Result<Inventory, InventoryError> {
+        Inventory::from_reader(&to_bytes(inventory)[..], None)
+            .map_err(|e| *e.downcast::<InventoryError>().unwrap())
+    }
+
+    #[test]
+    fn seal_detects_edited_records() {
+        let repository = TempDir::new();
+        create_files(repository.path(), FILES);
+
+        for &includes_paths in &[false, true] {
+            let mut inventory = sealed(repository.path(), includes_paths);
+            let loaded = reload(&inventory).unwrap();
+            assert_eq!(loaded.sealed(), Some(includes_paths));
+            assert_eq!(
+                loaded.seal.as_ref().unwrap().value,
+                inventory.seal.as_ref().unwrap().value
+            );
+
+            let record = inventory.records.get_mut(Path::new("a.txt")).unwrap();
+            record.hashes.insert(
+                HashAlgorithm::Md5,
+                HashValue::try_from("00".repeat(16)).unwrap(),
+            );
+            assert!(matches!(
+                reload(&inventory),
+                Err(InventoryError::SealMismatch)
+            ));
+        }
+
+        // Unsealed inventories are not checked.
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .build();
+        let mut inventory = build(configuration, repository.path());
+        inventory
+            .records
+            .get_mut(Path::new("a.txt"))
+            .unwrap()
+            .hashes
+            .clear();
+        assert!(reload(&inventory).unwrap().sealed().is_none());
+    }
+
+    #[test]
+    fn seal_includes_paths_only_if_enabled() {
+        let repository = TempDir::new();
+        create_files(repository.path(), FILES);
+
+        for &includes_paths in &[false, true] {
+            let mut inventory = sealed(repository.path(), includes_paths);
+            let record = inventory.records.remove(Path::new("a.txt")).unwrap();
+            inventory
+                .records
+                .insert(PathBuf::from("renamed.txt"), record);
+
+            let result = reload(&inventory);
+            if includes_paths {
+                assert!(matches!(result, Err(InventoryError::SealMismatch)));
+            } else {
+                assert!(result.is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn reseals_updated_inventories() {
+        let repository = TempDir::new();
+        create_files(repository.path(), FILES);
+
+        for &includes_paths in &[false, true] {
+            let mut inventory = sealed(repository.path(), includes_paths);
+            create_files(repository.path(), &[("new.txt", "new\n")]);
+            fs::remove_file(repository.path().join("sub/b.txt")).unwrap();
+            let outcome = inventory
+                .update(repository.path(), true, &BuildOptions::new(), None, None)
+                .unwrap();
+            assert_eq!(outcome.added, [Path::new("new.txt")]);
+            assert_eq!(outcome.removed, [Path::new("sub/b.txt")]);
+
+            let loaded = reload(&inventory).unwrap();
+            assert_eq!(loaded.sealed(), Some(includes_paths));
+            assert_eq!(loaded.records().count(), 3);
+            fs::remove_file(repository.path().join("new.txt")).unwrap();
+            create_files(repository.path(), FILES);
+        }
+    }
+
+    #[test]
+    fn merged_check_matches_the_default_one() {
+        // Names that sort differently as strings and as paths: `a/x` comes
+        // before `a-b` and `a.c` component-wise, but not byte-wise.
+        let repository = TempDir::new();
+        create_files(
+            repository.path(),
+            &[
+                ("a/x", "x\n"),
+                ("a/y", "y\n"),
+                ("a-b", "ab\n"),
+                ("a.c", "ac\n"),
+                ("b/z", "z\n"),
+            ],
+        );
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .build();
+        let inventory = build(configuration, repository.path());
+
+        fs::remove_file(repository.path().join("a/y")).unwrap();
+        create_files(
+            repository.path(),
+            &[
+                ("a/w", "w\n"),
+                ("a0", "0\n"),
+                ("a-b", "AB\n"),
+                ("a.c", "ac!\n"),
+            ],
+        );
+
+        let report = |low_memory: bool| {
+            let mut options = CheckOptions::new();
+            options.set_low_memory(low_memory).set_track_passed(true);
+            inventory.check(repository.path(), &options, None).unwrap()
+        };
+        let (default, merged) = (report(false), report(true));
+        assert_eq!(
+            sorted(merged.by_failure(FailureKind::MissingFromRepository)),
+            [Path::new("a/y")]
+        );
+        assert_eq!(
+            sorted(merged.by_failure(FailureKind::MissingFromInventory)),
+            [Path::new("a/w"), Path::new("a0")]
+        );
+        assert_eq!(
+            sorted(merged.by_failure(FailureKind::HashMismatch)),
+            [Path::new("a-b")]
+        );
+        assert_eq!(
+            sorted(merged.by_failure(FailureKind::SizeMismatch)),
+            [Path::new("a.c")]
+        );
+        assert_eq!(merged.contents, default.contents);
+        assert!(merged.notices == default.notices);
+        assert_eq!(merged.passed, default.passed);
+        assert_eq!(merged.passed.len(), 2);
+    }
+
+    #[test]
+    fn compares_hashes_of_shared_algorithms() {
+        let hashes = |values: &[(HashAlgorithm, &str)]| -> BTreeMap<_, _> {
+            values
+                .iter()
+                .map(|(a, v)| {
+                    (
+                        *a,
+                        HashValue::try_from(v.repeat(a.digest_len()).as_str()).unwrap(),
+                    )
+                })
+                .collect()
+        };
+        let md5 = hashes(&[(HashAlgorithm::Md5, "01")]);
+        let sha1 = hashes(&[(HashAlgorithm::Sha1, "01")]);
+        let both = hashes(&[(HashAlgorithm::Md5, "01"), (HashAlgorithm::Sha1, "01")]);
+        let other_sha1 = hashes(&[(HashAlgorithm::Md5, "01"), (HashAlgorithm::Sha1, "02")]);
+
+        assert_eq!(hashes_match(&md5, &sha1), None);
+        assert_eq!(hashes_match(&md5, &BTreeMap::new()), None);
+        assert_eq!(hashes_match(&md5, &both), Some(true));
+        assert_eq!(hashes_match(&both, &sha1), Some(true));
+        assert_eq!(hashes_match(&md5, &other_sha1), Some(true));
+        assert_eq!(hashes_match(&both, &other_sha1), Some(false));
+        assert_eq!(hashes_match(&sha1, &other_sha1), Some(false));
+    }
+
+    #[test]
+    fn diffs_inventories() {
+        let repository = TempDir::new();
+        create_files(repository.path(), FILES);
+        let configuration = |algorithms: Vec<HashAlgorithm>| {
+            Configuration::builder().hash_algorithms(algorithms).build()
+        };
+        let md5 = build(configuration(vec![HashAlgorithm::Md5]), repository.path());
+        let sha1 = build(configuration(vec![HashAlgorithm::Sha1]), repository.path());
+
+        // Without a shared algorithm, the records are compared by size.
+        let report = md5.diff(&sha1);
+        assert!(report.is_empty());
+        assert_eq!(
+            sorted(report.by_notice(NoticeKind::SizeComparedOnly)),
+            [
+                Path::new("a.txt"),
+                Path::new("sub/b.txt"),
+                Path::new("sub/deeper/c.txt")
+            ]
+        );
+
+        fs::remove_file(repository.path().join("a.txt")).unwrap();
+        create_files(
+            repository.path(),
+            &[
+                ("sub/b.txt", "World\n"),
+                ("sub/deeper/c.txt", "!!\n"),
+                ("d.txt", ""),
+            ],
+        );
+        let both = build(
+            configuration(vec![HashAlgorithm::Md5, HashAlgorithm::Sha1]),
+            repository.path(),
+        );
+        let report = md5.diff(&both);
+        assert!(report.notices().is_empty());
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::MissingFromRepository)),
+            [Path::new("a.txt")]
+        );
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::MissingFromInventory)),
+            [Path::new("d.txt")]
+        );
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::HashMismatch)),
+            [Path::new("sub/b.txt")]
+        );
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::SizeMismatch)),
+            [Path::new("sub/deeper/c.txt")]
+        );
+
+        // Swapping the inventories swaps the added and removed records.
+        let report = both.diff(&md5);
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::MissingFromRepository)),
+            [Path::new("d.txt")]
+        );
+        assert_eq!(
+            sorted(report.by_failure(FailureKind::MissingFromInventory)),
+            [Path::new("a.txt")]
+        );
+        assert!(md5.diff(&md5).is_empty());
+    }
+
+    #[test]
+    fn checkpoints_valid_inventories_while_updating() {
+        let repository = TempDir::new();
+        create_files(repository.path(), FILES);
+        let mut inventory = sealed(repository.path(), true);
+        let new: Vec<_> = (0..5).map(|i| format!("new/{}.txt", i)).collect();
+        for file in &new {
+            create_files(repository.path(), &[(file, "n\n")]);
+        }
+
+        let mut checkpoints = Vec::new();
+        let mut checkpoint = |inventory: &Inventory| -> Result<(), Box<dyn Error>> {
+            let loaded = reload(inventory)?;
+            assert_eq!(loaded.sealed(), Some(true));
+            checkpoints.push((
+                loaded.records().count(),
+                loaded.total_files,
+                loaded.total_bytes,
+            ));
+            Ok(())
+        };
+        let mut options = BuildOptions::new();
+        options.set_checkpoint_every(Some(2));
+        let outcome = inventory
+            .update(
+                repository.path(),
+                false,
+                &options,
+                None,
+                Some(&mut checkpoint),
+            )
+            .unwrap();
+        assert_eq!(outcome.added.len(), 5);
+
+        // Every checkpoint has the totals of the files added so far.
+        assert_eq!(
+            checkpoints,
+            [(5, Some(5), Some(18)), (7, Some(7), Some(22))]
+        );
+        assert_eq!(reload(&inventory).unwrap().total_files, Some(8));
+    }
+
+    #[test]
+    fn validates_the_recorded_totals() {
+        let json = json_with_paths(&["a.txt", "b.txt"]);
+        let with_totals = |files: usize, bytes: u64| {
+            json.replacen(
+                r#""records""#,
+                &format!(
+                    r#""total_files": {}, "total_bytes": {}, "records""#,
+                    files, bytes
+                ),
+                1,
+            )
+        };
+        let load = |json: &str| {
+            Inventory::from_reader(json.as_bytes(), None)
+                .map_err(|e| *e.downcast::<InventoryError>().unwrap())
+        };
+
+        let inventory = load(&with_totals(2, 6)).unwrap();
+        assert_eq!(
+            (inventory.total_files, inventory.total_bytes),
+            (Some(2), Some(6))
+        );
+        for json in &[with_totals(3, 6), with_totals(2, 7), with_totals(1, 3)] {
+            assert!(matches!(load(json), Err(InventoryError::TotalsMismatch)));
+        }
+
+        // Older inventories without the totals are accepted as is.
+        let inventory = load(&json).unwrap();
+        assert_eq!((inventory.total_files, inventory.total_bytes), (None, None));
+        assert!(inventory.validate().is_ok());
+    }
+
+    #[test]
+    fn loading_rejects_hashes_of_the_wrong_length() {
+        let md5 = "764efa883dda1e11db47671c4a3bbd9e";
+        for value in &[&md5[..30], &md5[..2], "", &format!("{}00", md5)[..]] {
+            let json = json_with_paths(&["a.txt"]).replace(md5, value);
+            let err = Inventory::from_reader(json.as_bytes(), None).unwrap_err();
+            match *err.downcast::<InventoryError>().unwrap() {
+                InventoryError::InvalidHashLength(path, algorithm) => {
+                    assert_eq!(path, Path::new("a.txt"));
+                    assert_eq!(algorithm, HashAlgorithm::Md5);
+                }
+                err => panic!("unexpected error: {}", err),
+            }
+        }
+        assert!(Inventory::from_reader(json_with_paths(&["a.txt"]).as_bytes(), None).is_ok());
+    }
+
+    #[test]
+    fn appends_repositories_under_a_prefix() {
+        let repository = TempDir::new();
+        let other = TempDir::new();
+        create_files(repository.path(), FILES);
+        create_files(other.path(), &[("a.txt", "other\n"), ("sub/d.txt", "d\n")]);
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .build();
+        let mut inventory = build(configuration, repository.path());
+        let md5 = [HashAlgorithm::Md5];
+
+        let report = inventory
+            .append(
+                other.path(),
+                Path::new("vendor/other"),
+                &md5,
+                &BuildOptions::new(),
+            )
+            .unwrap();
+        assert!(report.is_empty());
+        let paths: Vec<_> = inventory
+            .records()
+            .map(|(p, _)| p.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "a.txt",
+                "sub/b.txt",
+                "sub/deeper/c.txt",
+                "vendor/other/a.txt",
+                "vendor/other/sub/d.txt"
+            ]
+        );
+        assert_eq!(
+            inventory
+                .record(Path::new("vendor/other/a.txt"))
+                .unwrap()
+                .size(),
+            6
+        );
+        assert_eq!(
+            (inventory.total_files, inventory.total_bytes),
+            (Some(5), Some(22))
+        );
+
+        // Colliding keys fail the whole append, without adding any records.
+        create_files(other.path(), &[("new.txt", "new\n")]);
+        for prefix in &["vendor/other", ""] {
+            let err = inventory
+                .append(other.path(), Path::new(prefix), &md5, &BuildOptions::new())
+                .err()
+                .unwrap();
+            match *err.downcast::<InventoryError>().unwrap() {
+                InventoryError::RecordExists(path) => {
+                    assert_eq!(path, Path::new(prefix).join("a.txt"))
+                }
+                err => panic!("unexpected error: {}", err),
+            }
+        }
+        assert_eq!(inventory.records().count(), 5);
+
+        let err = inventory
+            .append(
+                other.path(),
+                Path::new("more"),
+                &[HashAlgorithm::Sha1],
+                &BuildOptions::new(),
+            )
+            .err()
+            .unwrap();
+        assert!(matches!(
+            *err.downcast::<InventoryError>().unwrap(),
+            InventoryError::AlgorithmMismatch
+        ));
+    }
+
+    #[test]
+    fn finds_paths_differing_only_in_case() {
+        let repository = TempDir::new();
+        create_files(
+            repository.path(),
+            &[("A.txt", "a\n"), ("a.txt", "a\n"), ("b.txt", "b\n")],
+        );
+        let configuration = || {
+            Configuration::builder()
+                .hash_algorithms(vec![HashAlgorithm::Md5])
+                .build()
+        };
+        let build_with = |strict: bool| {
+            let mut options = BuildOptions::new();
+            options.set_strict_case(strict);
+            Inventory::build(configuration(), repository.path(), &options, None)
+        };
+
+        // The collision is only warned about by default.
+        let (inventory, _) = build_with(false).unwrap();
+        assert_eq!(inventory.records().count(), 3);
+
+        let err = build_with(true).err().unwrap();
+        match *err.downcast::<InventoryError>().unwrap() {
+            InventoryError::CaseCollision(first, second) => {
+                assert_eq!(
+                    (first.as_path(), second.as_path()),
+                    (Path::new("A.txt"), Path::new("a.txt"))
+                )
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+
+        // New paths collide with the recorded ones as well, but not with
+        // themselves.
+        let mut options = BuildOptions::new();
+        options.set_strict_case(true);
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert!(inventory
+            .check_case_collisions(paths(&["c.txt", "sub/C.txt", "c.txt"]), &options)
+            .is_ok());
+        assert!(matches!(
+            inventory.check_case_collisions(paths(&["B.TXT"]), &options),
+            Err(InventoryError::CaseCollision(_, _))
+        ));
+        assert!(inventory
+            .check_case_collisions(paths(&["B.TXT"]), &BuildOptions::new())
+            .is_ok());
+    }
+
+    #[test]
+    fn groups_unique_contents() {
+        let repository = TempDir::new();
+        create_files(
+            repository.path(),
+            &[("b.txt", "x\n"), ("a/c.txt", "x\n"), ("d.txt", "y\n")],
+        );
+        fs::create_dir(repository.path().join("empty")).unwrap();
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .include_empty_dirs(true)
+            .build();
+        let inventory = build(configuration, repository.path());
+        assert_eq!(inventory.records().count(), 4);
+
+        // The empty directory is left out.
+        let contents: Vec<_> = inventory
+            .unique_contents(HashAlgorithm::Md5)
+            .unwrap()
+            .into_iter()
+            .map(|(value, count, path)| (value.to_hex(), count, path.to_str().unwrap()))
+            .collect();
+        assert_eq!(
+            contents,
+            [
+                ("009520053b00386d1173f3988c55d192".to_string(), 1, "d.txt"),
+                ("401b30e3b8b5d629635a5c613cdb7919".to_string(), 2, "a/c.txt")
+            ]
+        );
+        assert!(matches!(
+            inventory.unique_contents(HashAlgorithm::Sha1),
+            Err(InventoryError::AlgorithmNotPresent(HashAlgorithm::Sha1))
+        ));
+    }
+
+    #[test]
+    fn selects_algorithms_by_glob_and_extension() {
+        let repository = TempDir::new();
+        create_files(
+            repository.path(),
+            &[
+                ("a.txt", "a\n"),
+                ("b.ISO", "b\n"),
+                ("images/c.iso", "c\n"),
+                ("images/old/d.iso", "d\n"),
+            ],
+        );
+        let configuration = Configuration::builder()
+            .hash_algorithms(vec![HashAlgorithm::Md5])
+            .extension_algorithms(vec![("iso".to_string(), vec![HashAlgorithm::Sha1])])
+            .glob_algorithms(vec![
+                ("images/**".to_string(), vec![HashAlgorithm::Sha3_256]),
+                ("old/*.iso".to_string(), vec![HashAlgorithm::Md5]),
+                (
+                    "images/old/*".to_string(),
+                    vec![HashAlgorithm::Sha3_512, HashAlgorithm::Sha3_512],
+                ),
+            ])
+            .build();
+        let inventory = build(configuration, repository.path());
+
+        // The last matching glob takes precedence over the extension, and
+        // globs with a slash only match from the repository root.
+        for (path, expected) in &[
+            ("a.txt", HashAlgorithm::Md5),
+            ("b.ISO", HashAlgorithm::Sha1),
+            ("images/c.iso", HashAlgorithm::Sha3_256),
+            ("images/old/d.iso", HashAlgorithm::Sha3_512),
+        ] {
+            let path = Path::new(path);
+            assert_eq!(inventory.configuration().algorithms_for(path), [*expected]);
+            let recorded: Vec<_> = inventory
+                .record(path)
+                .unwrap()
+                .hashes
+                .keys()
+                .copied()
+                .collect();
+            assert_eq!(recorded, [*expected]);
+        }
+        assert_eq!(
+            inventory.configuration().all_algorithms(),
+            [
+                HashAlgorithm::Md5,
+                HashAlgorithm::Sha1,
+                HashAlgorithm::Sha3_256,
+                HashAlgorithm::Sha3_512
+            ]
+        );
+
+        // The globs are kept in order through serialization, and the files
+        // pass the verification with their algorithms.
+        let mut loaded = Inventory::from_reader(&to_bytes(&inventory)[..], None).unwrap();
+        assert_eq!(
+            loaded.configuration().glob_algorithms(),
+            inventory.configuration().glob_algorithms()
+        );
+        assert!(check(&loaded, repository.path()).is_empty());
+
+        // Setting a glob again moves it to the end, and no algorithms remove
+        // it.
+        let configuration = &mut loaded.configuration;
+        configuration.set_glob_algorithms("images/**", &[HashAlgorithm::Sha1]);
+        assert_eq!(
+            configuration.algorithms_for(Path::new("images/old/d.iso")),
+            [HashAlgorithm::Sha1]
+        );
+        configuration.set_glob_algorithms("images/**", &[]);
+        let globs: Vec<_> = configuration
+            .glob_algorithms()
+            .iter()
+            .map(|(g, _)| g.as_str())
+            .collect();
+        assert_eq!(globs, ["old/*.iso", "images/old/*"]);
     }
 }
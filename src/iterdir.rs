@@ -1,80 +1,286 @@
-use std::fs::{self, DirEntry, ReadDir};
-use std::io::Result as IoResult;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::{self, FileType};
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::vec::IntoIter;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ignore::IgnoreFile;
+use crate::util::FileError;
+
+/// A type of directory entries that can be excluded from the walk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    /// Named pipe.
+    Fifo,
+
+    /// Unix domain socket.
+    Socket,
+
+    /// Block device.
+    Block,
+
+    /// Character device.
+    Char,
+
+    /// Symbolic link, to either a file or a directory.
+    Symlink,
+}
+
+/// An error returned when the entry type name cannot be parsed.
+#[derive(Debug)]
+pub struct ParseEntryTypeError();
+
+impl FromStr for EntryType {
+    type Err = ParseEntryTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fifo" => Ok(EntryType::Fifo),
+            "socket" => Ok(EntryType::Socket),
+            "block" => Ok(EntryType::Block),
+            "char" => Ok(EntryType::Char),
+            "symlink" => Ok(EntryType::Symlink),
+            _ => Err(ParseEntryTypeError()),
+        }
+    }
+}
+
+impl Display for EntryType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let name = match self {
+            EntryType::Fifo => "fifo",
+            EntryType::Socket => "socket",
+            EntryType::Block => "block",
+            EntryType::Char => "char",
+            EntryType::Symlink => "symlink",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl EntryType {
+    /// Names of all entry types.
+    pub const NAMES: &'static [&'static str] = &["fifo", "socket", "block", "char", "symlink"];
+
+    /// Checks if the file type, which does not follow symbolic links, is of
+    /// this entry type.
+    ///
+    /// Only symbolic links exist on every platform; the other types are never
+    /// matched on non-Unix platforms.
+    pub fn matches(self, file_type: &FileType) -> bool {
+        match self {
+            EntryType::Symlink => file_type.is_symlink(),
+            #[cfg(unix)]
+            EntryType::Fifo => file_type.is_fifo(),
+            #[cfg(unix)]
+            EntryType::Socket => file_type.is_socket(),
+            #[cfg(unix)]
+            EntryType::Block => file_type.is_block_device(),
+            #[cfg(unix)]
+            EntryType::Char => file_type.is_char_device(),
+            #[cfg(not(unix))]
+            _ => false,
+        }
+    }
+}
 
 /// A recursive directory iterator.
 ///
 /// Unlike `std::fs::ReadDir`, this iterator visits subdirectories of the
-/// root directory. Entries for child directories are not returned.
+/// root directory. Entries for child directories are not returned, except
+/// for empty ones if enabled.
+///
+/// The files are visited in the depth-first order. If sorting is enabled,
+/// the entries of every directory are visited in the order of their names,
+/// so that the paths are produced in the `Path` order.
+///
+/// Symbolically linked directories are descended into only if following them
+/// is enabled; otherwise, the links are returned as regular entries. Entries
+/// of the excluded types are neither returned nor descended into; in
+/// particular, if links are excluded, they are skipped whether following them
+/// is enabled or not.
+///
+/// Subdirectories with the pruned names are neither descended into nor
+/// returned.
 ///
-/// The files are visited in the depth-first order.
+/// If ignore files are enabled, the `.inventorizeignore` file of every
+/// visited directory is read when the iterator descends into it, and its
+/// patterns apply to the whole subtree of the directory. The patterns of
+/// nested ignore files take precedence over the ones of their ancestors.
+/// Ignored entries are neither returned nor descended into.
+///
+/// The entries of a directory are read in full when the iterator descends
+/// into it, and only their paths and types are kept. A `DirEntry` holds on to
+/// the handle of its directory, so keeping the entries themselves would leave
+/// a handle open for every level of the walk.
 pub struct DirectoryIterator {
-    /// Stack of `std::fs::ReadDir` iterators.
-    stack: Vec<ReadDir>,
+    /// Stack of the paths and types of the remaining entries of the
+    /// directories being visited.
+    stack: Vec<IntoIter<IoResult<(PathBuf, FileType)>>>,
+
+    /// Ignore files of the directories being visited, parallel to `stack`.
+    ignores: Vec<Option<IgnoreFile>>,
+
+    /// Read the ignore files of the visited directories.
+    ignore_files: bool,
+
+    /// Descend into symbolically linked directories.
+    follow_symlinks: bool,
+
+    /// Types of the entries to skip.
+    excluded_types: Vec<EntryType>,
+
+    /// Names of the subdirectories to skip.
+    pruned_dirs: &'static [&'static str],
+
+    /// Return the entries of empty subdirectories.
+    empty_dirs: bool,
+
+    /// Visit the entries of every directory in the order of their names.
+    sorted: bool,
 }
 
 impl DirectoryIterator {
     /// Creates a new recursive directory iterator.
-    pub fn new<P: AsRef<Path>>(root: P) -> IoResult<Self> {
-        Ok(DirectoryIterator {
-            // Create the root directory iterator and push it onto the stack.
-            stack: vec![fs::read_dir(root)?],
-        })
+    pub fn new<P: AsRef<Path>>(
+        root: P,
+        follow_symlinks: bool,
+        excluded_types: Vec<EntryType>,
+        pruned_dirs: &'static [&'static str],
+        ignore_files: bool,
+        empty_dirs: bool,
+        sorted: bool,
+    ) -> IoResult<Self> {
+        let mut iter = DirectoryIterator {
+            stack: Vec::new(),
+            ignores: Vec::new(),
+            ignore_files,
+            follow_symlinks,
+            excluded_types,
+            pruned_dirs,
+            empty_dirs,
+            sorted,
+        };
+
+        // Read the root directory entries and push them onto the stack.
+        iter.descend(root)?;
+        Ok(iter)
     }
 
-    /// Descends into a subdirectory with the given path.
-    fn descend<P: AsRef<Path>>(&mut self, subdir: P) -> IoResult<()> {
-        // Create the subdirectory iterator and push it onto the stack.
-        let iter = fs::read_dir(subdir)?;
-        self.stack.push(iter);
-        Ok(())
+    /// Checks if the entry is a directory that should be descended into.
+    fn is_dir(&self, path: &Path, file_type: &FileType) -> bool {
+        if self.follow_symlinks {
+            path.is_dir()
+        } else {
+            // Unlike `Path::is_dir()`, the type of the directory entry does
+            // not follow symbolic links.
+            file_type.is_dir()
+        }
+    }
+
+    /// Checks if the entry is of one of the excluded types.
+    fn is_excluded(&self, file_type: &FileType) -> bool {
+        self.excluded_types.iter().any(|t| t.matches(file_type))
+    }
+
+    /// Checks if the entry is ignored by the patterns of the ignore files,
+    /// the innermost matching pattern deciding.
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.ignores
+            .iter()
+            .rev()
+            .flatten()
+            .find_map(|i| i.matched(path, is_dir))
+            .unwrap_or(false)
     }
 
-    /// Advances the iterator to the next directory entry, descending into
-    /// a subdirectory if needed.
+    /// Checks if the directory entry has one of the pruned names.
+    fn is_pruned(&self, path: &Path) -> bool {
+        match path.file_name() {
+            Some(name) => self.pruned_dirs.iter().any(|p| name == *p),
+            None => false,
+        }
+    }
+
+    /// Descends into a subdirectory with the given path.
     ///
-    /// If the end of the directory is reached, returns `None`. Never ascends
-    /// to the parent directory.
-    fn step(&mut self) -> Option<IoResult<DirEntry>> {
-        debug_assert!(self.stack.len() > 0);
+    /// Returns `true` if the subdirectory is empty.
+    fn descend<P: AsRef<Path>>(&mut self, subdir: P) -> IoResult<bool> {
+        let ignore = if self.ignore_files {
+            IgnoreFile::read(&subdir).map_err(|e| path_error(subdir.as_ref(), e))?
+        } else {
+            None
+        };
 
-        // Get the next directory entry and return it if it is a file, or
-        // descend into the subdirectory if it is a directory.
-        match self.stack.last_mut().unwrap().next() {
-            Some(dir_result) => match dir_result {
-                Ok(entry) => {
-                    let path = entry.path();
-                    if path.is_dir() {
-                        // Try to descend into the subdirectory and start
-                        // iterating over its entries.
-                        match self.descend(path) {
-                            Ok(_) => self.step(),
-                            Err(err) => Some(Err(err)),
-                        }
-                    } else {
-                        // A file entry was found, return it.
-                        Some(Ok(entry))
-                    }
-                }
-                Err(err) => Some(Err(err)),
-            },
-            None => None,
+        // Read the paths and types of the subdirectory entries and push them
+        // onto the stack. The entries are dropped right away, so that the
+        // directory handle is closed before descending any further.
+        let subdir = subdir.as_ref();
+        let mut entries: Vec<_> = fs::read_dir(subdir)
+            .map_err(|e| path_error(subdir, e))?
+            .map(|e| match e {
+                Ok(e) => match e.file_type() {
+                    Ok(file_type) => Ok((e.path(), file_type)),
+                    Err(err) => Err(path_error(&e.path(), err)),
+                },
+                Err(err) => Err(path_error(subdir, err)),
+            })
+            .collect();
+        if self.sorted {
+            // Errors sort first, so they are reported as soon as possible.
+            entries.sort_by(|a, b| {
+                let a = a.as_ref().ok().map(|(p, _)| p.file_name());
+                let b = b.as_ref().ok().map(|(p, _)| p.file_name());
+                a.cmp(&b)
+            });
         }
+        let empty = entries.is_empty();
+        self.stack.push(entries.into_iter());
+        self.ignores.push(ignore);
+        Ok(empty)
     }
 }
 
 impl Iterator for DirectoryIterator {
-    type Item = IoResult<DirEntry>;
+    type Item = IoResult<PathBuf>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        debug_assert!(self.stack.len() > 0);
-
-        while self.stack.len() > 0 {
-            let result = self.step();
-            if result.is_some() {
-                return result;
-            } else {
-                self.stack.pop().unwrap();
+        // Get the next directory entry and return it if it is a file, or
+        // descend into the subdirectory if it is a directory. Ascend to the
+        // parent directory once all entries of a directory are visited.
+        while let Some(entries) = self.stack.last_mut() {
+            match entries.next() {
+                Some(Ok((path, file_type))) => {
+                    if self.is_excluded(&file_type) {
+                        continue;
+                    }
+
+                    let is_dir = self.is_dir(&path, &file_type);
+                    if self.is_ignored(&path, is_dir) {
+                        continue;
+                    }
+
+                    if !is_dir {
+                        return Some(Ok(path));
+                    } else if !self.is_pruned(&path) {
+                        match self.descend(&path) {
+                            Ok(true) if self.empty_dirs => return Some(Ok(path)),
+                            Ok(_) => {}
+                            Err(err) => return Some(Err(err)),
+                        }
+                    }
+                }
+                Some(Err(err)) => return Some(Err(err)),
+                None => {
+                    self.stack.pop();
+                    self.ignores.pop();
+                }
             }
         }
 
@@ -82,6 +288,14 @@ impl Iterator for DirectoryIterator {
     }
 }
 
+/// Attaches the path of the entry to an error of the walk.
+///
+/// The error keeps its kind, so that the walk can still be inspected as an
+/// `IoResult`, and the `FileError` inside tells which entry failed.
+fn path_error(path: &Path, err: IoError) -> IoError {
+    IoError::new(err.kind(), FileError::new(path, err))
+}
+
 /// An adapter for a directory iterator that produces file paths relative to
 /// some root directory.
 pub struct RelativePathIterator {
@@ -94,9 +308,25 @@ pub struct RelativePathIterator {
 
 impl RelativePathIterator {
     /// Creates a new relative path iterator.
-    pub fn new<P: AsRef<Path>>(root: P) -> IoResult<Self> {
+    pub fn new<P: AsRef<Path>>(
+        root: P,
+        follow_symlinks: bool,
+        excluded_types: Vec<EntryType>,
+        pruned_dirs: &'static [&'static str],
+        ignore_files: bool,
+        empty_dirs: bool,
+        sorted: bool,
+    ) -> IoResult<Self> {
         Ok(RelativePathIterator {
-            iter: DirectoryIterator::new(&root)?,
+            iter: DirectoryIterator::new(
+                &root,
+                follow_symlinks,
+                excluded_types,
+                pruned_dirs,
+                ignore_files,
+                empty_dirs,
+                sorted,
+            )?,
             root: root.as_ref().to_path_buf(),
         })
     }
@@ -108,11 +338,255 @@ impl Iterator for RelativePathIterator {
     fn next(&mut self) -> Option<Self::Item> {
         // Advance the underlying directory iterator and try to produce the
         // relative path to the discovered entry.
-        Some(match self.iter.next()? {
-            // The returned value of strip_prefix() must be safe to unwrap,
-            // since root is always a prefix of the returned paths.
-            Ok(d) => Ok(d.path().strip_prefix(&self.root).unwrap().to_path_buf()),
-            Err(e) => Err(e),
-        })
+        Some(self.iter.next()?.and_then(|path| {
+            // The entry paths are joined to the root, even in directories
+            // reached through symbolic links, so this only fails if that no
+            // longer holds. An entry outside of the root is then an error
+            // rather than a panic.
+            match path.strip_prefix(&self.root) {
+                Ok(relative) => Ok(relative.to_path_buf()),
+                Err(_) => Err(path_error(
+                    &path,
+                    IoError::new(
+                        ErrorKind::InvalidData,
+                        format!("outside of the root directory {:?}", self.root),
+                    ),
+                )),
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{create_files, TempDir};
+
+    /// Walks the tree with the default options, producing the sorted
+    /// relative paths.
+    fn walk(root: &Path) -> Vec<PathBuf> {
+        RelativePathIterator::new(root, false, Vec::new(), &[], false, false, true)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn visits_files_in_path_order() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.path().join("b/c")).unwrap();
+        fs::write(dir.path().join("b/c/d"), b"").unwrap();
+        fs::write(dir.path().join("b/a"), b"").unwrap();
+        fs::write(dir.path().join("a"), b"").unwrap();
+        fs::create_dir(dir.path().join("empty")).unwrap();
+
+        let expected: Vec<PathBuf> = vec!["a".into(), "b/a".into(), "b/c/d".into()];
+        assert_eq!(walk(dir.path()), expected);
+    }
+
+    #[test]
+    fn produces_empty_dirs_if_enabled() {
+        let dir = TempDir::new();
+        fs::create_dir(dir.path().join("empty")).unwrap();
+        fs::write(dir.path().join("file"), b"").unwrap();
+
+        let paths: Vec<PathBuf> =
+            RelativePathIterator::new(dir.path(), false, Vec::new(), &[], false, true, true)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(paths, vec![PathBuf::from("empty"), PathBuf::from("file")]);
+    }
+
+    #[test]
+    fn skips_pruned_dirs() {
+        let dir = TempDir::new();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".git/config"), b"").unwrap();
+        fs::write(dir.path().join("file"), b"").unwrap();
+
+        let paths: Vec<PathBuf> =
+            RelativePathIterator::new(dir.path(), false, Vec::new(), &[".git"], false, false, true)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(paths, vec![PathBuf::from("file")]);
+    }
+
+    #[test]
+    fn parses_entry_type_names() {
+        for name in EntryType::NAMES {
+            assert_eq!(name.parse::<EntryType>().unwrap().to_string(), *name);
+        }
+        assert!("file".parse::<EntryType>().is_err());
+        assert!("FIFO".parse::<EntryType>().is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn skips_excluded_entry_types() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::net::UnixListener;
+
+        let dir = TempDir::new();
+        fs::write(dir.path().join("file"), b"").unwrap();
+        fs::create_dir(dir.path().join("dir")).unwrap();
+        fs::write(dir.path().join("dir/file"), b"").unwrap();
+        std::os::unix::fs::symlink("file", dir.path().join("link")).unwrap();
+        std::os::unix::fs::symlink("dir", dir.path().join("dir-link")).unwrap();
+        let _socket = UnixListener::bind(dir.path().join("socket")).unwrap();
+        let fifo = CString::new(dir.path().join("fifo").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+        let walk = |excluded: Vec<EntryType>| -> Vec<PathBuf> {
+            RelativePathIterator::new(dir.path(), true, excluded, &[], false, false, true)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect()
+        };
+        let paths = |names: &[&str]| -> Vec<PathBuf> { names.iter().map(PathBuf::from).collect() };
+
+        assert_eq!(
+            walk(Vec::new()),
+            paths(&[
+                "dir/file",
+                "dir-link/file",
+                "fifo",
+                "file",
+                "link",
+                "socket"
+            ])
+        );
+        assert_eq!(
+            walk(vec![EntryType::Fifo, EntryType::Socket]),
+            paths(&["dir/file", "dir-link/file", "file", "link"])
+        );
+        assert_eq!(
+            walk(vec![EntryType::Symlink, EntryType::Fifo]),
+            paths(&["dir/file", "file", "socket"])
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn links_leaving_the_root_produce_paths_under_it() {
+        let dir = TempDir::new();
+        let root = dir.path().join("root");
+        fs::create_dir_all(dir.path().join("out/sub")).unwrap();
+        fs::write(dir.path().join("out/sub/f"), b"").unwrap();
+        fs::create_dir(&root).unwrap();
+        std::os::unix::fs::symlink("../out", root.join("link")).unwrap();
+
+        let paths: Vec<PathBuf> =
+            RelativePathIterator::new(&root, true, Vec::new(), &[], false, false, true)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(paths, vec![PathBuf::from("link/sub/f")]);
+    }
+
+    #[test]
+    fn entries_outside_the_root_are_errors() {
+        let dir = TempDir::new();
+        fs::create_dir(dir.path().join("root")).unwrap();
+        fs::create_dir(dir.path().join("other")).unwrap();
+        fs::write(dir.path().join("other/f"), b"").unwrap();
+
+        // Walk another directory than the root, which is never the case
+        // otherwise.
+        let mut iter = RelativePathIterator {
+            iter: DirectoryIterator::new(
+                dir.path().join("other"),
+                false,
+                Vec::new(),
+                &[],
+                false,
+                false,
+                true,
+            )
+            .unwrap(),
+            root: dir.path().join("root"),
+        };
+        let err = iter.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("outside of the root directory"));
+        let err = err.get_ref().unwrap().downcast_ref::<FileError>().unwrap();
+        assert_eq!(err.path(), dir.path().join("other/f"));
+        assert!(iter.next().is_none());
+    }
+
+    /// Counts the open file descriptors of the process.
+    #[cfg(target_os = "linux")]
+    fn open_fds() -> usize {
+        fs::read_dir("/proc/self/fd").unwrap().count()
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn deep_tree_does_not_hold_directory_handles() {
+        const DEPTH: usize = 200;
+        const FILES: usize = 20;
+
+        let dir = TempDir::new();
+        let mut path = dir.path().to_path_buf();
+        for level in 0..DEPTH {
+            path.push(format!("d{}", level));
+            fs::create_dir(&path).unwrap();
+            for file in 0..FILES {
+                fs::write(path.join(format!("f{}", file)), b"").unwrap();
+            }
+        }
+
+        // Every level is left with files still to visit after its first one,
+        // so handles kept for the pending entries would pile up along the
+        // way down. The other tests may open descriptors concurrently, hence
+        // the margin.
+        let before = open_fds();
+        let iter =
+            RelativePathIterator::new(dir.path(), false, Vec::new(), &[], false, false, false)
+                .unwrap();
+        let mut count = 0;
+        let mut max = 0;
+        for path in iter {
+            path.unwrap();
+            count += 1;
+            max = max.max(open_fds());
+        }
+        assert_eq!(count, DEPTH * FILES);
+        assert!(max < before + DEPTH / 4, "{} descriptors open", max);
+    }
+
+    #[test]
+    fn nested_ignore_files_override_their_parents() {
+        let dir = TempDir::new();
+        create_files(
+            dir.path(),
+            &[
+                (".inventorizeignore", "*.log\nbuild/\n"),
+                ("a.log", ""),
+                ("keep.txt", ""),
+                ("build/out.txt", ""),
+                ("sub/.inventorizeignore", "!*.log\n"),
+                ("sub/b.log", ""),
+                ("z/c.log", ""),
+            ],
+        );
+
+        // The patterns of the nested file only apply to its own subtree.
+        let paths: Vec<_> =
+            RelativePathIterator::new(dir.path(), false, Vec::new(), &[], true, false, true)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new(".inventorizeignore"),
+                Path::new("keep.txt"),
+                Path::new("sub/.inventorizeignore"),
+                Path::new("sub/b.log"),
+            ]
+        );
     }
 }
@@ -0,0 +1,230 @@
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{BufRead, Error as IoError};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use log::warn;
+
+use crate::archive::EntryKind;
+use crate::hash::{HashAlgorithm, HashValue};
+use crate::util;
+
+/// Contents field of empty directories.
+const DIRECTORY_CONTENTS: &str = "(directory)";
+
+/// Prefix of the contents field of symbolic links, followed by the target.
+const SYMLINK_PREFIX: &str = "-> ";
+
+/// An error returned when a repository listing cannot be read.
+#[derive(Debug)]
+pub enum ListingError {
+    /// The underlying reader failed.
+    Io(IoError),
+
+    /// A line of the listing is malformed (line number).
+    InvalidLine(usize),
+
+    /// A line of the listing repeats the path of an earlier one (line
+    /// number).
+    DuplicatePath(usize),
+}
+
+impl Display for ListingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match &self {
+            ListingError::Io(err) => write!(f, "Listing I/O error: {}", err),
+            ListingError::InvalidLine(n) => write!(f, "Invalid listing line: {}", n),
+            ListingError::DuplicatePath(n) => write!(f, "Duplicate listing path on line {}", n),
+        }
+    }
+}
+
+impl Error for ListingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self {
+            ListingError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<IoError> for ListingError {
+    fn from(err: IoError) -> Self {
+        ListingError::Io(err)
+    }
+}
+
+/// A file of an external listing of the repository.
+#[derive(Debug)]
+pub struct ListingEntry {
+    /// Path of the file relative to the repository.
+    pub path: PathBuf,
+
+    /// Size of the file.
+    pub size: u64,
+
+    /// Kind of the file.
+    pub kind: EntryKind,
+
+    /// Known hashes of the file, which may be empty.
+    pub hashes: Vec<(HashAlgorithm, HashValue)>,
+}
+
+/// Reads a repository listing in the format produced by the `list`
+/// subcommand with the hex digest encoding.
+///
+/// Every line holds the tab separated path, size, and contents of a file. The
+/// contents are either the `alg:value` hashes separated by spaces (possibly
+/// none), `-> <target>` for symbolic links, or `(directory)` for directories.
+/// Empty lines are ignored.
+///
+/// A path listed more than once usually indicates a bug in the tool that
+/// produced the listing, e.g. concatenated outputs. Such paths are warned
+/// about, or rejected if strict.
+pub fn read_listing<R: BufRead>(
+    reader: R,
+    strict: bool,
+) -> Result<Vec<ListingEntry>, ListingError> {
+    let mut entries = Vec::new();
+    let mut seen = BTreeSet::new();
+
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+
+        let entry = parse_line(&line).ok_or(ListingError::InvalidLine(n + 1))?;
+        if !seen.insert(entry.path.clone()) {
+            if strict {
+                return Err(ListingError::DuplicatePath(n + 1));
+            }
+            warn!("Duplicate listing path on line {}: {:?}", n + 1, entry.path);
+        }
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// Parses a line of the listing.
+fn parse_line(line: &str) -> Option<ListingEntry> {
+    let mut fields = line.splitn(3, '\t');
+    let path = util::normalize_path(fields.next()?).filter(|p| !p.as_os_str().is_empty())?;
+    let size = u64::from_str(fields.next()?).ok()?;
+    let contents = fields.next().unwrap_or("");
+
+    let (kind, hashes) = if contents == DIRECTORY_CONTENTS {
+        (EntryKind::Directory, Vec::new())
+    } else if let Some(target) = contents.strip_prefix(SYMLINK_PREFIX) {
+        (EntryKind::Symlink(PathBuf::from(target)), Vec::new())
+    } else {
+        let hashes = contents
+            .split_whitespace()
+            .map(|h| {
+                let (algorithm, value) = h.split_at(h.find(':')?);
+                let algorithm = HashAlgorithm::from_str(algorithm).ok()?;
+                let value = HashValue::try_from(&value[1..])
+                    .ok()
+                    .filter(|v| v.as_bytes().len() == algorithm.digest_len())?;
+                Some((algorithm, value))
+            })
+            .collect::<Option<_>>()?;
+        (EntryKind::File, hashes)
+    };
+
+    Some(ListingEntry {
+        path,
+        size,
+        kind,
+        hashes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MD5: &str = "764efa883dda1e11db47671c4a3bbd9e";
+
+    #[test]
+    fn reads_listings() {
+        let listing = format!(
+            "a.txt\t3\tmd5:{md5}\n\n./sub//b.txt\t3\tmd5:{md5} sha1:{sha1}\n\
+             empty\t0\t\nsub/dir\t0\t(directory)\nlink\t0\t-> sub/b.txt\n",
+            md5 = MD5,
+            sha1 = "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12"
+        );
+        let entries = read_listing(listing.as_bytes(), false).unwrap();
+
+        let paths: Vec<_> = entries.iter().map(|e| e.path.to_str().unwrap()).collect();
+        assert_eq!(paths, ["a.txt", "sub/b.txt", "empty", "sub/dir", "link"]);
+
+        assert_eq!(entries[0].size, 3);
+        assert_eq!(entries[0].kind, EntryKind::File);
+        assert_eq!(entries[0].hashes.len(), 1);
+        assert_eq!(entries[0].hashes[0].0, HashAlgorithm::Md5);
+        assert_eq!(entries[0].hashes[0].1.to_hex(), MD5);
+        let algorithms: Vec<_> = entries[1].hashes.iter().map(|h| h.0).collect();
+        assert_eq!(algorithms, [HashAlgorithm::Md5, HashAlgorithm::Sha1]);
+        assert_eq!(entries[2].kind, EntryKind::File);
+        assert!(entries[2].hashes.is_empty());
+        assert_eq!(entries[3].kind, EntryKind::Directory);
+        assert!(entries[3].hashes.is_empty());
+        assert_eq!(
+            entries[4].kind,
+            EntryKind::Symlink(PathBuf::from("sub/b.txt"))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        let lines = [
+            "a.txt".to_string(),
+            "a.txt\tthree\t".to_string(),
+            "a.txt\t-1\t".to_string(),
+            format!("a.txt\t3\t{}", MD5),
+            format!("a.txt\t3\tcrc32:{}", MD5),
+            format!("a.txt\t3\tmd5:{}x", &MD5[..31]),
+            // A digest length that doesn't match the algorithm.
+            format!("a.txt\t3\tmd5:{}", &MD5[..30]),
+            format!("a.txt\t3\tsha1:{}", MD5),
+            // Paths outside of the repository, or none.
+            "../a.txt\t0\t".to_string(),
+            "/a.txt\t0\t".to_string(),
+            ".\t0\t(directory)".to_string(),
+            "\t0\t".to_string(),
+        ];
+        for line in &lines {
+            let listing = format!("ok\t0\t\n{}\n", line);
+            assert!(
+                matches!(
+                    read_listing(listing.as_bytes(), false),
+                    Err(ListingError::InvalidLine(2))
+                ),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn handles_duplicate_paths() {
+        let listing = "a.txt\t0\t\nb.txt\t0\t\n./a.txt\t1\t\n";
+
+        // Duplicates are only warned about by default, and kept.
+        let entries = read_listing(listing.as_bytes(), false).unwrap();
+        let paths: Vec<_> = entries.iter().map(|e| e.path.to_str().unwrap()).collect();
+        assert_eq!(paths, ["a.txt", "b.txt", "a.txt"]);
+        assert_eq!(entries[2].size, 1);
+
+        assert!(matches!(
+            read_listing(listing.as_bytes(), true),
+            Err(ListingError::DuplicatePath(3))
+        ));
+        assert!(read_listing(&b"a.txt\t0\t\nb.txt\t0\t\n"[..], true).is_ok());
+    }
+}
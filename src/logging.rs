@@ -0,0 +1,273 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Result as IoResult, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use serde::Serialize;
+
+/// Log output format.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Human,
+
+    /// One JSON object per line.
+    Json,
+}
+
+/// An error returned when the log format name cannot be parsed.
+#[derive(Debug)]
+pub struct ParseLogFormatError();
+
+impl FromStr for LogFormat {
+    type Err = ParseLogFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(ParseLogFormatError()),
+        }
+    }
+}
+
+/// A single log line in the JSON format.
+#[derive(Serialize)]
+struct JsonLine {
+    /// Time of the event in the RFC 3339 format.
+    timestamp: String,
+
+    /// Log level.
+    level: String,
+
+    /// Log message.
+    message: String,
+}
+
+/// Formats a log record as a single line in the given format, including the
+/// line break.
+///
+/// The human-readable lines match the ones written by `env_logger`.
+fn format_line(format: LogFormat, record: &Record<'_>) -> Option<String> {
+    let timestamp = humantime::format_rfc3339_millis(SystemTime::now()).to_string();
+    let mut line = match format {
+        LogFormat::Human => format!("[{} {:<5}] {}", timestamp, record.level(), record.args()),
+        LogFormat::Json => serde_json::to_string(&JsonLine {
+            timestamp,
+            level: record.level().to_string(),
+            message: record.args().to_string(),
+        })
+        .ok()?,
+    };
+    line.push('\n');
+    Some(line)
+}
+
+/// Installs the logger as the global logger.
+pub fn init(logger: Box<dyn Log>, level: LevelFilter) {
+    log::set_max_level(level);
+    log::set_boxed_logger(logger).expect("logger already initialized");
+}
+
+/// A logger that writes one JSON object per line to the standard error.
+pub struct JsonLogger {
+    /// Maximum level of the messages to output.
+    level: LevelFilter,
+}
+
+impl JsonLogger {
+    /// Creates a new JSON logger.
+    pub fn new(level: LevelFilter) -> Self {
+        JsonLogger { level }
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Write the whole line at once so that lines are not interleaved.
+        if let Some(line) = format_line(LogFormat::Json, record) {
+            let _ = io::stderr().lock().write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// A logger that passes the messages on to another logger and also appends
+/// them to a log file, in the same format and with the same level.
+///
+/// The file is not buffered, so every line is written to it as soon as it is
+/// logged and the file is complete even if the process is killed.
+pub struct TeeLogger {
+    /// Logger writing to the standard error.
+    inner: Box<dyn Log>,
+
+    /// Maximum level of the messages to output.
+    level: LevelFilter,
+
+    /// Format of the lines written to the file.
+    format: LogFormat,
+
+    /// The log file.
+    file: Mutex<File>,
+}
+
+impl TeeLogger {
+    /// Creates a new logger appending to the file with the specified path,
+    /// which is created if it does not exist.
+    pub fn new(
+        inner: Box<dyn Log>,
+        level: LevelFilter,
+        format: LogFormat,
+        path: &Path,
+    ) -> IoResult<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(TeeLogger {
+            inner,
+            level,
+            format,
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl Log for TeeLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        self.inner.log(record);
+        if let (Some(line), Ok(mut file)) = (format_line(self.format, record), self.file.lock()) {
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use log::Level;
+
+    use crate::util::TempDir;
+
+    /// A logger that counts the records passed on to it.
+    struct CountingLogger(Arc<AtomicUsize>);
+
+    impl Log for CountingLogger {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, _record: &Record<'_>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn formats_json_lines() {
+        let line = format_line(
+            LogFormat::Json,
+            &Record::builder()
+                .args(format_args!("say \"hi\"\n\tto {:?}", "a\\b"))
+                .level(Level::Warn)
+                .build(),
+        )
+        .unwrap();
+
+        // The line break in the message is escaped, so that the line holds
+        // the whole object.
+        assert_eq!(line.matches('\n').count(), 1);
+        assert!(line.ends_with("}\n"));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["message"], "say \"hi\"\n\tto \"a\\\\b\"");
+        let timestamp = value["timestamp"].as_str().unwrap();
+        assert!(humantime::parse_rfc3339(timestamp).is_ok());
+    }
+
+    #[test]
+    fn formats_human_lines() {
+        let line = format_line(
+            LogFormat::Human,
+            &Record::builder()
+                .args(format_args!("hello"))
+                .level(Level::Info)
+                .build(),
+        )
+        .unwrap();
+        assert!(line.starts_with('['));
+        assert!(line.ends_with(" INFO ] hello\n"));
+    }
+
+    #[test]
+    fn tee_appends_to_the_log_file() {
+        let dir = TempDir::new();
+        let path = dir.path().join("log.txt");
+        fs::write(&path, "earlier\n").unwrap();
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let logger = TeeLogger::new(
+            Box::new(CountingLogger(count.clone())),
+            LevelFilter::Info,
+            LogFormat::Json,
+            &path,
+        )
+        .unwrap();
+        for (level, message) in &[
+            (Level::Info, "first"),
+            (Level::Debug, "hidden"),
+            (Level::Error, "second"),
+        ] {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(*level)
+                    .build(),
+            );
+        }
+        logger.flush();
+
+        // Messages above the level are neither written nor passed on.
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        let log = fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "earlier");
+        for (line, message) in lines[1..].iter().zip(&["first", "second"]) {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(value["message"], *message);
+        }
+    }
+}
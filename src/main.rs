@@ -1,30 +1,53 @@
-use std::collections::BTreeSet;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
 use std::env;
 use std::error::Error;
 use std::ffi::OsString;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use std::fs::OpenOptions;
-use std::io::{BufReader, BufWriter};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Result as IoResult, Write};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
 
 use clap::{
     crate_authors, crate_version, value_t_or_exit, values_t_or_exit, App, AppSettings, Arg,
-    SubCommand,
+    ArgMatches, SubCommand,
 };
 
-use serde_json;
-
 use env_logger::{self, Builder as LogBuilder};
-use log::{self, error, info, LevelFilter};
+use log::{self, debug, error, info, warn, LevelFilter, Log};
+
+use serde::Serialize;
 
+mod archive;
+mod format;
 mod hash;
+#[cfg(feature = "http")]
+mod http;
+mod ignore;
+mod inflate;
 mod inventory;
 mod iterdir;
+mod listing;
+mod logging;
+mod output;
+mod progress;
+mod sha3;
+mod sumfile;
 mod util;
 
-use hash::HashAlgorithm;
-use inventory::{Configuration, FailureKind, Inventory};
+use format::Format;
+use hash::{DigestEncoding, HashAlgorithm};
+use inventory::{
+    BuildOptions, CheckOptions, Configuration, ErrorPolicy, FailureKind, Inventory, NoticeKind,
+    PathEncoding, Report,
+};
+use iterdir::EntryType;
+use logging::{JsonLogger, LogFormat, TeeLogger};
+use output::{JsonReport, OutputFormat};
+use progress::{LogProgress, ProgressObserver, WriterProgress};
 use util::FileError;
 
 /// High-level errors returned by the application.
@@ -32,6 +55,24 @@ use util::FileError;
 enum AppError {
     InventoryExists(PathBuf),
     VerificationFailed,
+    FilesFailed,
+    InventoriesDiffer,
+    ConfirmationRequired,
+    Aborted,
+    ProblemsFound(usize),
+    ManifestDiffers,
+    TotalsDiffer,
+    InvalidPathsLine(PathBuf, usize),
+    EmptyInventory,
+    AlgorithmsMissing(Vec<HashAlgorithm>),
+    RepositoryAccess(PathBuf, Box<dyn Error>),
+    NoHashAlgorithms,
+    NoContentHashes,
+    NoModificationTimes,
+    NoStoredRepository,
+    StoredRepositoryMissing(PathBuf),
+    RepositoryNotSpecified,
+    InventoryInRepository,
 }
 
 impl Display for AppError {
@@ -43,6 +84,86 @@ impl Display for AppError {
             AppError::VerificationFailed => {
                 write!(f, "Verification failed")
             }
+            AppError::FilesFailed => {
+                write!(f, "Some files could not be read")
+            }
+            AppError::InventoriesDiffer => {
+                write!(f, "Inventories differ")
+            }
+            AppError::ConfirmationRequired => {
+                write!(f, "Confirmation required, use --yes to proceed")
+            }
+            AppError::Aborted => {
+                write!(f, "Aborted")
+            }
+            AppError::ProblemsFound(count) => {
+                write!(f, "{} problem(s) found", count)
+            }
+            AppError::ManifestDiffers => {
+                write!(f, "Inventory and manifest disagree")
+            }
+            AppError::TotalsDiffer => {
+                write!(f, "Repository totals differ from the inventory")
+            }
+            AppError::InvalidPathsLine(path, n) => {
+                write!(f, "Invalid path on line {} of {:?}", n, path)
+            }
+            AppError::EmptyInventory => {
+                write!(f, "Inventory has no records")
+            }
+            AppError::AlgorithmsMissing(algorithms) => {
+                let names: Vec<_> = algorithms.iter().map(|a| a.to_string()).collect();
+                write!(
+                    f,
+                    "Inventory is not hashed with the required algorithm(s): {}",
+                    names.join(", ")
+                )
+            }
+            AppError::RepositoryAccess(path, _) => {
+                write!(f, "Cannot access the repository {:?}", path)
+            }
+            AppError::NoHashAlgorithms => {
+                write!(
+                    f,
+                    "Inventory records sizes only, it cannot be verified without comparing sizes"
+                )
+            }
+            AppError::NoContentHashes => {
+                write!(
+                    f,
+                    "Inventory records sizes only, it has no hashes to export"
+                )
+            }
+            AppError::NoModificationTimes => {
+                write!(
+                    f,
+                    "Inventory does not record modification times, build it with --record-mtime"
+                )
+            }
+            AppError::NoStoredRepository => {
+                write!(
+                    f,
+                    "Inventory does not store the repository path, build it with \
+                     --store-repository-path"
+                )
+            }
+            AppError::StoredRepositoryMissing(path) => {
+                write!(f, "Stored repository is not a directory: {:?}", path)
+            }
+            AppError::InventoryInRepository => {
+                write!(
+                    f,
+                    "Inventory must be located outside of the repository ({})",
+                    INSIDE_HINT
+                )
+            }
+            AppError::RepositoryNotSpecified => {
+                write!(
+                    f,
+                    "None of the recorded files exist in the current directory, specify the \
+                     repository with --repository"
+                )
+            }
         }
     }
 }
@@ -52,6 +173,24 @@ impl Error for AppError {
         match &self {
             AppError::InventoryExists(_) => None,
             AppError::VerificationFailed => None,
+            AppError::FilesFailed => None,
+            AppError::InventoriesDiffer => None,
+            AppError::ConfirmationRequired => None,
+            AppError::Aborted => None,
+            AppError::ProblemsFound(_) => None,
+            AppError::ManifestDiffers => None,
+            AppError::TotalsDiffer => None,
+            AppError::InvalidPathsLine(_, _) => None,
+            AppError::EmptyInventory => None,
+            AppError::AlgorithmsMissing(_) => None,
+            AppError::RepositoryAccess(_, err) => Some(err.as_ref()),
+            AppError::NoHashAlgorithms => None,
+            AppError::NoContentHashes => None,
+            AppError::NoModificationTimes => None,
+            AppError::NoStoredRepository => None,
+            AppError::StoredRepositoryMissing(_) => None,
+            AppError::RepositoryNotSpecified => None,
+            AppError::InventoryInRepository => None,
         }
     }
 }
@@ -64,22 +203,264 @@ struct CommandBuild {
     /// Skip hidden files in the repository.
     skip_hidden: bool,
 
+    /// Skip version control system metadata directories in the repository.
+    skip_vcs: bool,
+
+    /// Skip the entries matched by the `.inventorizeignore` files.
+    ignore_files: bool,
+
+    /// Storage of the record paths that are not valid UTF-8.
+    path_encoding: PathEncoding,
+
+    /// Record empty directories in the repository.
+    include_empty_dirs: bool,
+
     /// Hash algorithms to use.
     hash_algorithms: Vec<HashAlgorithm>,
+
+    /// Record the paths and sizes only, without hashing the contents.
+    sizes_only: bool,
+
+    /// Hash algorithms to use for files with specific extensions.
+    extension_algorithms: Vec<(String, Vec<HashAlgorithm>)>,
+
+    /// Hash algorithms to use for files with paths matching globs.
+    glob_algorithms: Vec<(String, Vec<HashAlgorithm>)>,
+
+    /// Descend into symbolically linked directories.
+    follow_symlink_dirs: bool,
+
+    /// Hash the targets of symbolically linked files.
+    follow_symlink_files: bool,
+
+    /// Skip all symbolic links.
+    exclude_symlinks: bool,
+
+    /// Skip the directory entries of these types.
+    excluded_types: Vec<EntryType>,
+
+    /// Seal the inventory, optionally including the file paths.
+    seal: Option<bool>,
+
+    /// Add the repository to an existing inventory under the path prefix.
+    append: Option<PathBuf>,
+
+    /// Free-form description of the inventory.
+    description: Option<String>,
+
+    /// Key/value tags of the inventory.
+    tags: Vec<(String, String)>,
+
+    /// Abort if the repository has more files than this.
+    max_files: Option<usize>,
+
+    /// Store the schema version instead of the app version.
+    reproducible: bool,
+
+    /// Name of the extended attribute to take the file hashes from.
+    trust_xattr: Option<String>,
+
+    /// Record the modification times of the files.
+    record_mtime: bool,
+
+    /// Record the creation times of the files.
+    record_btime: bool,
+
+    /// Skip files modified before this time, in nanoseconds since the Unix
+    /// epoch.
+    since_mtime: Option<u64>,
+
+    /// Store the repository path relative to the inventory file.
+    store_repository: bool,
+
+    /// Treat grown files with unchanged recorded contents as appended to.
+    append_aware: bool,
+
+    /// Fail on paths that differ only in case or on too little free space
+    /// for the inventory, instead of warning.
+    strict: bool,
+
+    /// Write a snapshot of the built inventory.
+    snapshots: Option<Snapshots>,
+
+    /// Verify the repository against the inventory once it is built.
+    verify_after: bool,
 }
 
 /// Arguments of the `verify` subcommand.
 struct CommandVerify {
     /// Quick verification mode (only file presence and their sizes are checked).
     quick: bool,
+
+    /// Only compare the number and the total size of the files.
+    quick_total: bool,
+
+    /// Fail right away if the inventory has no records.
+    fail_if_empty: bool,
+
+    /// Hash algorithms the inventory must be built with.
+    required_algorithms: Vec<HashAlgorithm>,
+
+    /// Only compare the hashes of the files, not their sizes.
+    no_size_check: bool,
+
+    /// Do not compare hashes of files larger than this size.
+    skip_hash_larger_than: Option<u64>,
+
+    /// Ignore hash mismatches of files smaller than this size.
+    exclude_hash_mismatch_under: Option<u64>,
+
+    /// Verify files in the order of their inode numbers.
+    optimize_io: bool,
+
+    /// Hash algorithms to compare; all recorded algorithms if empty.
+    hash_algorithms: Vec<HashAlgorithm>,
+
+    /// Verify a tar or zip archive of the repository instead of the repository.
+    archive: Option<PathBuf>,
+
+    /// Verify a listing of the repository read from the standard input
+    /// instead of the repository.
+    stdin_listing: bool,
+
+    /// Fail if the listing has duplicate paths, rather than warn.
+    strict_listing: bool,
+
+    /// File listing the record paths to verify instead of the whole
+    /// repository.
+    only_paths: Option<PathBuf>,
+
+    /// Report the failures, but do not fail the verification.
+    warn_only: bool,
+
+    /// Number of leading components to strip from the repository paths.
+    strip_components: usize,
+
+    /// Output the number of files per failure kind instead of the files.
+    summary: bool,
+
+    /// Check the repository in a single pass with bounded memory use.
+    low_memory: bool,
+
+    /// Failure kinds that fail the verification; all kinds if empty.
+    fail_on: Vec<FailureKind>,
+
+    /// Output the files that passed the verification.
+    print_ok: bool,
+
+    /// Write the failures and notices to this CSV file.
+    report_csv: Option<PathBuf>,
+
+    /// Only compare the hashes of files whose modification time changed.
+    verify_changed: bool,
+
+    /// Report files missing from the repository as not restored instead of
+    /// failing.
+    partial: bool,
+
+    /// Compare the inventory with a checksum manifest instead of the
+    /// repository.
+    manifest: Option<PathBuf>,
+
+    /// Verify the repository against a checksum manifest instead of the
+    /// inventory.
+    checksum_file: Option<PathBuf>,
+
+    /// Hash algorithm of the untagged manifest lines.
+    manifest_algorithm: Option<HashAlgorithm>,
+
+    /// Verify the repository at the path stored in the inventory.
+    repository_from_config: bool,
+
+    /// Download the repository files from under this URL instead of reading
+    /// them.
+    #[cfg(feature = "http")]
+    base_url: Option<http::BaseUrl>,
+}
+
+impl CommandVerify {
+    /// Checks if the files are read from the repository directory, rather
+    /// than from an archive, a listing, or a server.
+    fn reads_repository(&self) -> bool {
+        #[cfg(feature = "http")]
+        let downloads = self.base_url.is_some();
+        #[cfg(not(feature = "http"))]
+        let downloads = false;
+
+        self.archive.is_none() && !self.stdin_listing && !downloads
+    }
 }
 
 /// Arguments of the `update` subcommand.
 struct CommandUpdate {
     /// Remove missing files from the inventory.
     remove_missing: bool,
+
+    /// Number of leading components to strip from the repository paths.
+    strip_components: usize,
+
+    /// Write the inventory after every so many added files.
+    checkpoint_every: Option<usize>,
+
+    /// Only output the records that would be added and removed.
+    dry_run: bool,
+
+    /// Verify the added files once the inventory is updated.
+    verify_added: bool,
+
+    /// Fail on too little free space for the inventory instead of warning.
+    strict: bool,
+
+    /// Write a snapshot of the updated inventory.
+    snapshots: Option<Snapshots>,
+}
+
+/// Snapshots of the inventory to write after building or updating it.
+struct Snapshots {
+    /// Directory of the snapshots; the inventory directory if unset.
+    dir: Option<PathBuf>,
+
+    /// Number of the newest snapshots to keep; all if unset.
+    keep: Option<usize>,
+}
+
+/// Arguments of the `info` subcommand.
+struct CommandInfo {}
+
+/// Arguments of the `list` subcommand.
+struct CommandList {
+    /// Encoding of the hash values.
+    digest_encoding: DigestEncoding,
+
+    /// Only output the hash of the strongest recorded algorithm.
+    primary_hash: bool,
+}
+
+/// Arguments of the `export` subcommand.
+struct CommandExport {
+    /// Hash algorithm defining the content identity; the strongest default
+    /// algorithm of the inventory if unset.
+    algorithm: Option<HashAlgorithm>,
+
+    /// Encoding of the hash values.
+    digest_encoding: DigestEncoding,
+}
+
+/// Arguments of the `clean` subcommand.
+struct CommandClean {
+    /// Only list the files that would be removed.
+    dry_run: bool,
+}
+
+/// Arguments of the `diff` subcommand.
+struct CommandDiff {
+    /// Path to the inventory to compare with.
+    other: PathBuf,
 }
 
+/// Arguments of the `doctor` subcommand.
+struct CommandDoctor {}
+
 /// Supported subcommands and their arguments.
 enum Command {
     /// The `build` subcommand.
@@ -90,6 +471,24 @@ enum Command {
 
     /// The `update` subcommand.
     Update(CommandUpdate),
+
+    /// The `info` subcommand.
+    Info(CommandInfo),
+
+    /// The `list` subcommand.
+    List(CommandList),
+
+    /// The `export` subcommand.
+    Export(CommandExport),
+
+    /// The `clean` subcommand.
+    Clean(CommandClean),
+
+    /// The `diff` subcommand.
+    Diff(CommandDiff),
+
+    /// The `doctor` subcommand.
+    Doctor(CommandDoctor),
 }
 
 /// Common command-line options.
@@ -97,11 +496,58 @@ struct Options {
     /// Verbosity level.
     verbosity: usize,
 
+    /// Log output format.
+    log_format: LogFormat,
+
+    /// Also append the log messages to this file.
+    log_file: Option<PathBuf>,
+
+    /// Output format of the subcommand results.
+    output_format: OutputFormat,
+
+    /// Handling of per-file I/O errors.
+    error_policy: ErrorPolicy,
+
+    /// Maximum hashing throughput in bytes per second.
+    rate_limit: Option<u64>,
+
+    /// Size of the buffer the files are read into when hashing.
+    buffer_size: Option<usize>,
+
+    /// Number of threads to hash the files on.
+    threads: usize,
+
+    /// Skip the confirmation of destructive operations.
+    assume_yes: bool,
+
     /// Path to the inventory file.
     inventory: PathBuf,
 
     /// Path to the repository.
     repository: PathBuf,
+
+    /// The repository is specified on the command line rather than defaulted.
+    repository_specified: bool,
+
+    /// Accept an inventory inside the repository, excluding it from the
+    /// repository.
+    allow_inventory_inside: bool,
+
+    /// Directory to output the file paths relative to, instead of the
+    /// repository.
+    relative_to: Option<PathBuf>,
+
+    /// Write the records in the nested layout.
+    nested_records: bool,
+
+    /// Key to compute the HMAC values of the files with.
+    hmac_key: Option<Vec<u8>>,
+
+    /// File to write the progress to, instead of logging it.
+    progress_to: Option<PathBuf>,
+
+    /// Format of the inventory file, instead of the detected one.
+    inventory_format: Option<Format>,
 }
 
 /// Application parameters specified on the command line.
@@ -113,154 +559,1884 @@ struct Parameters {
     command: Command,
 }
 
+/// Asks the user to confirm a destructive operation, unless `--yes` is
+/// specified.
+fn confirm(options: &Options, prompt: &str) -> Result<(), Box<dyn Error>> {
+    if options.assume_yes {
+        return Ok(());
+    }
+
+    match util::ask_confirmation(prompt)? {
+        Some(true) => Ok(()),
+        Some(false) => Err(Box::new(AppError::Aborted)),
+        None => Err(Box::new(AppError::ConfirmationRequired)),
+    }
+}
+
 /// Builds the inventory file.
 fn build(options: Options, command: CommandBuild) -> Result<(), Box<dyn Error>> {
+    if let Some(prefix) = &command.append {
+        return append(&options, &command, prefix);
+    }
+
     // Check that the inventory exists before computing the hashes which can
-    // take quite a while.
-    if options.inventory.exists() && !command.overwrite {
-        return Err(Box::new(AppError::InventoryExists(options.inventory)));
+    // take quite a while. Named pipes and the like are written to as usual.
+    if options.inventory.is_file() {
+        if !command.overwrite {
+            return Err(Box::new(AppError::InventoryExists(options.inventory)));
+        }
+
+        confirm(
+            &options,
+            &format!("Overwrite the inventory {:?}?", options.inventory),
+        )?;
     }
 
     // Initialize the configuration and build the inventory.
-    let mut inventory_config = Configuration::new();
-    inventory_config.set_skip_hidden(command.skip_hidden);
-    inventory_config.set_hash_algorithms(command.hash_algorithms.as_slice());
-    let inventory = Inventory::build(inventory_config, &options.repository)?;
-
-    // Serialize the inventory to the JSON file.
-    let inventory_writer = BufWriter::new(
-        OpenOptions::new()
-            .create(command.overwrite)
-            .create_new(!command.overwrite)
-            .truncate(command.overwrite)
-            .write(true)
-            .open(&options.inventory)
-            .or_else(|e| file_err!(&options.inventory, e))?,
-    );
-    serde_json::to_writer_pretty(inventory_writer, &inventory)?;
+    let inventory_config = Configuration::builder()
+        .skip_hidden(command.skip_hidden)
+        .skip_vcs(command.skip_vcs)
+        .ignore_files(command.ignore_files)
+        .path_encoding(command.path_encoding)
+        .include_empty_dirs(command.include_empty_dirs)
+        .hash_algorithms(command.hash_algorithms.iter().copied())
+        .extension_algorithms(command.extension_algorithms)
+        .glob_algorithms(command.glob_algorithms)
+        .follow_symlink_dirs(command.follow_symlink_dirs)
+        .follow_symlink_files(command.follow_symlink_files)
+        .exclude_symlinks(command.exclude_symlinks)
+        .excluded_types(&command.excluded_types)
+        .reproducible(command.reproducible)
+        .trust_xattr(command.trust_xattr)
+        .record_mtime(command.record_mtime)
+        .record_btime(command.record_btime)
+        .since_mtime(command.since_mtime)
+        .append_aware(command.append_aware)
+        .hmac_key(options.hmac_key.as_deref())
+        .build();
+    let stored_repository = if command.store_repository {
+        Some(stored_repository_path(&options))
+    } else {
+        None
+    };
+    let mut build_options = BuildOptions::new();
+    build_options
+        .set_excluded_paths(&excluded_paths(&options))
+        .set_max_files(command.max_files)
+        .set_error_policy(options.error_policy)
+        .set_rate_limit(options.rate_limit)
+        .set_buffer_size(options.buffer_size)
+        .set_hmac_key(options.hmac_key.as_deref())
+        .set_strict_case(command.strict)
+        .set_strict_space(command.strict)
+        .set_output_path(Some(&options.inventory));
+    let (mut inventory, report) = Inventory::build(
+        inventory_config,
+        &options.repository,
+        &build_options,
+        Some(progress_observer(&options)?.as_mut()),
+    )
+    .map_err(|e| repository_error(&options.repository, e))?;
+    inventory.set_description(command.description);
+    inventory.set_repository(stored_repository);
+    for (key, value) in command.tags {
+        inventory.set_tag(key, value);
+    }
+    if let Some(includes_paths) = command.seal {
+        inventory.seal(includes_paths)?;
+    }
+
+    // Check the inventory once again, since building it might have taken a
+    // while.
+    if options.inventory.is_file() && !command.overwrite {
+        return Err(Box::new(AppError::InventoryExists(options.inventory)));
+    }
+
+    inventory.write_to_path(
+        &options.inventory,
+        options.inventory_format,
+        options.nested_records,
+    )?;
+    if let Some(snapshots) = &command.snapshots {
+        write_snapshot(&options, snapshots)?;
+    }
+
+    if !report.is_empty() {
+        print_report(&options, &report, describe_failure);
+        return Err(Box::new(AppError::FilesFailed));
+    }
 
     info!("Inventory built successfully.");
+    if command.sizes_only {
+        warn!("Only the file sizes are recorded, file contents cannot be verified");
+    }
+
+    // Re-hash the files right away. Since the repository is not expected to
+    // change in between, any discrepancy indicates unreliable reads.
+    if command.verify_after {
+        let mut check_options = CheckOptions::new();
+        check_options
+            .set_excluded_paths(&excluded_paths(&options))
+            .set_check_hashes(!command.sizes_only)
+            .set_error_policy(options.error_policy)
+            .set_rate_limit(options.rate_limit)
+            .set_buffer_size(options.buffer_size)
+            .set_threads(options.threads)
+            .set_hmac_key(options.hmac_key.as_deref());
+        let report = inventory
+            .check(
+                &options.repository,
+                &check_options,
+                Some(progress_observer(&options)?.as_mut()),
+            )
+            .map_err(|e| repository_error(&options.repository, e))?;
+
+        print_report(&options, &report, describe_failure);
+        if !report.is_empty() {
+            return Err(Box::new(AppError::VerificationFailed));
+        }
+
+        info!("No issues found.");
+    }
 
     Ok(())
 }
 
-/// Verifies the repository using a pre-built inventory.
-fn verify(options: Options, command: CommandVerify) -> Result<(), Box<dyn Error>> {
-    // Open the inventory file for reading.
-    let inventory_file = OpenOptions::new()
-        .read(true)
-        .open(&options.inventory)
-        .or_else(|e| file_err!(&options.inventory, e))?;
-    let inventory_reader = BufReader::new(inventory_file);
-    let inventory: Inventory = serde_json::from_reader(inventory_reader)?;
+/// Copies the written inventory to a new snapshot, then removes the oldest
+/// snapshots beyond the number to keep.
+fn write_snapshot(options: &Options, snapshots: &Snapshots) -> Result<(), Box<dyn Error>> {
+    let dir = match &snapshots.dir {
+        Some(dir) => dir.as_path(),
+        None => options.inventory.parent().unwrap_or_else(|| Path::new(".")),
+    };
+    let extension = options
+        .inventory_format
+        .unwrap_or_else(|| Format::from_path(&options.inventory))
+        .extension();
 
-    // Check the inventory and produce the report.
-    let report = inventory.check(&options.repository, !command.quick)?;
+    let path = util::write_snapshot(&options.inventory, dir, extension)?;
+    info!("Inventory snapshot written to {:?}", path);
+    if let Some(keep) = snapshots.keep {
+        for path in util::prune_snapshots(&options.inventory, dir, extension, keep)? {
+            debug!("Removed the old snapshot {:?}", path);
+        }
+    }
 
-    // Output the issues, if any.
-    for failure in report.failures() {
-        let descr = match failure {
-            FailureKind::MissingFromRepository => "Missing from repository",
-            FailureKind::MissingFromInventory => "Missing from inventory",
-            FailureKind::SizeMismatch => "Size mismatch",
-            FailureKind::HashMismatch => "Hash mismatch",
-        };
+    Ok(())
+}
 
-        let sorted: BTreeSet<_> = report.by_failure(failure).unwrap().collect();
-        for file in sorted {
-            error!("{}: {:?}", descr, file);
-        }
+/// Adds the repository to an existing inventory under the path prefix.
+fn append(options: &Options, command: &CommandBuild, prefix: &Path) -> Result<(), Box<dyn Error>> {
+    let mut inventory = Inventory::from_path(&options.inventory, options.inventory_format)?;
+    let mut build_options = BuildOptions::new();
+    build_options
+        .set_excluded_paths(&excluded_paths(options))
+        .set_max_files(command.max_files)
+        .set_error_policy(options.error_policy)
+        .set_rate_limit(options.rate_limit)
+        .set_buffer_size(options.buffer_size)
+        .set_hmac_key(options.hmac_key.as_deref())
+        .set_strict_case(command.strict)
+        .set_strict_space(command.strict)
+        .set_output_path(Some(&options.inventory));
+    let report = inventory
+        .append(
+            &options.repository,
+            prefix,
+            command.hash_algorithms.as_slice(),
+            &build_options,
+        )
+        .map_err(|e| repository_error(&options.repository, e))?;
+
+    inventory.write_to_path(
+        &options.inventory,
+        options.inventory_format,
+        options.nested_records,
+    )?;
+    if let Some(snapshots) = &command.snapshots {
+        write_snapshot(options, snapshots)?;
     }
 
     if !report.is_empty() {
-        Err(Box::new(AppError::VerificationFailed))
-    } else {
-        info!("No issues found.");
-        Ok(())
+        print_report(options, &report, describe_failure);
+        return Err(Box::new(AppError::FilesFailed));
     }
+
+    info!("Inventory extended successfully.");
+
+    Ok(())
 }
 
-/// Updates the inventory with files added to or removed from the repository.
-fn update(options: Options, command: CommandUpdate) -> Result<(), Box<dyn Error>> {
-    // Open the inventory file for reading.
-    let inventory_file = OpenOptions::new()
-        .read(true)
-        .open(&options.inventory)
-        .or_else(|e| file_err!(&options.inventory, e))?;
-    let inventory_reader = BufReader::new(inventory_file);
-    let mut inventory: Inventory = serde_json::from_reader(inventory_reader)?;
+/// Returns the description of a verification failure.
+fn describe_failure(kind: FailureKind) -> &'static str {
+    match kind {
+        FailureKind::MissingFromRepository => "Missing from repository",
+        FailureKind::MissingFromInventory => "Missing from inventory",
+        FailureKind::SizeMismatch => "Size mismatch",
+        FailureKind::HashMismatch => "Hash mismatch",
+        FailureKind::SymlinkMismatch => "Symbolic link mismatch",
+        FailureKind::ReadError => "Read error",
+        FailureKind::MissingDirectory => "Missing directory",
+        FailureKind::CreatedTimeMismatch => "Creation time mismatch (recreated)",
+        FailureKind::NetworkError => "Network error",
+    }
+}
 
-    // Update the inventory in-place.
-    inventory.update(&options.repository, command.remove_missing)?;
-
-    // Serialize the inventory to the JSON file.
-    let inventory_writer = BufWriter::new(
-        OpenOptions::new()
-            .truncate(true)
-            .write(true)
-            .open(&options.inventory)
-            .or_else(|e| file_err!(&options.inventory, e))?,
-    );
-    serde_json::to_writer_pretty(inventory_writer, &inventory)?;
+/// Returns the description of a difference between inventories.
+///
+/// The other inventory takes the place of the repository in the report.
+fn describe_difference(kind: FailureKind) -> &'static str {
+    match kind {
+        FailureKind::MissingFromRepository => "Removed",
+        FailureKind::MissingFromInventory => "Added",
+        FailureKind::SizeMismatch => "Size changed",
+        FailureKind::HashMismatch => "Hash changed",
+        FailureKind::SymlinkMismatch => "Symbolic link changed",
+        FailureKind::ReadError => "Read error",
+        FailureKind::MissingDirectory => "Directory changed",
+        FailureKind::CreatedTimeMismatch => "Recreated",
+        FailureKind::NetworkError => "Network error",
+    }
+}
 
-    info!("Inventory updated successfully.");
+/// Returns the description of a difference between the repository and a
+/// checksum manifest.
+///
+/// The manifest takes the place of the inventory in the report.
+fn describe_checksum_difference(kind: FailureKind) -> &'static str {
+    match kind {
+        FailureKind::MissingFromInventory => "Missing from checksum file",
+        kind => describe_failure(kind),
+    }
+}
 
-    Ok(())
+/// Returns the description of a difference between the inventory and a
+/// checksum manifest.
+///
+/// The manifest takes the place of the repository in the report.
+fn describe_manifest_difference(kind: FailureKind) -> &'static str {
+    match kind {
+        FailureKind::MissingFromRepository => "Missing from manifest",
+        FailureKind::MissingFromInventory => "Missing from inventory",
+        FailureKind::SizeMismatch => "Size mismatch",
+        FailureKind::HashMismatch => "Hash mismatch",
+        FailureKind::SymlinkMismatch => "Listed symbolic link",
+        FailureKind::ReadError => "Read error",
+        FailureKind::MissingDirectory => "Listed directory",
+        FailureKind::CreatedTimeMismatch => "Creation time mismatch",
+        FailureKind::NetworkError => "Network error",
+    }
 }
 
-/// Executes the subcommand specified by the caller.
-fn run(parameters: Parameters) -> Result<(), Box<dyn Error>> {
-    match parameters.command {
-        Command::Build(command) => build(parameters.options, command),
-        Command::Verify(command) => verify(parameters.options, command),
-        Command::Update(command) => update(parameters.options, command),
+/// Returns the description of a verification notice.
+fn describe_notice(notice: NoticeKind) -> &'static str {
+    match notice {
+        NoticeKind::HashSkipped => "Hash check skipped",
+        NoticeKind::SizeComparedOnly => "Size compared only (no common hash algorithm)",
+        NoticeKind::HashMismatchIgnored => "Hash mismatch ignored (small file)",
+        NoticeKind::Appended => "Appended to (recorded contents unchanged)",
+        NoticeKind::NotRestored => "Not restored (partial repository)",
+        NoticeKind::UnresolvedHardLink => "Hard link not verified (target not checked)",
     }
 }
 
-/// Canonicalizes the inventory file path.
+/// Returns the path of a file in the repository for output.
 ///
-/// The inventory file is not required to exist, since not every subcommand
-/// needs it (e.g. `build`). This means that it cannot be canonicalized using
-/// the `canonicalize()` method.
+/// With `--relative-to`, the path is relative to that directory if it
+/// contains the file; otherwise, the path is relative to the repository.
+fn display_path<'a>(options: &Options, file: &'a Path) -> Cow<'a, Path> {
+    match &options.relative_to {
+        Some(dir) => match options.repository.join(file).strip_prefix(dir) {
+            Ok(p) => Cow::Owned(p.to_path_buf()),
+            Err(_) => Cow::Borrowed(file),
+        },
+        None => Cow::Borrowed(file),
+    }
+}
+
+/// Outputs the failures and notices of the report, if any.
+fn print_report(options: &Options, report: &Report, describe: fn(FailureKind) -> &'static str) {
+    // Output the failures, if any.
+    for failure in report.failures() {
+        let descr = describe(failure);
+
+        let sorted: BTreeSet<_> = report.by_failure(failure).unwrap().collect();
+        for file in sorted {
+            error!("{}: {:?}", descr, display_path(options, file));
+        }
+    }
+
+    // Output the notices, if any.
+    for notice in report.notices() {
+        let descr = describe_notice(notice);
+
+        let sorted: BTreeSet<_> = report.by_notice(notice).unwrap().collect();
+        for file in sorted {
+            warn!("{}: {:?}", descr, display_path(options, file));
+        }
+    }
+}
+
+/// Writes the failures and notices of the report to a CSV file, sorted by
+/// category, kind, and path.
 ///
-/// However, the inventory's parent directory must exist. It can also be empty,
-/// which means that the current working directory is to be used (if e.g.
-/// "inventory.json" is specified as the inventory argument).
-fn canonicalize_inventory_path<P: AsRef<Path>>(inventory: P) -> Result<PathBuf, String> {
-    let inventory = inventory.as_ref();
-    let (dir_path, file_name) = (inventory.parent(), inventory.file_name());
+/// Besides the path, every row holds the recorded size of the file and, if
+/// the repository has been checked directly, its current size; either is
+/// empty if unknown.
+fn write_report_csv(
+    options: &Options,
+    path: &Path,
+    report: &Report,
+    inventory: &Inventory,
+    repository: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut rows: Vec<_> = report
+        .failures()
+        .into_iter()
+        .flat_map(|k| {
+            report
+                .by_failure(k)
+                .unwrap()
+                .map(move |f| ("failure", k.name(), f))
+        })
+        .chain(report.notices().into_iter().flat_map(|k| {
+            report
+                .by_notice(k)
+                .unwrap()
+                .map(move |f| ("notice", k.name(), f))
+        }))
+        .collect();
+    rows.sort();
 
-    if dir_path.is_none() || file_name.is_none() {
-        return Err(String::from("inventory filename not specified"));
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|e| FileError::new(path, e))?;
+    let mut writer = BufWriter::new(file);
+
+    let size = |size: Option<u64>| size.map_or_else(String::new, |s| s.to_string());
+    writeln!(writer, "category,kind,path,recorded_size,actual_size")?;
+    for (category, kind, file) in rows {
+        let recorded = inventory
+            .record(file)
+            .filter(|r| !r.is_directory() && r.symlink().is_none())
+            .map(|r| r.size());
+        let actual = if repository {
+            fs::metadata(options.repository.join(file))
+                .ok()
+                .filter(|m| m.is_file())
+                .map(|m| m.len())
+        } else {
+            None
+        };
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            category,
+            kind,
+            util::csv_field(&display_path(options, file).to_string_lossy()),
+            size(recorded),
+            size(actual)
+        )?;
     }
+    writer.flush()?;
 
-    let (dir_path, file_name) = (dir_path.unwrap(), file_name.unwrap());
+    Ok(())
+}
 
-    let dir_path = if dir_path.as_os_str().len() == 0 {
-        Path::new(".")
-    } else {
-        dir_path
-    };
+/// Outputs the number of files per failure and notice kind in the report.
+fn print_summary(report: &Report, describe: fn(FailureKind) -> &'static str) {
+    let failures: BTreeSet<_> = report
+        .failures()
+        .into_iter()
+        .map(|f| (describe(f), report.by_failure(f).unwrap().count()))
+        .collect();
+    for (descr, count) in failures {
+        error!("{}: {} file(s)", descr, count);
+    }
 
-    let mut ret = dir_path
-        .canonicalize()
-        .map_err(|_| "inventory directory is inaccessible or does not exist".to_string())?;
-    ret.push(file_name);
+    let notices: BTreeSet<_> = report
+        .notices()
+        .into_iter()
+        .map(|n| (describe_notice(n), report.by_notice(n).unwrap().count()))
+        .collect();
+    for (descr, count) in notices {
+        warn!("{}: {} file(s)", descr, count);
+    }
+}
 
-    Ok(ret)
+/// Returns the path to the repository relative to the directory of the
+/// inventory file, to be stored in the inventory.
+fn stored_repository_path(options: &Options) -> PathBuf {
+    // The inventory path is canonical, so it always has a parent.
+    let base = options.inventory.parent().unwrap();
+    util::relative_path(&options.repository, base)
 }
 
-/// Parses the command line arguments.
+/// Resolves the repository to verify.
 ///
-/// Prints an error message and exits the application if the command-line
-/// configuration is invalid.
-fn parse_cmd_line<I, T>(args: I) -> Parameters
-where
-    I: IntoIterator<Item = T>,
-    T: Into<OsString> + Clone,
+/// The path specified on the command line takes precedence, unless the path
+/// stored in the inventory is requested. Otherwise, the stored path is
+/// preferred to the current directory, which is only accepted if some of the
+/// recorded files exist in it.
+fn resolve_repository(
+    options: &Options,
+    command: &CommandVerify,
+    inventory: &Inventory,
+) -> Result<PathBuf, AppError> {
+    if options.repository_specified && !command.repository_from_config {
+        return Ok(options.repository.clone());
+    }
+
+    match inventory.repository() {
+        Some(stored) => {
+            // The inventory path is canonical, so it always has a parent.
+            let path = options.inventory.parent().unwrap().join(stored);
+            match path.canonicalize() {
+                Ok(path) if path.is_dir() => {
+                    info!("Using the stored repository: {:?}", path);
+                    Ok(path)
+                }
+                _ => Err(AppError::StoredRepositoryMissing(path)),
+            }
+        }
+        None if command.repository_from_config => Err(AppError::NoStoredRepository),
+        None => {
+            let looks_like_repository = inventory.records().next().is_none()
+                || inventory
+                    .records()
+                    .any(|(p, _)| fs::symlink_metadata(options.repository.join(p)).is_ok());
+            if looks_like_repository {
+                Ok(options.repository.clone())
+            } else {
+                Err(AppError::RepositoryNotSpecified)
+            }
+        }
+    }
+}
+
+/// Verifies the repository using a pre-built inventory.
+fn verify(mut options: Options, command: CommandVerify) -> Result<(), Box<dyn Error>> {
+    if let Some(checksum_file) = &command.checksum_file {
+        return verify_checksum_file(&options, &command, checksum_file);
+    }
+
+    let inventory = Inventory::from_path(&options.inventory, options.inventory_format)?;
+    if command.fail_if_empty && inventory.record_count() == 0 {
+        return Err(Box::new(AppError::EmptyInventory));
+    }
+    let recorded = inventory.configuration().hash_algorithms();
+    let missing: Vec<_> = command
+        .required_algorithms
+        .iter()
+        .filter(|a| !recorded.contains(a))
+        .copied()
+        .collect();
+    if !missing.is_empty() {
+        return Err(Box::new(AppError::AlgorithmsMissing(missing)));
+    }
+
+    if let Some(manifest) = &command.manifest {
+        return verify_manifest(&options, &command, &inventory, manifest);
+    }
+
+    // Archives, listings, and servers take the place of the repository.
+    if command.reads_repository() {
+        options.repository = resolve_repository(&options, &command, &inventory)?;
+        if !options.allow_inventory_inside && options.inventory.starts_with(&options.repository) {
+            return Err(Box::new(AppError::InventoryInRepository));
+        }
+    }
+
+    if command.quick_total {
+        return verify_totals(&options, &command, &inventory);
+    }
+
+    // Inventories without hash algorithms record sizes only, so they are
+    // always verified in the quick mode. Make it clear that the contents are
+    // not verified, rather than silently comparing sizes only.
+    let sizes_only = inventory.configuration().all_algorithms().is_empty();
+    if sizes_only {
+        if command.no_size_check {
+            return Err(Box::new(AppError::NoHashAlgorithms));
+        }
+        if !command.quick {
+            warn!("Inventory records sizes only, file contents are not verified");
+        }
+    }
+    let quick = command.quick || sizes_only;
+
+    // The algorithms come from the inventory, unless a subset is selected.
+    if !quick {
+        let algorithms = if command.hash_algorithms.is_empty() {
+            inventory.configuration().all_algorithms()
+        } else {
+            command.hash_algorithms.clone()
+        };
+        if command.verify_changed && !inventory.configuration().record_mtime() {
+            return Err(Box::new(AppError::NoModificationTimes));
+        }
+
+        let names: Vec<_> = algorithms.iter().map(|a| a.to_string()).collect();
+        info!("Verifying with: {}", names.join(", "));
+    }
+
+    if command.low_memory && options.threads > 1 {
+        warn!("The single-pass verification uses one thread, ignoring --threads");
+    }
+
+    // Check the inventory and produce the report.
+    let mut check_options = CheckOptions::new();
+    check_options
+        .set_excluded_paths(&excluded_paths(&options))
+        .set_check_hashes(!quick)
+        .set_check_sizes(!command.no_size_check)
+        .set_skip_hash_larger_than(command.skip_hash_larger_than)
+        .set_ignore_hash_mismatch_under(command.exclude_hash_mismatch_under)
+        .set_optimize_io(command.optimize_io)
+        .set_hash_algorithms(command.hash_algorithms.as_slice())
+        .set_error_policy(options.error_policy)
+        .set_rate_limit(options.rate_limit)
+        .set_buffer_size(options.buffer_size)
+        .set_threads(options.threads)
+        .set_hmac_key(options.hmac_key.as_deref())
+        .set_strip_components(command.strip_components)
+        .set_low_memory(command.low_memory)
+        .set_track_passed(command.print_ok)
+        .set_verify_changed(command.verify_changed)
+        .set_partial(command.partial);
+    if let Some(path) = &command.only_paths {
+        check_options.set_only_paths(Some(read_only_paths(path, &inventory)?));
+    }
+    let mut progress = progress_observer(&options)?;
+    #[cfg(feature = "http")]
+    let downloaded = match &command.base_url {
+        Some(base) => {
+            info!("Downloading the files from {}", base);
+            Some(inventory.check_url(base, &check_options, Some(progress.as_mut()))?)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "http"))]
+    let downloaded = None;
+
+    let report = if let Some(report) = downloaded {
+        report
+    } else if let Some(archive) = &command.archive {
+        inventory.check_archive(archive, &check_options, Some(progress.as_mut()))?
+    } else if command.stdin_listing {
+        let listing = listing::read_listing(io::stdin().lock(), command.strict_listing)?;
+        inventory.check_listing(listing, &check_options, Some(progress.as_mut()))?
+    } else {
+        inventory
+            .check(&options.repository, &check_options, Some(progress.as_mut()))
+            .map_err(|e| repository_error(&options.repository, e))?
+    };
+
+    print_verification(&options, &command, &report, describe_failure);
+
+    if let Some(path) = &command.report_csv {
+        // Current sizes are only known if the repository itself is checked
+        // under the recorded paths.
+        let repository = command.reads_repository() && command.strip_components == 0;
+        write_report_csv(&options, path, &report, &inventory, repository)?;
+    }
+
+    verification_result(&command, &report, "No issues found.")
+}
+
+/// Outputs the verification report, along with the files that passed the
+/// verification if requested.
+fn print_verification(
+    options: &Options,
+    command: &CommandVerify,
+    report: &Report,
+    describe: fn(FailureKind) -> &'static str,
+) {
+    if command.print_ok {
+        let sorted: BTreeSet<_> = report.passed().collect();
+        for file in sorted {
+            info!("OK: {:?}", display_path(options, file));
+        }
+    }
+
+    if command.summary {
+        print_summary(report, describe);
+    } else {
+        print_report(options, report, describe);
+    }
+}
+
+/// Returns the result of the verification, which fails if the report has
+/// failures of the selected kinds, unless only warnings are requested.
+///
+/// The message is logged if the report has no issues at all.
+fn verification_result(
+    command: &CommandVerify,
+    report: &Report,
+    no_issues: &str,
+) -> Result<(), Box<dyn Error>> {
+    // Only the selected failure kinds fail the verification, although all of
+    // them are reported.
+    let failed = report
+        .failures()
+        .iter()
+        .any(|k| command.fail_on.is_empty() || command.fail_on.contains(k));
+
+    if report.is_empty() {
+        info!("{}", no_issues);
+        Ok(())
+    } else if !failed {
+        warn!("Verification found issues, none of which are selected by --fail-on.");
+        Ok(())
+    } else if command.warn_only {
+        warn!("Verification failed, ignoring due to --warn-only.");
+        Ok(())
+    } else {
+        Err(Box::new(AppError::VerificationFailed))
+    }
+}
+
+/// Reads the record paths to verify, one per line, warning about the ones
+/// that have no records in the inventory.
+fn read_only_paths(
+    path: &Path,
+    inventory: &Inventory,
+) -> Result<BTreeSet<PathBuf>, Box<dyn Error>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .map_err(|e| FileError::new(path, e))?;
+    let mut paths = BTreeSet::new();
+
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| FileError::new(path, e))?;
+        if line.is_empty() {
+            continue;
+        }
+
+        let file = util::normalize_path(&line)
+            .filter(|p| !p.as_os_str().is_empty())
+            .ok_or_else(|| AppError::InvalidPathsLine(path.to_path_buf(), n + 1))?;
+        if inventory.record(&file).is_none() {
+            warn!("Path not in the inventory, skipping: {:?}", file);
+        }
+        paths.insert(file);
+    }
+
+    Ok(paths)
+}
+
+/// Compares the number and the total size of the repository files with the
+/// inventory, without hashing or comparing the files one by one.
+fn verify_totals(
+    options: &Options,
+    command: &CommandVerify,
+    inventory: &Inventory,
+) -> Result<(), Box<dyn Error>> {
+    let (files, bytes) = inventory
+        .repository_totals(&options.repository, &excluded_paths(options))
+        .map_err(|e| repository_error(&options.repository, e))?;
+    let (recorded_files, recorded_bytes) = (inventory.record_count(), inventory.total_bytes());
+    info!(
+        "Repository: {} files ({} bytes), inventory: {} files ({} bytes)",
+        files, bytes, recorded_files, recorded_bytes
+    );
+
+    if files == recorded_files && bytes == recorded_bytes {
+        info!("Repository totals match the inventory.");
+        Ok(())
+    } else if command.warn_only {
+        warn!("Repository totals differ from the inventory, ignoring due to --warn-only.");
+        Ok(())
+    } else {
+        Err(Box::new(AppError::TotalsDiffer))
+    }
+}
+
+/// Compares the inventory with a checksum manifest, without touching the
+/// repository.
+fn verify_manifest(
+    options: &Options,
+    command: &CommandVerify,
+    inventory: &Inventory,
+    manifest: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .open(manifest)
+        .map_err(|e| FileError::new(manifest, e))?;
+    let entries = sumfile::read_sumfile(BufReader::new(file), command.manifest_algorithm)?;
+    let report = inventory.diff_manifest(entries);
+
+    if command.summary {
+        print_summary(&report, describe_manifest_difference);
+    } else {
+        print_report(options, &report, describe_manifest_difference);
+    }
+
+    if report.is_empty() {
+        info!("Inventory and manifest agree.");
+        Ok(())
+    } else if command.warn_only {
+        warn!("Inventory and manifest disagree, ignoring due to --warn-only.");
+        Ok(())
+    } else {
+        Err(Box::new(AppError::ManifestDiffers))
+    }
+}
+
+/// Updates the inventory with files added to or removed from the repository.
+fn update(options: Options, command: CommandUpdate) -> Result<(), Box<dyn Error>> {
+    let mut inventory = Inventory::from_path(&options.inventory, options.inventory_format)?;
+
+    if command.dry_run {
+        return update_dry_run(&options, &command, &inventory);
+    }
+
+    if command.remove_missing {
+        confirm(
+            &options,
+            "Remove the records of files missing from the repository?",
+        )?;
+    }
+
+    // Update the inventory in-place.
+    let mut build_options = BuildOptions::new();
+    build_options
+        .set_excluded_paths(&excluded_paths(&options))
+        .set_error_policy(options.error_policy)
+        .set_rate_limit(options.rate_limit)
+        .set_buffer_size(options.buffer_size)
+        .set_hmac_key(options.hmac_key.as_deref())
+        .set_strip_components(command.strip_components)
+        .set_checkpoint_every(command.checkpoint_every)
+        .set_strict_space(command.strict)
+        .set_output_path(Some(&options.inventory));
+
+    // Checkpoints are written atomically, just like the final inventory.
+    let mut write_checkpoint = |inventory: &Inventory| {
+        debug!("Writing a checkpoint of the inventory");
+        inventory.write_to_path(
+            &options.inventory,
+            options.inventory_format,
+            options.nested_records,
+        )
+    };
+    let outcome = inventory
+        .update(
+            &options.repository,
+            command.remove_missing,
+            &build_options,
+            Some(progress_observer(&options)?.as_mut()),
+            Some(&mut write_checkpoint),
+        )
+        .map_err(|e| repository_error(&options.repository, e))?;
+
+    info!(
+        "Added {} records, updated {} appended records, removed {}",
+        outcome.added().count(),
+        outcome.appended().count(),
+        outcome.removed().count()
+    );
+
+    // An unchanged inventory is left as is rather than rewritten.
+    if outcome.is_changed() {
+        inventory.write_to_path(
+            &options.inventory,
+            options.inventory_format,
+            options.nested_records,
+        )?;
+        if let Some(snapshots) = &command.snapshots {
+            write_snapshot(&options, snapshots)?;
+        }
+    } else {
+        info!("No records added or removed, the inventory is left as is.");
+    }
+
+    if !outcome.report().is_empty() {
+        print_report(&options, outcome.report(), describe_failure);
+        return Err(Box::new(AppError::FilesFailed));
+    }
+
+    info!("Inventory updated successfully.");
+
+    if command.verify_added {
+        let added: BTreeSet<_> = outcome.added().map(Path::to_path_buf).collect();
+        if added.is_empty() {
+            info!("No files added, nothing to verify.");
+            return Ok(());
+        }
+
+        info!("Verifying {} added files", added.len());
+        let mut check_options = CheckOptions::new();
+        check_options
+            .set_only_paths(Some(added))
+            .set_excluded_paths(&excluded_paths(&options))
+            .set_strip_components(command.strip_components)
+            .set_error_policy(options.error_policy)
+            .set_rate_limit(options.rate_limit)
+            .set_buffer_size(options.buffer_size)
+            .set_threads(options.threads)
+            .set_hmac_key(options.hmac_key.as_deref());
+        let report = inventory
+            .check(
+                &options.repository,
+                &check_options,
+                Some(progress_observer(&options)?.as_mut()),
+            )
+            .map_err(|e| repository_error(&options.repository, e))?;
+
+        print_report(&options, &report, describe_failure);
+        if !report.is_empty() {
+            return Err(Box::new(AppError::VerificationFailed));
+        }
+
+        info!("No issues found.");
+    }
+
+    Ok(())
+}
+
+/// Verifies the repository against a checksum manifest instead of an
+/// inventory.
+fn verify_checksum_file(
+    options: &Options,
+    command: &CommandVerify,
+    checksum_file: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .open(checksum_file)
+        .map_err(|e| FileError::new(checksum_file, e))?;
+    let entries = sumfile::read_sumfile(BufReader::new(file), command.manifest_algorithm)?;
+
+    let mut check_options = CheckOptions::new();
+    check_options
+        .set_excluded_paths(&excluded_paths(options))
+        .set_error_policy(options.error_policy)
+        .set_rate_limit(options.rate_limit)
+        .set_buffer_size(options.buffer_size)
+        .set_track_passed(command.print_ok)
+        .set_partial(command.partial);
+    let report = Inventory::check_sumfile(
+        &options.repository,
+        entries,
+        &check_options,
+        Some(progress_observer(options)?.as_mut()),
+    )
+    .map_err(|e| repository_error(&options.repository, e))?;
+
+    print_verification(options, command, &report, describe_checksum_difference);
+    verification_result(command, &report, "Repository and checksum file agree.")
+}
+
+/// Outputs the records that updating the inventory would add and remove,
+/// without hashing the files or writing the inventory.
+fn update_dry_run(
+    options: &Options,
+    command: &CommandUpdate,
+    inventory: &Inventory,
+) -> Result<(), Box<dyn Error>> {
+    let mut build_options = BuildOptions::new();
+    build_options
+        .set_strip_components(command.strip_components)
+        .set_excluded_paths(&excluded_paths(options));
+    let delta = inventory
+        .update_delta(&options.repository, &build_options)
+        .map_err(|e| repository_error(&options.repository, Box::new(e)))?;
+
+    info!(
+        "Would add {} files ({} bytes)",
+        delta.added().count(),
+        delta.added_size(&options.repository)
+    );
+    for file in delta.added() {
+        match delta.added_file_size(&options.repository, file) {
+            Some(size) => debug!(
+                "Would add {:?} ({} bytes)",
+                display_path(options, file),
+                size
+            ),
+            None => debug!("Would add {:?} (inaccessible)", display_path(options, file)),
+        }
+    }
+
+    if inventory.configuration().append_aware() {
+        info!(
+            "Would re-hash {} grown files, updating the ones appended to",
+            delta.grown().count()
+        );
+        for file in delta.grown() {
+            debug!("Would re-hash {:?}", display_path(options, file));
+        }
+    }
+
+    let verb = if command.remove_missing {
+        "Would remove"
+    } else {
+        "Would keep (without --remove-missing)"
+    };
+    info!(
+        "{} {} records of missing files",
+        verb,
+        delta.missing().count()
+    );
+    for file in delta.missing() {
+        debug!("{} {:?}", verb, display_path(options, file));
+    }
+
+    Ok(())
+}
+
+/// Information about the inventory output by `info`.
+#[derive(Serialize)]
+struct InfoReport<'a> {
+    version: &'a str,
+    hash_algorithms: &'a [HashAlgorithm],
+    extension_algorithms: &'a BTreeMap<String, Vec<HashAlgorithm>>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    glob_algorithms: &'a [(String, Vec<HashAlgorithm>)],
+    skip_hidden: bool,
+    skip_vcs: bool,
+    ignore_files: bool,
+    path_encoding: PathEncoding,
+    include_empty_dirs: bool,
+    follow_symlink_dirs: bool,
+    follow_symlink_files: bool,
+    exclude_symlinks: bool,
+    excluded_types: Vec<EntryType>,
+    trust_xattr: Option<&'a str>,
+    record_mtime: bool,
+    record_btime: bool,
+    since_mtime: Option<u64>,
+    append_aware: bool,
+    keyed: bool,
+    repository: Option<&'a Path>,
+    records: usize,
+    total_bytes: u64,
+    sealed: bool,
+    seal_includes_paths: bool,
+    description: Option<&'a str>,
+    tags: &'a BTreeMap<String, String>,
+}
+
+impl<'a> InfoReport<'a> {
+    /// Collects the information about the inventory.
+    fn new(inventory: &'a Inventory) -> Self {
+        let configuration = inventory.configuration();
+
+        InfoReport {
+            version: configuration.version(),
+            hash_algorithms: configuration.hash_algorithms(),
+            extension_algorithms: configuration.extension_algorithms(),
+            glob_algorithms: configuration.glob_algorithms(),
+            skip_hidden: configuration.skip_hidden(),
+            skip_vcs: configuration.skip_vcs(),
+            ignore_files: configuration.ignore_files(),
+            path_encoding: configuration.path_encoding(),
+            include_empty_dirs: configuration.include_empty_dirs(),
+            follow_symlink_dirs: configuration.follow_symlink_dirs(),
+            follow_symlink_files: configuration.follow_symlink_files(),
+            exclude_symlinks: configuration.exclude_symlinks(),
+            excluded_types: configuration.excluded_types(),
+            trust_xattr: configuration.trust_xattr(),
+            record_mtime: configuration.record_mtime(),
+            record_btime: configuration.record_btime(),
+            since_mtime: configuration.since_mtime(),
+            append_aware: configuration.append_aware(),
+            keyed: configuration.is_keyed(),
+            repository: inventory.repository(),
+            records: inventory.record_count(),
+            total_bytes: inventory.total_bytes(),
+            sealed: inventory.sealed().is_some(),
+            seal_includes_paths: inventory.sealed() == Some(true),
+            description: inventory.description(),
+            tags: inventory.metadata(),
+        }
+    }
+}
+
+impl JsonReport for InfoReport<'_> {
+    fn write_text(&self, w: &mut dyn Write) -> IoResult<()> {
+        let yes_no = |b| if b { "yes" } else { "no" };
+        let names = |algorithms: &[HashAlgorithm]| {
+            let names: Vec<_> = algorithms.iter().map(|a| a.to_string()).collect();
+            names.join(", ")
+        };
+
+        writeln!(w, "Version: {}", self.version)?;
+        if self.hash_algorithms.is_empty()
+            && self.extension_algorithms.is_empty()
+            && self.glob_algorithms.is_empty()
+        {
+            writeln!(w, "Hash algorithms: none (sizes only)")?;
+        } else {
+            writeln!(w, "Hash algorithms: {}", names(self.hash_algorithms))?;
+        }
+        for (extension, algorithms) in self.extension_algorithms {
+            writeln!(
+                w,
+                "Hash algorithms for *.{}: {}",
+                extension,
+                names(algorithms)
+            )?;
+        }
+        for (glob, algorithms) in self.glob_algorithms {
+            writeln!(w, "Hash algorithms for {}: {}", glob, names(algorithms))?;
+        }
+        writeln!(w, "Skip hidden files: {}", yes_no(self.skip_hidden))?;
+        writeln!(w, "Skip VCS directories: {}", yes_no(self.skip_vcs))?;
+        writeln!(w, "Apply ignore files: {}", yes_no(self.ignore_files))?;
+        writeln!(w, "Non-UTF-8 path encoding: {}", self.path_encoding)?;
+        writeln!(
+            w,
+            "Include empty directories: {}",
+            yes_no(self.include_empty_dirs)
+        )?;
+        writeln!(
+            w,
+            "Follow symbolic links: directories: {}, files: {}",
+            yes_no(self.follow_symlink_dirs),
+            yes_no(self.follow_symlink_files)
+        )?;
+        writeln!(
+            w,
+            "Exclude symbolic links: {}",
+            yes_no(self.exclude_symlinks)
+        )?;
+        if !self.excluded_types.is_empty() {
+            let names: Vec<_> = self.excluded_types.iter().map(|t| t.to_string()).collect();
+            writeln!(w, "Excluded entry types: {}", names.join(", "))?;
+        }
+        if let Some(name) = self.trust_xattr {
+            writeln!(w, "Trusted hash attribute: {}", name)?;
+        }
+        writeln!(
+            w,
+            "Record modification times: {}",
+            yes_no(self.record_mtime)
+        )?;
+        writeln!(w, "Record creation times: {}", yes_no(self.record_btime))?;
+        if let Some(since) = self.since_mtime {
+            let time = UNIX_EPOCH + Duration::from_nanos(since);
+            writeln!(w, "Modified since: {}", humantime::format_rfc3339(time))?;
+        }
+        writeln!(w, "Append-aware: {}", yes_no(self.append_aware))?;
+        writeln!(w, "Keyed (HMAC): {}", yes_no(self.keyed))?;
+        if let Some(repository) = self.repository {
+            writeln!(w, "Stored repository: {}", repository.display())?;
+        }
+        writeln!(w, "Records: {}", self.records)?;
+        writeln!(w, "Total size: {} bytes", self.total_bytes)?;
+        writeln!(
+            w,
+            "Sealed: {}",
+            match (self.sealed, self.seal_includes_paths) {
+                (true, true) => "yes (including paths)",
+                (true, false) => "yes",
+                (false, _) => "no",
+            }
+        )?;
+
+        if let Some(description) = self.description {
+            writeln!(w, "Description: {}", description)?;
+        }
+
+        if !self.tags.is_empty() {
+            writeln!(w, "Tags:")?;
+            for (key, value) in self.tags {
+                writeln!(w, "  {}={}", key, value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Prints the information about the inventory.
+fn info(options: Options, _command: CommandInfo) -> Result<(), Box<dyn Error>> {
+    let inventory = Inventory::from_path(&options.inventory, options.inventory_format)?;
+
+    output::write_report(
+        io::stdout().lock(),
+        &InfoReport::new(&inventory),
+        options.output_format,
+    )
+}
+
+/// An inventory record output by `list`.
+#[derive(Serialize)]
+struct ListEntry<'a> {
+    path: Cow<'a, Path>,
+    size: u64,
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<&'a Path>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    hashes: BTreeMap<HashAlgorithm, String>,
+}
+
+/// The inventory records output by `list`, ordered by path.
+#[derive(Serialize)]
+#[serde(transparent)]
+struct ListReport<'a> {
+    records: Vec<ListEntry<'a>>,
+}
+
+impl<'a> ListReport<'a> {
+    /// Collects the inventory records, with the hash values in the given
+    /// encoding, optionally restricted to the primary hash of every record.
+    fn new(options: &Options, inventory: &'a Inventory, command: &CommandList) -> Self {
+        let records = inventory
+            .records()
+            .map(|(path, record)| ListEntry {
+                path: Cow::Owned(display_path(options, path).into_owned()),
+                size: record.size(),
+                kind: if record.is_directory() {
+                    "directory"
+                } else if record.symlink().is_some() {
+                    "symlink"
+                } else {
+                    "file"
+                },
+                target: record.symlink(),
+                hashes: if command.primary_hash {
+                    record
+                        .primary_hash()
+                        .map(|(a, v)| (a, v.encode(command.digest_encoding)))
+                        .into_iter()
+                        .collect()
+                } else {
+                    record
+                        .hashes()
+                        .iter()
+                        .map(|(a, v)| (*a, v.encode(command.digest_encoding)))
+                        .collect()
+                },
+            })
+            .collect();
+
+        ListReport { records }
+    }
+}
+
+impl JsonReport for ListReport<'_> {
+    fn write_text(&self, w: &mut dyn Write) -> IoResult<()> {
+        for entry in &self.records {
+            let contents = match (entry.kind, entry.target) {
+                ("directory", _) => "(directory)".to_string(),
+                (_, Some(target)) => format!("-> {}", target.display()),
+                _ => {
+                    let hashes: Vec<_> = entry
+                        .hashes
+                        .iter()
+                        .map(|(a, v)| format!("{}:{}", a, v))
+                        .collect();
+                    hashes.join(" ")
+                }
+            };
+
+            writeln!(w, "{}\t{}\t{}", entry.path.display(), entry.size, contents)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Prints the inventory records.
+fn list(options: Options, command: CommandList) -> Result<(), Box<dyn Error>> {
+    let inventory = Inventory::from_path(&options.inventory, options.inventory_format)?;
+    let report = ListReport::new(&options, &inventory, &command);
+
+    output::write_report(
+        BufWriter::new(io::stdout().lock()),
+        &report,
+        options.output_format,
+    )
+}
+
+/// A unique content output by `export`.
+#[derive(Serialize)]
+struct ExportEntry<'a> {
+    hash: String,
+    count: usize,
+    path: Cow<'a, Path>,
+}
+
+/// The unique contents of the inventory output by `export`, ordered by the
+/// hash values, for a content-addressed store.
+#[derive(Serialize)]
+struct ExportReport<'a> {
+    algorithm: HashAlgorithm,
+    contents: Vec<ExportEntry<'a>>,
+}
+
+impl JsonReport for ExportReport<'_> {
+    fn write_text(&self, w: &mut dyn Write) -> IoResult<()> {
+        for entry in &self.contents {
+            writeln!(w, "{} {} {}", entry.hash, entry.count, entry.path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Prints every unique content of the inventory once, with the number of the
+/// files that have it and a representative path.
+fn export(options: Options, command: CommandExport) -> Result<(), Box<dyn Error>> {
+    let inventory = Inventory::from_path(&options.inventory, options.inventory_format)?;
+    let report = export_report(&options, &inventory, &command)?;
+
+    output::write_report(
+        BufWriter::new(io::stdout().lock()),
+        &report,
+        options.output_format,
+    )
+}
+
+/// Collects the unique contents of the inventory to export, warning about
+/// the files without a hash of the algorithm.
+fn export_report<'a>(
+    options: &Options,
+    inventory: &'a Inventory,
+    command: &CommandExport,
+) -> Result<ExportReport<'a>, Box<dyn Error>> {
+    let algorithm = match command.algorithm {
+        Some(algorithm) => algorithm,
+        None => inventory
+            .configuration()
+            .hash_algorithms()
+            .iter()
+            .copied()
+            .max_by_key(|a| a.strength())
+            .ok_or(AppError::NoContentHashes)?,
+    };
+
+    let contents: Vec<_> = inventory
+        .unique_contents(algorithm)?
+        .into_iter()
+        .map(|(value, count, path)| ExportEntry {
+            hash: value.encode(command.digest_encoding),
+            count,
+            path: Cow::Owned(display_path(options, path).into_owned()),
+        })
+        .collect();
+
+    let files = inventory
+        .records()
+        .filter(|(_, r)| !r.is_directory() && r.symlink().is_none())
+        .count();
+    let exported: usize = contents.iter().map(|e| e.count).sum();
+    if exported < files {
+        warn!(
+            "{} files have no {} hash and are not exported",
+            files - exported,
+            algorithm
+        );
+    }
+
+    Ok(ExportReport {
+        algorithm,
+        contents,
+    })
+}
+
+/// A difference between inventories output by `diff`.
+#[derive(Serialize)]
+struct Difference<'a> {
+    path: Cow<'a, Path>,
+    change: &'static str,
+}
+
+/// Returns the name of a difference between inventories in the JSON output.
+fn difference_name(kind: FailureKind) -> &'static str {
+    match kind {
+        FailureKind::MissingFromRepository => "removed",
+        FailureKind::MissingFromInventory => "added",
+        FailureKind::SizeMismatch => "size-changed",
+        FailureKind::HashMismatch => "hash-changed",
+        FailureKind::SymlinkMismatch => "symlink-changed",
+        FailureKind::ReadError => "read-error",
+        FailureKind::MissingDirectory => "directory-changed",
+        FailureKind::CreatedTimeMismatch => "recreated",
+        FailureKind::NetworkError => "network-error",
+    }
+}
+
+/// Compares the inventory with another one.
+fn diff(options: Options, command: CommandDiff) -> Result<(), Box<dyn Error>> {
+    let inventory = Inventory::from_path(&options.inventory, options.inventory_format)?;
+    let other = Inventory::from_path(&command.other, None)?;
+    let report = inventory.diff(&other);
+
+    // The text form of the differences is logged, like the verification
+    // report.
+    match options.output_format {
+        OutputFormat::Text => print_report(&options, &report, describe_difference),
+        OutputFormat::Json => {
+            let options = &options;
+            let mut differences: Vec<_> = report
+                .failures()
+                .into_iter()
+                .flat_map(|kind| {
+                    report
+                        .by_failure(kind)
+                        .unwrap()
+                        .map(move |path| Difference {
+                            path: display_path(options, path),
+                            change: difference_name(kind),
+                        })
+                })
+                .collect();
+            differences.sort_by(|a, b| a.path.cmp(&b.path));
+            output::write_json(io::stdout().lock(), &differences)?;
+        }
+    }
+
+    if !report.is_empty() {
+        Err(Box::new(AppError::InventoriesDiffer))
+    } else {
+        info!("No differences found.");
+        Ok(())
+    }
+}
+
+/// Removes the temporary files left next to the inventory.
+fn clean(options: Options, command: CommandClean) -> Result<(), Box<dyn Error>> {
+    // Only the files named after inventorize's own patterns for this inventory
+    // are considered, and never anything inside the repository.
+    let candidates = [util::temp_path(&options.inventory)];
+    let mut found = false;
+
+    for path in candidates
+        .iter()
+        .filter(|p| p.is_file() && !p.starts_with(&options.repository))
+    {
+        found = true;
+        if command.dry_run {
+            info!("Would remove {:?}", path);
+        } else {
+            fs::remove_file(path).map_err(|e| FileError::new(path, e))?;
+            info!("Removed {:?}", path);
+        }
+    }
+
+    if !found {
+        info!("Nothing to clean.");
+    }
+
+    Ok(())
+}
+
+/// Findings of the `doctor` subcommand, printed as they are made.
+#[derive(Default)]
+struct Diagnosis {
+    /// Number of warnings found.
+    warnings: usize,
+
+    /// Number of problems found.
+    problems: usize,
+}
+
+impl Diagnosis {
+    /// Starts a new category of checks.
+    fn category(&self, name: &str) {
+        println!("{}:", name);
+    }
+
+    /// Records a passed check.
+    fn ok(&self, message: &str) {
+        println!("  [ok] {}", message);
+    }
+
+    /// Records an issue that may cause unexpected results.
+    fn warning(&mut self, message: &str, fix: &str) {
+        self.warnings += 1;
+        println!("  [warning] {}\n    fix: {}", message, fix);
+    }
+
+    /// Records an issue that causes subcommands to fail.
+    fn problem(&mut self, message: &str, fix: &str) {
+        self.problems += 1;
+        println!("  [problem] {}\n    fix: {}", message, fix);
+    }
+}
+
+/// Parses the numeric components of a version string.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version.split('.').map(|c| c.parse().ok()).collect()
+}
+
+/// Diagnoses common problems with the inventory and the repository.
+///
+/// All checks are run and their results are printed, rather than stopping at
+/// the first problem. Nothing is modified.
+fn doctor(options: Options, _command: CommandDoctor) -> Result<(), Box<dyn Error>> {
+    let mut diagnosis = Diagnosis::default();
+
+    diagnosis.category("Paths");
+    if options.inventory.starts_with(&options.repository) {
+        diagnosis.problem(
+            "Inventory is located inside the repository",
+            "move the inventory outside of the repository",
+        );
+    } else {
+        diagnosis.ok("Inventory is located outside of the repository");
+    }
+    if util::temp_path(&options.inventory).exists() {
+        diagnosis.warning(
+            "Stale temporary inventory file found",
+            "run the clean subcommand",
+        );
+    }
+
+    diagnosis.category("Repository");
+    match fs::read_dir(&options.repository) {
+        Ok(_) => diagnosis.ok("Repository is readable"),
+        Err(err) => diagnosis.problem(
+            &format!("Repository cannot be read: {}", err),
+            "check the repository path and permissions",
+        ),
+    }
+
+    diagnosis.category("Inventory");
+    let inventory = if !options.inventory.exists() {
+        diagnosis.problem(
+            "Inventory file does not exist",
+            "check the inventory path, or run the build subcommand",
+        );
+        None
+    } else {
+        match Inventory::from_path(&options.inventory, options.inventory_format) {
+            Ok(inventory) => {
+                diagnosis.ok("Inventory is readable and valid");
+                Some(inventory)
+            }
+            Err(err) => {
+                diagnosis.problem(
+                    &format!("Inventory cannot be read: {}", describe_error(err.as_ref())),
+                    "restore the inventory from a backup, or rebuild it",
+                );
+                None
+            }
+        }
+    };
+
+    if let Some(inventory) = &inventory {
+        let configuration = inventory.configuration();
+
+        // Reproducible inventories store the schema version instead of the
+        // app version.
+        let version = configuration.version();
+        let newer = match (parse_version(version), parse_version(crate_version!())) {
+            (Some(v), Some(current)) => version != inventory::SCHEMA_VERSION && v > current,
+            _ => true,
+        };
+        if newer {
+            diagnosis.warning(
+                &format!(
+                    "Inventory was built by a newer or unknown version: {}",
+                    version
+                ),
+                "upgrade inventorize",
+            );
+        } else {
+            diagnosis.ok("Inventory version is supported");
+        }
+
+        let algorithms = configuration.all_algorithms();
+        if algorithms.is_empty() {
+            diagnosis.warning(
+                "Inventory has no hash algorithms, only file sizes can be verified",
+                "rebuild the inventory with --hash-algorithm",
+            );
+        } else if inventory
+            .records()
+            .any(|(_, r)| r.hashes().keys().any(|a| !algorithms.contains(a)))
+        {
+            diagnosis.warning(
+                "Some records use hash algorithms missing from the configuration",
+                "rebuild the inventory",
+            );
+        } else {
+            diagnosis.ok("Hash algorithms are consistent");
+        }
+
+        let non_utf8 = inventory
+            .records()
+            .filter(|(p, _)| p.to_str().is_none())
+            .count();
+        if non_utf8 > 0 {
+            diagnosis.warning(
+                &format!("{} record path(s) are not valid UTF-8", non_utf8),
+                "rename the files if the inventory is used on other platforms",
+            );
+        }
+
+        // Only the presence of the recorded files is checked, which is cheap
+        // and catches a wrong repository path.
+        let total = inventory.record_count();
+        let missing = inventory
+            .records()
+            .filter(|(p, _)| fs::symlink_metadata(options.repository.join(p)).is_err())
+            .count();
+        if total == 0 {
+            diagnosis.warning(
+                "Inventory has no records",
+                "check the build options, e.g. --skip-hidden",
+            );
+        } else if missing == total {
+            diagnosis.problem(
+                "None of the recorded files exist in the repository",
+                "check the repository path",
+            );
+        } else if missing > 0 {
+            diagnosis.warning(
+                &format!("{} of {} recorded files are missing", missing, total),
+                "run the verify subcommand for details",
+            );
+        } else {
+            diagnosis.ok("All recorded files are present");
+        }
+    }
+
+    if diagnosis.warnings > 0 {
+        warn!("{} warning(s) found.", diagnosis.warnings);
+    }
+
+    if diagnosis.problems > 0 {
+        Err(Box::new(AppError::ProblemsFound(diagnosis.problems)))
+    } else {
+        info!("No problems found.");
+        Ok(())
+    }
+}
+
+/// Executes the subcommand specified by the caller.
+fn run(parameters: Parameters) -> Result<(), Box<dyn Error>> {
+    // A temporary file next to the inventory is left behind by an interrupted
+    // write. It is overwritten by the next write, but the user should know.
+    let temp_path = util::temp_path(&parameters.options.inventory);
+    let reports_temp = matches!(parameters.command, Command::Clean(_) | Command::Doctor(_));
+    if temp_path.exists() && !reports_temp {
+        warn!(
+            "Stale temporary inventory file found, consider removing it: {:?}",
+            temp_path
+        );
+    }
+
+    match parameters.command {
+        Command::Build(command) => build(parameters.options, command),
+        Command::Verify(command) => verify(parameters.options, command),
+        Command::Update(command) => update(parameters.options, command),
+        Command::Info(command) => info(parameters.options, command),
+        Command::List(command) => list(parameters.options, command),
+        Command::Export(command) => export(parameters.options, command),
+        Command::Clean(command) => clean(parameters.options, command),
+        Command::Diff(command) => diff(parameters.options, command),
+        Command::Doctor(command) => doctor(parameters.options, command),
+    }
+}
+
+/// Canonicalizes the inventory file path.
+///
+/// The inventory file is not required to exist, since not every subcommand
+/// needs it (e.g. `build`). This means that it cannot be canonicalized using
+/// the `canonicalize()` method.
+///
+/// However, the inventory's parent directory must exist. It can also be empty,
+/// which means that the current working directory is to be used (if e.g.
+/// "inventory.json" is specified as the inventory argument).
+fn canonicalize_inventory_path<P: AsRef<Path>>(inventory: P) -> Result<PathBuf, String> {
+    let inventory = inventory.as_ref();
+    let (dir_path, file_name) = (inventory.parent(), inventory.file_name());
+
+    if dir_path.is_none() || file_name.is_none() {
+        return Err(String::from("inventory filename not specified"));
+    }
+
+    let (dir_path, file_name) = (dir_path.unwrap(), file_name.unwrap());
+
+    let dir_path = if dir_path.as_os_str().len() == 0 {
+        Path::new(".")
+    } else {
+        dir_path
+    };
+
+    let mut ret = dir_path
+        .canonicalize()
+        .map_err(|_| "inventory directory is inaccessible or does not exist".to_string())?;
+    ret.push(file_name);
+
+    Ok(ret)
+}
+
+/// Suggestion for placing the inventory, if it is inside the repository.
+const INSIDE_HINT: &str = "place it next to the repository with --inventory-outside=<DIR>, or \
+                           exclude it from the repository with --allow-inventory-inside";
+
+/// Returns the path of the inventory placed in a directory next to the
+/// repository, keeping its file name.
+///
+/// A relative directory is taken relative to the parent of the repository.
+/// The directory is created if requested, and must not be inside the
+/// repository.
+fn outside_inventory_path(
+    repository: &Path,
+    dir: &Path,
+    inventory: &Path,
+    create: bool,
+) -> Result<PathBuf, String> {
+    let parent = repository
+        .parent()
+        .ok_or_else(|| "the repository has no parent directory".to_string())?;
+    let dir = parent.join(dir);
+    if create {
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("cannot create the inventory directory {:?}: {}", dir, e))?;
+    }
+
+    let dir = dir
+        .canonicalize()
+        .map_err(|e| format!("cannot access the inventory directory {:?}: {}", dir, e))?;
+    if dir.starts_with(repository) {
+        return Err(format!(
+            "the inventory directory {:?} is inside the repository",
+            dir
+        ));
+    }
+
+    // The inventory path has been canonicalized, so it has a file name.
+    Ok(dir.join(inventory.file_name().unwrap()))
+}
+
+/// Suffix of the inventory file names derived from the repository names.
+const NAMED_INVENTORY_SUFFIX: &str = ".inventory.json";
+
+/// Returns the path of the inventory in the output directory, named after
+/// the repository directory.
+///
+/// The directory is created if requested.
+fn named_inventory_path(repository: &Path, dir: &Path, create: bool) -> Result<PathBuf, String> {
+    let name = repository
+        .file_name()
+        .ok_or_else(|| "the repository has no name to name the inventory after".to_string())?;
+    if create {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("cannot create the output directory {:?}: {}", dir, e))?;
+    }
+
+    let dir = dir
+        .canonicalize()
+        .map_err(|e| format!("cannot access the output directory {:?}: {}", dir, e))?;
+    let mut file_name = name.to_os_string();
+    file_name.push(NAMED_INVENTORY_SUFFIX);
+
+    Ok(dir.join(file_name))
+}
+
+/// Attributes an error of walking or reading the repository to it.
+///
+/// Only the failures to access the repository root itself, and the I/O
+/// errors without any path, are wrapped, so that they tell which repository
+/// could not be accessed. The walk errors of the entries below the root are
+/// unwrapped to their `FileError`, and these and all other errors, e.g. a
+/// wrong HMAC key or a failed checkpoint write, are returned with their own
+/// path and cause.
+fn repository_error(repository: &Path, err: Box<dyn Error>) -> Box<dyn Error> {
+    let err: Box<dyn Error> = match err.downcast::<io::Error>() {
+        Ok(io_err) if io_err.get_ref().is_some_and(|e| e.is::<FileError>()) => {
+            io_err.into_inner().expect("the error has an inner error")
+        }
+        Ok(io_err) => io_err,
+        Err(err) => err,
+    };
+    let is_access = err.is::<io::Error>()
+        || err
+            .downcast_ref::<FileError>()
+            .is_some_and(|e| e.path() == repository);
+    if is_access {
+        Box::new(AppError::RepositoryAccess(repository.to_path_buf(), err))
+    } else {
+        err
+    }
+}
+
+/// Returns the repository paths to leave out of the repository walk: the
+/// inventory, if it is inside the repository.
+fn excluded_paths(options: &Options) -> Vec<PathBuf> {
+    match options.inventory.strip_prefix(&options.repository) {
+        Ok(path) => vec![path.to_path_buf()],
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Returns the observer of the progress: a writer to the `--progress-to`
+/// file, if any, or a logger otherwise.
+fn progress_observer(options: &Options) -> Result<Box<dyn ProgressObserver>, Box<dyn Error>> {
+    match &options.progress_to {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .map_err(|e| FileError::new(path, e))?;
+            Ok(Box::new(WriterProgress::new(file)))
+        }
+        None => Ok(Box::new(LogProgress::new())),
+    }
+}
+
+/// Converts the `--progress-to` value to a path: file descriptor numbers are
+/// opened via `/dev/fd`.
+fn progress_path(s: &str) -> PathBuf {
+    if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+        PathBuf::from(format!("/dev/fd/{}", s))
+    } else {
+        PathBuf::from(s)
+    }
+}
+
+/// Parses a file-specific hash algorithm setting in the
+/// `EXT=ALG[,ALG...]` or `GLOB=ALG[,ALG...]` format.
+///
+/// The setting is taken as a glob if it has any of the `*`, `?`, `/` or `\`
+/// characters, which extensions lack; see `is_glob()`.
+fn parse_extension_algorithms(s: &str) -> Option<(String, Vec<HashAlgorithm>)> {
+    let (key, algorithms) = s.split_at(s.find('=')?);
+    let key = if is_glob(key) {
+        key
+    } else {
+        key.trim_start_matches('.')
+    };
+    if key.is_empty() {
+        return None;
+    }
+
+    let algorithms = algorithms[1..]
+        .split(',')
+        .map(|a| HashAlgorithm::from_str(a).ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    Some((key.to_string(), algorithms))
+}
+
+/// Returns whether the key of a file-specific hash algorithm setting is a
+/// glob rather than an extension.
+fn is_glob(key: &str) -> bool {
+    key.contains(&['*', '?', '/', '\\'][..])
+}
+
+/// Returns the arguments of `verify` that download the repository files.
+#[cfg(feature = "http")]
+fn http_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![Arg::with_name("base-url")
+        .conflicts_with_all(&[
+            "archive",
+            "stdin-repository-listing",
+            "compare-with-archive-manifest",
+            "repository-from-config",
+            "optimize-io",
+            "low-memory",
+            "verify-changed",
+            "quick-total",
+            "only-paths",
+            "checksum-file",
+        ])
+        .help("Download the repository files from under this http:// URL and verify them")
+        .long("base-url")
+        .number_of_values(1)
+        .value_name("URL")
+        .validator(|s| {
+            http::BaseUrl::from_str(&s)
+                .and(Ok(()))
+                .map_err(|e| e.to_string())
+        })]
+}
+
+/// Returns the arguments of `verify` that download the repository files,
+/// which are not built in.
+#[cfg(not(feature = "http"))]
+fn http_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    Vec::new()
+}
+
+/// Returns the arguments of `build` and `update` that write snapshots of the
+/// inventory.
+fn snapshot_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("snapshot")
+            .conflicts_with("dry-run")
+            .help("Also copy the written inventory to a timestamped snapshot")
+            .long("snapshot"),
+        Arg::with_name("snapshot-dir")
+            .help("Write the snapshots to this directory [default: the inventory directory]")
+            .long("snapshot-dir")
+            .number_of_values(1)
+            .requires("snapshot")
+            .value_name("DIR"),
+        Arg::with_name("snapshot-keep")
+            .help("Remove the oldest snapshots beyond this number")
+            .long("snapshot-keep")
+            .number_of_values(1)
+            .requires("snapshot")
+            .value_name("N")
+            .validator(|s| match usize::from_str(&s) {
+                Ok(n) if n > 0 => Ok(()),
+                _ => Err("must be a positive integer".to_string()),
+            }),
+    ]
+}
+
+/// Extracts the `--algorithm-per-file` settings of `build` keyed by globs,
+/// or by extensions if not `globs`, in the order of their specification.
+fn file_algorithms(matches: &ArgMatches, globs: bool) -> Vec<(String, Vec<HashAlgorithm>)> {
+    matches
+        .values_of("algorithm-per-file")
+        .map(|v| {
+            // The settings have been validated and thus can be parsed
+            // safely.
+            v.map(|s| parse_extension_algorithms(s).unwrap())
+                .filter(|(key, _)| is_glob(key) == globs)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Extracts the snapshot options of `build` and `update`, if snapshots are
+/// enabled.
+fn snapshots(matches: &ArgMatches) -> Option<Snapshots> {
+    if !matches.is_present("snapshot") {
+        return None;
+    }
+
+    Some(Snapshots {
+        dir: matches.value_of("snapshot-dir").map(PathBuf::from),
+        keep: matches
+            .value_of("snapshot-keep")
+            .map(|s| usize::from_str(s).unwrap()),
+    })
+}
+
+/// Parses the command line arguments.
+///
+/// Prints an error message and exits the application if the command-line
+/// configuration is invalid.
+fn parse_cmd_line<I, T>(args: I) -> Parameters
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
 {
     const DEFAULT_REPOSITORY_DIR: &str = ".";
+    const DEFAULT_LOG_FORMAT: &str = "human";
+    const DEFAULT_OUTPUT_FORMAT: &str = "text";
+    const DEFAULT_ERROR_POLICY: &str = "abort";
     const DEFAULT_HASH_ALGORITHM: &str = "md5";
+    const DEFAULT_FOLLOW: &str = "yes";
+    const DEFAULT_DIGEST_ENCODING: &str = "hex";
 
     let matches = App::new("inventorize")
         .about("Builds and maintains an inventory of files in a repository directory")
@@ -273,143 +2449,1697 @@ where
                 .long("verbose")
                 .multiple(true),
         )
-        .arg(
-            Arg::with_name("inventory")
-                .help("Path to the inventory file (must be outside of the repository)")
-                .long("inventory")
-                .number_of_values(1)
-                .required(true)
-                .validator(|s| canonicalize_inventory_path(PathBuf::from(s)).and_then(|_| Ok(()))),
+        .arg(
+            Arg::with_name("log-format")
+                .default_value(DEFAULT_LOG_FORMAT)
+                .help("Log output format")
+                .long("log-format")
+                .number_of_values(1)
+                .possible_values(&["human", "json"]),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .help("Also append the log messages to this file")
+                .long("log-file")
+                .number_of_values(1)
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .default_value(DEFAULT_OUTPUT_FORMAT)
+                .help("Output format of the info, list, export and diff results")
+                .long("format")
+                .number_of_values(1)
+                .possible_values(&["text", "json"]),
+        )
+        .arg(
+            Arg::with_name("on-error")
+                .default_value(DEFAULT_ERROR_POLICY)
+                .help("Handling of unreadable files")
+                .long("on-error")
+                .number_of_values(1)
+                .possible_values(&["abort", "skip", "report"]),
+        )
+        .arg(
+            Arg::with_name("inventory-format")
+                .help("Format of the inventory file, overriding the detected one")
+                .long("inventory-format")
+                .number_of_values(1)
+                .possible_values(&["json", "bin"]),
+        )
+        .arg(
+            Arg::with_name("progress-to")
+                .help("Write the progress to the file or file descriptor")
+                .long("progress-to")
+                .number_of_values(1)
+                .value_name("PATH|FD"),
+        )
+        .arg(
+            Arg::with_name("rate-limit")
+                .help("Maximum hashing throughput in bytes per second")
+                .long("rate-limit")
+                .number_of_values(1)
+                .value_name("SIZE")
+                .validator(|s| match util::parse_size(&s) {
+                    Some(n) if n > 0 => Ok(()),
+                    _ => Err("invalid rate".to_string()),
+                }),
+        )
+        .arg(
+            Arg::with_name("hash-buffer-size")
+                .help("Size of the buffer the files are read into when hashing [default: 128K]")
+                .long("hash-buffer-size")
+                .number_of_values(1)
+                .value_name("SIZE")
+                .validator(
+                    |s| match util::parse_size(&s).and_then(|n| usize::try_from(n).ok()) {
+                        Some(n) if n > 0 => Ok(()),
+                        _ => Err("invalid buffer size".to_string()),
+                    },
+                ),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .default_value("1")
+                .help("Number of threads to verify the files on (0: one per CPU)")
+                .long("threads")
+                .short("j")
+                .number_of_values(1)
+                .value_name("N")
+                .validator(|s| {
+                    usize::from_str(&s)
+                        .and(Ok(()))
+                        .or(Err("invalid number of threads".to_string()))
+                }),
+        )
+        .arg(
+            Arg::with_name("max-threads")
+                .help("Maximum number of threads, also when one per CPU is requested")
+                .long("max-threads")
+                .number_of_values(1)
+                .value_name("N")
+                .validator(|s| match usize::from_str(&s) {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err("invalid maximum number of threads".to_string()),
+                }),
+        )
+        .arg(
+            Arg::with_name("max-open-files")
+                .help("Maximum number of repository files open at once")
+                .long("max-open-files")
+                .number_of_values(1)
+                .value_name("N")
+                .validator(|s| match usize::from_str(&s) {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err("invalid maximum number of open files".to_string()),
+                }),
+        )
+        .arg(
+            Arg::with_name("yes")
+                .help("Do not ask to confirm overwriting the inventory or removing records")
+                .long("yes")
+                .visible_alias("force"),
+        )
+        .arg(
+            Arg::with_name("relative-to")
+                .help("Output file paths relative to the directory instead of the repository")
+                .long("relative-to")
+                .number_of_values(1)
+                .value_name("DIR")
+                .validator(|s| {
+                    if Path::new(&s).is_dir() {
+                        Ok(())
+                    } else {
+                        Err("directory does not exist".to_string())
+                    }
+                }),
+        )
+        .arg(
+            Arg::with_name("hmac-key-file")
+                .help("Hash files with HMAC using the key read from the file")
+                .long("hmac-key-file")
+                .number_of_values(1)
+                .value_name("FILE")
+                .validator(|s| match fs::metadata(&s) {
+                    Ok(m) if m.is_file() && m.len() > 0 => Ok(()),
+                    Ok(_) => Err("key file is empty or not a file".to_string()),
+                    Err(_) => Err("key file does not exist".to_string()),
+                }),
+        )
+        .arg(
+            Arg::with_name("nested-records")
+                .help("Write the inventory records nested by directory to reduce its size")
+                .long("nested-records"),
+        )
+        .arg(
+            Arg::with_name("inventory")
+                .help("Path to the inventory file (must be outside of the repository)")
+                .long("inventory")
+                .number_of_values(1)
+                .validator(|s| canonicalize_inventory_path(PathBuf::from(s)).and_then(|_| Ok(()))),
+        )
+        .arg(
+            Arg::with_name("output-dir")
+                .conflicts_with_all(&["inventory", "inventory-outside"])
+                .help("Name the inventory after the repository, in this directory")
+                .long("output-dir")
+                .number_of_values(1)
+                .value_name("DIR"),
+        )
+        .arg(
+            Arg::with_name("inventory-outside")
+                .conflicts_with("allow-inventory-inside")
+                .help("Place the inventory in this directory next to the repository")
+                .long("inventory-outside")
+                .number_of_values(1)
+                .value_name("DIR"),
+        )
+        .arg(
+            Arg::with_name("allow-inventory-inside")
+                .help("Allow the inventory inside the repository, excluding it from the repository")
+                .long("allow-inventory-inside"),
+        )
+        .arg(
+            Arg::with_name("repository")
+                .default_value(DEFAULT_REPOSITORY_DIR)
+                .help("Path to the repository")
+                .long("repository")
+                .number_of_values(1)
+                .validator(|s| {
+                    let p = PathBuf::from(s);
+                    if !p.exists() {
+                        Err("repository does not exist".to_string())
+                    } else if !p.is_dir() {
+                        Err("repository is not a directory".to_string())
+                    } else if p.canonicalize().is_err() {
+                        Err("cannot canonicalize repository path".to_string())
+                    } else {
+                        Ok(())
+                    }
+                }),
+        )
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Builds the inventory")
+                .arg(
+                    Arg::with_name("overwrite")
+                        .help("Overwrite inventory file if it exists")
+                        .long("overwrite"),
+                )
+                .arg(
+                    Arg::with_name("skip-hidden")
+                        .help("Skip hidden files")
+                        .long("skip-hidden"),
+                )
+                .arg(
+                    Arg::with_name("skip-vcs")
+                        .help("Skip version control system metadata directories")
+                        .long("skip-vcs"),
+                )
+                .arg(
+                    Arg::with_name("use-ignore-files")
+                        .help("Skip the entries matched by the .inventorizeignore files")
+                        .long("use-ignore-files"),
+                )
+                .arg(
+                    Arg::with_name("path-encoding")
+                        .default_value("bytes")
+                        .help("Storage of the paths that are not valid UTF-8")
+                        .long("path-encoding")
+                        .number_of_values(1)
+                        .possible_values(&["utf8", "lossy", "bytes"]),
+                )
+                .arg(
+                    Arg::with_name("include-empty-dirs")
+                        .help("Record empty directories")
+                        .long("include-empty-dirs"),
+                )
+                .arg(
+                    Arg::with_name("hash-algorithm")
+                        .default_value(DEFAULT_HASH_ALGORITHM)
+                        .help("Hash algorithm(s) to use")
+                        .long("hash-algorithm")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .validator(|s| {
+                            HashAlgorithm::from_str(&s)
+                                .and(Ok(()))
+                                .or(Err("invalid algorithm name".to_string()))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("sizes-only")
+                        .help("Record file sizes only, without hashing the contents")
+                        .long("sizes-only")
+                        .conflicts_with_all(&["algorithm-per-file", "trust-xattr", "append-aware"]),
+                )
+                .arg(
+                    Arg::with_name("algorithm-per-file")
+                        .help("Hash algorithm(s) to use for files with the extension or matching the glob")
+                        .long("algorithm-per-file")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("EXT|GLOB=ALG[,ALG...]")
+                        .validator(|s| {
+                            parse_extension_algorithms(&s).and(Some(())).ok_or_else(|| {
+                                "setting must be in the EXT=ALG[,ALG...] or GLOB=ALG[,ALG...] \
+                                 format"
+                                    .to_string()
+                            })
+                        }),
+                )
+                .arg(
+                    Arg::with_name("follow-dirs")
+                        .default_value(DEFAULT_FOLLOW)
+                        .help("Descend into symbolically linked directories")
+                        .long("follow-dirs")
+                        .number_of_values(1)
+                        .possible_values(&["yes", "no"]),
+                )
+                .arg(
+                    Arg::with_name("follow-files")
+                        .default_value(DEFAULT_FOLLOW)
+                        .help("Hash symbolically linked files instead of recording the links")
+                        .long("follow-files")
+                        .number_of_values(1)
+                        .possible_values(&["yes", "no"]),
+                )
+                .arg(
+                    Arg::with_name("exclude-symlinks")
+                        .help("Skip all symbolic links, overriding --follow-dirs and --follow-files")
+                        .long("exclude-symlinks"),
+                )
+                .arg(
+                    Arg::with_name("exclude-type")
+                        .help("Skip the directory entries of the type")
+                        .long("exclude-type")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("TYPE")
+                        .possible_values(EntryType::NAMES),
+                )
+                .arg(
+                    Arg::with_name("seal")
+                        .help("Seal the inventory to detect its modifications")
+                        .long("seal"),
+                )
+                .arg(
+                    Arg::with_name("seal-includes-paths")
+                        .help("Include file paths in the seal (implies --seal)")
+                        .long("seal-includes-paths"),
+                )
+                .arg(
+                    Arg::with_name("description")
+                        .help("Free-form description of the inventory")
+                        .long("description")
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .help("Key/value tag of the inventory (KEY=VALUE)")
+                        .long("tag")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .validator(|s| match s.find('=') {
+                            Some(i) if i > 0 => Ok(()),
+                            _ => Err("tag must be in the KEY=VALUE format".to_string()),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("max-files")
+                        .help("Abort if the repository has more than the given number of files")
+                        .long("max-files")
+                        .number_of_values(1)
+                        .value_name("N")
+                        .validator(|s| {
+                            usize::from_str(&s)
+                                .and(Ok(()))
+                                .or(Err("invalid file count".to_string()))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("trust-xattr")
+                        .help("Take file hashes from the extended attribute if present")
+                        .long("trust-xattr")
+                        .number_of_values(1)
+                        .value_name("NAME"),
+                )
+                .arg(
+                    Arg::with_name("record-mtime")
+                        .help("Record the modification times of the files")
+                        .long("record-mtime"),
+                )
+                .arg(
+                    Arg::with_name("track-btime")
+                        .help("Record the creation times of the files where available")
+                        .long("track-btime"),
+                )
+                .arg(
+                    Arg::with_name("since-mtime")
+                        .help("Only inventory the files modified at or after this time")
+                        .long("since-mtime")
+                        .number_of_values(1)
+                        .value_name("TIME")
+                        .validator(|s| {
+                            util::parse_timestamp(&s)
+                                .and(Some(()))
+                                .ok_or_else(|| "invalid timestamp or duration".to_string())
+                        }),
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .help(
+                            "Fail instead of warning if paths differ only in case or if the \
+                             inventory may not fit on its filesystem",
+                        )
+                        .long("strict"),
+                )
+                .args(&snapshot_args())
+                .arg(
+                    Arg::with_name("append-aware")
+                        .help("Treat grown files with unchanged recorded contents as appended to")
+                        .long("append-aware"),
+                )
+                .arg(
+                    Arg::with_name("store-repository-path")
+                        .help("Store the repository path relative to the inventory file")
+                        .long("store-repository-path"),
+                )
+                .arg(
+                    Arg::with_name("reproducible")
+                        .help("Produce byte-identical inventories of the same repository")
+                        .long("reproducible"),
+                )
+                .arg(
+                    Arg::with_name("verify-after")
+                        .help("Verify the repository once the inventory is built")
+                        .long("verify-after"),
+                )
+                .arg(
+                    Arg::with_name("append")
+                        .conflicts_with_all(&[
+                            "overwrite",
+                            "seal",
+                            "seal-includes-paths",
+                            "reproducible",
+                            "verify-after",
+                            "trust-xattr",
+                            "include-empty-dirs",
+                            "algorithm-per-file",
+                            "record-mtime",
+                            "track-btime",
+                            "store-repository-path",
+                            "append-aware",
+                        ])
+                        .help("Add the repository to an existing inventory")
+                        .long("append"),
+                )
+                .arg(
+                    Arg::with_name("prefix")
+                        .help("Path prefix of the appended records")
+                        .long("prefix")
+                        .number_of_values(1)
+                        .requires("append")
+                        .validator(|s| {
+                            util::normalize_path(&s).and(Some(())).ok_or_else(|| {
+                                "prefix must be a relative path without '..'".to_string()
+                            })
+                        }),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Verifies files")
+                .arg(
+                    Arg::with_name("quick")
+                        .help("Quick verification")
+                        .long("quick"),
+                )
+                .arg(
+                    Arg::with_name("quick-total")
+                        .conflicts_with_all(&[
+                            "quick",
+                            "no-size-check",
+                            "skip-hash-larger-than",
+                            "exclude-hash-mismatch-under",
+                            "verify-changed",
+                            "optimize-io",
+                            "baseline-hash",
+                            "fail-on",
+                            "print-ok",
+                            "report-csv",
+                            "summary",
+                            "strip-components",
+                            "compare-with-archive-manifest",
+                            "archive",
+                            "low-memory",
+                            "stdin-repository-listing",
+                            "partial",
+                        ])
+                        .help("Only compare the number and the total size of the files")
+                        .long("quick-total"),
+                )
+                .arg(
+                    Arg::with_name("fail-if-empty-inventory")
+                        .help("Fail if the inventory has no records")
+                        .long("fail-if-empty-inventory"),
+                )
+                .arg(
+                    Arg::with_name("require-algorithm")
+                        .help("Fail unless the inventory is hashed with the algorithm(s)")
+                        .long("require-algorithm")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("ALG")
+                        .validator(|s| {
+                            HashAlgorithm::from_str(&s)
+                                .and(Ok(()))
+                                .or(Err("invalid algorithm name".to_string()))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("no-size-check")
+                        .conflicts_with("quick")
+                        .help("Hash all files and only compare the hashes, not the sizes")
+                        .long("no-size-check"),
+                )
+                .arg(
+                    Arg::with_name("skip-hash-larger-than")
+                        .help("Do not compare hashes of files larger than the given size")
+                        .long("skip-hash-larger-than")
+                        .number_of_values(1)
+                        .value_name("SIZE")
+                        .validator(|s| {
+                            util::parse_size(&s)
+                                .and(Some(()))
+                                .ok_or_else(|| "invalid size".to_string())
+                        }),
+                )
+                .arg(
+                    Arg::with_name("exclude-hash-mismatch-under")
+                        .help(
+                            "Only warn about hash mismatches of files smaller than the given size",
+                        )
+                        .long("exclude-hash-mismatch-under")
+                        .number_of_values(1)
+                        .value_name("SIZE")
+                        .validator(|s| {
+                            util::parse_size(&s)
+                                .and(Some(()))
+                                .ok_or_else(|| "invalid size".to_string())
+                        }),
+                )
+                .arg(
+                    Arg::with_name("verify-changed")
+                        .conflicts_with_all(&["quick", "archive", "stdin-repository-listing"])
+                        .help("Only compare the hashes of files modified since they were recorded")
+                        .long("verify-changed"),
+                )
+                .arg(
+                    Arg::with_name("partial")
+                        .conflicts_with("compare-with-archive-manifest")
+                        .help("Report files missing from the repository as not restored")
+                        .long("partial"),
+                )
+                .arg(
+                    Arg::with_name("optimize-io")
+                        .help("Verify files in the on-disk order where possible")
+                        .long("optimize-io"),
+                )
+                .arg(
+                    Arg::with_name("baseline-hash")
+                        .help("Hash algorithm(s) to compare (default: all recorded)")
+                        .long("baseline-hash")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("ALG")
+                        .validator(|s| {
+                            HashAlgorithm::from_str(&s)
+                                .and(Ok(()))
+                                .or(Err("invalid algorithm name".to_string()))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("warn-only")
+                        .help("Report the failures, but exit successfully")
+                        .long("warn-only"),
+                )
+                .arg(
+                    Arg::with_name("fail-on")
+                        .help("Failure kind(s) that fail the verification (default: all)")
+                        .long("fail-on")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .use_delimiter(true)
+                        .value_name("KIND")
+                        .possible_values(&[
+                            "missing-from-repository",
+                            "missing-from-inventory",
+                            "size-mismatch",
+                            "hash-mismatch",
+                            "symlink-mismatch",
+                            "read-error",
+                            "missing-directory",
+                            "created-time-mismatch",
+                            "network-error",
+                        ]),
+                )
+                .arg(
+                    Arg::with_name("print-ok")
+                        .help("Output the files that passed the verification")
+                        .long("print-ok"),
+                )
+                .arg(
+                    Arg::with_name("report-csv")
+                        .conflicts_with("compare-with-archive-manifest")
+                        .help("Also write the failures and notices to a CSV file")
+                        .long("report-csv")
+                        .number_of_values(1)
+                        .value_name("FILE"),
+                )
+                .arg(
+                    Arg::with_name("summary")
+                        .help("Output the number of files per failure kind instead of the files")
+                        .long("summary"),
+                )
+                .arg(
+                    Arg::with_name("strip-components")
+                        .help(
+                            "Strip leading components from the repository paths when matching them",
+                        )
+                        .long("strip-components")
+                        .number_of_values(1)
+                        .value_name("N")
+                        .validator(|s| {
+                            usize::from_str(&s)
+                                .and(Ok(()))
+                                .or(Err("invalid number of components".to_string()))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("compare-with-archive-manifest")
+                        .conflicts_with_all(&[
+                            "archive",
+                            "stdin-repository-listing",
+                            "quick",
+                            "low-memory",
+                            "optimize-io",
+                            "verify-changed",
+                            "strip-components",
+                            "print-ok",
+                        ])
+                        .help("Compare the inventory with a checksum manifest instead of the repository")
+                        .long("compare-with-archive-manifest")
+                        .number_of_values(1)
+                        .value_name("FILE")
+                        .validator(|s| {
+                            if PathBuf::from(s).is_file() {
+                                Ok(())
+                            } else {
+                                Err("manifest does not exist".to_string())
+                            }
+                        }),
+                )
+                .arg(
+                    Arg::with_name("checksum-file")
+                        .conflicts_with_all(&[
+                            "archive",
+                            "stdin-repository-listing",
+                            "compare-with-archive-manifest",
+                            "quick",
+                            "quick-total",
+                            "fail-if-empty-inventory",
+                            "require-algorithm",
+                            "no-size-check",
+                            "skip-hash-larger-than",
+                            "exclude-hash-mismatch-under",
+                            "low-memory",
+                            "optimize-io",
+                            "baseline-hash",
+                            "verify-changed",
+                            "strip-components",
+                            "report-csv",
+                            "repository-from-config",
+                            "only-paths",
+                        ])
+                        .help("Verify the repository against a checksum manifest instead of the inventory")
+                        .long("checksum-file")
+                        .number_of_values(1)
+                        .value_name("FILE")
+                        .validator(|s| {
+                            if PathBuf::from(s).is_file() {
+                                Ok(())
+                            } else {
+                                Err("checksum file does not exist".to_string())
+                            }
+                        }),
+                )
+                .arg(
+                    Arg::with_name("repository-from-config")
+                        .conflicts_with_all(&[
+                            "archive",
+                            "stdin-repository-listing",
+                            "compare-with-archive-manifest",
+                        ])
+                        .help("Verify the repository at the path stored in the inventory")
+                        .long("repository-from-config"),
+                )
+                .arg(
+                    Arg::with_name("manifest-algorithm")
+                        .help("Hash algorithm of the manifest lines without an algorithm tag")
+                        .long("manifest-algorithm")
+                        .number_of_values(1)
+                        .value_name("ALG")
+                        .validator(|s| {
+                            HashAlgorithm::from_str(&s)
+                                .and(Ok(()))
+                                .or(Err("invalid algorithm name".to_string()))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("archive")
+                        .conflicts_with("optimize-io")
+                        .help("Verify a tar or zip archive of the repository instead of the repository")
+                        .long("archive")
+                        .number_of_values(1)
+                        .value_name("FILE")
+                        .validator(|s| {
+                            if PathBuf::from(s).is_file() {
+                                Ok(())
+                            } else {
+                                Err("archive does not exist".to_string())
+                            }
+                        }),
+                )
+                .arg(
+                    Arg::with_name("low-memory")
+                        .conflicts_with_all(&[
+                            "optimize-io",
+                            "strip-components",
+                            "only-paths",
+                            "archive",
+                            "stdin-repository-listing",
+                        ])
+                        .help(
+                            "Verify the repository in a single sorted pass with bounded memory use",
+                        )
+                        .long("low-memory"),
+                )
+                .arg(
+                    Arg::with_name("stdin-repository-listing")
+                        .conflicts_with_all(&["archive", "optimize-io"])
+                        .help("Verify a listing of the repository read from the standard input")
+                        .long("stdin-repository-listing"),
+                )
+                .arg(
+                    Arg::with_name("only-paths")
+                        .conflicts_with_all(&[
+                            "archive",
+                            "stdin-repository-listing",
+                            "compare-with-archive-manifest",
+                            "quick-total",
+                        ])
+                        .help("Only verify the records whose paths are listed in the file")
+                        .long("only-paths")
+                        .number_of_values(1)
+                        .value_name("FILE"),
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .help("Fail instead of warning if the listing has duplicate paths")
+                        .long("strict")
+                        .requires("stdin-repository-listing"),
+                )
+                .args(&http_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("update")
+                .about("Updates the inventory")
+                .arg(
+                    Arg::with_name("remove-missing")
+                        .help("Remove missing files from inventory")
+                        .long("remove-missing"),
+                )
+                .arg(
+                    Arg::with_name("strip-components")
+                        .help(
+                            "Strip leading components from the repository paths when matching them",
+                        )
+                        .long("strip-components")
+                        .number_of_values(1)
+                        .value_name("N")
+                        .validator(|s| {
+                            usize::from_str(&s)
+                                .and(Ok(()))
+                                .or(Err("invalid number of components".to_string()))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .conflicts_with("checkpoint-every")
+                        .help("Only output the records that would be added and removed")
+                        .long("dry-run"),
+                )
+                .arg(
+                    Arg::with_name("verify-added")
+                        .conflicts_with("dry-run")
+                        .help("Verify the added files once the inventory is updated")
+                        .long("verify-added"),
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .help("Fail instead of warning if the inventory may not fit on its filesystem")
+                        .long("strict"),
+                )
+                .args(&snapshot_args())
+                .arg(
+                    Arg::with_name("checkpoint-every")
+                        .help("Write the inventory after every N added files")
+                        .long("checkpoint-every")
+                        .number_of_values(1)
+                        .value_name("N")
+                        .validator(|s| match usize::from_str(&s) {
+                            Ok(n) if n > 0 => Ok(()),
+                            _ => Err("invalid number of files".to_string()),
+                        }),
+                ),
         )
-        .arg(
-            Arg::with_name("repository")
-                .default_value(DEFAULT_REPOSITORY_DIR)
-                .help("Path to the repository")
-                .long("repository")
-                .number_of_values(1)
-                .validator(|s| {
-                    let p = PathBuf::from(s);
-                    if !p.exists() {
-                        Err("repository does not exist".to_string())
-                    } else if !p.is_dir() {
-                        Err("repository is not a directory".to_string())
-                    } else if p.canonicalize().is_err() {
-                        Err("cannot canonicalize repository path".to_string())
-                    } else {
-                        Ok(())
-                    }
-                }),
+        .subcommand(SubCommand::with_name("info").about("Prints the inventory information"))
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Diagnoses common problems with the inventory and the repository"),
         )
         .subcommand(
-            SubCommand::with_name("build")
-                .about("Builds the inventory")
+            SubCommand::with_name("list")
+                .about("Lists the inventory records")
                 .arg(
-                    Arg::with_name("overwrite")
-                        .help("Overwrite inventory file if it exists")
-                        .long("overwrite"),
+                    Arg::with_name("digest-encoding")
+                        .default_value(DEFAULT_DIGEST_ENCODING)
+                        .help("Encoding of the hash values")
+                        .long("digest-encoding")
+                        .number_of_values(1)
+                        .possible_values(&["hex", "base64"]),
                 )
                 .arg(
-                    Arg::with_name("skip-hidden")
-                        .help("Skip hidden files")
-                        .long("skip-hidden"),
+                    Arg::with_name("primary-hash")
+                        .help("Only output the hash of the strongest recorded algorithm")
+                        .long("primary-hash"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Exports a view of the inventory records")
+                .arg(
+                    Arg::with_name("format")
+                        .default_value("cas")
+                        .help("Format of the export: each unique content once (cas)")
+                        .long("format")
+                        .number_of_values(1)
+                        .possible_values(&["cas"]),
                 )
                 .arg(
-                    Arg::with_name("hash-algorithm")
-                        .default_value(DEFAULT_HASH_ALGORITHM)
-                        .help("Hash algorithm(s) to use")
-                        .long("hash-algorithm")
-                        .multiple(true)
+                    Arg::with_name("algorithm")
+                        .help(
+                            "Hash algorithm defining the content identity [default: the \
+                             strongest one of the inventory]",
+                        )
+                        .long("algorithm")
                         .number_of_values(1)
+                        .value_name("ALG")
                         .validator(|s| {
                             HashAlgorithm::from_str(&s)
                                 .and(Ok(()))
                                 .or(Err("invalid algorithm name".to_string()))
                         }),
+                )
+                .arg(
+                    Arg::with_name("digest-encoding")
+                        .default_value(DEFAULT_DIGEST_ENCODING)
+                        .help("Encoding of the hash values")
+                        .long("digest-encoding")
+                        .number_of_values(1)
+                        .possible_values(&["hex", "base64"]),
                 ),
         )
         .subcommand(
-            SubCommand::with_name("verify").about("Verifies files").arg(
-                Arg::with_name("quick")
-                    .help("Quick verification")
-                    .long("quick"),
-            ),
+            SubCommand::with_name("diff")
+                .about("Compares the inventory with another one")
+                .arg(
+                    Arg::with_name("other")
+                        .help("Path to the inventory to compare with")
+                        .required(true)
+                        .validator(|s| {
+                            if PathBuf::from(s).is_file() {
+                                Ok(())
+                            } else {
+                                Err("inventory does not exist".to_string())
+                            }
+                        }),
+                ),
         )
         .subcommand(
-            SubCommand::with_name("update")
-                .about("Updates the inventory")
+            SubCommand::with_name("clean")
+                .about("Removes temporary files left next to the inventory")
                 .arg(
-                    Arg::with_name("remove-missing")
-                        .help("Remove missing files from inventory")
-                        .long("remove-missing"),
+                    Arg::with_name("dry-run")
+                        .help("Only list the files that would be removed")
+                        .long("dry-run"),
                 ),
         )
         .get_matches_from(args);
 
+    // The hash algorithm has a default value, so the conflict is only
+    // detected if it is specified explicitly.
+    if let ("build", Some(matches)) = matches.subcommand() {
+        if matches.is_present("sizes-only") && matches.occurrences_of("hash-algorithm") > 0 {
+            eprintln!("error: --sizes-only cannot be used with --hash-algorithm");
+            std::process::exit(1);
+        }
+
+        // Repeated algorithms are likely a mistake, so they are rejected
+        // rather than silently collapsed. Aliases count as the same
+        // algorithm.
+        let algorithms = values_t_or_exit!(matches, "hash-algorithm", HashAlgorithm);
+        for (i, algorithm) in algorithms.iter().enumerate() {
+            if algorithms[..i].contains(algorithm) {
+                eprintln!(
+                    "error: hash algorithm '{}' is specified more than once",
+                    algorithm
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
     // Extract the subcommand-specific options.
     let command = match matches.subcommand() {
         ("build", Some(matches)) => Command::Build(CommandBuild {
             overwrite: matches.is_present("overwrite"),
             skip_hidden: matches.is_present("skip-hidden"),
-            hash_algorithms: values_t_or_exit!(matches, "hash-algorithm", HashAlgorithm),
+            skip_vcs: matches.is_present("skip-vcs"),
+            ignore_files: matches.is_present("use-ignore-files"),
+            path_encoding: value_t_or_exit!(matches, "path-encoding", PathEncoding),
+            include_empty_dirs: matches.is_present("include-empty-dirs"),
+            hash_algorithms: if matches.is_present("sizes-only") {
+                Vec::new()
+            } else {
+                values_t_or_exit!(matches, "hash-algorithm", HashAlgorithm)
+            },
+            sizes_only: matches.is_present("sizes-only"),
+            extension_algorithms: file_algorithms(matches, false),
+            glob_algorithms: file_algorithms(matches, true),
+            follow_symlink_dirs: matches.value_of("follow-dirs") == Some("yes"),
+            follow_symlink_files: matches.value_of("follow-files") == Some("yes"),
+            exclude_symlinks: matches.is_present("exclude-symlinks"),
+            excluded_types: matches
+                .values_of("exclude-type")
+                .map(|v| v.map(|s| EntryType::from_str(s).unwrap()).collect())
+                .unwrap_or_default(),
+            description: matches.value_of("description").map(String::from),
+            tags: matches
+                .values_of("tag")
+                .map(|v| {
+                    v.map(|t| {
+                        let (key, value) = t.split_at(t.find('=').unwrap());
+                        (key.to_string(), value[1..].to_string())
+                    })
+                    .collect()
+                })
+                .unwrap_or_default(),
+            max_files: matches
+                .value_of("max-files")
+                .and_then(|s| usize::from_str(s).ok()),
+            reproducible: matches.is_present("reproducible"),
+            verify_after: matches.is_present("verify-after"),
+            trust_xattr: matches.value_of("trust-xattr").map(String::from),
+            record_mtime: matches.is_present("record-mtime"),
+            record_btime: matches.is_present("track-btime"),
+            since_mtime: matches
+                .value_of("since-mtime")
+                .and_then(util::parse_timestamp),
+            store_repository: matches.is_present("store-repository-path"),
+            append_aware: matches.is_present("append-aware"),
+            strict: matches.is_present("strict"),
+            snapshots: snapshots(matches),
+            append: if matches.is_present("append") {
+                // The prefix has been validated and thus can be normalized
+                // safely.
+                Some(util::normalize_path(matches.value_of("prefix").unwrap_or("")).unwrap())
+            } else {
+                None
+            },
+            seal: if matches.is_present("seal-includes-paths") {
+                Some(true)
+            } else if matches.is_present("seal") {
+                Some(false)
+            } else {
+                None
+            },
         }),
         ("verify", Some(matches)) => Command::Verify(CommandVerify {
             quick: matches.is_present("quick"),
+            quick_total: matches.is_present("quick-total"),
+            fail_if_empty: matches.is_present("fail-if-empty-inventory"),
+            required_algorithms: if matches.is_present("require-algorithm") {
+                values_t_or_exit!(matches, "require-algorithm", HashAlgorithm)
+            } else {
+                Vec::new()
+            },
+            no_size_check: matches.is_present("no-size-check"),
+            skip_hash_larger_than: matches
+                .value_of("skip-hash-larger-than")
+                .and_then(util::parse_size),
+            exclude_hash_mismatch_under: matches
+                .value_of("exclude-hash-mismatch-under")
+                .and_then(util::parse_size),
+            optimize_io: matches.is_present("optimize-io"),
+            hash_algorithms: if matches.is_present("baseline-hash") {
+                values_t_or_exit!(matches, "baseline-hash", HashAlgorithm)
+            } else {
+                Vec::new()
+            },
+            archive: matches.value_of("archive").map(PathBuf::from),
+            manifest: matches
+                .value_of("compare-with-archive-manifest")
+                .map(PathBuf::from),
+            checksum_file: matches.value_of("checksum-file").map(PathBuf::from),
+            manifest_algorithm: matches
+                .value_of("manifest-algorithm")
+                .map(|s| HashAlgorithm::from_str(s).unwrap()),
+            stdin_listing: matches.is_present("stdin-repository-listing"),
+            strict_listing: matches.is_present("strict"),
+            only_paths: matches.value_of("only-paths").map(PathBuf::from),
+            warn_only: matches.is_present("warn-only"),
+            strip_components: matches
+                .value_of("strip-components")
+                .map_or(0, |s| usize::from_str(s).unwrap()),
+            summary: matches.is_present("summary"),
+            low_memory: matches.is_present("low-memory"),
+            print_ok: matches.is_present("print-ok"),
+            report_csv: matches.value_of("report-csv").map(PathBuf::from),
+            verify_changed: matches.is_present("verify-changed"),
+            partial: matches.is_present("partial"),
+            repository_from_config: matches.is_present("repository-from-config"),
+            #[cfg(feature = "http")]
+            base_url: matches
+                .value_of("base-url")
+                .map(|s| http::BaseUrl::from_str(s).unwrap()),
+            fail_on: if matches.is_present("fail-on") {
+                values_t_or_exit!(matches, "fail-on", FailureKind)
+            } else {
+                Vec::new()
+            },
         }),
         ("update", Some(matches)) => Command::Update(CommandUpdate {
             remove_missing: matches.is_present("remove-missing"),
+            strip_components: matches
+                .value_of("strip-components")
+                .map_or(0, |s| usize::from_str(s).unwrap()),
+            checkpoint_every: matches
+                .value_of("checkpoint-every")
+                .map(|s| usize::from_str(s).unwrap()),
+            dry_run: matches.is_present("dry-run"),
+            verify_added: matches.is_present("verify-added"),
+            strict: matches.is_present("strict"),
+            snapshots: snapshots(matches),
+        }),
+        ("info", Some(_)) => Command::Info(CommandInfo {}),
+        ("doctor", Some(_)) => Command::Doctor(CommandDoctor {}),
+        ("list", Some(matches)) => Command::List(CommandList {
+            digest_encoding: value_t_or_exit!(matches, "digest-encoding", DigestEncoding),
+            primary_hash: matches.is_present("primary-hash"),
+        }),
+        ("export", Some(matches)) => Command::Export(CommandExport {
+            algorithm: matches
+                .value_of("algorithm")
+                .map(|s| HashAlgorithm::from_str(s).unwrap()),
+            digest_encoding: value_t_or_exit!(matches, "digest-encoding", DigestEncoding),
+        }),
+        ("diff", Some(matches)) => Command::Diff(CommandDiff {
+            other: PathBuf::from(matches.value_of("other").unwrap()),
+        }),
+        ("clean", Some(matches)) => Command::Clean(CommandClean {
+            dry_run: matches.is_present("dry-run"),
         }),
         _ => unreachable!(),
     };
 
     // Both inventory and repository paths have been validated and thus can be
-    // canonicalized safely.
-    let inventory =
-        canonicalize_inventory_path(&value_t_or_exit!(matches, "inventory", PathBuf)).unwrap();
+    // canonicalized safely. Only `build` creates the directories of the
+    // inventory, the other subcommands expect the inventory to exist already.
     let repository = value_t_or_exit!(matches, "repository", PathBuf)
         .canonicalize()
         .unwrap();
+    if let Command::Verify(command) = &command {
+        if command.manifest_algorithm.is_some()
+            && command.manifest.is_none()
+            && command.checksum_file.is_none()
+        {
+            eprintln!(
+                "error: --manifest-algorithm requires --compare-with-archive-manifest or \
+                 --checksum-file"
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let creates_inventory = matches!(command, Command::Build(_));
+    let checksum_file = match &command {
+        Command::Verify(command) => command.checksum_file.as_deref(),
+        _ => None,
+    };
+    let inventory = match (
+        matches.value_of("output-dir"),
+        matches.value_of("inventory"),
+    ) {
+        // The checksum file takes the place of the inventory, so that it is
+        // left out of the repository walk like an inventory would be.
+        _ if checksum_file.is_some() => {
+            if ["inventory", "output-dir", "inventory-outside"]
+                .iter()
+                .any(|a| matches.is_present(a))
+            {
+                eprintln!("error: --checksum-file cannot be used with an inventory");
+                std::process::exit(1);
+            }
+            checksum_file.unwrap().canonicalize().unwrap()
+        }
+        (Some(dir), _) => named_inventory_path(&repository, Path::new(dir), creates_inventory)
+            .unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }),
+        (None, Some(path)) => canonicalize_inventory_path(path).unwrap(),
+        (None, None) => {
+            eprintln!("error: either --inventory or --output-dir must be specified");
+            std::process::exit(1);
+        }
+    };
+    // The inventory keeps its file name when placed next to the repository.
+    let inventory = match matches.value_of("inventory-outside") {
+        Some(dir) => {
+            outside_inventory_path(&repository, Path::new(dir), &inventory, creates_inventory)
+                .unwrap_or_else(|e| {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                })
+        }
+        None => inventory,
+    };
+    let relative_to = matches
+        .value_of("relative-to")
+        .map(|s| Path::new(s).canonicalize().unwrap());
 
-    if inventory.starts_with(&repository) {
-        eprintln!("error: inventory must be located outside of the repository");
+    // The key file has been validated, but may still fail to be read.
+    let hmac_key = matches.value_of("hmac-key-file").map(|path| {
+        fs::read(path).unwrap_or_else(|e| {
+            eprintln!("error: cannot read the HMAC key file: {}", e);
+            std::process::exit(1);
+        })
+    });
+
+    let repository_specified = matches.occurrences_of("repository") > 0;
+    if let Command::Verify(CommandVerify {
+        repository_from_config: true,
+        ..
+    }) = command
+    {
+        if repository_specified {
+            eprintln!("error: --repository-from-config cannot be used with --repository");
+            std::process::exit(1);
+        }
+    }
+
+    // Subcommands that only read inventories do not touch the repository,
+    // and `doctor` reports the misplaced inventory itself. `verify` resolves
+    // the defaulted repository once the inventory is read.
+    let uses_repository = match &command {
+        Command::Info(_)
+        | Command::List(_)
+        | Command::Export(_)
+        | Command::Diff(_)
+        | Command::Doctor(_) => false,
+        Command::Verify(command) if command.checksum_file.is_some() => false,
+        Command::Verify(_) => repository_specified,
+        _ => true,
+    };
+    let allow_inventory_inside = matches.is_present("allow-inventory-inside");
+    if uses_repository && !allow_inventory_inside && inventory.starts_with(&repository) {
+        eprintln!(
+            "error: inventory must be located outside of the repository ({})",
+            INSIDE_HINT
+        );
         std::process::exit(1);
     }
 
+    // Every thread keeps a single repository file open at a time, so the
+    // number of open files is bounded by capping the threads. The explicit
+    // limit must allow all the requested threads, while the default one only
+    // caps one thread per CPU.
+    let threads = value_t_or_exit!(matches, "threads", usize);
+    let max_threads = matches
+        .value_of("max-threads")
+        .and_then(|s| usize::from_str(s).ok());
+    let max_open_files = match matches.value_of("max-open-files") {
+        Some(s) => {
+            let max = usize::from_str(s).unwrap();
+            if threads > max {
+                eprintln!(
+                    "error: --max-open-files ({}) must be at least the number of threads ({})",
+                    max, threads
+                );
+                std::process::exit(1);
+            }
+            Some(max)
+        }
+        None => util::default_max_open_files(),
+    };
+
     Parameters {
         options: Options {
             verbosity: matches.occurrences_of("verbose") as usize,
+            log_format: value_t_or_exit!(matches, "log-format", LogFormat),
+            log_file: matches.value_of("log-file").map(PathBuf::from),
+            output_format: value_t_or_exit!(matches, "format", OutputFormat),
+            error_policy: value_t_or_exit!(matches, "on-error", ErrorPolicy),
+            rate_limit: matches.value_of("rate-limit").and_then(util::parse_size),
+            buffer_size: matches
+                .value_of("hash-buffer-size")
+                .and_then(util::parse_size)
+                .and_then(|n| usize::try_from(n).ok()),
+            threads: util::resolve_threads(
+                threads,
+                [max_threads, max_open_files]
+                    .iter()
+                    .flatten()
+                    .min()
+                    .copied(),
+            ),
+            assume_yes: matches.is_present("yes"),
             inventory,
             repository,
+            repository_specified,
+            allow_inventory_inside,
+            relative_to,
+            nested_records: matches.is_present("nested-records"),
+            hmac_key,
+            progress_to: matches.value_of("progress-to").map(progress_path),
+            inventory_format: matches
+                .value_of("inventory-format")
+                .map(|s| Format::from_str(s).unwrap()),
         },
         command,
     }
 }
 
 /// Initializes the global logger.
-fn init_logging(verbosity: usize) {
-    let mut builder = LogBuilder::new();
-
+fn init_logging(verbosity: usize, format: LogFormat, log_file: Option<&Path>) -> IoResult<()> {
     let level = match verbosity {
         0 => LevelFilter::Info,
         1 => LevelFilter::Debug,
         _ => LevelFilter::Trace,
     };
 
-    builder
-        .filter_level(level)
-        .format_module_path(false)
-        .format_timestamp_millis()
-        .init();
+    let logger: Box<dyn Log> = match format {
+        LogFormat::Human => Box::new(
+            LogBuilder::new()
+                .filter_level(level)
+                .format_module_path(false)
+                .format_timestamp_millis()
+                .build(),
+        ),
+        LogFormat::Json => Box::new(JsonLogger::new(level)),
+    };
+
+    // The file gets the same messages as the standard error.
+    match log_file {
+        Some(path) => logging::init(
+            Box::new(TeeLogger::new(logger, level, format, path)?),
+            level,
+        ),
+        None => logging::init(logger, level),
+    }
+
+    Ok(())
+}
+
+/// Formats an error followed by the chain of its sources, down to the root
+/// cause.
+///
+/// Many errors already include the message of their source (e.g. the I/O
+/// error of a file error), so the sources whose messages are already included
+/// are skipped.
+fn describe_error(err: &dyn Error) -> String {
+    let mut ret = err.to_string();
+    let mut source = err.source();
+
+    while let Some(err) = source {
+        let message = err.to_string();
+        if !ret.contains(&message) {
+            ret.push_str(": ");
+            ret.push_str(&message);
+        }
+        source = err.source();
+    }
+
+    ret
 }
 
 fn main() {
     let parameters = parse_cmd_line(env::args());
-    init_logging(parameters.options.verbosity);
+    if let Err(err) = init_logging(
+        parameters.options.verbosity,
+        parameters.options.log_format,
+        parameters.options.log_file.as_deref(),
+    ) {
+        eprintln!("error: cannot open the log file: {}", err);
+        std::process::exit(1);
+    }
 
     std::process::exit(match run(parameters) {
         Ok(_) => 0,
         Err(err) => {
-            error!("{}", err);
+            error!("{}", describe_error(err.as_ref()));
             1
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::InventoryError;
+    use crate::util::{create_files, TempDir};
+
+    /// A repository and an inventory file next to it, in a temporary
+    /// directory.
+    struct Fixture {
+        dir: TempDir,
+    }
+
+    impl Fixture {
+        /// Creates the repository with the given files.
+        fn new(files: &[(&str, &str)]) -> Self {
+            let fixture = Fixture {
+                dir: TempDir::new(),
+            };
+            fs::create_dir(fixture.repository()).unwrap();
+            create_files(&fixture.repository(), files);
+            fixture
+        }
+
+        /// Returns the path to the repository.
+        fn repository(&self) -> PathBuf {
+            self.path("repository")
+        }
+
+        /// Returns the path to the inventory file.
+        fn inventory(&self) -> PathBuf {
+            self.path("inventory.json")
+        }
+
+        /// Returns the path to a file in the temporary directory.
+        fn path(&self, name: &str) -> PathBuf {
+            self.dir.path().join(name)
+        }
+
+        /// Runs the application on the repository and the inventory with the
+        /// given arguments, which start with the subcommand or further global
+        /// options.
+        fn run(&self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+            self.run_with_inventory(Some(&self.inventory()), args)
+        }
+
+        /// Runs the application on the repository and the given inventory,
+        /// if any, see `run()`.
+        fn run_with_inventory(
+            &self,
+            inventory: Option<&Path>,
+            args: &[&str],
+        ) -> Result<(), Box<dyn Error>> {
+            let repository = self.repository();
+            let mut cmd_line = vec!["inventorize", "--repository", repository.to_str().unwrap()];
+            if let Some(inventory) = inventory {
+                cmd_line.extend_from_slice(&["--inventory", inventory.to_str().unwrap()]);
+            }
+            cmd_line.extend_from_slice(args);
+            run(parse_cmd_line(cmd_line))
+        }
+    }
+
+    /// Checks that the result is the given application error.
+    fn assert_app_error(result: Result<(), Box<dyn Error>>, expected: AppError) {
+        let err = result.unwrap_err();
+        match err.downcast_ref::<AppError>() {
+            Some(err) => assert_eq!(err.to_string(), expected.to_string()),
+            None => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn writes_the_report_as_csv() {
+        let fixture = Fixture::new(&[("a.txt", "hello\n"), ("sub/b.txt", "world\n")]);
+        fixture.run(&["build"]).unwrap();
+
+        fs::remove_file(fixture.repository().join("a.txt")).unwrap();
+        create_files(
+            &fixture.repository(),
+            &[("sub/b.txt", "World\n"), ("x,\"y\".txt", "!\n")],
+        );
+        let csv = fixture.path("report.csv");
+        assert_app_error(
+            fixture.run(&["verify", "--report-csv", csv.to_str().unwrap()]),
+            AppError::VerificationFailed,
+        );
+
+        assert_eq!(
+            fs::read_to_string(&csv).unwrap(),
+            "category,kind,path,recorded_size,actual_size\n\
+             failure,hash-mismatch,sub/b.txt,6,6\n\
+             failure,missing-from-inventory,\"x,\"\"y\"\".txt\",,2\n\
+             failure,missing-from-repository,a.txt,6,\n"
+        );
+    }
+
+    #[test]
+    fn accepts_inventory_inside_if_allowed() {
+        let fixture = Fixture::new(&[("a.txt", "hello\n")]);
+        let inventory = fixture.repository().join("inventory.json");
+        let run = |args: &[&str]| {
+            let mut cmd_line = vec!["--allow-inventory-inside"];
+            cmd_line.extend_from_slice(args);
+            fixture.run_with_inventory(Some(&inventory), &cmd_line)
+        };
+
+        run(&["build"]).unwrap();
+        let records: Vec<_> = Inventory::from_path(&inventory, None)
+            .unwrap()
+            .records()
+            .map(|(path, _)| path.to_path_buf())
+            .collect();
+        assert_eq!(records, [Path::new("a.txt")]);
+
+        // The inventory is neither reported nor added.
+        run(&["verify"]).unwrap();
+        run(&["update"]).unwrap();
+        assert_eq!(
+            Inventory::from_path(&inventory, None)
+                .unwrap()
+                .record_count(),
+            1
+        );
+    }
+
+    #[test]
+    fn places_inventory_outside_of_the_repository() {
+        let fixture = Fixture::new(&[("a.txt", "hello\n")]);
+        let args = ["--inventory-outside", "inventories"];
+
+        fixture.run(&[&args[..], &["build"]].concat()).unwrap();
+        assert!(!fixture.inventory().exists());
+        let inventory = fixture.path("inventories").join("inventory.json");
+        assert_eq!(
+            Inventory::from_path(&inventory, None)
+                .unwrap()
+                .record_count(),
+            1
+        );
+
+        fs::remove_file(fixture.repository().join("a.txt")).unwrap();
+        assert_app_error(
+            fixture.run(&[&args[..], &["verify"]].concat()),
+            AppError::VerificationFailed,
+        );
+    }
+
+    #[test]
+    fn resolves_inventory_directories() {
+        let fixture = Fixture::new(&[]);
+        let repository = fixture.repository().canonicalize().unwrap();
+        let inventory = Path::new("/elsewhere/inventory.json");
+
+        assert!(outside_inventory_path(&repository, Path::new("out"), inventory, false).is_err());
+        let path = outside_inventory_path(&repository, Path::new("out"), inventory, true).unwrap();
+        assert_eq!(
+            path,
+            fixture
+                .path("out")
+                .canonicalize()
+                .unwrap()
+                .join("inventory.json")
+        );
+        assert_eq!(
+            outside_inventory_path(&repository, &fixture.path("out"), inventory, false).unwrap(),
+            path
+        );
+
+        fs::create_dir(repository.join("sub")).unwrap();
+        assert!(
+            outside_inventory_path(&repository, Path::new("repository/sub"), inventory, false)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn verifies_added_files_with_stripped_components() {
+        let fixture = Fixture::new(&[("a.txt", "hello\n")]);
+        fixture.run(&["build"]).unwrap();
+
+        // Move the files one level down, as if the repository was copied
+        // into a directory.
+        let repository = fixture.repository();
+        fs::create_dir(repository.join("top")).unwrap();
+        fs::rename(repository.join("a.txt"), repository.join("top/a.txt")).unwrap();
+        create_files(&repository, &[("top/b.txt", "world\n")]);
+        fixture
+            .run(&["update", "--strip-components", "1", "--verify-added"])
+            .unwrap();
+
+        let inventory = Inventory::from_path(&fixture.inventory(), None).unwrap();
+        let records: Vec<_> = inventory.records().map(|(path, _)| path).collect();
+        assert_eq!(records, [Path::new("a.txt"), Path::new("b.txt")]);
+    }
+
+    #[test]
+    fn checksum_file_verification_honors_the_failure_options() {
+        let fixture = Fixture::new(&[("a.txt", "hello\n"), ("b.txt", "world\n")]);
+        let sums = fixture.path("MD5SUMS");
+        fs::write(
+            &sums,
+            "b1946ac92492d2347c6235b4d2611184  a.txt\n\
+             591785b794601e212b260e25925636fd  b.txt\n",
+        )
+        .unwrap();
+        let verify = |args: &[&str]| {
+            let mut cmd_line = vec!["verify", "--checksum-file", sums.to_str().unwrap()];
+            cmd_line.extend_from_slice(args);
+            fixture.run_with_inventory(None, &cmd_line)
+        };
+        verify(&[]).unwrap();
+
+        create_files(&fixture.repository(), &[("b.txt", "World\n")]);
+        assert_app_error(verify(&[]), AppError::VerificationFailed);
+        verify(&["--warn-only"]).unwrap();
+        verify(&["--fail-on", "missing-from-repository"]).unwrap();
+        assert_app_error(
+            verify(&["--fail-on", "hash-mismatch"]),
+            AppError::VerificationFailed,
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reports_errors_below_the_root_with_their_own_paths() {
+        let fixture = Fixture::new(&[("a.txt", "hello\n")]);
+        let link = fixture.repository().join("link");
+        std::os::unix::fs::symlink("missing", &link).unwrap();
+
+        let err = fixture.run(&["build"]).unwrap_err();
+        match err.downcast_ref::<FileError>() {
+            Some(err) => assert_eq!(err.path(), link),
+            None => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn attributes_only_root_failures_to_the_repository() {
+        let repository = Path::new("/repository");
+        let not_found = || io::Error::from(io::ErrorKind::NotFound);
+        let is_access = |err: Box<dyn Error>| {
+            matches!(
+                repository_error(repository, err).downcast_ref::<AppError>(),
+                Some(AppError::RepositoryAccess(path, _)) if path == repository
+            )
+        };
+
+        assert!(is_access(Box::new(FileError::new(repository, not_found()))));
+        assert!(is_access(Box::new(not_found())));
+
+        // A walk error below the root is unwrapped to its own path.
+        let subdir = repository.join("sub");
+        let err = io::Error::new(
+            io::ErrorKind::NotFound,
+            FileError::new(&subdir, not_found()),
+        );
+        let err = repository_error(repository, Box::new(err));
+        assert_eq!(err.downcast_ref::<FileError>().unwrap().path(), subdir);
+
+        let file = repository.join("file");
+        let err = repository_error(repository, Box::new(FileError::new(&file, not_found())));
+        assert_eq!(err.downcast_ref::<FileError>().unwrap().path(), file);
+    }
+
+    #[test]
+    fn cleans_the_temporary_file_of_the_inventory() {
+        let fixture = Fixture::new(&[("a.txt", "hello\n")]);
+        let temp = util::temp_path(fixture.inventory());
+        fs::write(&temp, b"partial").unwrap();
+
+        fixture.run(&["clean", "--dry-run"]).unwrap();
+        assert!(temp.is_file());
+        fixture.run(&["clean"]).unwrap();
+        assert!(!temp.exists());
+        fixture.run(&["clean"]).unwrap();
+    }
+
+    #[test]
+    fn never_cleans_inside_the_repository() {
+        let fixture = Fixture::new(&[("a.txt", "hello\n")]);
+        let inventory = fixture.repository().join("inventory.json");
+        let temp = util::temp_path(&inventory);
+        fs::write(&temp, b"partial").unwrap();
+
+        fixture
+            .run_with_inventory(Some(&inventory), &["--allow-inventory-inside", "clean"])
+            .unwrap();
+        assert!(temp.is_file());
+    }
+
+    #[test]
+    fn appends_repositories_to_the_inventory() {
+        let fixture = Fixture::new(&[("a.txt", "hello\n")]);
+        let other = Fixture::new(&[("a.txt", "other\n"), ("sub/b.txt", "world\n")]);
+        fixture.run(&["build"]).unwrap();
+        let append = |args: &[&str]| {
+            let mut cmd_line = vec!["build", "--append"];
+            cmd_line.extend_from_slice(args);
+            other.run_with_inventory(Some(&fixture.inventory()), &cmd_line)
+        };
+        let records = || -> Vec<_> {
+            Inventory::from_path(&fixture.inventory(), None)
+                .unwrap()
+                .records()
+                .map(|(path, _)| path.to_path_buf())
+                .collect()
+        };
+
+        append(&["--prefix", "./vendor//other/"]).unwrap();
+        assert_eq!(
+            records(),
+            [
+                Path::new("a.txt"),
+                Path::new("vendor/other/a.txt"),
+                Path::new("vendor/other/sub/b.txt")
+            ]
+        );
+
+        // Without a prefix, `a.txt` collides with the existing record.
+        let err = append(&[]).unwrap_err();
+        match err.downcast_ref::<InventoryError>() {
+            Some(InventoryError::RecordExists(path)) => {
+                assert_eq!(path, Path::new("a.txt"))
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+        assert_eq!(records().len(), 3);
+    }
+
+    #[test]
+    fn exports_unique_contents() {
+        let fixture = Fixture::new(&[
+            ("b.txt", "hello\n"),
+            ("a/copy.txt", "hello\n"),
+            ("c.txt", "world\n"),
+            ("sub/d.txt", "hello\n"),
+        ]);
+        fixture
+            .run(&[
+                "build",
+                "--hash-algorithm",
+                "md5",
+                "--hash-algorithm",
+                "sha1",
+            ])
+            .unwrap();
+        let inventory = Inventory::from_path(&fixture.inventory(), None).unwrap();
+        let export = |args: &[&str]| {
+            let repository = fixture.repository();
+            let inventory_path = fixture.inventory();
+            let mut cmd_line = vec![
+                "inventorize",
+                "--repository",
+                repository.to_str().unwrap(),
+                "--inventory",
+                inventory_path.to_str().unwrap(),
+                "export",
+            ];
+            cmd_line.extend_from_slice(args);
+            let parameters = parse_cmd_line(cmd_line);
+            let command = match parameters.command {
+                Command::Export(command) => command,
+                _ => unreachable!(),
+            };
+            let report = export_report(&parameters.options, &inventory, &command)?;
+            let mut text = Vec::new();
+            output::write_report(&mut text, &report, OutputFormat::Text)?;
+            Ok::<_, Box<dyn Error>>(String::from_utf8(text).unwrap())
+        };
+
+        // Each content is exported once, with the first of its paths.
+        assert_eq!(
+            export(&["--algorithm", "md5"]).unwrap(),
+            "591785b794601e212b260e25925636fd 1 c.txt\n\
+             b1946ac92492d2347c6235b4d2611184 3 a/copy.txt\n"
+        );
+        assert_eq!(
+            export(&[]).unwrap(),
+            "9591818c07e900db7e1e0bc4b884c945e6a61b24 1 c.txt\n\
+             f572d396fae9206628714fb2ce00f72e94f2258f 3 a/copy.txt\n"
+        );
+
+        let err = export(&["--algorithm", "sha3-256"]).unwrap_err();
+        match err.downcast_ref::<InventoryError>() {
+            Some(InventoryError::AlgorithmNotPresent(algorithm)) => {
+                assert_eq!(*algorithm, HashAlgorithm::Sha3_256)
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn parses_file_algorithm_settings() {
+        let md5 = vec![HashAlgorithm::Md5];
+        let parse = |s| parse_extension_algorithms(s);
+        assert_eq!(parse(".ISO=md5"), Some(("ISO".to_string(), md5.clone())));
+        assert_eq!(
+            parse("pdf=sha1,md5"),
+            Some((
+                "pdf".to_string(),
+                vec![HashAlgorithm::Sha1, HashAlgorithm::Md5]
+            ))
+        );
+        assert_eq!(
+            parse("*.tar.gz=md5"),
+            Some(("*.tar.gz".to_string(), md5.clone()))
+        );
+        assert_eq!(parse("/data/**=md5"), Some(("/data/**".to_string(), md5)));
+        for s in &["iso", "=md5", ".=md5", "iso=", "iso=crc32", "iso=md5,"] {
+            assert_eq!(parse(s), None, "{}", s);
+        }
+
+        assert!(!is_glob("tar.gz"));
+        for key in &["*.iso", "a?c", "data/x", "\\*"] {
+            assert!(is_glob(key), "{}", key);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::error::Error;
+use std::io::{Result as IoResult, Write};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+/// Output format of the subcommand results.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable text.
+    Text,
+
+    /// A single pretty-printed JSON document.
+    Json,
+}
+
+/// An error returned when the output format name cannot be parsed.
+#[derive(Debug)]
+pub struct ParseOutputFormatError();
+
+impl FromStr for OutputFormat {
+    type Err = ParseOutputFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(ParseOutputFormatError()),
+        }
+    }
+}
+
+/// A structured subcommand result that can be output either as text or as
+/// JSON.
+///
+/// The JSON schema is the serialized form of the result, so that all
+/// subcommands are scripted the same way.
+pub trait JsonReport: Serialize {
+    /// Writes the result as human-readable text.
+    fn write_text(&self, writer: &mut dyn Write) -> IoResult<()>;
+}
+
+/// Writes the subcommand result in the specified format.
+pub fn write_report<R, W>(
+    mut writer: W,
+    report: &R,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>>
+where
+    R: JsonReport,
+    W: Write,
+{
+    match format {
+        OutputFormat::Text => {
+            report.write_text(&mut writer)?;
+            writer.flush()?;
+        }
+        OutputFormat::Json => write_json(writer, report)?,
+    }
+
+    Ok(())
+}
+
+/// Writes the subcommand result as JSON.
+///
+/// Results whose text form is logged rather than written (e.g. the
+/// differences reported by `diff`) are written with this function in the
+/// JSON format.
+pub fn write_json<R, W>(mut writer: W, report: &R) -> Result<(), Box<dyn Error>>
+where
+    R: Serialize + ?Sized,
+    W: Write,
+{
+    serde_json::to_writer_pretty(&mut writer, report)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use std::io::Write;
+use std::path::Path;
+
+use log::debug;
+
+use crate::inventory::{FailureKind, Report};
+
+/// Number of progress steps logged by `LogProgress`.
+const LOG_PROGRESS_STEPS: u64 = 10;
+
+/// Number of progress steps written by `WriterProgress`.
+const WRITER_PROGRESS_STEPS: u64 = 100;
+
+/// An observer of the progress of inventory operations.
+///
+/// Inventory operations that walk the repository (building, verifying and
+/// updating) report their progress to an optional observer, which keeps the
+/// presentation separate from the operations themselves. All methods do
+/// nothing by default.
+pub trait ProgressObserver {
+    /// Called once the files to process are known.
+    fn on_start(&mut self, _total_files: usize, _total_bytes: u64) {}
+
+    /// Called after a file has been processed, whether successfully or not.
+    fn on_file(&mut self, _path: &Path, _bytes: u64) {}
+
+    /// Called when a file fails the verification or cannot be read.
+    fn on_failure(&mut self, _path: &Path, _kind: FailureKind) {}
+
+    /// Called once the operation is complete.
+    fn on_finish(&mut self, _report: &Report) {}
+}
+
+/// An observer that ignores the progress.
+impl ProgressObserver for () {}
+
+/// An observer that logs the progress at the debug level, in steps of a
+/// tenth of the total size.
+#[derive(Default)]
+pub struct LogProgress {
+    /// Total number of files to process.
+    total_files: usize,
+
+    /// Total size of the files to process, in bytes.
+    total_bytes: u64,
+
+    /// Number of files processed so far.
+    files: usize,
+
+    /// Size of the files processed so far, in bytes.
+    bytes: u64,
+
+    /// Number of the last logged progress step.
+    step: u64,
+}
+
+impl LogProgress {
+    /// Creates a new progress logger.
+    pub fn new() -> Self {
+        LogProgress::default()
+    }
+}
+
+impl ProgressObserver for LogProgress {
+    fn on_start(&mut self, total_files: usize, total_bytes: u64) {
+        *self = LogProgress {
+            total_files,
+            total_bytes,
+            ..LogProgress::default()
+        };
+        debug!("Processing {} files ({} bytes)", total_files, total_bytes);
+    }
+
+    fn on_file(&mut self, _path: &Path, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+
+        let step = progress_step(
+            self.files,
+            self.total_files,
+            self.bytes,
+            self.total_bytes,
+            LOG_PROGRESS_STEPS,
+        );
+        if step > self.step {
+            self.step = step;
+            debug!(
+                "Progress: {}% ({} of {} files)",
+                step * 100 / LOG_PROGRESS_STEPS,
+                self.files,
+                self.total_files
+            );
+        }
+    }
+
+    fn on_finish(&mut self, report: &Report) {
+        let failed: usize = report
+            .failures()
+            .into_iter()
+            .map(|k| report.by_failure(k).unwrap().count())
+            .sum();
+        debug!(
+            "Processed {} files ({} bytes), {} failed",
+            self.files, self.bytes, failed
+        );
+    }
+}
+
+/// An observer that writes the progress to a dedicated sink, such as a named
+/// pipe, in steps of a hundredth of the total size.
+///
+/// Every line is written as soon as the step is reached. Write errors are
+/// ignored, so that a closed sink does not interrupt the operation.
+pub struct WriterProgress<W: Write> {
+    /// Sink to write the progress to.
+    writer: W,
+
+    /// Total number of files to process.
+    total_files: usize,
+
+    /// Total size of the files to process, in bytes.
+    total_bytes: u64,
+
+    /// Number of files processed so far.
+    files: usize,
+
+    /// Size of the files processed so far, in bytes.
+    bytes: u64,
+
+    /// Number of the last written progress step.
+    step: u64,
+}
+
+impl<W: Write> WriterProgress<W> {
+    /// Creates a new progress writer.
+    pub fn new(writer: W) -> Self {
+        WriterProgress {
+            writer,
+            total_files: 0,
+            total_bytes: 0,
+            files: 0,
+            bytes: 0,
+            step: 0,
+        }
+    }
+
+    /// Writes the current progress.
+    fn write_progress(&mut self) {
+        let _ = writeln!(
+            self.writer,
+            "Progress: {}% ({} of {} files, {} of {} bytes)",
+            self.step * 100 / WRITER_PROGRESS_STEPS,
+            self.files,
+            self.total_files,
+            self.bytes,
+            self.total_bytes
+        )
+        .and_then(|_| self.writer.flush());
+    }
+}
+
+impl<W: Write> ProgressObserver for WriterProgress<W> {
+    fn on_start(&mut self, total_files: usize, total_bytes: u64) {
+        self.total_files = total_files;
+        self.total_bytes = total_bytes;
+        self.files = 0;
+        self.bytes = 0;
+        self.step = 0;
+        self.write_progress();
+    }
+
+    fn on_file(&mut self, _path: &Path, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+
+        let step = progress_step(
+            self.files,
+            self.total_files,
+            self.bytes,
+            self.total_bytes,
+            WRITER_PROGRESS_STEPS,
+        );
+        if step > self.step {
+            self.step = step;
+            self.write_progress();
+        }
+    }
+
+    fn on_finish(&mut self, report: &Report) {
+        let failed: usize = report
+            .failures()
+            .into_iter()
+            .map(|k| report.by_failure(k).unwrap().count())
+            .sum();
+        let _ = writeln!(
+            self.writer,
+            "Done: {} files ({} bytes), {} failed",
+            self.files, self.bytes, failed
+        )
+        .and_then(|_| self.writer.flush());
+    }
+}
+
+/// Returns the number of progress steps reached.
+///
+/// Progress is measured by size, or by count if all files are empty.
+fn progress_step(
+    files: usize,
+    total_files: usize,
+    bytes: u64,
+    total_bytes: u64,
+    steps: u64,
+) -> u64 {
+    (bytes.min(total_bytes) * steps)
+        .checked_div(total_bytes)
+        .unwrap_or_else(|| files.min(total_files) as u64 * steps / total_files.max(1) as u64)
+}
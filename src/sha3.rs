@@ -0,0 +1,230 @@
+use std::marker::PhantomData;
+
+use digest::generic_array::typenum::{U32, U64};
+use digest::generic_array::{ArrayLength, GenericArray};
+use digest::{FixedOutputDirty, Reset, Update};
+
+/// Round constants of the Keccak-f[1600] permutation.
+const ROUND_CONSTANTS: [u64; 24] = [
+    0x0000_0000_0000_0001,
+    0x0000_0000_0000_8082,
+    0x8000_0000_0000_808a,
+    0x8000_0000_8000_8000,
+    0x0000_0000_0000_808b,
+    0x0000_0000_8000_0001,
+    0x8000_0000_8000_8081,
+    0x8000_0000_0000_8009,
+    0x0000_0000_0000_008a,
+    0x0000_0000_0000_0088,
+    0x0000_0000_8000_8009,
+    0x0000_0000_8000_000a,
+    0x0000_0000_8000_808b,
+    0x8000_0000_0000_008b,
+    0x8000_0000_0000_8089,
+    0x8000_0000_0000_8003,
+    0x8000_0000_0000_8002,
+    0x8000_0000_0000_0080,
+    0x0000_0000_0000_800a,
+    0x8000_0000_8000_000a,
+    0x8000_0000_8000_8081,
+    0x8000_0000_0000_8080,
+    0x0000_0000_8000_0001,
+    0x8000_0000_8000_8008,
+];
+
+/// Rotation offsets of the lanes visited by the combined rho and pi steps.
+const ROTATIONS: [u32; 24] = [
+    1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+];
+
+/// Order in which the combined rho and pi steps visit the lanes.
+const PI_LANES: [usize; 24] = [
+    10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+];
+
+/// Size of the Keccak state, in bytes.
+const STATE_SIZE: usize = 200;
+
+/// Applies the Keccak-f[1600] permutation to the state.
+fn keccak_f(a: &mut [u64; 25]) {
+    for rc in &ROUND_CONSTANTS {
+        // Theta.
+        let mut c = [0u64; 5];
+        for x in 0..5 {
+            c[x] = a[x] ^ a[x + 5] ^ a[x + 10] ^ a[x + 15] ^ a[x + 20];
+        }
+        for x in 0..5 {
+            let d = c[(x + 4) % 5] ^ c[(x + 1) % 5].rotate_left(1);
+            for y in 0..5 {
+                a[y * 5 + x] ^= d;
+            }
+        }
+
+        // Rho and pi.
+        let mut last = a[1];
+        for (&lane, &rotation) in PI_LANES.iter().zip(ROTATIONS.iter()) {
+            let next = a[lane];
+            a[lane] = last.rotate_left(rotation);
+            last = next;
+        }
+
+        // Chi.
+        for y in 0..5 {
+            let mut row = [0u64; 5];
+            row.copy_from_slice(&a[y * 5..y * 5 + 5]);
+            for x in 0..5 {
+                a[y * 5 + x] = row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+            }
+        }
+
+        // Iota.
+        a[0] ^= rc;
+    }
+}
+
+/// A SHA-3 hash function with the digest length of `N` bytes.
+///
+/// The input is absorbed directly into the state, at the rate of
+/// `200 - 2 * N` bytes per permutation.
+#[derive(Clone)]
+pub struct Sha3<N: ArrayLength<u8>> {
+    /// The Keccak state.
+    state: [u64; 25],
+
+    /// Number of bytes absorbed into the state since the last permutation.
+    pos: usize,
+
+    /// Digest length marker.
+    output: PhantomData<N>,
+}
+
+/// The SHA3-256 hash function.
+pub type Sha3_256 = Sha3<U32>;
+
+/// The SHA3-512 hash function.
+pub type Sha3_512 = Sha3<U64>;
+
+impl<N: ArrayLength<u8>> Sha3<N> {
+    /// Number of input bytes absorbed per permutation.
+    const RATE: usize = STATE_SIZE - 2 * N::USIZE;
+
+    /// XORs a byte into the state at the given offset.
+    fn xor_byte(&mut self, offset: usize, byte: u8) {
+        self.state[offset / 8] ^= u64::from(byte) << (8 * (offset % 8));
+    }
+
+    /// Absorbs the input into the state, permuting it every `RATE` bytes.
+    fn absorb(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // Absorb whole lanes at once when aligned to them.
+            if self.pos.is_multiple_of(8) && data.len() >= 8 {
+                let mut lane = [0u8; 8];
+                lane.copy_from_slice(&data[..8]);
+                self.state[self.pos / 8] ^= u64::from_le_bytes(lane);
+                self.pos += 8;
+                data = &data[8..];
+            } else {
+                self.xor_byte(self.pos, data[0]);
+                self.pos += 1;
+                data = &data[1..];
+            }
+
+            if self.pos == Self::RATE {
+                keccak_f(&mut self.state);
+                self.pos = 0;
+            }
+        }
+    }
+}
+
+impl<N: ArrayLength<u8>> Default for Sha3<N> {
+    fn default() -> Self {
+        Sha3 {
+            state: [0; 25],
+            pos: 0,
+            output: PhantomData,
+        }
+    }
+}
+
+impl<N: ArrayLength<u8>> Update for Sha3<N> {
+    fn update(&mut self, input: impl AsRef<[u8]>) {
+        self.absorb(input.as_ref());
+    }
+}
+
+impl<N: ArrayLength<u8>> FixedOutputDirty for Sha3<N> {
+    type OutputSize = N;
+
+    fn finalize_into_dirty(&mut self, out: &mut GenericArray<u8, N>) {
+        // Pad with the SHA-3 domain separation bits and the final bit.
+        self.xor_byte(self.pos, 0x06);
+        self.xor_byte(Self::RATE - 1, 0x80);
+        keccak_f(&mut self.state);
+
+        // The digest is always shorter than the rate, so a single squeeze
+        // is enough.
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = (self.state[i / 8] >> (8 * (i % 8))) as u8;
+        }
+    }
+}
+
+impl<N: ArrayLength<u8>> Reset for Sha3<N> {
+    fn reset(&mut self) {
+        *self = Sha3::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::bytes_to_hex_string;
+    use digest::Digest;
+
+    /// Known-answer vectors: the input and its SHA3-256 and SHA3-512 digests.
+    const VECTORS: &[(&[u8], &str, &str)] = &[
+        (
+            b"",
+            "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a",
+            "a69f73cca23a9ac5c8b567dc185a756e97c982164fe25859e0d1dcc1475c80a6\
+             15b2123af1f5f94c11e3e9402c3ac558f500199d95b6d3e301758586281dcd26",
+        ),
+        (
+            b"abc",
+            "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532",
+            "b751850b1a57168a5693cd924b6b096e08f621827444f70d884f5d0240d2712e\
+             10e116e9192af3c91a7ec57647e3934057340b4cf408d5a56592f8274eec53f0",
+        ),
+        (
+            &[0xa3; 200],
+            "79f38adec5c20307a98ef76e8324afbfd46cfd81b22e3973c65fa1bd9de31787",
+            "e76dfad22084a8b1467fcf2ffa58361bec7628edf5f3fdc0e4805dc48caeeca8\
+             1b7c13c30adf52a3659584739a2df46be589c51ca1a4a8416df6545a1ce8ba00",
+        ),
+    ];
+
+    #[test]
+    fn matches_known_answers() {
+        for (input, sha3_256, sha3_512) in VECTORS {
+            assert_eq!(bytes_to_hex_string(&Sha3_256::digest(input)), *sha3_256);
+            assert_eq!(bytes_to_hex_string(&Sha3_512::digest(input)), *sha3_512);
+        }
+    }
+
+    #[test]
+    fn digest_does_not_depend_on_chunking() {
+        let input: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let expected = Sha3_256::digest(&input);
+
+        for chunk in &[1, 3, 8, 13, 136, 137] {
+            let mut hasher = Sha3_256::new();
+            for part in input.chunks(*chunk) {
+                Digest::update(&mut hasher, part);
+            }
+            assert_eq!(hasher.finalize_reset(), expected);
+            Digest::update(&mut hasher, &input);
+            assert_eq!(hasher.finalize(), expected);
+        }
+    }
+}
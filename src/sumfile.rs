@@ -0,0 +1,308 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{BufRead, Error as IoError};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::hash::{HashAlgorithm, HashValue};
+use crate::util;
+
+/// An error returned when a checksum manifest cannot be read.
+#[derive(Debug)]
+pub enum SumfileError {
+    /// The underlying reader failed.
+    Io(IoError),
+
+    /// A line of the manifest is malformed (line number).
+    InvalidLine(usize),
+
+    /// The hash algorithm of a line is not supported or cannot be told from
+    /// the digest length (line number).
+    UnknownAlgorithm(usize),
+}
+
+impl Display for SumfileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match &self {
+            SumfileError::Io(err) => write!(f, "Manifest I/O error: {}", err),
+            SumfileError::InvalidLine(n) => write!(f, "Invalid manifest line: {}", n),
+            SumfileError::UnknownAlgorithm(n) => write!(
+                f,
+                "Unknown or unsupported hash algorithm of manifest line {}, specify it with \
+                 --manifest-algorithm",
+                n
+            ),
+        }
+    }
+}
+
+impl Error for SumfileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self {
+            SumfileError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<IoError> for SumfileError {
+    fn from(err: IoError) -> Self {
+        SumfileError::Io(err)
+    }
+}
+
+/// A file hash listed in a checksum manifest.
+#[derive(Debug)]
+pub struct SumfileEntry {
+    /// Path of the file relative to the manifest root.
+    pub path: PathBuf,
+
+    /// Hash algorithm of the value.
+    pub algorithm: HashAlgorithm,
+
+    /// Hash value of the file.
+    pub value: HashValue,
+}
+
+/// Reads a checksum manifest in the format of the coreutils `*sum` tools.
+///
+/// Both the default format (`<hex>  <path>`, with `*` marking binary mode)
+/// and the BSD-style tagged format (`<ALG> (<path>) = <hex>`) are accepted,
+/// including the backslash-escaped paths. The algorithm of untagged lines is
+/// the given one, or is told from the digest length if unambiguous (MD5 and
+/// SHA-1). Empty lines and `#` comments are ignored.
+pub fn read_sumfile<R: BufRead>(
+    reader: R,
+    algorithm: Option<HashAlgorithm>,
+) -> Result<Vec<SumfileEntry>, SumfileError> {
+    let mut entries = Vec::new();
+
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (line, escaped) = match line.strip_prefix('\\') {
+            Some(rest) => (rest, true),
+            None => (line.as_str(), false),
+        };
+        let (tag, path, hex) = parse_line(line).ok_or(SumfileError::InvalidLine(n + 1))?;
+
+        let algorithm = match tag {
+            Some(tag) => HashAlgorithm::from_str(tag).ok(),
+            None => algorithm.or_else(|| algorithm_by_length(hex.len())),
+        }
+        .ok_or(SumfileError::UnknownAlgorithm(n + 1))?;
+
+        let value = HashValue::try_from(hex)
+            .ok()
+            .filter(|v| v.as_bytes().len() == algorithm.digest_len())
+            .ok_or(SumfileError::InvalidLine(n + 1))?;
+
+        let path = if escaped {
+            unescape(path).ok_or(SumfileError::InvalidLine(n + 1))?
+        } else {
+            path.to_string()
+        };
+        let path = util::normalize_path(&path)
+            .filter(|p| !p.as_os_str().is_empty())
+            .ok_or(SumfileError::InvalidLine(n + 1))?;
+
+        entries.push(SumfileEntry {
+            path,
+            algorithm,
+            value,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Parses a manifest line into the optional algorithm tag, the path, and the
+/// hex digest.
+fn parse_line(line: &str) -> Option<(Option<&str>, &str, &str)> {
+    // Tagged lines: `ALG (path) = hex`.
+    if let Some(open) = line.find(" (") {
+        if let Some(close) = line.rfind(") = ") {
+            if close > open {
+                let tag = &line[..open];
+                if !tag.is_empty() && !tag.contains(char::is_whitespace) {
+                    return Some((Some(tag), &line[open + 2..close], &line[close + 4..]));
+                }
+            }
+        }
+    }
+
+    // Untagged lines: `hex  path` or `hex *path`.
+    let space = line.find(' ')?;
+    let (hex, rest) = (&line[..space], &line[space + 1..]);
+    let path = rest.strip_prefix(|c| c == ' ' || c == '*')?;
+    if hex.is_empty() || path.is_empty() {
+        return None;
+    }
+
+    Some((None, path, hex))
+}
+
+/// Returns the algorithm that produces hex digests of the given length, if
+/// no other commonly used one does.
+fn algorithm_by_length(len: usize) -> Option<HashAlgorithm> {
+    // Longer digests are ambiguous: SHA-256 and SHA3-256, for instance,
+    // produce digests of the same length.
+    match len {
+        32 => Some(HashAlgorithm::Md5),
+        40 => Some(HashAlgorithm::Sha1),
+        _ => None,
+    }
+}
+
+/// Reverts the escaping of backslashes and newlines in a path.
+fn unescape(path: &str) -> Option<String> {
+    let mut ret = String::with_capacity(path.len());
+    let mut chars = path.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next()? {
+                '\\' => ret.push('\\'),
+                'n' => ret.push('\n'),
+                _ => return None,
+            }
+        } else {
+            ret.push(c);
+        }
+    }
+
+    Some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MD5: &str = "764efa883dda1e11db47671c4a3bbd9e";
+    const SHA1: &str = "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12";
+
+    /// Reads the manifest and returns the paths, algorithms and hex values.
+    fn read(
+        manifest: &str,
+        algorithm: Option<HashAlgorithm>,
+    ) -> Result<Vec<(PathBuf, HashAlgorithm, String)>, SumfileError> {
+        let entries = read_sumfile(manifest.as_bytes(), algorithm)?;
+        Ok(entries
+            .into_iter()
+            .map(|e| (e.path, e.algorithm, e.value.to_hex()))
+            .collect())
+    }
+
+    #[test]
+    fn parses_lines() {
+        assert_eq!(parse_line("abc  a b"), Some((None, "a b", "abc")));
+        assert_eq!(parse_line("abc *a"), Some((None, "a", "abc")));
+        assert_eq!(parse_line("MD5 (a) = abc"), Some((Some("MD5"), "a", "abc")));
+        assert_eq!(
+            parse_line("MD5 (a (1)) = abc"),
+            Some((Some("MD5"), "a (1)", "abc"))
+        );
+        assert_eq!(
+            parse_line("MD5 (a) = b) = abc"),
+            Some((Some("MD5"), "a) = b", "abc"))
+        );
+        assert_eq!(parse_line("abc"), None);
+        assert_eq!(parse_line("abc a"), None);
+        assert_eq!(parse_line("abc  "), None);
+        assert_eq!(parse_line(" *a"), None);
+    }
+
+    #[test]
+    fn unescapes_paths() {
+        assert_eq!(unescape("a\\\\b").as_deref(), Some("a\\b"));
+        assert_eq!(unescape("a\\nb").as_deref(), Some("a\nb"));
+        assert_eq!(unescape("plain").as_deref(), Some("plain"));
+        assert_eq!(unescape("a\\tb"), None);
+        assert_eq!(unescape("a\\"), None);
+    }
+
+    #[test]
+    fn reads_untagged_and_tagged_lines() {
+        let manifest = format!(
+            "# comment\n\n{md5}  a.txt\n{sha1} *sub/b.txt\nMD5 (c (1).txt) = {md5}\n\
+             SHA1 (./d.txt) = {sha1}\n\\{md5}  e\\\\f\\ng\n",
+            md5 = MD5,
+            sha1 = SHA1
+        );
+        let md5 = (HashAlgorithm::Md5, MD5.to_string());
+        let sha1 = (HashAlgorithm::Sha1, SHA1.to_string());
+        let expected = vec![
+            ("a.txt", &md5),
+            ("sub/b.txt", &sha1),
+            ("c (1).txt", &md5),
+            ("d.txt", &sha1),
+            ("e\\f\ng", &md5),
+        ];
+
+        let entries = read(&manifest, None).unwrap();
+        assert_eq!(entries.len(), expected.len());
+        for (entry, (path, (algorithm, hex))) in entries.iter().zip(expected) {
+            assert_eq!(entry, &(PathBuf::from(path), *algorithm, hex.clone()));
+        }
+    }
+
+    #[test]
+    fn tells_the_algorithm_of_untagged_lines() {
+        let hex = "0".repeat(64);
+        let line = format!("{}  a\n", hex);
+        assert!(matches!(
+            read(&line, None),
+            Err(SumfileError::UnknownAlgorithm(1))
+        ));
+        assert_eq!(
+            read(&line, Some(HashAlgorithm::Sha3_256)).unwrap(),
+            vec![(PathBuf::from("a"), HashAlgorithm::Sha3_256, hex)]
+        );
+
+        // The given algorithm applies to the untagged lines only.
+        let tagged = format!("MD5 (a) = {}\n", MD5);
+        assert_eq!(
+            read(&tagged, Some(HashAlgorithm::Sha1)).unwrap()[0].1,
+            HashAlgorithm::Md5
+        );
+        assert!(matches!(
+            read(&format!("CRC32 (a) = {}\n", MD5), None),
+            Err(SumfileError::UnknownAlgorithm(1))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        let lines = [
+            // A digest length that doesn't match the algorithm.
+            format!("{}  a", SHA1),
+            format!("MD5 (a) = {}", SHA1),
+            format!("{}  a", &MD5[..30]),
+            // Not a hex digest.
+            format!("{}x  a", &MD5[..31]),
+            // Paths outside of the root.
+            format!("{}  ../a", MD5),
+            format!("{}  a/../../b", MD5),
+            format!("{}  /a", MD5),
+            format!("{}  .", MD5),
+            // Invalid escapes and missing paths.
+            format!("\\{}  a\\tb", MD5),
+            MD5.to_string(),
+        ];
+        for line in &lines {
+            let manifest = format!("{}  ok\n{}\n", MD5, line);
+            assert!(
+                matches!(
+                    read(&manifest, Some(HashAlgorithm::Md5)),
+                    Err(SumfileError::InvalidLine(2))
+                ),
+                "{}",
+                line
+            );
+        }
+    }
+}
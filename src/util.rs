@@ -1,7 +1,17 @@
+use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::error::Error;
+use std::ffi::OsString;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use std::io::Error as IoError;
-use std::path::{Path, PathBuf};
+use std::fs;
+use std::io::{self, BufRead, Error as IoError, Result as IoResult, Write};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Suffix appended to the inventory file name to produce the name of the
+/// temporary file used for atomic writes.
+pub const TEMP_SUFFIX: &str = ".inventorize-tmp";
 
 /// Produces a new `Err(FileError)` with the given `std::io::Error` and
 /// the file path.
@@ -31,6 +41,11 @@ impl FileError {
             io_err,
         }
     }
+
+    /// Returns the path to the file that caused the error.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 }
 
 impl Display for FileError {
@@ -69,26 +84,17 @@ fn char_to_nibble(c: u8) -> Option<u8> {
 
 /// Converts a byte slice to the hexadecimal string representation.
 pub fn bytes_to_hex_string(b: &[u8]) -> String {
-    // Allocate an uninitialized vector of bytes.
     // The length of the returned string is exactly twice the length of the
     // input slice, since each byte is represented by two characters.
-    let mut ret = Vec::<u8>::with_capacity(b.len() * 2);
-    let mut p = ret.as_mut_ptr();
+    let mut ret = String::with_capacity(b.len() * 2);
 
-    unsafe {
-        // Convert bytes to their hex string representation, nibble by nibble.
-        b.iter().for_each(|x| {
-            p.write(nibble_to_char(x >> 4));
-            p = p.add(1);
-            p.write(nibble_to_char(x & 0x0f));
-            p = p.add(1);
-        });
-
-        ret.set_len(b.len() * 2);
-
-        // `ret` only contains ASCII characters and is safe to convert to UTF-8.
-        String::from_utf8_unchecked(ret)
+    // Convert bytes to their hex string representation, nibble by nibble.
+    for x in b {
+        ret.push(char::from(nibble_to_char(x >> 4)));
+        ret.push(char::from(nibble_to_char(x & 0x0f)));
     }
+
+    ret
 }
 
 /// Converts a string of hexadecimal values to bytes.
@@ -98,40 +104,91 @@ pub fn hex_string_to_bytes(s: &str) -> Option<Box<[u8]>> {
     // There is no need to check that the string is pure ASCII, since
     // `char_to_nibble()` will return `None` if it stumbles upon a character
     // that it cannot decode, including non-ASCII UTF-8 code points.
-    let len = s.len();
-    if len % 2 != 0 {
+    if !s.len().is_multiple_of(2) {
         return None;
     }
 
-    // Allocate an uninitialized vector of bytes.
-    let mut ret = Vec::<u8>::with_capacity(len / 2);
-    let mut p = ret.as_mut_ptr();
-    let mut it = s.bytes();
-
-    unsafe {
-        // Read characters of the input string and convert them to nibbles,
-        // then produce bytes from each pair of nibbles.
-        loop {
-            // Read the high-order nibble character.
-            let hi = it.next();
-            if let Some(hi) = hi {
-                // The length of the input string is guaranteed to be even,
-                // so it can be safely assumed that the low-order nibble
-                // character is present.
-                // Immediately return `None` if a nibble fails to decode.
-                let lo = it.next().unwrap();
-                p.write(char_to_nibble(hi)? << 4 | char_to_nibble(lo)?);
-                p = p.add(1);
+    // Produce bytes from each pair of nibble characters. Immediately return
+    // `None` if a nibble fails to decode.
+    s.as_bytes()
+        .chunks_exact(2)
+        .map(|pair| Some(char_to_nibble(pair[0])? << 4 | char_to_nibble(pair[1])?))
+        .collect()
+}
+
+/// Characters of the standard base64 alphabet.
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Converts a byte slice to the padded base64 string representation, using
+/// the standard alphabet.
+pub fn bytes_to_base64_string(b: &[u8]) -> String {
+    let mut ret = String::with_capacity(b.len().div_ceil(3) * 4);
+
+    // Each group of three bytes is represented by four characters of six
+    // bits each. The last group is padded to four characters.
+    for group in b.chunks(3) {
+        let n = group
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &x)| n | u32::from(x) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= group.len() {
+                ret.push(char::from(
+                    BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize],
+                ));
             } else {
-                // End of the string has been reached.
-                break;
+                ret.push('=');
             }
         }
+    }
+
+    ret
+}
+
+/// Converts a padded base64 string using the standard alphabet to bytes.
+pub fn base64_string_to_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut ret = Vec::with_capacity(s.len() / 4 * 3);
+    for group in s.as_bytes().chunks_exact(4) {
+        // Only the last one or two characters of a group can be padding.
+        let padding = group.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+
+        let mut n = 0u32;
+        for (i, &c) in group[..4 - padding].iter().enumerate() {
+            let sextet = BASE64_ALPHABET.iter().position(|&a| a == c)? as u32;
+            n |= sextet << (18 - 6 * i);
+        }
+        ret.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+
+    Some(ret)
+}
 
-        ret.set_len(len / 2);
+/// Asks the user to confirm an operation on the terminal.
+///
+/// Returns `Ok(None)` without asking if the standard input is not a terminal,
+/// so that scripts do not hang waiting for an answer.
+pub fn ask_confirmation(prompt: &str) -> IoResult<Option<bool>> {
+    if !atty::is(atty::Stream::Stdin) {
+        return Ok(None);
     }
 
-    Some(ret.into_boxed_slice())
+    eprint!("{} [y/N] ", prompt);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim().to_lowercase();
+
+    Ok(Some(answer == "y" || answer == "yes"))
 }
 
 /// Checks if a file specified by path is considered hidden.
@@ -144,3 +201,620 @@ pub fn is_hidden<P: AsRef<Path>>(path: P) -> bool {
         .and_then(|p| p.to_str())
         .map_or(false, |s| s.starts_with("."))
 }
+
+/// Normalizes a relative path by collapsing `.` components, redundant
+/// separators and trailing slashes.
+///
+/// Returns `None` if the path is absolute or contains `..` components, which
+/// could escape the repository.
+pub fn normalize_path<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+    let mut ret = PathBuf::new();
+
+    for component in path.as_ref().components() {
+        match component {
+            Component::Normal(c) => ret.push(c),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    Some(ret)
+}
+
+/// Returns the path that leads from the base directory to the specified
+/// path, using `..` components to leave the base directory where necessary.
+///
+/// Both paths must be absolute and canonical.
+pub fn relative_path<P: AsRef<Path>, B: AsRef<Path>>(path: P, base: B) -> PathBuf {
+    let mut path = path.as_ref().components().peekable();
+    let mut base = base.as_ref().components().peekable();
+
+    // Skip the common prefix of the paths.
+    while let (Some(p), Some(b)) = (path.peek(), base.peek()) {
+        if p != b {
+            break;
+        }
+        path.next();
+        base.next();
+    }
+
+    let mut ret: PathBuf = base.map(|_| Component::ParentDir).collect();
+    ret.extend(path);
+    if ret.as_os_str().is_empty() {
+        ret.push(Component::CurDir);
+    }
+    ret
+}
+
+/// Resolves the requested number of worker threads.
+///
+/// Zero requests one thread per available CPU. The number is clamped to the
+/// maximum, if any, and is never less than one.
+pub fn resolve_threads(requested: usize, max: Option<usize>) -> usize {
+    let threads = if requested == 0 {
+        thread::available_parallelism().map_or(1, |n| n.get())
+    } else {
+        requested
+    };
+
+    max.map_or(threads, |max| threads.min(max)).max(1)
+}
+
+/// Number of file descriptors left for other uses than hashing the
+/// repository files, such as the standard streams, the inventory, and logs.
+const RESERVED_FILES: usize = 16;
+
+/// Returns the default maximum number of repository files open at once: the
+/// soft limit of open file descriptors, less a reserve for other uses.
+///
+/// Returns `None` if the limit is not known or is unlimited.
+#[cfg(unix)]
+pub fn default_max_open_files() -> Option<usize> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0
+        || limit.rlim_cur == libc::RLIM_INFINITY
+    {
+        return None;
+    }
+
+    let soft = usize::try_from(limit.rlim_cur).ok()?;
+    Some(soft.saturating_sub(RESERVED_FILES).max(1))
+}
+
+/// Returns the number of bytes available to unprivileged users on the
+/// filesystem of the given path, or `None` if it cannot be queried.
+#[cfg(unix)]
+pub fn available_space<P: AsRef<Path>>(path: P) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_ref().as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+/// Returns the number of bytes available on the filesystem of the path.
+///
+/// Returns `None`, since the free space is not known on this platform.
+#[cfg(not(unix))]
+pub fn available_space<P: AsRef<Path>>(_path: P) -> Option<u64> {
+    None
+}
+
+/// Returns the default maximum number of repository files open at once.
+///
+/// Returns `None`, since the limit is not known on this platform.
+#[cfg(not(unix))]
+pub fn default_max_open_files() -> Option<usize> {
+    None
+}
+
+/// Quotes a CSV field if it contains a separator, a quote, or a line break,
+/// doubling the quotes inside it.
+pub fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Names of the version control system metadata directories.
+pub const VCS_DIRS: &[&str] = &[".bzr", ".git", ".hg", ".svn", "_darcs", "CVS"];
+
+/// Checks if any of the path components is a version control system metadata
+/// directory.
+pub fn is_in_vcs_dir<P: AsRef<Path>>(path: P) -> bool {
+    let mut components = path.as_ref().components();
+    // The last component is the file itself.
+    components.next_back();
+    components.any(|c| VCS_DIRS.iter().any(|d| c.as_os_str() == *d))
+}
+
+/// Returns the path to the temporary file used to write the specified file
+/// atomically.
+pub fn temp_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let mut file_name = path
+        .file_name()
+        .map_or_else(OsString::new, |n| n.to_os_string());
+    file_name.push(TEMP_SUFFIX);
+    path.with_file_name(file_name)
+}
+
+/// Checks if a file specified by path is an inventorize temporary file.
+pub fn is_temp_file<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .file_name()
+        .and_then(|p| p.to_str())
+        .is_some_and(|s| s.ends_with(TEMP_SUFFIX))
+}
+
+/// Returns the name the snapshots of the inventory start with: the inventory
+/// file name without the extension.
+fn snapshot_stem(inventory: &Path) -> Cow<'_, str> {
+    inventory
+        .file_stem()
+        .map_or(Cow::Borrowed("inventory"), |s| s.to_string_lossy())
+}
+
+/// Returns the time of the snapshot with the given file name, or `None` if
+/// the name is not the name of a snapshot with the stem and the extension.
+fn snapshot_time(name: &str, stem: &str, extension: &str) -> Option<SystemTime> {
+    let time = name
+        .strip_prefix(stem)?
+        .strip_prefix('-')?
+        .strip_suffix(extension)?
+        .strip_suffix('.')?;
+    humantime::parse_rfc3339(time).ok()
+}
+
+/// Copies the inventory file to a new snapshot in the directory, creating
+/// the directory if needed, and returns the snapshot path.
+///
+/// The snapshot is named `<stem>-<time>.<extension>`, with the inventory
+/// file name without the extension as the stem and the current time in
+/// RFC 3339 format, and is written atomically.
+pub fn write_snapshot(inventory: &Path, dir: &Path, extension: &str) -> Result<PathBuf, FileError> {
+    fs::create_dir_all(dir).map_err(|e| FileError::new(dir, e))?;
+    let name = format!(
+        "{}-{}.{}",
+        snapshot_stem(inventory),
+        humantime::format_rfc3339_millis(SystemTime::now()),
+        extension
+    );
+    let path = dir.join(name);
+
+    let temp_path = temp_path(&path);
+    let result = fs::copy(inventory, &temp_path)
+        .map_err(|e| FileError::new(inventory, e))
+        .and_then(|_| fs::rename(&temp_path, &path).map_err(|e| FileError::new(&path, e)));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result.map(|_| path)
+}
+
+/// Removes the oldest snapshots of the inventory with the extension from the
+/// directory, keeping the given number of the newest ones, and returns the
+/// paths of the removed snapshots.
+///
+/// Only regular files named exactly like the snapshots of the inventory
+/// written by `write_snapshot()` are considered, so other files in the
+/// directory, including the snapshots of other inventories, are never
+/// removed.
+pub fn prune_snapshots(
+    inventory: &Path,
+    dir: &Path,
+    extension: &str,
+    keep: usize,
+) -> Result<Vec<PathBuf>, FileError> {
+    let stem = snapshot_stem(inventory);
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| FileError::new(dir, e))? {
+        let entry = entry.map_err(|e| FileError::new(dir, e))?;
+        let is_file = entry
+            .file_type()
+            .map_err(|e| FileError::new(entry.path(), e))?
+            .is_file();
+        let time = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| snapshot_time(name, &stem, extension));
+        if let (true, Some(time)) = (is_file, time) {
+            snapshots.push((time, entry.path()));
+        }
+    }
+
+    // Newest first.
+    snapshots.sort_by(|a, b| b.cmp(a));
+    let mut removed = Vec::new();
+    for (_, path) in snapshots.into_iter().skip(keep) {
+        fs::remove_file(&path).map_err(|e| FileError::new(&path, e))?;
+        removed.push(path);
+    }
+
+    Ok(removed)
+}
+
+/// Parses a size in bytes with an optional binary unit suffix.
+///
+/// Supported suffixes are `K`, `M`, `G`, and `T` (case-insensitive), which
+/// denote powers of 1024.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (digits, multiplier) = match s.chars().last()?.to_ascii_uppercase() {
+        'K' => (&s[..s.len() - 1], 1u64 << 10),
+        'M' => (&s[..s.len() - 1], 1u64 << 20),
+        'G' => (&s[..s.len() - 1], 1u64 << 30),
+        'T' => (&s[..s.len() - 1], 1u64 << 40),
+        _ => (s, 1),
+    };
+
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Parses a point in time, returning it in nanoseconds since the Unix epoch.
+///
+/// Either an RFC 3339 timestamp (e.g. `2024-05-01T12:00:00Z`,
+/// `2024-05-01T14:00:00+02:00`, or `2024-05-01` for midnight UTC) is
+/// accepted, or a duration before the current time (e.g. `7d` or `24h`).
+/// Timestamps without a UTC offset are taken to be in UTC.
+pub fn parse_timestamp(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let time = if s.len() == 10 && s.as_bytes()[4] == b'-' {
+        humantime::parse_rfc3339_weak(&format!("{}T00:00:00Z", s)).ok()?
+    } else if let Ok(time) = humantime::parse_rfc3339_weak(s) {
+        time
+    } else if let Some((local, offset)) = split_utc_offset(s) {
+        // The parser only supports UTC, so the local time is parsed as if it
+        // were in UTC and shifted by the offset.
+        let local = humantime::parse_rfc3339_weak(local).ok()?;
+        let shift = Duration::from_secs(offset.unsigned_abs());
+        if offset >= 0 {
+            local.checked_sub(shift)?
+        } else {
+            local.checked_add(shift)?
+        }
+    } else {
+        SystemTime::now().checked_sub(humantime::parse_duration(s).ok()?)?
+    };
+
+    u64::try_from(time.duration_since(UNIX_EPOCH).ok()?.as_nanos()).ok()
+}
+
+/// Splits the UTC offset (e.g. `+02:00`) off an RFC 3339 timestamp,
+/// returning the local time and the offset in seconds east of UTC.
+fn split_utc_offset(s: &str) -> Option<(&str, i64)> {
+    let at = s.len().checked_sub(6).filter(|&n| s.is_char_boundary(n))?;
+    let (local, offset) = s.split_at(at);
+    let b = offset.as_bytes();
+    let sign = match b[0] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    if b[3] != b':' || ![b[1], b[2], b[4], b[5]].iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    let hours = offset[1..3].parse::<i64>().ok()?;
+    let minutes = offset[4..6].parse::<i64>().ok()?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+
+    Some((local, sign * (hours * 3600 + minutes * 60)))
+}
+
+/// Returns the inode number of the file, if the platform exposes it.
+#[cfg(unix)]
+pub fn inode<P: AsRef<Path>>(path: P) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    path.as_ref().metadata().ok().map(|m| m.ino())
+}
+
+/// Returns the inode number of the file, if the platform exposes it.
+#[cfg(not(unix))]
+pub fn inode<P: AsRef<Path>>(_path: P) -> Option<u64> {
+    None
+}
+
+/// Reads an extended attribute of the file, following symbolic links.
+///
+/// Returns `None` if the attribute is not set or cannot be read, or if the
+/// platform does not support extended attributes.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn xattr<P: AsRef<Path>>(path: P, name: &str) -> Option<Vec<u8>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_ref().as_os_str().as_bytes()).ok()?;
+    let name = CString::new(name).ok()?;
+
+    #[cfg(target_os = "linux")]
+    let getxattr = |buf: *mut libc::c_void, size| unsafe {
+        libc::getxattr(path.as_ptr(), name.as_ptr(), buf, size)
+    };
+    #[cfg(target_os = "macos")]
+    let getxattr = |buf: *mut libc::c_void, size| unsafe {
+        libc::getxattr(path.as_ptr(), name.as_ptr(), buf, size, 0, 0)
+    };
+
+    // Query the size of the value first. If the value grows between the
+    // calls, the second call fails and the attribute is treated as missing.
+    let len = getxattr(std::ptr::null_mut(), 0);
+    if len < 0 {
+        return None;
+    }
+
+    let mut buf = vec![0u8; len as usize];
+    let len = getxattr(buf.as_mut_ptr() as *mut libc::c_void, buf.len());
+    if len < 0 {
+        return None;
+    }
+
+    buf.truncate(len as usize);
+    Some(buf)
+}
+
+/// Reads an extended attribute of the file, following symbolic links.
+///
+/// Returns `None` if the attribute is not set or cannot be read, or if the
+/// platform does not support extended attributes.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn xattr<P: AsRef<Path>>(_path: P, _name: &str) -> Option<Vec<u8>> {
+    None
+}
+
+/// A temporary directory for the tests, removed with its contents when
+/// dropped.
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    /// Creates a new empty temporary directory with a unique name.
+    pub fn new() -> Self {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "inventorize-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        let path = std::env::temp_dir().join(name);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    /// Returns the path to the directory.
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Creates the files with the given contents under the directory, along
+/// with their parent directories.
+#[cfg(test)]
+pub fn create_files(dir: &Path, files: &[(&str, &str)]) {
+    for (path, contents) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_conversion_round_trips() {
+        let all: Vec<u8> = (0..=255).collect();
+        let hex = bytes_to_hex_string(&all);
+        assert_eq!(hex.len(), 512);
+        assert!(hex.starts_with("000102"));
+        assert!(hex.ends_with("fdfeff"));
+        assert_eq!(&hex_string_to_bytes(&hex).unwrap()[..], &all[..]);
+
+        // Pseudo-random inputs of every length up to 64 bytes.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for len in 0..64 {
+            let bytes: Vec<u8> = (0..len)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            let hex = bytes_to_hex_string(&bytes);
+            assert_eq!(&hex_string_to_bytes(&hex).unwrap()[..], &bytes[..]);
+            let upper = hex.to_uppercase();
+            assert_eq!(&hex_string_to_bytes(&upper).unwrap()[..], &bytes[..]);
+        }
+    }
+
+    #[test]
+    fn hex_conversion_rejects_invalid_strings() {
+        assert_eq!(hex_string_to_bytes("").as_deref(), Some(&[][..]));
+        assert_eq!(
+            hex_string_to_bytes("0aF0").as_deref(),
+            Some(&[0x0a, 0xf0][..])
+        );
+        assert_eq!(hex_string_to_bytes("abc"), None);
+        assert_eq!(hex_string_to_bytes("0g"), None);
+        assert_eq!(hex_string_to_bytes("0 "), None);
+        assert_eq!(hex_string_to_bytes("é00"), None);
+        assert_eq!(hex_string_to_bytes("0é0"), None);
+    }
+
+    #[test]
+    fn base64_conversion_round_trips() {
+        let cases: &[(&[u8], &str)] = &[
+            (b"", ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"\xff\xfe\x00", "//4A"),
+        ];
+        for (bytes, base64) in cases {
+            assert_eq!(bytes_to_base64_string(bytes), *base64);
+            assert_eq!(base64_string_to_bytes(base64).as_deref(), Some(*bytes));
+        }
+
+        for invalid in &["Zg=", "Z===", "Zg!=", "Zm9v\n"] {
+            assert_eq!(base64_string_to_bytes(invalid), None);
+        }
+    }
+
+    #[test]
+    fn quotes_csv_fields_if_needed() {
+        assert_eq!(csv_field("sub/a.txt"), "sub/a.txt");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("cr\r"), "\"cr\r\"");
+    }
+
+    #[test]
+    fn prunes_only_the_snapshots_of_the_inventory() {
+        let dir = TempDir::new();
+        let photos = dir.path().join("photos.json");
+        let music = dir.path().join("music.json");
+        create_files(
+            dir.path(),
+            &[
+                ("photos.json", "{}"),
+                ("music.json", "{}"),
+                ("music-2020-01-01T00:00:00.000Z.json", "{}"),
+                ("photos-2020-01-01T00:00:00.000Z.json", "{}"),
+                ("photos-2020-01-02T00:00:00.000Z.json", "{}"),
+                ("photos-2020-01-03T00:00:00.000Z.bin", "{}"),
+                ("photos-archive-2020-01-01T00:00:00.000Z.json", "{}"),
+                ("photos-notes.json", "{}"),
+            ],
+        );
+
+        let snapshot = write_snapshot(&photos, dir.path(), "json").unwrap();
+        let name = snapshot.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("photos-") && name.ends_with(".json"));
+        assert!(snapshot_time(name, "photos", "json").is_some());
+
+        let mut removed = prune_snapshots(&photos, dir.path(), "json", 1).unwrap();
+        removed.sort();
+        assert_eq!(
+            removed,
+            [
+                dir.path().join("photos-2020-01-01T00:00:00.000Z.json"),
+                dir.path().join("photos-2020-01-02T00:00:00.000Z.json"),
+            ]
+        );
+        assert!(snapshot.exists());
+
+        // The snapshots of the other inventory are left alone.
+        assert!(prune_snapshots(&music, dir.path(), "json", 1)
+            .unwrap()
+            .is_empty());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 7);
+    }
+
+    #[test]
+    fn resolves_thread_counts() {
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+        assert_eq!(resolve_threads(0, None), cpus);
+        assert_eq!(resolve_threads(0, Some(1)), 1);
+        assert_eq!(resolve_threads(3, None), 3);
+        assert_eq!(resolve_threads(8, Some(2)), 2);
+        assert_eq!(resolve_threads(2, Some(8)), 2);
+        assert_eq!(resolve_threads(1, Some(0)), 1);
+    }
+
+    #[test]
+    fn normalizes_relative_paths() {
+        let normalize = |p: &str| normalize_path(p).map(|p| p.to_str().unwrap().to_owned());
+        assert_eq!(normalize("./a").as_deref(), Some("a"));
+        assert_eq!(normalize("a//b").as_deref(), Some("a/b"));
+        assert_eq!(normalize("a/./b/").as_deref(), Some("a/b"));
+        assert_eq!(normalize(".").as_deref(), Some(""));
+        assert_eq!(normalize("a/../b"), None);
+        assert_eq!(normalize(".."), None);
+        assert_eq!(normalize("/a"), None);
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        let at = |s| parse_timestamp(s).map(|ns| ns / 1_000_000_000);
+        assert_eq!(at("2024-05-01"), Some(1_714_521_600));
+        assert_eq!(at("2024-05-01T12:00:00Z"), Some(1_714_564_800));
+        assert_eq!(at(" 2024-05-01 12:00:00 "), Some(1_714_564_800));
+        assert_eq!(at("2024-05-01T14:00:00+02:00"), Some(1_714_564_800));
+        assert_eq!(at("2024-05-01T07:30:00-04:30"), Some(1_714_564_800));
+        assert_eq!(
+            parse_timestamp("2024-05-01T12:00:00.5Z"),
+            Some(1_714_564_800_500_000_000)
+        );
+
+        // Durations are taken before the current time.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        for &(s, secs) in &[("7d", 7 * 86400), ("24h", 86400), ("1h 30m", 5400)] {
+            let ago = now - at(s).unwrap();
+            assert!((secs..secs + 60).contains(&ago), "{}: {}", s, ago);
+        }
+
+        for s in &[
+            "",
+            "yesterday",
+            "2024-13-01",
+            "2024-05-01T25:00:00Z",
+            "2024-05-01T12:00:00+2:00",
+            "2024-05-01T12:00:00+24:00",
+            "2024-05-01T12:00:00+02:00Z",
+            "-7d",
+        ] {
+            assert_eq!(parse_timestamp(s), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("0"), Some(0));
+        assert_eq!(parse_size("1234"), Some(1234));
+        assert_eq!(parse_size(" 10K "), Some(10 << 10));
+        assert_eq!(parse_size("3m"), Some(3 << 20));
+        assert_eq!(parse_size("2G"), Some(2 << 30));
+        assert_eq!(parse_size("1t"), Some(1 << 40));
+        assert_eq!(parse_size("16777215T"), Some(u64::MAX - (1 << 40) + 1));
+
+        // Overflows, with and without a suffix.
+        assert_eq!(parse_size("16777216T"), None);
+        assert_eq!(parse_size("18446744073709551616"), None);
+
+        for s in &["", " ", "K", "m", "-1", "1.5G", "1KB", "K1", "1 K", "0x10"] {
+            assert_eq!(parse_size(s), None, "{:?}", s);
+        }
+    }
+}